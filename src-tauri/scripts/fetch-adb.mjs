@@ -0,0 +1,127 @@
+// Fetches the pinned platform tools and places adb where Tauri expects the sidecar, at
+// `src-tauri/binaries/adb-<target triple>`. Archives are checked against the SHA-256 in
+// `platform-tools.json` before anything is extracted.
+//
+//   node src-tauri/scripts/fetch-adb.mjs         fetch adb for the target being built
+//   node src-tauri/scripts/fetch-adb.mjs --pin   record the checksum of every platform's archive
+
+import { createHash } from "node:crypto";
+import { execFileSync } from "node:child_process";
+import {
+  chmodSync,
+  copyFileSync,
+  existsSync,
+  mkdirSync,
+  mkdtempSync,
+  readFileSync,
+  rmSync,
+  writeFileSync,
+} from "node:fs";
+import { tmpdir } from "node:os";
+import { dirname, join } from "node:path";
+import { fileURLToPath } from "node:url";
+
+const root = join(dirname(fileURLToPath(import.meta.url)), "..");
+const manifestPath = join(root, "platform-tools.json");
+const binaries = join(root, "binaries");
+const manifest = JSON.parse(readFileSync(manifestPath, "utf8"));
+
+async function download(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(`Failed to download ${url}: HTTP ${response.status}`);
+  }
+  return Buffer.from(await response.arrayBuffer());
+}
+
+const sha256 = (bytes) => createHash("sha256").update(bytes).digest("hex");
+
+async function pin() {
+  for (const [os, archive] of Object.entries(manifest.archives)) {
+    archive.sha256 = sha256(await download(archive.url));
+    console.log(`${os}: ${archive.sha256}`);
+  }
+  writeFileSync(manifestPath, JSON.stringify(manifest, null, 2) + "\n");
+}
+
+function targetTriple() {
+  if (process.env.TAURI_ENV_TARGET_TRIPLE) {
+    return process.env.TAURI_ENV_TARGET_TRIPLE;
+  }
+  const host = execFileSync("rustc", ["-vV"], { encoding: "utf8" }).match(/^host: (\S+)$/m);
+  if (!host) {
+    throw new Error("Failed to read the host target from rustc");
+  }
+  return host[1];
+}
+
+function osOf(triple) {
+  if (triple.includes("windows")) return "windows";
+  if (triple.includes("apple-darwin")) return "macos";
+  if (triple.includes("linux")) return "linux";
+  throw new Error(`Platform tools are not available for ${triple}`);
+}
+
+async function fetchSidecar() {
+  const triple = targetTriple();
+  const os = osOf(triple);
+  const extension = os === "windows" ? ".exe" : "";
+  const sidecar = join(binaries, `adb-${triple}${extension}`);
+  const stamp = join(binaries, `adb-${triple}.version`);
+
+  if (existsSync(sidecar) && existsSync(stamp) && readFileSync(stamp, "utf8") === manifest.version) {
+    return;
+  }
+
+  const archive = manifest.archives[os];
+  if (!archive.sha256) {
+    throw new Error(
+      `No checksum is pinned for the ${os} platform tools. Run this script with --pin on a ` +
+        "trusted network and commit platform-tools.json"
+    );
+  }
+
+  console.log(`Fetching platform tools ${manifest.version} for ${triple}`);
+  const bytes = await download(archive.url);
+  const actual = sha256(bytes);
+  if (actual !== archive.sha256) {
+    throw new Error(`Platform tools checksum mismatch: expected ${archive.sha256}, got ${actual}`);
+  }
+
+  const scratch = mkdtempSync(join(tmpdir(), "penumbra-platform-tools-"));
+  try {
+    const zip = join(scratch, "platform-tools.zip");
+    writeFileSync(zip, bytes);
+    // bsdtar reads zips on macOS and Windows, while Linux has unzip
+    if (process.platform === "linux") {
+      execFileSync("unzip", ["-q", zip, "-d", scratch]);
+    } else {
+      execFileSync("tar", ["-xf", zip, "-C", scratch]);
+    }
+
+    const tools = join(scratch, "platform-tools");
+    copyFileSync(join(tools, `adb${extension}`), sidecar);
+    chmodSync(sidecar, 0o755);
+    // adb.exe loads these from its own directory, where tauri.windows.conf.json bundles them
+    if (os === "windows") {
+      for (const dll of ["AdbWinApi.dll", "AdbWinUsbApi.dll"]) {
+        copyFileSync(join(tools, dll), join(binaries, dll));
+      }
+    }
+    writeFileSync(stamp, manifest.version);
+  } finally {
+    rmSync(scratch, { recursive: true, force: true });
+  }
+}
+
+try {
+  if (process.argv.includes("--pin")) {
+    await pin();
+  } else {
+    mkdirSync(binaries, { recursive: true });
+    await fetchSidecar();
+  }
+} catch (e) {
+  console.error(e.message);
+  process.exit(1);
+}
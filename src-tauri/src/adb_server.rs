@@ -0,0 +1,209 @@
+use penumbra_installer::host;
+use penumbra_installer::platform::Platform;
+use penumbra_installer::task;
+use penumbra_installer::AdbManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zip::ZipArchive;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdbServerStatus {
+    pub running: bool,
+    pub version: Option<String>,
+    pub adb_path: Option<String>,
+    /// Whether `adb_path` is the copy shipped with or downloaded by the installer rather than a
+    /// system install
+    pub bundled: bool,
+}
+
+/// The platform tools release the installer ships and downloads, shared with
+/// `scripts/fetch-adb.mjs`, which places it as the sidecar
+const PLATFORM_TOOLS: &str = include_str!("../platform-tools.json");
+
+#[derive(Deserialize)]
+struct PlatformTools {
+    version: String,
+    archives: HashMap<String, PlatformToolsArchive>,
+}
+
+#[derive(Deserialize)]
+struct PlatformToolsArchive {
+    url: String,
+    /// Checked before the archive is extracted, so a tampered download is never run
+    sha256: Option<String>,
+}
+
+/// The pinned platform tools archive for this OS, with its expected SHA-256
+fn platform_tools_archive() -> Result<(String, String), String> {
+    let tools: PlatformTools = serde_json::from_str(PLATFORM_TOOLS)
+        .map_err(|e| format!("Invalid platform tools manifest: {}", e))?;
+    let os = std::env::consts::OS;
+    let archive = tools
+        .archives
+        .get(os)
+        .ok_or_else(|| format!("Platform tools are not available for {}", os))?;
+    let sha256 = archive.sha256.clone().ok_or_else(|| {
+        format!(
+            "No checksum is pinned for platform tools {} on {}",
+            tools.version, os
+        )
+    })?;
+    Ok((archive.url.clone(), sha256))
+}
+
+fn adb_filename() -> String {
+    format!("adb{}", Platform::executable_extension())
+}
+
+/// Location of the installer-managed platform tools
+pub fn bundled_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("platform-tools")
+}
+
+/// The `adb` sidecar shipped with the app, which Tauri places next to its executable
+fn sidecar_adb() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.join(adb_filename())).filter(|path| path.is_file())
+}
+
+/// Finds an `adb` binary, preferring the shipped sidecar, then the downloaded copy, over one on
+/// `PATH`
+pub fn find_adb(app_data_dir: &Path) -> Option<(PathBuf, bool)> {
+    if let Some(sidecar) = sidecar_adb() {
+        return Some((sidecar, true));
+    }
+
+    let bundled = bundled_dir(app_data_dir).join(adb_filename());
+    if bundled.is_file() {
+        return Some((bundled, true));
+    }
+
+    host::find_adb().map(|system| (system, false))
+}
+
+pub fn status(app_data_dir: &Path) -> AdbServerStatus {
+    let adb = find_adb(app_data_dir);
+    let version = AdbManager::server_version().ok();
+
+    AdbServerStatus {
+        running: version.is_some(),
+        version,
+        adb_path: adb
+            .as_ref()
+            .map(|(path, _)| path.to_string_lossy().to_string()),
+        bundled: adb.is_some_and(|(_, bundled)| bundled),
+    }
+}
+
+/// Runs an adb server control command such as `start-server` or `kill-server`
+pub fn run_adb(app_data_dir: &Path, command: &str) -> Result<(), String> {
+    let (adb, _) = find_adb(app_data_dir).ok_or_else(|| {
+        "adb was not found. Download the platform tools or install them manually".to_string()
+    })?;
+
+    let output = Command::new(&adb)
+        .arg(command)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", adb.display(), e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "adb {} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Downloads Google's platform tools for this OS into the installer-managed directory, for when
+/// the sidecar is missing. The archive must match its pinned SHA-256
+pub async fn download_platform_tools(app_data_dir: &Path) -> Result<PathBuf, String> {
+    let (url, expected) = platform_tools_archive()?;
+    let response = reqwest::Client::builder()
+        .user_agent(Platform::user_agent())
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download platform tools: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to download platform tools: HTTP {}",
+            response.status()
+        ));
+    }
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to download platform tools: {}", e))?;
+
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(format!(
+            "Platform tools checksum mismatch: expected {}, got {}",
+            expected, actual
+        ));
+    }
+
+    let dest = bundled_dir(app_data_dir);
+    let dest_clone = dest.clone();
+    task::spawn_blocking(move || extract_platform_tools(&bytes, &dest_clone))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    Ok(dest.join(adb_filename()))
+}
+
+fn extract_platform_tools(archive: &[u8], dest: &Path) -> Result<(), String> {
+    let mut archive = ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| format!("Invalid platform tools archive: {}", e))?;
+
+    std::fs::create_dir_all(dest).map_err(|e| format!("Failed to create {:?}: {}", dest, e))?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| format!("Invalid platform tools archive: {}", e))?;
+
+        // Archive entries are nested under `platform-tools/`
+        let Some(relative) = entry.enclosed_name().and_then(|name| {
+            name.strip_prefix("platform-tools")
+                .ok()
+                .map(Path::to_path_buf)
+        }) else {
+            continue;
+        };
+
+        let path = dest.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)
+                .map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| format!("Failed to extract {:?}: {}", path, e))?;
+        std::fs::write(&path, contents)
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                .map_err(|e| format!("Failed to set permissions on {:?}: {}", path, e))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use once_cell::sync::Lazy;
+use penumbra_installer::resolve::ResolutionProgress;
+use penumbra_installer::{InstallPhase, InstallWarning, ProgressSnapshot};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+use crate::messages::Message;
+
+const PROGRESS_EVENT: &str = "installation_progress";
+const PHASE_EVENT: &str = "installation_phase";
+const WARNING_EVENT: &str = "installation_warning";
+const RESOLUTION_EVENT: &str = "resolution_progress";
+const BYTES_EVENT: &str = "byte_progress";
+
+/// How often queued progress messages are flushed to the webview
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+pub static PROGRESS: Lazy<ProgressEmitter> = Lazy::new(ProgressEmitter::new);
+
+/// Coalesces progress messages into batched events so high-volume logging (APK installs, log
+/// streaming) doesn't flood the webview. Errors and phase changes bypass the batch window.
+pub struct ProgressEmitter {
+    app_handle: Mutex<Option<AppHandle>>,
+    pending: Mutex<Vec<Message>>,
+    last_message: Mutex<Option<Message>>,
+}
+
+impl ProgressEmitter {
+    fn new() -> Self {
+        Self {
+            app_handle: Mutex::new(None),
+            pending: Mutex::new(Vec::new()),
+            last_message: Mutex::new(None),
+        }
+    }
+
+    pub fn set_app_handle(&'static self, app: AppHandle) {
+        *self.app_handle.lock().unwrap() = Some(app);
+
+        thread::spawn(move || loop {
+            thread::sleep(FLUSH_INTERVAL);
+            self.flush();
+        });
+    }
+
+    /// Queues a message for the next batch, dropping consecutive duplicates
+    pub fn queue(&self, message: Message) {
+        {
+            let mut last_message = self.last_message.lock().unwrap();
+            if last_message.as_ref() == Some(&message) {
+                return;
+            }
+            *last_message = Some(message.clone());
+        }
+
+        self.pending.lock().unwrap().push(message);
+    }
+
+    /// Emits a message immediately, after any queued messages to preserve ordering
+    pub fn emit_now(&self, message: Message) {
+        *self.last_message.lock().unwrap() = Some(message.clone());
+
+        let mut batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        batch.push(message);
+        self.emit(batch);
+    }
+
+    /// Emits a phase change immediately, after any queued messages from the previous phase
+    pub fn emit_phase(&self, phase: InstallPhase) {
+        self.flush();
+        if let Some(ref app) = *self.app_handle.lock().unwrap() {
+            let _ = app.emit(PHASE_EVENT, phase);
+        }
+    }
+
+    /// Emits the combined progress of downloads and pushes immediately. The engine already
+    /// limits how often each of them reports
+    pub fn emit_bytes(&self, snapshot: ProgressSnapshot) {
+        if let Some(ref app) = *self.app_handle.lock().unwrap() {
+            let _ = app.emit(BYTES_EVENT, snapshot);
+        }
+    }
+
+    /// Emits the progress of resolving repositories immediately, as each one finishes
+    pub fn emit_resolution(&self, progress: ResolutionProgress) {
+        if let Some(ref app) = *self.app_handle.lock().unwrap() {
+            let _ = app.emit(RESOLUTION_EVENT, progress);
+        }
+    }
+
+    /// Emits a warning immediately, after any queued messages leading up to it
+    pub fn emit_warning(&self, warning: InstallWarning) {
+        self.flush();
+        if let Some(ref app) = *self.app_handle.lock().unwrap() {
+            let _ = app.emit(WARNING_EVENT, warning);
+        }
+    }
+
+    pub fn flush(&self) {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        if !batch.is_empty() {
+            self.emit(batch);
+        }
+    }
+
+    fn emit(&self, batch: Vec<Message>) {
+        if let Some(ref app) = *self.app_handle.lock().unwrap() {
+            let _ = app.emit(PROGRESS_EVENT, batch);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::events::PROGRESS;
+use crate::messages::Message;
+
+/// Maximum number of log lines retained for error reports
+const LOG_HISTORY_LIMIT: usize = 10_000;
+
+/// Span fields copied onto progress messages, so the frontend can group them without parsing
+const SPAN_FIELDS: [&str; 2] = ["repo", "step"];
+
+pub static LOGGER: Lazy<TauriLogger> = Lazy::new(TauriLogger::new);
+
+/// Forwards installer events to stdout, the progress stream, and the error report history
+pub struct TauriLogger {
+    history: Mutex<Vec<String>>,
+}
+
+impl TauriLogger {
+    fn new() -> Self {
+        Self {
+            history: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn history(&self) -> Vec<String> {
+        self.history.lock().unwrap().clone()
+    }
+
+    pub fn clear_history(&self) {
+        self.history.lock().unwrap().clear();
+    }
+}
+
+/// Installs `LOGGER` as the global subscriber. `log` records from dependencies are forwarded to it
+pub fn init() {
+    tracing_subscriber::registry()
+        // Spans are debug level so they stay out of plain `log` output
+        .with(filter_fn(|metadata| {
+            metadata.is_span() || *metadata.level() <= Level::INFO
+        }))
+        .with(&*LOGGER)
+        .init();
+}
+
+/// Field values of an event or span, rendered as strings
+#[derive(Default)]
+struct Fields(BTreeMap<&'static str, String>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+impl<S> Layer<S> for &'static TauriLogger
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let text = fields.0.remove("message").unwrap_or_default();
+
+        println!("{text}");
+
+        if let Ok(mut history) = self.history.lock() {
+            if history.len() >= LOG_HISTORY_LIMIT {
+                history.remove(0);
+            }
+            history.push(text.clone());
+        }
+
+        let mut message = Message::log(text);
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    for key in SPAN_FIELDS {
+                        if let Some(value) = span_fields.0.get(key) {
+                            message = message.with(key, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        if *event.metadata().level() <= Level::WARN {
+            PROGRESS.emit_now(message);
+        } else {
+            PROGRESS.queue(message);
+        }
+    }
+}
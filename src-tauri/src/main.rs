@@ -3,62 +3,54 @@
     windows_subsystem = "windows"
 )]
 
-use log::{warn, Level, Metadata, Record};
-use once_cell::sync::Lazy;
+use adb_server::AdbServerStatus;
+use events::PROGRESS;
+use logging::LOGGER;
+use messages::{Message, MessageId};
 use penumbra_installer::{
-    AdbManager, ConfigLoader, InstallConfig, InstallationEngine, InstallerError, Repository,
+    adb::ConnectedDevice,
+    github::GitHubClient,
+    keys::{self, KeyRotation, KeyStore},
+    platform::Platform,
+    resolve::ResolutionProgress,
+    task::{self, spawn_blocking},
+    telemetry::{self, InstallEvent},
+    AdbManager, ConfigLoader, ErrorDetails, HostReport, InstallConfig, InstallPhase, InstallPlan,
+    InstallReport, InstallWarning, InstallationEngine, InstallerError, ProgressTracker,
+    RemoteConfigAuth, Repository, RepositoryOverrides, UninstallPlan,
 };
+use prompts::PROMPTS;
+use report::InstallRecord;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
-use tauri::{AppHandle, Emitter, State};
-use tokio::{runtime::Handle, task::spawn_blocking};
+use setup_state::{SetupProgress, SetupState, SetupStep};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+use tauri_plugin_shell::ShellExt;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch};
 use tokio_util::sync::CancellationToken;
+use tracing::warn;
 
-struct TauriLogger {
-    app_handle: Arc<Mutex<Option<AppHandle>>>,
-}
-
-impl TauriLogger {
-    fn new() -> Self {
-        Self {
-            app_handle: Arc::new(Mutex::new(None)),
-        }
-    }
-
-    fn set_app_handle(&self, app: AppHandle) {
-        let mut handle = self.app_handle.lock().unwrap();
-        *handle = Some(app);
-    }
-}
-
-impl log::Log for TauriLogger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
-    }
-
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let message = format!("{}", record.args());
-
-            println!("{message}");
-
-            if let Ok(handle_guard) = self.app_handle.lock() {
-                if let Some(ref app) = *handle_guard {
-                    let _ = app.emit("installation_progress", &message);
-                }
-            }
-        }
-    }
-
-    fn flush(&self) {}
-}
-
-static LOGGER: Lazy<TauriLogger> = Lazy::new(|| TauriLogger::new());
+mod adb_server;
+mod events;
+mod logging;
+mod messages;
+mod prompts;
+mod report;
+mod secrets;
+mod setup_state;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct DeviceInfo {
     connected: bool,
     device_count: usize,
+    devices: Vec<ConnectedDevice>,
+    selected_serial: Option<String>,
+    error: Option<Message>,
+    /// English rendering of `error`
     error_message: Option<String>,
 }
 
@@ -74,57 +66,140 @@ struct RepositoryInfo {
     owner: String,
     repo: String,
     description: Option<String>,
+    /// Whether the repository is installed when none are chosen, being neither optional nor
+    /// disabled by the user
+    enabled: bool,
+    /// Only uninstalled when forced, as other repositories rely on it
+    protected: bool,
 }
 
-impl From<&Repository> for RepositoryInfo {
-    fn from(repo: &Repository) -> Self {
+impl RepositoryInfo {
+    fn new(config: &InstallConfig, repo: &Repository) -> Self {
         Self {
             name: repo.name.clone(),
             owner: repo.owner.clone(),
             repo: repo.repo.clone(),
             description: None, // Repository struct doesn't have description field
+            enabled: config.is_default_selected(repo),
+            protected: repo.protected,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CacheInfo {
+    path: String,
+    total_bytes: u64,
+    file_count: usize,
+    repositories: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct InstallerUpdateInfo {
+    current_version: String,
+    latest_version: String,
+    update_available: bool,
+    release_notes: Option<String>,
+    download_url: String,
+}
+
 // State for managing the installation process
 struct AppState {
     cancellation_token: Mutex<Option<CancellationToken>>,
+    selected_device: Mutex<Option<String>>,
+    last_install: Mutex<Option<InstallRecord>>,
+}
+
+impl AppState {
+    fn selected_serial(&self) -> Option<String> {
+        self.selected_device.lock().unwrap().clone()
+    }
 }
 
 #[tauri::command]
-async fn check_device_connection() -> Result<DeviceInfo, String> {
-    match AdbManager::connect().await {
-        Ok(_) => Ok(DeviceInfo {
-            connected: true,
-            device_count: 1,
-            error_message: None,
-        }),
-        Err(InstallerError::NoDevice) => Ok(DeviceInfo {
-            connected: false,
-            device_count: 0,
-            error_message: Some(
-                "No Android device connected. Please connect a device and enable USB debugging."
-                    .to_string(),
-            ),
-        }),
-        Err(InstallerError::MultipleDevices) => Ok(DeviceInfo {
-            connected: false,
-            device_count: 2, // Indicating multiple devices
-            error_message: Some(
-                "Multiple devices connected. Please connect exactly one device.".to_string(),
-            ),
-        }),
-        Err(e) => Ok(DeviceInfo {
-            connected: false,
-            device_count: 0,
-            error_message: Some(format!("ADB connection failed: {}", e)),
-        }),
+async fn check_device_connection(state: State<'_, AppState>) -> Result<DeviceInfo, String> {
+    let selected_serial = state.selected_serial();
+    // The server may not be running when connecting directly over USB
+    let devices = AdbManager::list_devices().unwrap_or_default();
+
+    let mut info = DeviceInfo {
+        connected: false,
+        device_count: devices.len(),
+        devices,
+        selected_serial: selected_serial.clone(),
+        error: None,
+        error_message: None,
+    };
+
+    let error = match AdbManager::connect_device(selected_serial.as_deref(), None).await {
+        Ok(_) => {
+            info.connected = true;
+            info.device_count = info.device_count.max(1);
+            None
+        }
+        Err(InstallerError::NoDevice) => Some(Message::new(MessageId::DeviceNotConnected)),
+        Err(InstallerError::MultipleDevices) => {
+            info.device_count = info.device_count.max(2);
+            Some(Message::new(MessageId::DeviceMultiple))
+        }
+        Err(e) => Some(Message::new(MessageId::DeviceConnectionFailed).with("error", e)),
+    };
+
+    info.error_message = error.as_ref().map(|message| message.text.clone());
+    info.error = error;
+
+    Ok(info)
+}
+
+#[tauri::command]
+async fn select_device(serial: Option<String>, state: State<'_, AppState>) -> Result<(), Message> {
+    if let Some(ref serial) = serial {
+        let devices = AdbManager::list_devices()
+            .map_err(|e| Message::new(MessageId::DeviceConnectionFailed).with("error", e))?;
+        if !devices.iter().any(|device| &device.serial == serial) {
+            return Err(Message::new(MessageId::DeviceNotFound).with("serial", serial));
+        }
     }
+
+    *state.selected_device.lock().unwrap() = serial;
+    Ok(())
+}
+
+#[tauri::command]
+async fn pair_wireless(host: String, port: u16, code: String) -> Result<(), String> {
+    let address = AdbManager::parse_address(&host, port).map_err(|e| e.to_string())?;
+
+    spawn_blocking(move || AdbManager::pair_wireless(address, code.trim()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn list_installed_packages() -> Result<Vec<PackageInfo>, String> {
+async fn connect_wireless(
+    host: String,
+    port: u16,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let address = AdbManager::parse_address(&host, port).map_err(|e| e.to_string())?;
+
+    let serial = spawn_blocking(move || AdbManager::connect_wireless(address))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let mut setup_state = SetupState::load()?;
+    setup_state.last_wireless_address = Some(serial.clone());
+    setup_state.save()?;
+
+    *state.selected_device.lock().unwrap() = Some(serial.clone());
+    Ok(serial)
+}
+
+#[tauri::command]
+async fn list_installed_packages(state: State<'_, AppState>) -> Result<Vec<PackageInfo>, String> {
+    let serial = state.selected_serial();
+
     spawn_blocking(move || {
         let package_names = vec![
             "com.penumbraos.pinitd",
@@ -143,7 +218,7 @@ async fn list_installed_packages() -> Result<Vec<PackageInfo>, String> {
 
         let rt = tokio::runtime::Handle::current();
 
-        let mut adb = match rt.block_on(AdbManager::connect()) {
+        let mut adb = match rt.block_on(AdbManager::connect_device(serial.as_deref(), None)) {
             Ok(adb) => adb,
             Err(_) => {
                 return Ok(vec![]);
@@ -178,36 +253,167 @@ async fn list_installed_packages() -> Result<Vec<PackageInfo>, String> {
         Ok(installed_packages)
     })
     .await
-    .map_err(|e| format!("Task join error: {}", e))?
+    .map_err(|e| e.to_string())?
+}
+
+/// The config chosen during setup: the remote config if one was set, otherwise the built-in one.
+/// Repositories the user disabled are left out of the default selection
+async fn load_config() -> penumbra_installer::Result<InstallConfig> {
+    let state = SetupState::load().map_err(InstallerError::Config)?;
+
+    let mut config = match &state.config_url {
+        Some(url) => {
+            let auth = RemoteConfigAuth {
+                bearer_token: state.config_token().map_err(InstallerError::Config)?,
+                headers: Vec::new(),
+            };
+            ConfigLoader::load_from_url_with_auth(url, &auth).await?
+        }
+        None => ConfigLoader::load_builtin("penumbra")?,
+    };
+    config.apply_overrides(&RepositoryOverrides::load());
+    Ok(config)
 }
 
 #[tauri::command]
 async fn get_available_repositories() -> Result<Vec<RepositoryInfo>, String> {
-    let config = ConfigLoader::load_builtin("penumbra")
+    let config = load_config()
+        .await
         .map_err(|e| format!("Failed to load config: {}", e))?;
 
-    let repos: Vec<RepositoryInfo> = config.repositories.iter().map(|repo| repo.into()).collect();
+    let repos: Vec<RepositoryInfo> = config
+        .all_repositories()
+        .iter()
+        .map(|repo| RepositoryInfo::new(&config, repo))
+        .collect();
 
     Ok(repos)
 }
 
+/// Includes or leaves out a repository when none are chosen, for this and later runs
+#[tauri::command]
+async fn set_repository_enabled(name: String, enabled: bool) -> Result<(), String> {
+    let mut overrides = RepositoryOverrides::load();
+    if overrides.set_enabled(&name, enabled) {
+        overrides
+            .save()
+            .map_err(|e| format!("Failed to save repository settings: {}", e))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn preview_installation(
+    repos: Vec<String>,
+    variables: HashMap<String, String>,
+    state: State<'_, AppState>,
+) -> Result<InstallPlan, ErrorDetails> {
+    let mut config = load_config()
+        .await
+        .map_err(|e| error_details("Failed to load config", &e))?;
+
+    let repo_filter = if repos.is_empty() { None } else { Some(repos) };
+    let mut active_repos = config
+        .filter_repositories(repo_filter)
+        .map_err(|e| error_details("Failed to select repositories", &e))?;
+
+    config
+        .resolve_and_apply_variables(&mut active_repos, &variables)
+        .map_err(|e| error_details("Failed to resolve variables", &e))?;
+
+    let github = GitHubClient::new_with_token(setup_state::load_github_token());
+    let mut plan = InstallPlan::resolve(&config, &active_repos, &github)
+        .await
+        .map_err(|e| error_details("Failed to resolve installation plan", &e))?;
+
+    // Firmware checks are best effort, as the preview doesn't require a connected device
+    if !config.firmware_compatibility.is_empty() {
+        if let Ok(mut adb) =
+            AdbManager::connect_device(state.selected_serial().as_deref(), None).await
+        {
+            if let Ok(identity) = adb.device_identity().await {
+                plan.check_firmware(&config, &identity.fingerprint);
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+#[tauri::command]
+async fn preview_uninstall(
+    repos: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<UninstallPlan, ErrorDetails> {
+    let config = load_config()
+        .await
+        .map_err(|e| error_details("Failed to load config", &e))?;
+
+    let repo_filter = if repos.is_empty() { None } else { Some(repos) };
+    let active_repos = config
+        .filter_repositories(repo_filter)
+        .map_err(|e| error_details("Failed to select repositories", &e))?;
+
+    let mut adb = AdbManager::connect_device(state.selected_serial().as_deref(), None)
+        .await
+        .map_err(|e| error_details("Failed to connect to device", &e))?;
+
+    UninstallPlan::resolve(&config, &active_repos, &mut adb)
+        .await
+        .map_err(|e| error_details("Failed to resolve uninstall plan", &e))
+}
+
+/// Downloads the repositories' release assets into the cache without needing a device. Later
+/// installs run from the cache
+#[tauri::command]
+async fn download_repositories(
+    repos: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<InstallReport, ErrorDetails> {
+    install_repositories(repos, Some(true), None, None, None, state).await
+}
+
 #[tauri::command]
 async fn install_repositories(
     repos: Vec<String>,
-    app: AppHandle,
+    download_only: Option<bool>,
+    force_unlock: Option<bool>,
+    from_downloads: Option<bool>,
+    ignore_battery: Option<bool>,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    let _ = app.emit("installation_progress", "Loading configuration...");
-
-    let config = ConfigLoader::load_builtin("penumbra").map_err(|e| {
-        let _ = app.emit(
-            "installation_progress",
-            format!("Error: Failed to load config - {}", e),
-        );
-        format!("Failed to load config: {}", e)
+) -> Result<InstallReport, ErrorDetails> {
+    let download_only = download_only.unwrap_or(false);
+    let force_unlock = force_unlock.unwrap_or(false);
+    let ignore_battery = ignore_battery.unwrap_or(false);
+    let from_downloads = from_downloads.unwrap_or(false);
+
+    PROGRESS.emit_now(Message::new(MessageId::LoadingConfig));
+
+    let config = load_config().await.map_err(|e| {
+        PROGRESS.emit_now(Message::new(MessageId::ConfigLoadFailed).with("error", &e));
+        error_details("Failed to load config", &e)
     })?;
 
-    let _ = app.emit("installation_progress", "Starting installation...");
+    let repo_filter = if repos.is_empty() { None } else { Some(repos) };
+    let active_repos = config.filter_repositories(repo_filter).map_err(|e| {
+        PROGRESS.emit_now(Message::new(MessageId::RepositorySelectionFailed).with("error", &e));
+        error_details("Failed to select repositories", &e)
+    })?;
+
+    LOGGER.clear_history();
+    *state.last_install.lock().unwrap() = Some(InstallRecord {
+        config_name: config.name.clone(),
+        config_source: config.source().cloned(),
+        repositories: active_repos.clone(),
+        download_only,
+        result: None,
+    });
+
+    if download_only {
+        PROGRESS.emit_now(Message::new(MessageId::DownloadStarted));
+    } else {
+        PROGRESS.emit_now(Message::new(MessageId::InstallStarted));
+    }
 
     let cancellation_token = CancellationToken::new();
 
@@ -216,54 +422,493 @@ async fn install_repositories(
         *token = Some(cancellation_token.clone());
     }
 
-    let installation_result = run_installation(config, repos, cancellation_token.clone()).await;
+    let installation_result = run_installation(
+        config,
+        active_repos,
+        download_only,
+        force_unlock,
+        from_downloads,
+        state.selected_serial(),
+        cancellation_token.clone(),
+    )
+    .await;
 
     {
         let mut token = state.cancellation_token.lock().unwrap();
         *token = None;
     }
 
+    if let Some(record) = state.last_install.lock().unwrap().as_mut() {
+        record.result = Some(installation_result.clone());
+    }
+
     match installation_result {
-        Ok(()) => {
-            let _ = app.emit(
-                "installation_progress",
-                "Installation completed successfully!",
-            );
-            Ok("Installation completed successfully".to_string())
+        Ok(report) if download_only => {
+            PROGRESS.emit_now(Message::new(MessageId::DownloadCompleted));
+            Ok(report)
+        }
+        Ok(report) if report.succeeded() => {
+            PROGRESS.emit_now(Message::new(MessageId::InstallCompleted));
+            Ok(report)
+        }
+        Ok(report) => {
+            PROGRESS.emit_now(Message::new(MessageId::InstallCompletedWithFailures));
+            Ok(report)
         }
-        Err(error_msg) => {
-            let _ = app.emit("installation_progress", format!("Error: {}", error_msg));
-            Err(error_msg)
+        Err(error) => {
+            PROGRESS.emit_now(Message::new(MessageId::InstallFailed).with("error", &error.message));
+            Err(error)
         }
     }
 }
 
 async fn run_installation(
     config: InstallConfig,
-    repos: Vec<String>,
+    active_repos: Vec<Repository>,
+    download_only: bool,
+    force_unlock: bool,
+    from_downloads: bool,
+    serial: Option<String>,
     cancellation_token: CancellationToken,
-) -> Result<(), String> {
-    spawn_blocking(move || {
+) -> Result<InstallReport, ErrorDetails> {
+    let config_name = config.name.clone();
+    let started = Instant::now();
+
+    let result = spawn_blocking(move || {
         let rt = Handle::current();
 
-        let mut engine = match rt.block_on(InstallationEngine::new_with_token(
-            config,
-            None,
-            Some(cancellation_token),
-        )) {
-            Ok(engine) => engine,
-            Err(e) => return Err(format!("Failed to initialize installation engine: {}", e)),
+        let github_token = setup_state::load_github_token();
+
+        if download_only {
+            let mut engine = rt
+                .block_on(InstallationEngine::new_offline(
+                    config,
+                    Platform::cache_dir(),
+                    github_token,
+                    Some(cancellation_token),
+                ))
+                .map_err(|e| ("Failed to initialize installation engine", e))?;
+            forward_phases(&rt, engine.subscribe_phase());
+            forward_bytes(&rt, &mut engine);
+            forward_warnings(&rt, engine.subscribe_warnings());
+            forward_resolution(&rt, engine.subscribe_resolution());
+
+            return rt
+                .block_on(engine.download(&active_repos))
+                .map_err(|e| ("Download failed", e));
+        }
+
+        // Only reuse assets staged by a download-only run when asked to, since they may be
+        // older than the current releases
+        let cache_dir = if from_downloads {
+            Platform::cache_dir()
+        } else {
+            Platform::temp_dir()
         };
 
-        let repo_filter = if repos.is_empty() { None } else { Some(repos) };
+        let adb = rt
+            .block_on(AdbManager::connect_device(serial.as_deref(), None))
+            .map_err(|e| ("Failed to connect to device", e))?;
+
+        let mut engine = rt
+            .block_on(InstallationEngine::new_with_device(
+                config,
+                cache_dir,
+                github_token,
+                adb,
+                Some(cancellation_token),
+            ))
+            .map_err(|e| ("Failed to initialize installation engine", e))?;
+
+        engine.set_force_unlock(force_unlock);
+        engine.set_ignore_battery(ignore_battery);
+        engine.set_check_known_device(true);
+        engine.set_prompter(&*PROMPTS);
+        forward_phases(&rt, engine.subscribe_phase());
+        forward_bytes(&rt, &mut engine);
+        forward_warnings(&rt, engine.subscribe_warnings());
+        forward_resolution(&rt, engine.subscribe_resolution());
+        rt.block_on(engine.install(&active_repos, from_downloads))
+            .map_err(|e| ("Installation failed", e))
+    })
+    .await
+    .unwrap_or_else(|e| Err(("Installation task failed", e)));
+
+    if !download_only {
+        report_telemetry(&config_name, &result, started.elapsed());
+    }
+
+    result.map_err(|(context, e)| error_details(context, &e))
+}
+
+/// Emits each phase the engine enters, until the engine is dropped
+fn forward_phases(rt: &Handle, mut phases: watch::Receiver<InstallPhase>) {
+    rt.spawn(async move {
+        while phases.changed().await.is_ok() {
+            let phase = *phases.borrow_and_update();
+            PROGRESS.emit_phase(phase);
+        }
+    });
+}
+
+/// Emits the combined progress of asset downloads and pushes to the device as it changes,
+/// until the engine is dropped
+fn forward_bytes(rt: &Handle, engine: &mut InstallationEngine) {
+    let tracker = ProgressTracker::new();
+    let mut snapshots = tracker.subscribe();
+    rt.spawn(engine.track_progress(&tracker));
+    rt.spawn(async move {
+        while snapshots.changed().await.is_ok() {
+            let snapshot = snapshots.borrow_and_update().clone();
+            PROGRESS.emit_bytes(snapshot);
+        }
+    });
+}
 
-        match rt.block_on(engine.install(repo_filter, false)) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(format!("Installation failed: {}", e)),
+/// Emits the progress of resolving repositories, until the engine is dropped
+fn forward_resolution(rt: &Handle, mut resolution: mpsc::UnboundedReceiver<ResolutionProgress>) {
+    rt.spawn(async move {
+        while let Some(progress) = resolution.recv().await {
+            PROGRESS.emit_resolution(progress);
         }
+    });
+}
+
+/// Emits each warning as the engine records it, until the engine is dropped
+fn forward_warnings(rt: &Handle, mut warnings: mpsc::UnboundedReceiver<InstallWarning>) {
+    rt.spawn(async move {
+        while let Some(warning) = warnings.recv().await {
+            PROGRESS.emit_warning(warning);
+        }
+    });
+}
+
+/// Structured error details with `context` prepended to the message
+fn error_details(context: &str, error: &InstallerError) -> ErrorDetails {
+    let mut details = error.details();
+    details.message = format!("{}: {}", context, details.message);
+    // The library's hint names the command line flag
+    if matches!(error.root(), InstallerError::LowBattery { .. }) {
+        details.hint =
+            Some("Charge the device and retry, or choose Install anyway to go ahead".to_string());
+    }
+    details
+}
+
+/// Sends an anonymous install summary in the background if the user has opted in
+fn report_telemetry(
+    config_name: &str,
+    result: &Result<InstallReport, (&str, InstallerError)>,
+    duration: Duration,
+) {
+    let endpoint = match SetupState::load() {
+        Ok(state) => state.telemetry_endpoint().map(str::to_string),
+        Err(_) => None,
+    };
+
+    let Some(endpoint) = endpoint else {
+        return;
+    };
+
+    let event = InstallEvent::new(config_name, result.as_ref().map_err(|(_, e)| e), duration);
+    tokio::spawn(async move {
+        if let Err(e) = telemetry::submit(&endpoint, &event).await {
+            warn!("Failed to send telemetry: {}", e);
+        }
+    });
+}
+
+fn cache_info() -> Result<CacheInfo, String> {
+    let usage = Platform::cache_usage().map_err(|e| format!("Failed to read cache: {}", e))?;
+
+    Ok(CacheInfo {
+        path: usage.path.to_string_lossy().to_string(),
+        total_bytes: usage.total_bytes,
+        file_count: usage.file_count,
+        repositories: usage.entries.into_iter().map(|entry| entry.name).collect(),
+    })
+}
+
+#[tauri::command]
+async fn get_cache_info() -> Result<CacheInfo, String> {
+    spawn_blocking(cache_info)
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn clear_cache() -> Result<(), String> {
+    let cache_dir = Platform::cache_dir();
+    if cache_dir.exists() {
+        tokio::fs::remove_dir_all(&cache_dir)
+            .await
+            .map_err(|e| format!("Failed to clear cache: {}", e))?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn check_installer_update() -> Result<InstallerUpdateInfo, String> {
+    let github = GitHubClient::new_with_token(setup_state::load_github_token());
+    let release = github
+        .get_latest_release("PenumbraOS", "installer")
+        .await
+        .map_err(|e| format!("Failed to check for installer updates: {}", e))?;
+
+    let current_version = env!("CARGO_PKG_VERSION").to_string();
+    let update_available = parse_version(&release.tag_name) > parse_version(&current_version);
+
+    Ok(InstallerUpdateInfo {
+        current_version,
+        latest_version: release.tag_name,
+        update_available,
+        release_notes: release.body,
+        download_url: release.html_url,
+    })
+}
+
+/// Parses `v1.2.3`-style tags into comparable numeric components, ignoring any suffix
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+#[tauri::command]
+async fn get_setup_state() -> Result<SetupState, String> {
+    SetupState::load()
+}
+
+/// Imports an ADB private key as the setup's ADB source, keeping an encrypted copy and
+/// restoring it to `~/.android/adbkey` when no key is present there
+#[tauri::command]
+async fn import_adb_key(path: String) -> Result<SetupProgress, String> {
+    let private_key = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| format!("Failed to read ADB key: {}", e))?;
+
+    if !private_key.contains("PRIVATE KEY") {
+        return Err("File does not look like an ADB private key".to_string());
+    }
+
+    let mut state = SetupState::load()?;
+    state.set_adb_key(&private_key)?;
+    state.mark_complete(SetupStep::AdbSource);
+    state.save()?;
+
+    restore_adb_key(&state)?;
+    Ok(state.progress())
+}
+
+fn restore_adb_key(state: &SetupState) -> Result<(), String> {
+    let Some(android_dir) = dirs_home().map(|home| home.join(".android")) else {
+        return Ok(());
+    };
+
+    let key_path = android_dir.join("adbkey");
+    if key_path.exists() {
+        return Ok(());
+    }
+
+    if let Some(private_key) = state.adb_key()? {
+        std::fs::create_dir_all(&android_dir)
+            .map_err(|e| format!("Failed to create .android directory: {}", e))?;
+        setup_state::write_private(&key_path, private_key.as_bytes())
+            .map_err(|e| format!("Failed to restore ADB key: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Replaces `~/.android/adbkey` with a new key authorized on the attached device, and the
+/// retired key's imported copy with the new one
+#[tauri::command]
+async fn rotate_adb_key() -> Result<KeyRotation, String> {
+    let store = KeyStore::local().map_err(|e| e.to_string())?;
+    let rotation = keys::rotate(&store, None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut state = SetupState::load()?;
+    if let Some(private_key) = &rotation.private_key {
+        if state.adb_key()?.is_some() {
+            state.set_adb_key(private_key)?;
+            state.save()?;
+        }
+    }
+
+    Ok(rotation)
+}
+
+fn dirs_home() -> Option<std::path::PathBuf> {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }).map(Into::into)
+}
+
+#[tauri::command]
+async fn get_setup_progress() -> Result<SetupProgress, String> {
+    Ok(SetupState::load()?.progress())
+}
+
+#[tauri::command]
+async fn mark_step_complete(step: SetupStep) -> Result<SetupProgress, String> {
+    let mut state = SetupState::load()?;
+    state.mark_complete(step);
+    state.save()?;
+    Ok(state.progress())
+}
+
+#[tauri::command]
+async fn set_github_token(token: Option<String>) -> Result<(), String> {
+    let token = token.filter(|t| !t.trim().is_empty());
+    setup_state::store_github_token(token.as_deref())?;
+
+    let mut state = SetupState::load()?;
+    state.has_github_token = token.is_some();
+    state.save()
+}
+
+/// Chooses a remote config, optionally behind a bearer token, or the built-in config when `url`
+/// is empty. Remote configs are fetched before saving, so bad URLs and credentials are reported
+#[tauri::command]
+async fn set_remote_config(
+    url: Option<String>,
+    token: Option<String>,
+) -> Result<SetupProgress, ErrorDetails> {
+    let url = url.filter(|u| !u.trim().is_empty());
+    let token = token.filter(|t| !t.trim().is_empty());
+
+    if let Some(url) = &url {
+        let auth = RemoteConfigAuth {
+            bearer_token: token.clone(),
+            headers: Vec::new(),
+        };
+        ConfigLoader::load_from_url_with_auth(url, &auth)
+            .await
+            .map_err(|e| error_details("Failed to load config", &e))?;
+    }
+
+    let save = || -> Result<SetupProgress, String> {
+        let mut state = SetupState::load()?;
+        state.config_url = url.clone();
+        state.set_config_token(token.as_deref())?;
+        state.mark_complete(SetupStep::ConfigChosen);
+        state.save()?;
+        Ok(state.progress())
+    };
+    save().map_err(|e| error_details("Failed to save config choice", &InstallerError::Config(e)))
+}
+
+#[tauri::command]
+async fn set_telemetry(enabled: bool, endpoint: Option<String>) -> Result<SetupState, String> {
+    let mut state = SetupState::load()?;
+    state.telemetry_enabled = enabled;
+    if endpoint.is_some() {
+        state.telemetry_endpoint = endpoint.filter(|e| !e.trim().is_empty());
+    }
+    state.save()?;
+    Ok(state)
+}
+
+#[tauri::command]
+async fn generate_error_report(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let device_info = report::collect_device_info(state.selected_serial().as_deref()).await;
+    let last_install = state.last_install.lock().unwrap().clone();
+
+    let report_dir = app_data_dir(&app)?.join("reports");
+
+    let path = report::write_report(
+        &report_dir,
+        &LOGGER.history(),
+        last_install.as_ref(),
+        &PROMPTS.entered_values(),
+        &device_info,
+    )?;
+
+    // Reveal the report by opening its containing directory in the file manager
+    #[allow(deprecated)]
+    if let Err(e) = app
+        .shell()
+        .open(report_dir.to_string_lossy().to_string(), None)
+    {
+        warn!("Failed to open report directory: {}", e);
+    }
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+fn app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate app data directory: {}", e))
+}
+
+#[tauri::command]
+async fn get_adb_server_status(app: AppHandle) -> Result<AdbServerStatus, String> {
+    let data_dir = app_data_dir(&app)?;
+    spawn_blocking(move || adb_server::status(&data_dir))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Checks this computer for the preflight screen: libusb, adb, free disk space, access to
+/// GitHub, and its clock
+#[tauri::command]
+async fn check_host(app: AppHandle) -> Result<HostReport, String> {
+    let adb = adb_server::find_adb(&app_data_dir(&app)?).map(|(path, _)| path);
+    let github = GitHubClient::new_with_token(setup_state::load_github_token());
+    Ok(HostReport::run(&github, adb).await)
+}
+
+#[tauri::command]
+async fn download_adb(app: AppHandle) -> Result<AdbServerStatus, String> {
+    let data_dir = app_data_dir(&app)?;
+    PROGRESS.emit_now(Message::new(MessageId::PlatformToolsDownloading));
+    adb_server::download_platform_tools(&data_dir).await?;
+    Ok(adb_server::status(&data_dir))
+}
+
+#[tauri::command]
+async fn start_adb_server(app: AppHandle) -> Result<AdbServerStatus, String> {
+    control_adb_server(app, &["start-server"]).await
+}
+
+#[tauri::command]
+async fn stop_adb_server(app: AppHandle) -> Result<AdbServerStatus, String> {
+    control_adb_server(app, &["kill-server"]).await
+}
+
+#[tauri::command]
+async fn restart_adb_server(app: AppHandle) -> Result<AdbServerStatus, String> {
+    control_adb_server(app, &["kill-server", "start-server"]).await
+}
+
+async fn control_adb_server(
+    app: AppHandle,
+    commands: &'static [&'static str],
+) -> Result<AdbServerStatus, String> {
+    let data_dir = app_data_dir(&app)?;
+    spawn_blocking(move || {
+        for command in commands {
+            adb_server::run_adb(&data_dir, command)?;
+        }
+        Ok(adb_server::status(&data_dir))
     })
     .await
-    .map_err(|e| format!("Task join error: {}", e))?
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn get_message_catalog() -> BTreeMap<MessageId, &'static str> {
+    messages::catalog()
 }
 
 #[tauri::command]
@@ -275,29 +920,87 @@ async fn cancel_installation(state: State<'_, AppState>) -> Result<(), String> {
             warn!("Cancelled installation");
         }
     }
+    PROMPTS.dismiss_all();
 
     Ok(())
 }
 
+#[tauri::command]
+async fn answer_prompt(id: u64, confirmed: bool) -> Result<(), String> {
+    if PROMPTS.answer(id, confirmed) {
+        Ok(())
+    } else {
+        Err(format!("Prompt {} is no longer waiting for an answer", id))
+    }
+}
+
+/// Gives the value asked for by an `installation_input` event, or none to stop the install
+#[tauri::command]
+async fn answer_input(id: u64, value: Option<String>) -> Result<(), String> {
+    if PROMPTS.answer_input(id, value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Input request {} is no longer waiting for a value",
+            id
+        ))
+    }
+}
+
 fn main() {
-    log::set_logger(&*LOGGER)
-        .map(|()| log::set_max_level(log::LevelFilter::Info))
-        .expect("Failed to initialize logger");
+    logging::init();
+    task::install_panic_hook();
+
+    std::thread::spawn(|| {
+        Platform::clean_stale_temp_dirs();
+        Platform::enforce_cache_limit();
+    });
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .manage(AppState {
             cancellation_token: Mutex::new(None),
+            selected_device: Mutex::new(None),
+            last_install: Mutex::new(None),
         })
         .invoke_handler(tauri::generate_handler![
             check_device_connection,
+            select_device,
+            pair_wireless,
+            connect_wireless,
             list_installed_packages,
             get_available_repositories,
+            set_repository_enabled,
+            preview_installation,
+            preview_uninstall,
             install_repositories,
-            cancel_installation
+            download_repositories,
+            get_cache_info,
+            clear_cache,
+            check_installer_update,
+            get_setup_state,
+            get_setup_progress,
+            import_adb_key,
+            rotate_adb_key,
+            mark_step_complete,
+            set_github_token,
+            set_telemetry,
+            set_remote_config,
+            generate_error_report,
+            get_adb_server_status,
+            check_host,
+            download_adb,
+            start_adb_server,
+            stop_adb_server,
+            restart_adb_server,
+            get_message_catalog,
+            cancel_installation,
+            answer_prompt,
+            answer_input
         ])
         .setup(|app| {
-            LOGGER.set_app_handle(app.handle().clone());
+            PROGRESS.set_app_handle(app.handle().clone());
+            PROMPTS.set_app_handle(app.handle().clone());
             Ok(())
         })
         .run(tauri::generate_context!())
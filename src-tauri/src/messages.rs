@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Stable identifiers for user-facing messages, so the frontend can localize them without
+/// matching on English text
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageId {
+    /// Free-form installer log output, which is not localized
+    Log,
+    DeviceNotConnected,
+    DeviceMultiple,
+    DeviceNotFound,
+    DeviceConnectionFailed,
+    LoadingConfig,
+    ConfigLoadFailed,
+    RepositorySelectionFailed,
+    DownloadStarted,
+    InstallStarted,
+    DownloadCompleted,
+    InstallCompleted,
+    InstallCompletedWithFailures,
+    InstallFailed,
+    PlatformToolsDownloading,
+}
+
+impl MessageId {
+    pub const ALL: [MessageId; 15] = [
+        MessageId::Log,
+        MessageId::DeviceNotConnected,
+        MessageId::DeviceMultiple,
+        MessageId::DeviceNotFound,
+        MessageId::DeviceConnectionFailed,
+        MessageId::LoadingConfig,
+        MessageId::ConfigLoadFailed,
+        MessageId::RepositorySelectionFailed,
+        MessageId::DownloadStarted,
+        MessageId::InstallStarted,
+        MessageId::DownloadCompleted,
+        MessageId::InstallCompleted,
+        MessageId::InstallCompletedWithFailures,
+        MessageId::InstallFailed,
+        MessageId::PlatformToolsDownloading,
+    ];
+
+    /// English template. `{name}` placeholders are filled from the message params
+    pub fn template(self) -> &'static str {
+        match self {
+            MessageId::Log => "{text}",
+            MessageId::DeviceNotConnected => {
+                "No Android device connected. Please connect a device and enable USB debugging."
+            }
+            MessageId::DeviceMultiple => "Multiple devices connected. Please select a device.",
+            MessageId::DeviceNotFound => "Device '{serial}' is not connected",
+            MessageId::DeviceConnectionFailed => "ADB connection failed: {error}",
+            MessageId::LoadingConfig => "Loading configuration...",
+            MessageId::ConfigLoadFailed => "Error: Failed to load config - {error}",
+            MessageId::RepositorySelectionFailed => "Error: {error}",
+            MessageId::DownloadStarted => "Starting download...",
+            MessageId::InstallStarted => "Starting installation...",
+            MessageId::DownloadCompleted => "Download completed successfully!",
+            MessageId::InstallCompleted => "Installation completed successfully!",
+            MessageId::InstallCompletedWithFailures => "Installation finished with failures",
+            MessageId::InstallFailed => "Error: {error}",
+            MessageId::PlatformToolsDownloading => "Downloading Android platform tools...",
+        }
+    }
+}
+
+/// A user-facing message with its identifier, parameters, and English rendering
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Message {
+    pub id: MessageId,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    pub text: String,
+}
+
+impl Message {
+    pub fn new(id: MessageId) -> Self {
+        Self {
+            id,
+            params: BTreeMap::new(),
+            text: id.template().to_string(),
+        }
+    }
+
+    pub fn log(text: impl Into<String>) -> Self {
+        Self::new(MessageId::Log).with("text", text.into())
+    }
+
+    /// Sets a parameter and re-renders the English text
+    pub fn with(mut self, key: &str, value: impl ToString) -> Self {
+        self.params.insert(key.to_string(), value.to_string());
+        self.text = render(self.id.template(), &self.params);
+        self
+    }
+}
+
+/// `template` with its `{name}` placeholders filled from `params` in one pass, so placeholders
+/// inside the values are left as they are
+fn render(template: &str, params: &BTreeMap<String, String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest
+            .find('}')
+            .and_then(|end| Some((end, params.get(&rest[1..end])?)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// The English template for every message, for the frontend to fall back on
+pub fn catalog() -> BTreeMap<MessageId, &'static str> {
+    MessageId::ALL
+        .iter()
+        .map(|id| (*id, id.template()))
+        .collect()
+}
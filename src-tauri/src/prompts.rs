@@ -0,0 +1,149 @@
+use once_cell::sync::Lazy;
+use penumbra_installer::step::{InputFuture, PromptFuture};
+use penumbra_installer::{InputRequest, InstallerError, Prompter};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+
+use crate::events::PROGRESS;
+
+const PROMPT_EVENT: &str = "installation_prompt";
+const INPUT_EVENT: &str = "installation_input";
+
+pub static PROMPTS: Lazy<DialogPrompter> = Lazy::new(DialogPrompter::new);
+
+#[derive(Clone, Serialize)]
+struct PromptRequest {
+    id: u64,
+    message: String,
+    default: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct InputEvent<'a> {
+    id: u64,
+    #[serde(flatten)]
+    request: &'a InputRequest,
+}
+
+/// Shows `Prompt` steps as a dialog in the frontend, which answers them through `answer_prompt`,
+/// and asks for variables without a value, answered through `answer_input`
+pub struct DialogPrompter {
+    app_handle: Mutex<Option<AppHandle>>,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<bool>>>,
+    pending_inputs: Mutex<HashMap<u64, oneshot::Sender<Option<String>>>>,
+    /// Values given for variables, kept out of error reports
+    entered: Mutex<Vec<String>>,
+}
+
+impl DialogPrompter {
+    fn new() -> Self {
+        Self {
+            app_handle: Mutex::new(None),
+            next_id: AtomicU64::new(0),
+            pending: Mutex::new(HashMap::new()),
+            pending_inputs: Mutex::new(HashMap::new()),
+            entered: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn set_app_handle(&self, app: AppHandle) {
+        *self.app_handle.lock().unwrap() = Some(app);
+    }
+
+    /// Answers prompt `id`, returning false if it is no longer waiting
+    pub fn answer(&self, id: u64, confirmed: bool) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .is_some_and(|sender| sender.send(confirmed).is_ok())
+    }
+
+    /// Answers input request `id` with `value`, or `None` if the user gave none. Returns false
+    /// if it is no longer waiting
+    pub fn answer_input(&self, id: u64, value: Option<String>) -> bool {
+        self.pending_inputs
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .is_some_and(|sender| sender.send(value).is_ok())
+    }
+
+    /// Every value given for a variable since the app started
+    pub fn entered_values(&self) -> Vec<String> {
+        self.entered.lock().unwrap().clone()
+    }
+
+    /// Drops every unanswered prompt, such as when the installation is cancelled
+    pub fn dismiss_all(&self) {
+        self.pending.lock().unwrap().clear();
+        self.pending_inputs.lock().unwrap().clear();
+    }
+
+    fn app_handle(&self) -> Result<AppHandle, InstallerError> {
+        self.app_handle
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| InstallerError::Internal("No window to show the prompt in".to_string()))
+    }
+}
+
+impl Prompter for &'static DialogPrompter {
+    fn confirm<'a>(&'a self, message: &'a str, default: bool) -> PromptFuture<'a> {
+        Box::pin(async move {
+            let app = self.app_handle()?;
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let (sender, receiver) = oneshot::channel();
+            self.pending.lock().unwrap().insert(id, sender);
+
+            // Show the log leading up to the prompt before the dialog
+            PROGRESS.flush();
+            let request = PromptRequest {
+                id,
+                message: message.to_string(),
+                default,
+            };
+            if let Err(e) = app.emit(PROMPT_EVENT, request) {
+                self.pending.lock().unwrap().remove(&id);
+                return Err(InstallerError::Internal(format!(
+                    "Failed to show prompt: {}",
+                    e
+                )));
+            }
+
+            receiver.await.map_err(|_| InstallerError::Cancelled)
+        })
+    }
+
+    fn input<'a>(&'a self, request: &'a InputRequest) -> InputFuture<'a> {
+        Box::pin(async move {
+            let app = self.app_handle()?;
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let (sender, receiver) = oneshot::channel();
+            self.pending_inputs.lock().unwrap().insert(id, sender);
+
+            PROGRESS.flush();
+            if let Err(e) = app.emit(INPUT_EVENT, InputEvent { id, request }) {
+                self.pending_inputs.lock().unwrap().remove(&id);
+                return Err(InstallerError::Internal(format!(
+                    "Failed to ask for {}: {}",
+                    request.variable, e
+                )));
+            }
+
+            let value = receiver.await.map_err(|_| InstallerError::Cancelled)?;
+            if let Some(value) = &value {
+                self.entered.lock().unwrap().push(value.clone());
+            }
+            Ok(value)
+        })
+    }
+}
@@ -0,0 +1,130 @@
+use penumbra_installer::redact::redact;
+use penumbra_installer::{
+    AdbManager, ConfigSource, ErrorDetails, InstallEnvironment, InstallReport, Repository,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// Record of the most recent installation attempt, kept for error reports
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstallRecord {
+    pub config_name: String,
+    /// Where the config was loaded from, and its hash
+    #[serde(default)]
+    pub config_source: Option<ConfigSource>,
+    pub repositories: Vec<Repository>,
+    pub download_only: bool,
+    pub result: Option<Result<InstallReport, ErrorDetails>>,
+}
+
+const DEVICE_PROPERTIES: [&str; 5] = [
+    "ro.product.manufacturer",
+    "ro.product.model",
+    "ro.build.fingerprint",
+    "ro.build.version.release",
+    "ro.build.version.sdk",
+];
+
+/// Collects basic device properties, or the reason they couldn't be read
+pub async fn collect_device_info(serial: Option<&str>) -> String {
+    let mut adb = match AdbManager::connect_device(serial, None).await {
+        Ok(adb) => adb,
+        Err(e) => return format!("Device unavailable: {}\n", e),
+    };
+
+    let mut output = String::new();
+    for property in DEVICE_PROPERTIES {
+        let value = adb
+            .shell(&format!("getprop {}", property))
+            .await
+            .unwrap_or_else(|e| format!("<error: {}>", e));
+        output.push_str(&format!("{}: {}\n", property, value));
+    }
+
+    output
+}
+
+/// Writes an error report zip into `dir`, returning its path. Variable values, including
+/// `entered` ones, config contents, command arguments and credentials are redacted, and only the
+/// user can read the file
+pub fn write_report(
+    dir: &Path,
+    logs: &[String],
+    last_install: Option<&InstallRecord>,
+    entered: &[String],
+    device_info: &str,
+) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create report dir: {}", e))?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let path = dir.join(format!("penumbra_error_report_{timestamp}.zip"));
+
+    let file = create_private(&path).map_err(|e| format!("Failed to create report: {}", e))?;
+    let mut zip = ZipWriter::new(file);
+
+    // The installer's report has the full environment, but only when the install got far enough
+    // to produce one
+    let environment =
+        InstallEnvironment::host(last_install.and_then(|record| record.config_source.as_ref()));
+    let environment = serde_json::to_string_pretty(&environment)
+        .map_err(|e| format!("Failed to serialize environment: {}", e))?;
+
+    let mut secrets = entered.to_vec();
+    let last_install = last_install.map(|record| {
+        let mut record = record.clone();
+        for repo in &mut record.repositories {
+            secrets.extend(repo.secrets());
+            *repo = repo.redacted();
+        }
+        record
+    });
+
+    let install = match &last_install {
+        Some(record) => serde_json::to_string_pretty(record)
+            .map_err(|e| format!("Failed to serialize install record: {}", e))?,
+        None => "No installation has been run in this session".to_string(),
+    };
+
+    let entries = [
+        ("environment.json", environment),
+        ("install.log", logs.join("\n")),
+        ("install.json", install),
+        ("device.txt", device_info.to_string()),
+    ];
+
+    for (name, contents) in entries {
+        zip.start_file(name, SimpleFileOptions::default())
+            .and_then(|()| {
+                zip.write_all(redact(&contents, &secrets).as_bytes())
+                    .map_err(Into::into)
+            })
+            .map_err(|e| format!("Failed to write {} to report: {}", name, e))?;
+    }
+
+    zip.finish()
+        .map_err(|e| format!("Failed to finish report: {}", e))?;
+
+    Ok(path)
+}
+
+/// Creates a new file readable only by the current user
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
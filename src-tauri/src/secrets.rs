@@ -0,0 +1,66 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use keyring::Entry;
+
+const KEYRING_SERVICE: &str = "penumbra-installer";
+const STATE_KEY: &str = "state_encryption_key";
+const NONCE_LEN: usize = 12;
+
+/// Loads the per-install encryption key from the OS keyring, generating it on first use
+fn cipher() -> Result<ChaCha20Poly1305, String> {
+    let entry = Entry::new(KEYRING_SERVICE, STATE_KEY)
+        .map_err(|e| format!("Failed to access keyring: {}", e))?;
+
+    let key = match entry.get_password() {
+        Ok(encoded) => {
+            let bytes = STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("Corrupt state encryption key: {}", e))?;
+            if bytes.len() != 32 {
+                return Err("Corrupt state encryption key: invalid length".to_string());
+            }
+            *Key::from_slice(&bytes)
+        }
+        Err(keyring::Error::NoEntry) => {
+            let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+            entry
+                .set_password(&STANDARD.encode(key))
+                .map_err(|e| format!("Failed to store state encryption key: {}", e))?;
+            key
+        }
+        Err(e) => return Err(format!("Failed to read state encryption key: {}", e)),
+    };
+
+    Ok(ChaCha20Poly1305::new(&key))
+}
+
+/// Encrypts `plaintext`, returning base64 of the nonce followed by the ciphertext
+pub fn encrypt(plaintext: &[u8]) -> Result<String, String> {
+    let cipher = cipher()?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| format!("Failed to encrypt secret: {}", e))?;
+
+    let mut output = nonce.to_vec();
+    output.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(output))
+}
+
+pub fn decrypt(encoded: &str) -> Result<Vec<u8>, String> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Corrupt encrypted secret: {}", e))?;
+
+    if bytes.len() < NONCE_LEN {
+        return Err("Corrupt encrypted secret: too short".to_string());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    cipher()?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|e| format!("Failed to decrypt secret: {}", e))
+}
@@ -0,0 +1,237 @@
+use keyring::Entry;
+use penumbra_installer::platform::Platform;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::secrets;
+
+const KEYRING_SERVICE: &str = "penumbra-installer";
+const GITHUB_TOKEN_KEY: &str = "github_token";
+
+/// Steps of the GUI setup wizard, in the order they are presented
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupStep {
+    AdbSource,
+    GithubToken,
+    DeviceVerified,
+    ConfigChosen,
+}
+
+impl SetupStep {
+    pub const ALL: [SetupStep; 4] = [
+        SetupStep::AdbSource,
+        SetupStep::GithubToken,
+        SetupStep::DeviceVerified,
+        SetupStep::ConfigChosen,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetupProgress {
+    pub completed_steps: Vec<SetupStep>,
+    pub next_step: Option<SetupStep>,
+    pub complete: bool,
+}
+
+/// Persistent GUI setup state, stored in `setup_state.json` in the installer config directory.
+/// Secrets are either kept in the OS keyring or encrypted with a key stored there.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SetupState {
+    /// Plaintext token written by older versions. Only read to migrate it into the keyring
+    #[serde(default, skip_serializing)]
+    github_token: Option<String>,
+
+    #[serde(default)]
+    pub has_github_token: bool,
+
+    /// Last `host:port` successfully used for wireless debugging
+    #[serde(default)]
+    pub last_wireless_address: Option<String>,
+
+    #[serde(default)]
+    pub completed_steps: Vec<SetupStep>,
+
+    /// Whether anonymous install outcomes are reported. Off unless the user opts in
+    #[serde(default)]
+    pub telemetry_enabled: bool,
+
+    #[serde(default)]
+    pub telemetry_endpoint: Option<String>,
+
+    /// Remote config chosen during setup, used instead of the built-in config
+    #[serde(default)]
+    pub config_url: Option<String>,
+
+    /// Bearer token for `config_url`, encrypted with the per-install key held in the OS keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config_token: Option<String>,
+
+    /// Imported ADB private key, encrypted with the per-install key held in the OS keyring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adb_key: Option<String>,
+}
+
+impl SetupState {
+    fn path() -> PathBuf {
+        Platform::config_dir().join("setup_state.json")
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents =
+            fs::read_to_string(&path).map_err(|e| format!("Failed to read setup state: {}", e))?;
+        let mut state: SetupState = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse setup state: {}", e))?;
+
+        if let Some(token) = state.github_token.take() {
+            // Migrate plaintext token from older versions into the keyring
+            store_github_token(Some(&token))?;
+            state.has_github_token = true;
+            state.save()?;
+        }
+
+        Ok(state)
+    }
+
+    pub fn progress(&self) -> SetupProgress {
+        let next_step = SetupStep::ALL
+            .into_iter()
+            .find(|step| !self.completed_steps.contains(step));
+
+        SetupProgress {
+            completed_steps: self.completed_steps.clone(),
+            next_step,
+            complete: next_step.is_none(),
+        }
+    }
+
+    pub fn mark_complete(&mut self, step: SetupStep) {
+        if !self.completed_steps.contains(&step) {
+            self.completed_steps.push(step);
+        }
+    }
+
+    /// The endpoint to report install outcomes to, if the user has opted in
+    pub fn telemetry_endpoint(&self) -> Option<&str> {
+        self.telemetry_endpoint
+            .as_deref()
+            .filter(|_| self.telemetry_enabled)
+    }
+
+    pub fn set_adb_key(&mut self, private_key: &str) -> Result<(), String> {
+        self.adb_key = Some(secrets::encrypt(private_key.as_bytes())?);
+        Ok(())
+    }
+
+    pub fn adb_key(&self) -> Result<Option<String>, String> {
+        self.adb_key
+            .as_deref()
+            .map(|encrypted| {
+                let bytes = secrets::decrypt(encrypted)?;
+                String::from_utf8(bytes).map_err(|e| format!("Corrupt ADB key: {}", e))
+            })
+            .transpose()
+    }
+
+    pub fn set_config_token(&mut self, token: Option<&str>) -> Result<(), String> {
+        self.config_token = token
+            .map(|token| secrets::encrypt(token.as_bytes()))
+            .transpose()?;
+        Ok(())
+    }
+
+    pub fn config_token(&self) -> Result<Option<String>, String> {
+        self.config_token
+            .as_deref()
+            .map(|encrypted| {
+                let bytes = secrets::decrypt(encrypted)?;
+                String::from_utf8(bytes).map_err(|e| format!("Corrupt config token: {}", e))
+            })
+            .transpose()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize setup state: {}", e))?;
+        write_private(&path, contents.as_bytes())
+            .map_err(|e| format!("Failed to write setup state: {}", e))
+    }
+}
+
+/// Writes a file readable only by the current user. The contents go to a new file created with
+/// those permissions, which then replaces `path`, so they are never readable by anyone else
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let _ = fs::remove_file(&temp);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+fn token_entry() -> Result<Entry, String> {
+    Entry::new(KEYRING_SERVICE, GITHUB_TOKEN_KEY)
+        .map_err(|e| format!("Failed to access keyring: {}", e))
+}
+
+/// Stores the token in the OS keyring, or removes it when `None`
+pub fn store_github_token(token: Option<&str>) -> Result<(), String> {
+    let entry = token_entry()?;
+
+    match token {
+        Some(token) => entry
+            .set_password(token)
+            .map_err(|e| format!("Failed to store GitHub token: {}", e)),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to remove GitHub token: {}", e)),
+        },
+    }
+}
+
+/// Loads the stored GitHub token, if any. Keyring failures are logged and treated as no token
+pub fn load_github_token() -> Option<String> {
+    if let Err(e) = SetupState::load() {
+        warn!("Failed to load setup state: {}", e);
+    }
+
+    let entry = match token_entry() {
+        Ok(entry) => entry,
+        Err(e) => {
+            warn!("{}", e);
+            return None;
+        }
+    };
+
+    match entry.get_password() {
+        Ok(token) => Some(token),
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            warn!("Failed to read GitHub token: {}", e);
+            None
+        }
+    }
+}
@@ -9,23 +9,74 @@ import {
 import { PackageList } from "./components/PackageList";
 import { RepositorySelector } from "./components/RepositorySelector";
 import { ConsoleOutput } from "./components/ConsoleOutput";
-import { useTauri } from "./hooks/useTauri";
+import {
+  ErrorDetails,
+  InstallPhase,
+  useInstallationPhase,
+  useTauri,
+} from "./hooks/useTauri";
 import "@mantine/core/styles.css";
 import { useDeviceConnectionStatus } from "./hooks/useDeviceConnectionStatus";
 import { NoDevice } from "./components/NoDevice";
+import { PromptDialog } from "./components/PromptDialog";
+import { InputDialog } from "./components/InputDialog";
+import { LowBatteryDialog } from "./components/LowBatteryDialog";
+
+// An install refused for the battery level, which the user can retry regardless
+interface LowBatteryInstall {
+  repos: string[];
+  fromDownloads: boolean;
+  message: string;
+}
 
 export const App: React.FC<{}> = () => {
   const [installing, setInstalling] = useState(false);
+  const [phase, setPhase] = useState<InstallPhase>("idle");
+  const [lowBattery, setLowBattery] = useState<LowBatteryInstall | null>(
+    null
+  );
   const api = useTauri();
 
+  useInstallationPhase(setPhase);
+
   const [deviceInfo, _, checkDevice] = useDeviceConnectionStatus();
 
-  const handleInstall = async (selectedRepos: string[]) => {
+  const handleInstall = async (
+    selectedRepos: string[],
+    fromDownloads: boolean,
+    ignoreBattery = false
+  ) => {
+    setPhase("idle");
     setInstalling(true);
     try {
-      await api.installRepositories(selectedRepos);
+      await api.installRepositories(
+        selectedRepos,
+        fromDownloads,
+        ignoreBattery
+      );
     } catch (error) {
       console.error("Installation failed:", error);
+      const details = error as ErrorDetails;
+      if (details?.code === "low_battery") {
+        setLowBattery({
+          repos: selectedRepos,
+          fromDownloads,
+          message: details.message,
+        });
+      }
+    } finally {
+      setInstalling(false);
+    }
+  };
+
+  // Stages release assets in the cache, so a later install doesn't need the network
+  const handleDownload = async (selectedRepos: string[]) => {
+    setPhase("idle");
+    setInstalling(true);
+    try {
+      await api.downloadRepositories(selectedRepos);
+    } catch (error) {
+      console.error("Download failed:", error);
     } finally {
       setInstalling(false);
     }
@@ -57,10 +108,28 @@ export const App: React.FC<{}> = () => {
                 <RepositorySelector
                   deviceConnected={true}
                   installing={installing}
+                  phase={phase}
                   onInstall={handleInstall}
+                  onDownload={handleDownload}
                   onCancel={handleCancel}
                 />
                 <ConsoleOutput installing={installing} />
+                <PromptDialog installing={installing} />
+                <InputDialog installing={installing} />
+                <LowBatteryDialog
+                  message={lowBattery?.message ?? null}
+                  onCancel={() => setLowBattery(null)}
+                  onInstallAnyway={() => {
+                    if (lowBattery) {
+                      setLowBattery(null);
+                      handleInstall(
+                        lowBattery.repos,
+                        lowBattery.fromDownloads,
+                        true
+                      );
+                    }
+                  }}
+                />
               </>
             ) : (
               <NoDevice onReload={checkDevice} />
@@ -0,0 +1,74 @@
+import React, { useCallback, useEffect, useState } from "react";
+import { Button, Group, Modal, Text, TextInput } from "@mantine/core";
+import {
+  InstallInputRequest,
+  useInstallationInputs,
+  useTauri,
+} from "../hooks/useTauri";
+
+export const InputDialog: React.FC<{
+  installing: boolean;
+}> = ({ installing }) => {
+  const [request, setRequest] = useState<InstallInputRequest | null>(null);
+  const [value, setValue] = useState("");
+  const api = useTauri();
+
+  useInstallationInputs(
+    useCallback((request) => {
+      setValue("");
+      setRequest(request);
+    }, [])
+  );
+
+  // Requests are dropped on the backend when the installation ends or is cancelled
+  useEffect(() => {
+    if (!installing) {
+      setRequest(null);
+    }
+  }, [installing]);
+
+  const answer = async (value: string | null) => {
+    if (!request) {
+      return;
+    }
+    setRequest(null);
+    try {
+      await api.answerInput(request.id, value);
+    } catch (error) {
+      console.error("Failed to answer input request:", error);
+    }
+  };
+
+  return (
+    <Modal
+      opened={request !== null}
+      onClose={() => answer(null)}
+      title={`${request?.repository} needs a value`}
+      centered
+    >
+      <form
+        onSubmit={(event) => {
+          event.preventDefault();
+          answer(value);
+        }}
+      >
+        <TextInput
+          label={request?.variable}
+          description={request?.description}
+          value={value}
+          onChange={(event) => setValue(event.currentTarget.value)}
+          data-autofocus
+        />
+        <Text size="sm" c="dimmed" mt="xs">
+          Needed by: {request?.step}
+        </Text>
+        <Group justify="flex-end" mt="md">
+          <Button variant="default" onClick={() => answer(null)}>
+            Cancel
+          </Button>
+          <Button type="submit">Continue</Button>
+        </Group>
+      </form>
+    </Modal>
+  );
+};
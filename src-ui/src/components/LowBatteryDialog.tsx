@@ -0,0 +1,33 @@
+import React from "react";
+import { Button, Group, Modal, Text } from "@mantine/core";
+
+// Offered when an install was refused for the battery level, as the command line's
+// --ignore-battery is out of reach from the app
+export const LowBatteryDialog: React.FC<{
+  message: string | null;
+  onCancel: () => void;
+  onInstallAnyway: () => void;
+}> = ({ message, onCancel, onInstallAnyway }) => {
+  return (
+    <Modal
+      opened={message !== null}
+      onClose={onCancel}
+      title="Battery is low"
+      centered
+    >
+      <Text>{message}</Text>
+      <Text mt="sm">
+        The device may shut down before the install finishes. Charge it and
+        retry, or install anyway.
+      </Text>
+      <Group justify="flex-end" mt="md">
+        <Button variant="filled" data-autofocus onClick={onCancel}>
+          Cancel
+        </Button>
+        <Button variant="default" onClick={onInstallAnyway}>
+          Install anyway
+        </Button>
+      </Group>
+    </Modal>
+  );
+};
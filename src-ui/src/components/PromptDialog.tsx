@@ -0,0 +1,62 @@
+import React, { useCallback, useEffect, useState } from "react";
+import { Button, Group, Modal, Text } from "@mantine/core";
+import {
+  InstallPrompt,
+  useInstallationPrompts,
+  useTauri,
+} from "../hooks/useTauri";
+
+export const PromptDialog: React.FC<{
+  installing: boolean;
+}> = ({ installing }) => {
+  const [prompt, setPrompt] = useState<InstallPrompt | null>(null);
+  const api = useTauri();
+
+  useInstallationPrompts(useCallback((prompt) => setPrompt(prompt), []));
+
+  // Prompts are dropped on the backend when the installation ends or is cancelled
+  useEffect(() => {
+    if (!installing) {
+      setPrompt(null);
+    }
+  }, [installing]);
+
+  const answer = async (confirmed: boolean) => {
+    if (!prompt) {
+      return;
+    }
+    setPrompt(null);
+    try {
+      await api.answerPrompt(prompt.id, confirmed);
+    } catch (error) {
+      console.error("Failed to answer prompt:", error);
+    }
+  };
+
+  return (
+    <Modal
+      opened={prompt !== null}
+      onClose={() => answer(false)}
+      title="Confirm to continue"
+      centered
+    >
+      <Text>{prompt?.message}</Text>
+      <Group justify="flex-end" mt="md">
+        <Button
+          variant={prompt?.default ? "default" : "filled"}
+          data-autofocus={!prompt?.default || undefined}
+          onClick={() => answer(false)}
+        >
+          Cancel
+        </Button>
+        <Button
+          variant={prompt?.default ? "filled" : "default"}
+          data-autofocus={prompt?.default || undefined}
+          onClick={() => answer(true)}
+        >
+          Continue
+        </Button>
+      </Group>
+    </Modal>
+  );
+};
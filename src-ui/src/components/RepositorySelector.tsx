@@ -1,4 +1,4 @@
-import React, { useState, useEffect } from "react";
+import React, { useState, useEffect, useCallback } from "react";
 import {
   Paper,
   Group,
@@ -9,41 +9,105 @@ import {
   Stack,
   Checkbox,
   ScrollArea,
+  Progress,
+  Badge,
+  Tooltip,
 } from "@mantine/core";
 import {
+  IconCloudDownload,
   IconDownload,
   IconPlayerStop,
   IconAlertCircle,
+  IconAlertTriangle,
   IconGitBranch,
 } from "@tabler/icons-react";
-import { useTauri, RepositoryInfo } from "../hooks/useTauri";
+import {
+  useTauri,
+  useByteProgress,
+  useInstallationWarnings,
+  useResolutionProgress,
+  ProgressSnapshot,
+  ResolutionProgress,
+  InstallWarning,
+  RepositoryInfo,
+  InstallPhase,
+} from "../hooks/useTauri";
+
+const PHASE_LABELS: Record<InstallPhase, string> = {
+  idle: "Starting...",
+  connecting: "Preparing device...",
+  resolving: "Finding releases...",
+  downloading: "Downloading...",
+  cleaning: "Cleaning up...",
+  installing: "Installing...",
+  verifying: "Verifying...",
+  rebooting: "Rebooting device...",
+  done: "Finishing...",
+};
 
 interface RepositorySelectorProps {
   deviceConnected: boolean;
   installing: boolean;
-  onInstall: (selectedRepos: string[]) => void;
+  phase: InstallPhase;
+  onInstall: (selectedRepos: string[], fromDownloads: boolean) => void;
+  onDownload: (selectedRepos: string[]) => void;
   onCancel: () => void;
 }
 
+const formatMegabytes = (bytes: number) => (bytes / 1_048_576).toFixed(1);
+
 export const RepositorySelector: React.FC<RepositorySelectorProps> = ({
   deviceConnected,
   installing,
+  phase,
   onInstall,
+  onDownload,
   onCancel,
 }) => {
   const [repositories, setRepositories] = useState<RepositoryInfo[]>([]);
   const [selectedRepos, setSelectedRepos] = useState<string[]>([]);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  // Install from assets staged by Download Only instead of fetching the current releases
+  const [fromDownloads, setFromDownloads] = useState(false);
+  // Combined progress of the current batch of downloads and pushes
+  const [bytes, setBytes] = useState<ProgressSnapshot | null>(null);
+  const [resolution, setResolution] = useState<ResolutionProgress | null>(
+    null
+  );
+  // Warnings of the current or last run, kept after it finishes
+  const [warnings, setWarnings] = useState<InstallWarning[]>([]);
   const api = useTauri();
 
+  useByteProgress(setBytes);
+
+  useResolutionProgress(setResolution);
+
+  useInstallationWarnings(
+    useCallback((warning: InstallWarning) => {
+      setWarnings((prev) => [...prev, warning]);
+    }, [])
+  );
+
+  useEffect(() => {
+    if (installing) {
+      setBytes(null);
+      setResolution(null);
+      setWarnings([]);
+    }
+  }, [installing]);
+
+  const activeTasks = bytes ? bytes.tasks.filter((task) => !task.done) : [];
+
   const loadRepositories = async () => {
     setLoading(true);
     setError(null);
     try {
       const repos = await api.getAvailableRepositories();
       setRepositories(repos);
-      setSelectedRepos(repos.map((repo) => repo.name));
+      setSelectedRepos(
+        repos.filter((repo) => repo.enabled).map((repo) => repo.name)
+      );
     } catch (err) {
       console.error("Failed to load repositories:", err);
       setError(
@@ -67,7 +131,11 @@ export const RepositorySelector: React.FC<RepositorySelectorProps> = ({
   };
 
   const handleInstallSelected = () => {
-    onInstall(selectedRepos);
+    onInstall(selectedRepos, fromDownloads);
+  };
+
+  const handleDownloadSelected = () => {
+    onDownload(selectedRepos);
   };
 
   const canInstall = deviceConnected && !installing;
@@ -116,6 +184,13 @@ export const RepositorySelector: React.FC<RepositorySelectorProps> = ({
                           <Text size="sm" fw={500}>
                             {repo.name}
                           </Text>
+                          {repo.protected && (
+                            <Tooltip label="Other components stop working without it, so it can only be uninstalled when forced">
+                              <Badge size="xs" variant="light" color="orange">
+                                Protected
+                              </Badge>
+                            </Tooltip>
+                          )}
                         </Group>
                         <Text size="xs" c="dimmed">
                           {repo.owner}/{repo.repo}
@@ -140,6 +215,25 @@ export const RepositorySelector: React.FC<RepositorySelectorProps> = ({
                   Install Selected ({selectedRepos.length})
                 </Button>
 
+                <Button
+                  onClick={handleDownloadSelected}
+                  disabled={installing || selectedRepos.length === 0}
+                  leftSection={<IconCloudDownload size={16} />}
+                  variant="light"
+                >
+                  Download Only
+                </Button>
+
+                <Checkbox
+                  size="xs"
+                  label="Install from downloaded assets"
+                  checked={fromDownloads}
+                  onChange={(event) =>
+                    setFromDownloads(event.currentTarget.checked)
+                  }
+                  disabled={installing}
+                />
+
                 {installing && (
                   <Button
                     onClick={onCancel}
@@ -149,10 +243,68 @@ export const RepositorySelector: React.FC<RepositorySelectorProps> = ({
                     Cancel
                   </Button>
                 )}
+
+                {installing && (
+                  <Text size="sm" c="dimmed">
+                    {phase === "resolving" && resolution
+                      ? `Resolved ${resolution.resolved} of ${resolution.total} repositories...`
+                      : PHASE_LABELS[phase]}
+                  </Text>
+                )}
               </Group>
+
+              {installing && bytes && activeTasks.length > 0 && (
+                <div>
+                  <Text size="xs" c="dimmed">
+                    {formatMegabytes(bytes.done_bytes)} of{" "}
+                    {formatMegabytes(bytes.total_bytes)} MB (
+                    {Math.floor(bytes.percent)}%)
+                  </Text>
+                  <Progress size="sm" value={bytes.percent} />
+                </div>
+              )}
+
+              {installing &&
+                activeTasks.map((task) => (
+                  <div key={`${task.kind}:${task.key}`}>
+                    <Text size="xs" c="dimmed">
+                      {task.kind === "transfer" ? "Sending " : ""}
+                      {task.name}: {formatMegabytes(task.done_bytes)} of{" "}
+                      {formatMegabytes(task.total_bytes)} MB
+                    </Text>
+                    <Progress
+                      size="xs"
+                      color={task.kind === "transfer" ? "teal" : undefined}
+                      value={
+                        task.total_bytes > 0
+                          ? (task.done_bytes / task.total_bytes) * 100
+                          : 0
+                      }
+                    />
+                  </div>
+                ))}
             </Stack>
           )}
 
+          {warnings.length > 0 && (
+            <Alert
+              icon={<IconAlertTriangle size={16} />}
+              title={`${warnings.length} warning${
+                warnings.length === 1 ? "" : "s"
+              }`}
+              color="yellow"
+              variant="light"
+            >
+              <Stack gap={4}>
+                {warnings.map((warning, index) => (
+                  <Text key={index} size="xs">
+                    [{warning.repository ?? "global"}] {warning.message}
+                  </Text>
+                ))}
+              </Stack>
+            </Alert>
+          )}
+
           {!deviceConnected && (
             <Alert color="orange" variant="light">
               Connect a device to enable installation
@@ -2,8 +2,26 @@ import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { useEffect } from "react";
 
+// Backend messages carry a stable id for localization alongside the English text
+export interface Message {
+  id: string;
+  params?: Record<string, string>;
+  text: string;
+}
+
+// Structured error returned by install and preview commands
+export interface ErrorDetails {
+  code: string;
+  numeric_code: number;
+  message: string;
+  context: Record<string, unknown>;
+  retryable: boolean;
+  hint?: string | null;
+}
+
 export interface DeviceInfo {
   connected: boolean;
+  error?: Message;
   error_message?: string;
 }
 
@@ -17,24 +35,171 @@ export interface RepositoryInfo {
   owner: string;
   repo: string;
   description?: string;
+  // Installed when none are chosen: neither optional nor disabled by the user
+  enabled: boolean;
+  // Only uninstalled when forced, as other repositories rely on it
+  protected: boolean;
+}
+
+export interface RepositoryResult {
+  name: string;
+  optional: boolean;
+  status: "installed" | "downloaded" | "skipped" | "failed";
+  version?: string;
+  reason?: string;
+  error?: string;
+  warnings?: string[];
+  duration_ms: number;
+  steps?: StepTiming[];
+}
+
+// How long an installation step took, and how many shell invocations it needed
+export interface StepTiming {
+  step: string;
+  duration_ms: number;
+  round_trips: number;
+  // More than round_trips when the step batched its commands
+  commands: number;
+}
+
+export interface RebootOutcome {
+  status: "rebooted" | "verified" | "verification_failed" | "failed";
+  boot_ms?: number;
+  error?: string;
 }
 
+export type WarningKind =
+  | "missing_assets"
+  | "excluded_asset"
+  | "unsupported_abi"
+  | "directory_not_empty"
+  | "app_op_not_applied"
+  | "apk_failed"
+  | "command_failed"
+  | "clock_skew"
+  | "incompatible_firmware";
+
+// A problem that didn't stop the install
+export interface InstallWarning {
+  kind: WarningKind;
+  // Repository being installed, or null for global setup
+  repository: string | null;
+  message: string;
+}
+
+export interface InstallReport {
+  repositories: RepositoryResult[];
+  reboot?: RebootOutcome;
+  warnings?: InstallWarning[];
+}
+
+// Progress of resolving the selected repositories, sent as each one finishes
+export interface ResolutionProgress {
+  repo: string;
+  version?: string;
+  error?: string;
+  resolved: number;
+  total: number;
+}
+
+// Progress of one release asset download or push to the device
+export interface TaskProgress {
+  kind: "download" | "transfer";
+  key: string;
+  name: string;
+  done_bytes: number;
+  total_bytes: number;
+  done: boolean;
+}
+
+// Combined progress of the current batch of downloads and pushes, as every frontend shows it
+export interface ProgressSnapshot {
+  percent: number;
+  done_bytes: number;
+  total_bytes: number;
+  tasks: TaskProgress[];
+}
+
+// Result of one check of this computer, for the preflight screen
+export interface HostCheck {
+  id: "libusb" | "adb" | "disk_space" | "github" | "clock";
+  status: "passed" | "warning" | "failed" | "skipped";
+  message: string;
+  hint?: string;
+}
+
+export interface HostReport {
+  checks: HostCheck[];
+}
+
+// Confirmation requested by a `Prompt` install step
+export interface InstallPrompt {
+  id: number;
+  message: string;
+  default: boolean;
+}
+
+// Value requested for a variable a step needs that was given none
+export interface InstallInputRequest {
+  id: number;
+  repository: string;
+  variable: string;
+  description?: string;
+  step: string;
+}
+
+// What the installation engine is currently doing
+export type InstallPhase =
+  | "idle"
+  | "connecting"
+  | "resolving"
+  | "downloading"
+  | "cleaning"
+  | "installing"
+  | "verifying"
+  | "rebooting"
+  | "done";
+
 export interface UseTauriAPI {
   checkDeviceConnection: () => Promise<DeviceInfo>;
   listInstalledPackages: () => Promise<PackageInfo[]>;
-  installRepositories: (repos: string[]) => Promise<string>;
+  installRepositories: (
+    repos: string[],
+    fromDownloads?: boolean,
+    ignoreBattery?: boolean
+  ) => Promise<InstallReport>;
+  downloadRepositories: (repos: string[]) => Promise<InstallReport>;
   getAvailableRepositories: () => Promise<RepositoryInfo[]>;
+  setRepositoryEnabled: (name: string, enabled: boolean) => Promise<void>;
+  checkHost: () => Promise<HostReport>;
   cancelInstallation: () => Promise<void>;
+  answerPrompt: (id: number, confirmed: boolean) => Promise<void>;
+  answerInput: (id: number, value: string | null) => Promise<void>;
+  getMessageCatalog: () => Promise<Record<string, string>>;
 }
 
 export const useTauri = (): UseTauriAPI => {
   return {
     checkDeviceConnection: () => invoke("check_device_connection"),
     listInstalledPackages: () => invoke("list_installed_packages"),
-    installRepositories: (repos: string[]) =>
-      invoke("install_repositories", { repos }),
+    installRepositories: (
+      repos: string[],
+      fromDownloads?: boolean,
+      ignoreBattery?: boolean
+    ) =>
+      invoke("install_repositories", { repos, fromDownloads, ignoreBattery }),
+    downloadRepositories: (repos: string[]) =>
+      invoke("download_repositories", { repos }),
     getAvailableRepositories: () => invoke("get_available_repositories"),
+    setRepositoryEnabled: (name: string, enabled: boolean) =>
+      invoke("set_repository_enabled", { name, enabled }),
+    checkHost: () => invoke("check_host"),
     cancelInstallation: () => invoke("cancel_installation"),
+    answerPrompt: (id: number, confirmed: boolean) =>
+      invoke("answer_prompt", { id, confirmed }),
+    answerInput: (id: number, value: string | null) =>
+      invoke("answer_input", { id, value }),
+    getMessageCatalog: () => invoke("get_message_catalog"),
   };
 };
 
@@ -43,8 +208,9 @@ export const useInstallationProgress = (
   callback: (message: string) => void
 ) => {
   useEffect(() => {
-    const unlisten = listen("installation_progress", (event) => {
-      callback(event.payload as string);
+    // Progress messages are delivered in batches to avoid flooding the webview
+    const unlisten = listen<Message[]>("installation_progress", (event) => {
+      event.payload.forEach((message) => callback(message.text));
     });
 
     return () => {
@@ -52,3 +218,89 @@ export const useInstallationProgress = (
     };
   }, [callback]);
 };
+
+export const useInstallationPrompts = (
+  callback: (prompt: InstallPrompt) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<InstallPrompt>("installation_prompt", (event) =>
+      callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
+
+export const useInstallationInputs = (
+  callback: (request: InstallInputRequest) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<InstallInputRequest>(
+      "installation_input",
+      (event) => callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
+
+export const useInstallationPhase = (
+  callback: (phase: InstallPhase) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<InstallPhase>("installation_phase", (event) =>
+      callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
+
+export const useResolutionProgress = (
+  callback: (progress: ResolutionProgress) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<ResolutionProgress>(
+      "resolution_progress",
+      (event) => callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
+
+export const useInstallationWarnings = (
+  callback: (warning: InstallWarning) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<InstallWarning>("installation_warning", (event) =>
+      callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
+
+export const useByteProgress = (
+  callback: (snapshot: ProgressSnapshot) => void
+) => {
+  useEffect(() => {
+    const unlisten = listen<ProgressSnapshot>("byte_progress", (event) =>
+      callback(event.payload)
+    );
+
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, [callback]);
+};
@@ -1,17 +1,428 @@
+use crate::apk::InstalledPackage;
+use crate::config::RebootTarget;
+use crate::error::ApkFailure;
+use crate::host::find_adb;
 use crate::logs::LineBuffer;
-use crate::{InstallerError, Result};
-use adb_client::{ADBDeviceExt, ADBServer, ADBUSBDevice};
-use std::io::Write;
+use crate::platform::Platform;
+use crate::session::{Request, Response, Session};
+use crate::{InstallerError, Result, ResultExt};
+use adb_client::{ADBDeviceExt, ADBServer, ADBUSBDevice, DeviceState};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::net::{Ipv4Addr, SocketAddrV4};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
 pub struct AdbManager {
-    device: Box<dyn ADBDeviceExt + Send>,
+    /// `None` when replaying a recorded session
+    device: Option<Box<dyn ADBDeviceExt + Send>>,
+    /// Serial of a device reached through the ADB server, which allows further connections
+    serial: Option<String>,
+    cancellation_token: Option<CancellationToken>,
+    session: Option<Arc<Session>>,
+    /// Signing server for USB connections, kept to reconnect after a reboot
+    remote_auth_url: Option<String>,
+    shell_stats: ShellStats,
+    /// Compress large pushes when the device can unpack them
+    compress_pushes: bool,
+    /// The device's decompressor, once looked for
+    push_compression: Option<Option<PushCompression>>,
+    transfer_progress: Option<mpsc::UnboundedSender<TransferProgress>>,
+    retry: RetryPolicy,
+}
+
+/// How device operations are retried when the connection drops partway through
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries of each operation, including the first, so 1 never retries
+    pub attempts: u32,
+    /// Wait before reconnecting for the first retry, doubling for each later one
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retries
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The default policy, with its attempts and backoff overridden by `PENUMBRA_ADB_ATTEMPTS`
+    /// and `PENUMBRA_ADB_BACKOFF_MS`
+    pub fn from_env() -> Self {
+        let env = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+
+        let default = Self::default();
+        Self {
+            attempts: env("PENUMBRA_ADB_ATTEMPTS").map_or(default.attempts, |attempts| {
+                attempts.clamp(1, u32::MAX as u64) as u32
+            }),
+            backoff: env("PENUMBRA_ADB_BACKOFF_MS").map_or(default.backoff, Duration::from_millis),
+        }
+    }
+}
+
+/// Progress of a file push or APK upload to the device
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TransferProgress {
+    /// Name of the local file
+    pub file: String,
+    /// Path the file is pushed to on the device
+    pub remote: String,
+    pub transferred_bytes: u64,
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+/// Charge of the device battery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Battery {
+    /// Percentage
+    pub level: u8,
+    pub charging: bool,
+}
+
+/// Format large pushes are compressed in, chosen by the decompressors the device has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushCompression {
+    Zstd,
+    Gzip,
+}
+
+impl PushCompression {
+    fn tool(self) -> &'static str {
+        match self {
+            PushCompression::Zstd => "zstd",
+            PushCompression::Gzip => "gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            PushCompression::Zstd => "zst",
+            PushCompression::Gzip => "gz",
+        }
+    }
+
+    /// Compresses `source` into `destination`, returning the compressed size
+    fn compress(self, source: &Path, destination: &Path) -> io::Result<u64> {
+        let mut input = std::fs::File::open(source)?;
+        let output = std::fs::File::create(destination)?;
+        match self {
+            PushCompression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(output, 3)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            PushCompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(std::fs::metadata(destination)?.len())
+    }
+}
+
+/// Shell commands run over a connection, for timing metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShellStats {
+    /// Shell invocations sent to the device
+    pub round_trips: u64,
+    /// Commands run, counting each command of a batch
+    pub commands: u64,
+}
+
+/// A failed try at a device operation, and whether `AdbManager::retrying` tries it again
+enum Attempt {
+    /// The connection failed, so the operation may not have reached the device
+    Disconnected(InstallerError),
+    /// The device reported the failure, or the operation can't be repeated safely
+    Failed(InstallerError),
+}
+
+impl From<InstallerError> for Attempt {
+    fn from(error: InstallerError) -> Self {
+        match error {
+            // The device is gone after a failed reconnect, and may come back for the next
+            InstallerError::NoDevice => Attempt::Disconnected(error),
+            error => Attempt::Failed(error),
+        }
+    }
+}
+
+/// Wraps the stream of a blocking transfer so it fails at the next chunk once cancelled
+struct Cancellable<'a, T> {
+    inner: T,
+    token: Option<&'a CancellationToken>,
+}
+
+impl<T> Cancellable<'_, T> {
+    fn check(&self) -> io::Result<()> {
+        if self.token.is_some_and(|token| token.is_cancelled()) {
+            return Err(io::Error::other("cancelled"));
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for Cancellable<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for Cancellable<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps the stream of a transfer to the device, reporting how much of it has been sent at
+/// most every `PROGRESS_INTERVAL`
+struct Reporting<'a, T> {
+    inner: T,
+    sender: Option<&'a mpsc::UnboundedSender<TransferProgress>>,
+    progress: TransferProgress,
+    reported: Instant,
+}
+
+impl<'a, T> Reporting<'a, T> {
+    fn new(
+        inner: T,
+        sender: Option<&'a mpsc::UnboundedSender<TransferProgress>>,
+        local: &Path,
+        remote: &str,
+    ) -> Self {
+        let total_bytes = std::fs::metadata(local).map_or(0, |metadata| metadata.len());
+        Self {
+            inner,
+            sender,
+            progress: TransferProgress {
+                file: local
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                remote: remote.to_string(),
+                transferred_bytes: 0,
+                total_bytes,
+                done: false,
+            },
+            reported: Instant::now(),
+        }
+    }
+
+    /// Reports the transfer as finished, whether or not it succeeded
+    fn finish(mut self) {
+        self.progress.done = true;
+        self.report();
+    }
+
+    fn report(&mut self) {
+        if let Some(sender) = self.sender {
+            let _ = sender.send(self.progress.clone());
+        }
+        self.reported = Instant::now();
+    }
+}
+
+impl<T: Read> Read for Reporting<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.transferred_bytes += read as u64;
+        if self.reported.elapsed() >= PROGRESS_INTERVAL {
+            self.report();
+        }
+        Ok(read)
+    }
+}
+
+/// A device visible to the ADB server, which may or may not be ready for use
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectedDevice {
+    pub serial: String,
+    pub model: Option<String>,
+    pub state: String,
+}
+
+/// Kind of filesystem entry at a device path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// Metadata of a device path, from the sync protocol's STAT request. Symlinks aren't followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RemoteStat {
+    pub file_type: FileType,
+    /// Permission and type bits
+    pub mode: u32,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl RemoteStat {
+    /// `None` for the all-zero response STAT gives for missing paths
+    fn from_response(mode: u32, size: u32, modified: u32) -> Option<Self> {
+        if mode == 0 {
+            return None;
+        }
+
+        let file_type = match mode & 0o170000 {
+            0o100000 => FileType::File,
+            0o040000 => FileType::Directory,
+            0o120000 => FileType::Symlink,
+            _ => FileType::Other,
+        };
+        Some(Self {
+            file_type,
+            mode,
+            size: size.into(),
+            modified: modified.into(),
+        })
+    }
+}
+
+/// Manufacturer reported by the Humane Ai Pin
+const PIN_MANUFACTURER: &str = "Humane";
+/// Where APKs are pushed to be installed from, as `pm` can't read the host's files
+const STAGED_APK: &str = "/data/local/tmp/penumbra-install.apk";
+/// Staging paths of split APKs, each followed by its index in the package's splits
+const STAGED_SPLIT_PREFIX: &str = "/data/local/tmp/penumbra-split-";
+/// Largest file written through the shell when a push fails, keeping the command well within
+/// the device's argument length limit
+const MAX_SHELL_WRITE_BYTES: usize = 48 * 1024;
+/// How often to check whether a rebooting device has finished booting
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest wait before reconnecting, however many retries came before
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// Longest wait for the framework to stop after `am restart`
+const FRAMEWORK_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Reports whether the device has booted and its framework is running, as
+/// `sys.boot_completed` stays set through a soft reboot
+const BOOT_CHECK: &str = "getprop sys.boot_completed; service check package";
+/// Longest time between progress reports of a transfer to the device
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Smallest file compressed before pushing, when compressed pushes are enabled
+const MIN_COMPRESSED_PUSH_BYTES: u64 = 4 * 1024 * 1024;
+/// Port adbd listens on after `adb tcpip` without a port
+const DEFAULT_TCPIP_PORT: u16 = 5555;
+/// `status` values of `dumpsys battery` while the battery is gaining or holding a full charge
+const BATTERY_STATUS_CHARGING: u64 = 2;
+const BATTERY_STATUS_FULL: u64 = 5;
+/// Most commands joined into one shell invocation, keeping it well within the device's argument
+/// length limit
+const MAX_BATCH_COMMANDS: usize = 32;
+/// Echoed before each command of a batch, followed by its index, to split the output
+const BATCH_MARKER: &str = "@@penumbra-batch:";
+/// Printed on its own line after a checked command, followed by its exit code
+const EXIT_MARKER: &str = "@@penumbra-exit:";
+
+/// Identifying build properties of a connected device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub manufacturer: String,
+    pub model: String,
+    pub fingerprint: String,
+}
+
+impl DeviceIdentity {
+    pub fn is_pin(&self) -> bool {
+        self.manufacturer.eq_ignore_ascii_case(PIN_MANUFACTURER)
+    }
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({})",
+            self.manufacturer, self.model, self.fingerprint
+        )
+    }
+}
+
+/// Whether two `package/activity` components name the same activity, expanding the
+/// `package/.Activity` shorthand
+pub fn same_component(a: &str, b: &str) -> bool {
+    fn expand(component: &str) -> String {
+        match component.split_once('/') {
+            Some((package, activity)) if activity.starts_with('.') => {
+                format!("{}/{}{}", package, package, activity)
+            }
+            _ => component.to_string(),
+        }
+    }
+
+    expand(a) == expand(b)
+}
+
+impl ConnectedDevice {
+    pub fn is_ready(&self) -> bool {
+        self.state == format!("{:?}", DeviceState::Device)
+    }
 }
 
 impl AdbManager {
     pub async fn connect(remote_auth_url: Option<String>) -> Result<Self> {
-        let server = Self::connect_server(remote_auth_url.clone());
+        Self::connect_device(None, remote_auth_url).await
+    }
+
+    /// Connects to the device with the given serial, or the only connected device if `None`
+    pub async fn connect_device(
+        serial: Option<&str>,
+        remote_auth_url: Option<String>,
+    ) -> Result<Self> {
+        // A running server holds the USB interface, and only a server can pick a device by
+        // serial, so either goes through the server. Otherwise USB is tried first, rather than
+        // starting a server that would go on holding the device after the installer exits
+        if serial.is_none() && remote_auth_url.is_none() && Self::server_version().is_err() {
+            match Self::connect_usb_device(None).await {
+                Err(e) if !matches!(e, InstallerError::MultipleDevices) => {
+                    debug!("Connecting over USB failed, trying the ADB server: {}", e);
+                }
+                result => return result,
+            }
+        }
+
+        let server = Self::connect_server(serial, remote_auth_url.clone());
 
         if let Some(server) = server {
             server
@@ -22,26 +433,202 @@ impl AdbManager {
         }
     }
 
-    fn connect_server(remote_auth_url: Option<String>) -> Option<Result<AdbManager>> {
+    /// Lists all devices known to the local ADB server, starting it if needed
+    pub fn list_devices() -> Result<Vec<ConnectedDevice>> {
+        Self::ensure_server()?;
+        let mut server = ADBServer::new(Self::server_address());
+
+        let devices = server
+            .devices_long()
+            .map_err(|e| InstallerError::Adb(format!("Failed to list devices: {}", e)))?;
+
+        Ok(devices
+            .into_iter()
+            .map(|device| ConnectedDevice {
+                serial: device.identifier,
+                model: Some(device.model).filter(|model| !model.is_empty()),
+                state: format!("{:?}", device.state),
+            })
+            .collect())
+    }
+
+    /// Pairs with a device using an Android 11+ wireless debugging pairing code
+    pub fn pair_wireless(address: SocketAddrV4, code: &str) -> Result<()> {
+        Self::ensure_server()?;
+        let mut server = ADBServer::new(Self::server_address());
+
+        server
+            .pair(address, code.to_string())
+            .map_err(|e| InstallerError::Adb(format!("Failed to pair with {}: {}", address, e)))
+    }
+
+    /// Connects the ADB server to a device over TCP, returning its serial
+    pub fn connect_wireless(address: SocketAddrV4) -> Result<String> {
+        Self::ensure_server()?;
+        let mut server = ADBServer::new(Self::server_address());
+
+        server
+            .connect_device(address)
+            .map_err(|e| InstallerError::Adb(format!("Failed to connect to {}: {}", address, e)))?;
+
+        Ok(address.to_string())
+    }
+
+    /// Disconnects the ADB server from a device connected over TCP
+    pub fn disconnect_wireless(address: SocketAddrV4) -> Result<()> {
+        let mut server = ADBServer::new(Self::server_address());
+
+        server.disconnect_device(address).map_err(|e| {
+            InstallerError::Adb(format!("Failed to disconnect from {}: {}", address, e))
+        })
+    }
+
+    /// Parses `IP:PORT`, or an IP alone for a device put in TCP mode with `adb tcpip`, which
+    /// listens on port 5555
+    pub fn parse_socket_address(address: &str) -> Result<SocketAddrV4> {
+        let address = address.trim();
+        match address.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse().map_err(|_| {
+                    InstallerError::Adb(format!("Invalid port in {}: {}", address, port))
+                })?;
+                Self::parse_address(host, port)
+            }
+            None => Self::parse_address(address, DEFAULT_TCPIP_PORT),
+        }
+    }
+
+    pub fn parse_address(host: &str, port: u16) -> Result<SocketAddrV4> {
+        let ip: Ipv4Addr = host
+            .trim()
+            .parse()
+            .map_err(|_| InstallerError::Adb(format!("Invalid IPv4 address: {}", host)))?;
+
+        Ok(SocketAddrV4::new(ip, port))
+    }
+
+    /// Returns the version of the running local ADB server
+    pub fn server_version() -> Result<String> {
+        let mut server = ADBServer::new(Self::server_address());
+
+        let version = server
+            .version()
+            .map_err(|e| InstallerError::Adb(format!("ADB server not reachable: {}", e)))?;
+
+        Ok(format!(
+            "{}.{}.{}",
+            version.major, version.minor, version.revision
+        ))
+    }
+
+    /// Starts the local ADB server with `adb start-server` if nothing is listening on its port,
+    /// unless `PENUMBRA_START_ADB_SERVER` is `0`
+    pub fn ensure_server() -> Result<()> {
+        Self::start_server().map(|_| ())
+    }
+
+    /// Like [`AdbManager::ensure_server`], returning whether the server had to be started
+    fn start_server() -> Result<bool> {
+        if Self::server_version().is_ok() {
+            return Ok(false);
+        }
+        if std::env::var("PENUMBRA_START_ADB_SERVER").is_ok_and(|value| value == "0") {
+            return Err(InstallerError::Adb(
+                "No ADB server is running, and PENUMBRA_START_ADB_SERVER=0".into(),
+            ));
+        }
+
+        let adb = find_adb().ok_or_else(|| {
+            InstallerError::Adb(
+                "No ADB server is running, and adb was not found on PATH to start one. Install \
+                 Android platform-tools from \
+                 https://developer.android.com/tools/releases/platform-tools, or connect over USB \
+                 without a server"
+                    .into(),
+            )
+        })?;
+
+        info!("Starting the ADB server with {}", adb.display());
+        let output = Command::new(&adb)
+            .arg("start-server")
+            .output()
+            .map_err(|e| InstallerError::Adb(format!("Failed to run {}: {}", adb.display(), e)))?;
+        if !output.status.success() {
+            return Err(InstallerError::Adb(format!(
+                "adb start-server failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Self::server_version().map(|_| true)
+    }
+
+    /// Stops the local ADB server, so one the installer started doesn't go on holding the USB
+    /// interface of a device it didn't connect to
+    fn kill_server() {
+        let Some(adb) = find_adb() else {
+            return;
+        };
+        info!("Stopping the ADB server");
+        if let Err(e) = Command::new(&adb).arg("kill-server").output() {
+            debug!("Failed to run {} kill-server: {}", adb.display(), e);
+        }
+    }
+
+    fn server_address() -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 5037)
+    }
+
+    fn connect_server(
+        serial: Option<&str>,
+        remote_auth_url: Option<String>,
+    ) -> Option<Result<AdbManager>> {
         if remote_auth_url.is_some() {
             // Cannot use remote auth with normal ADB server
             return None;
         }
 
-        let addr = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 5037);
-        let mut server = ADBServer::new(addr);
+        let started = match Self::start_server() {
+            Ok(started) => started,
+            Err(e) => {
+                // The USB transport doesn't need a server
+                debug!("{}", e);
+                return None;
+            }
+        };
 
-        let devices = server.devices();
+        let mut server = ADBServer::new(Self::server_address());
+
+        let devices = match server.devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                if started {
+                    Self::kill_server();
+                }
+                return Some(Err(InstallerError::Adb(format!(
+                    "Failed to list devices: {}",
+                    e
+                ))));
+            }
+        };
 
-        let devices = devices.unwrap();
+        let devices: Vec<_> = devices
+            .into_iter()
+            .filter(|device| serial.is_none_or(|serial| device.identifier == serial))
+            .collect();
         let result = match devices.len() {
-            0 => Err(InstallerError::NoDevice),
+            0 => match serial {
+                Some(serial) => Err(InstallerError::Adb(format!(
+                    "Device '{}' is not connected",
+                    serial
+                ))),
+                None => Err(InstallerError::NoDevice),
+            },
             1 => {
                 let device_info = devices.into_iter().next().unwrap();
 
                 match device_info.state {
-                    adb_client::DeviceState::Device => {
-                        // TODO: Check if device is Pin
+                    DeviceState::Device => {
                         let device =
                             server
                                 .get_device_by_name(&device_info.identifier)
@@ -51,12 +638,21 @@ impl AdbManager {
 
                         match device {
                             Ok(device) => Ok(Self {
-                                device: Box::new(device),
+                                device: Some(Box::new(device)),
+                                serial: Some(device_info.identifier),
+                                cancellation_token: None,
+                                session: None,
+                                remote_auth_url: None,
+                                shell_stats: ShellStats::default(),
+                                compress_pushes: false,
+                                push_compression: None,
+                                transfer_progress: None,
+                                retry: RetryPolicy::from_env(),
                             }),
                             Err(err) => Err(err),
                         }
                     }
-                    adb_client::DeviceState::Unauthorized => Err(InstallerError::Adb(
+                    DeviceState::Unauthorized => Err(InstallerError::Adb(
                         "Device unauthorized. Please ensure you have installed your ADB cert to .android and confirm the USB debugging prompt on your Pin's laser".to_string(),
                     )),
                     _ => Err(InstallerError::Adb(format!(
@@ -68,12 +664,22 @@ impl AdbManager {
             _ => Err(InstallerError::MultipleDevices),
         };
 
+        // Several devices are left to the server, which the user picks between
+        if started && result.is_err() && !matches!(result, Err(InstallerError::MultipleDevices)) {
+            Self::kill_server();
+        }
         Some(result)
     }
 
+    /// Connects over USB without going through the ADB server, even if one is running
+    pub async fn connect_usb_device(remote_auth_url: Option<String>) -> Result<Self> {
+        Self::connect_usb(remote_auth_url)
+            .unwrap_or(Err(InstallerError::Adb("Failed all transports".into())))
+    }
+
     fn connect_usb(remote_auth_url: Option<String>) -> Option<Result<AdbManager>> {
         // TODO: This may fail randomly due to https://github.com/cocool97/adb_client/issues/108
-        let device = ADBUSBDevice::autodetect(remote_auth_url).map_err(|e| match e {
+        let device = ADBUSBDevice::autodetect(remote_auth_url.clone()).map_err(|e| match e {
             adb_client::RustADBError::DeviceNotFound(msg) => {
                 if msg.contains("two Android devices") {
                     InstallerError::MultipleDevices
@@ -86,139 +692,1545 @@ impl AdbManager {
 
         Some(match device {
             Ok(device) => Ok(Self {
-                device: Box::new(device),
+                device: Some(Box::new(device)),
+                serial: None,
+                cancellation_token: None,
+                session: None,
+                remote_auth_url,
+                shell_stats: ShellStats::default(),
+                compress_pushes: false,
+                push_compression: None,
+                transfer_progress: None,
+                retry: RetryPolicy::from_env(),
             }),
             Err(err) => Err(err),
         })
     }
 
-    pub async fn install_apk(&mut self, path: &Path) -> Result<()> {
-        self.device
-            .install(&path)
-            .map_err(|e| InstallerError::ApkInstallation {
-                apk: path
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string(),
-                reason: format!("{}", e),
-            })?;
-
-        Ok(())
+    /// A manager without a device, answering every command from a recorded session
+    pub fn replay(session: Arc<Session>) -> Self {
+        Self {
+            device: None,
+            serial: None,
+            cancellation_token: None,
+            session: Some(session),
+            remote_auth_url: None,
+            shell_stats: ShellStats::default(),
+            compress_pushes: false,
+            push_compression: None,
+            transfer_progress: None,
+            retry: RetryPolicy::from_env(),
+        }
     }
 
-    pub async fn uninstall_package(&mut self, package: &str) -> Result<()> {
-        let _ = self
-            .shell(&format!("pm uninstall --user 0 {}", package))
-            .await;
-        let _ = self.shell(&format!("pm uninstall {}", package)).await;
+    /// Opens an additional connection to the same device, sharing this manager's session and
+    /// cancellation token. `None` when the transport only allows one connection (USB), or when
+    /// replaying, as replays must stay in recorded order
+    pub fn open_connection(&self) -> Result<Option<Self>> {
+        let Some(serial) = &self.serial else {
+            return Ok(None);
+        };
 
-        Ok(())
-    }
+        let device = ADBServer::new(Self::server_address())
+            .get_device_by_name(serial)
+            .map_err(|e| InstallerError::Adb(format!("Failed to get device: {}", e)))?;
 
-    pub async fn shell(&mut self, command: &str) -> Result<String> {
-        let mut output = Vec::new();
-        let cmd_parts: Vec<&str> = command.split_whitespace().collect();
+        Ok(Some(Self {
+            device: Some(Box::new(device)),
+            serial: Some(serial.clone()),
+            cancellation_token: self.cancellation_token.clone(),
+            session: self.session.clone(),
+            remote_auth_url: self.remote_auth_url.clone(),
+            shell_stats: ShellStats::default(),
+            compress_pushes: self.compress_pushes,
+            push_compression: self.push_compression,
+            transfer_progress: self.transfer_progress.clone(),
+        }))
+    }
 
-        self.device
-            .shell_command(&cmd_parts, &mut output)
-            .map_err(|e| InstallerError::Adb(format!("Failed to run shell command: {}", e)))?;
+    /// Records every command and its result to `session`, or replays them from it
+    pub fn set_session(&mut self, session: Option<Arc<Session>>) {
+        self.session = session;
+    }
 
-        let output_str = String::from_utf8_lossy(&output);
-        Ok(output_str.trim().to_string())
+    /// Redacts `value` from the commands recorded to the session
+    pub fn add_secret(&self, value: &str) {
+        if let Some(session) = &self.session {
+            session.add_secret(value);
+        }
     }
 
-    pub fn shell_stream<T>(&mut self, command: &str, writer: T) -> Result<()>
-    where
-        T: Write,
-    {
-        let cmd_parts: Vec<&str> = command.split_whitespace().collect();
-        let mut line_buffer = LineBuffer::new(writer);
+    fn replayed(&self, request: &Request) -> Result<Option<Response>> {
+        match &self.session {
+            Some(session) => session.replayed(request),
+            None => Ok(None),
+        }
+    }
 
-        self.device
-            .shell_command(&cmd_parts, &mut line_buffer)
-            .map_err(|e| InstallerError::Adb(format!("Failed to run shell command: {}", e)))
+    fn record(&self, request: Request, response: Response) {
+        if let Some(session) = &self.session {
+            session.log(request, response);
+        }
     }
 
-    pub async fn push_file(&mut self, local: &Path, remote: &str) -> Result<()> {
-        let mut file = std::fs::File::open(local)
-            .map_err(|e| InstallerError::Adb(format!("Failed to open file: {}", e)))?;
+    /// Makes pushes and shell commands fail with `InstallerError::Cancelled` once `token` is
+    /// cancelled. Transfers stop at the next chunk; APK installs run to completion
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.cancellation_token = token;
+    }
 
-        self.device
-            .push(&mut file, &remote)
-            .map_err(|e| InstallerError::Adb(format!("Push failed: {}", e)))?;
+    /// `error` from `adb_client` while doing `action`. Failures the device reported are final,
+    /// while any other may be the connection dropping
+    fn transfer_error(&self, action: &str, error: adb_client::RustADBError) -> Attempt {
+        if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Attempt::Failed(InstallerError::Cancelled);
+        }
 
-        Ok(())
+        let message = InstallerError::Adb(format!("{}: {}", action, error));
+        match error {
+            adb_client::RustADBError::ADBRequestFailed(_) => Attempt::Failed(message),
+            _ => Attempt::Disconnected(message),
+        }
     }
 
-    pub async fn grant_permission(&mut self, package: &str, permission: &str) -> Result<()> {
-        self.shell(&format!("pm grant {} {}", package, permission))
-            .await?;
-        Ok(())
+    /// Reads the device's manufacturer, model, and build fingerprint
+    pub async fn device_identity(&mut self) -> Result<DeviceIdentity> {
+        Ok(DeviceIdentity {
+            manufacturer: self.shell("getprop ro.product.manufacturer").await?,
+            model: self.shell("getprop ro.product.model").await?,
+            fingerprint: self.shell("getprop ro.build.fingerprint").await?,
+        })
     }
 
-    pub async fn set_app_op(&mut self, package: &str, operation: &str, mode: &str) -> Result<()> {
-        self.shell(&format!("appops set {} {} {}", package, operation, mode))
-            .await?;
-        Ok(())
+    /// ABIs the device supports, most preferred first
+    pub async fn abis(&mut self) -> Result<Vec<String>> {
+        let output = self.shell("getprop ro.product.cpu.abilist").await?;
+        Ok(output
+            .split(',')
+            .map(str::trim)
+            .filter(|abi| !abi.is_empty())
+            .map(str::to_string)
+            .collect())
     }
 
-    pub async fn set_launcher(&mut self, component: &str) -> Result<()> {
-        self.shell(&format!("cmd package set-home-activity {}", component))
+    /// Seconds the device clock is ahead of the host clock, negative when behind
+    pub async fn clock_skew(&mut self) -> Result<i64> {
+        let host = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        // Computed on the device so a replayed session reproduces the recorded skew
+        let output = self
+            .shell(&format!("echo $(( $(date +%s) - {} ))", host))
             .await?;
-        Ok(())
+        output
+            .trim()
+            .parse()
+            .map_err(|_| InstallerError::Adb(format!("Unexpected device clock output: {}", output)))
     }
 
-    pub async fn create_directory(&mut self, path: &str) -> Result<()> {
-        self.shell(&format!("mkdir -p {}", path)).await?;
-        Ok(())
-    }
+    /// Battery charge as a percentage, and whether it is charging, from `dumpsys battery`
+    pub async fn battery(&mut self) -> Result<Battery> {
+        let output = self.shell("dumpsys battery").await?;
+        let field = |name: &str| {
+            output.lines().find_map(|line| {
+                let (key, value) = line.trim().split_once(':')?;
+                (key == name).then(|| value.trim().parse::<u64>().ok())?
+            })
+        };
 
-    pub async fn remove_directory(&mut self, path: &str) -> Result<()> {
-        self.shell(&format!("rm -rf {}", path)).await?;
-        Ok(())
+        // Being powered over USB doesn't mean charging, as a computer may not supply enough
+        let charging = matches!(
+            field("status"),
+            Some(BATTERY_STATUS_CHARGING | BATTERY_STATUS_FULL)
+        );
+        match (field("level"), field("scale").unwrap_or(100)) {
+            (Some(level), scale) if scale > 0 => Ok(Battery {
+                level: (level * 100 / scale).min(100) as u8,
+                charging,
+            }),
+            _ => Err(InstallerError::Adb(format!(
+                "Unexpected dumpsys battery output: {}",
+                output
+            ))),
+        }
     }
 
-    pub async fn remove_file(&mut self, path: &str) -> Result<()> {
-        self.shell(&format!("rm -f {}", path)).await?;
-        Ok(())
+    pub async fn serial_number(&mut self) -> Result<String> {
+        self.shell("getprop ro.serialno").await
     }
 
-    pub async fn file_exists(&mut self, path: &str) -> Result<bool> {
+    /// Installs the APK at `path` with `pm install`, so failures carry the package manager's full
+    /// output. Replaces any installed version, and `downgrade` allows replacing a newer one.
+    /// Installs for every Android user unless `user` is given
+    pub async fn install_apk(
+        &mut self,
+        path: &Path,
+        user: Option<u32>,
+        downgrade: bool,
+    ) -> Result<()> {
+        let apk = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let request = Request::Install { apk: apk.clone() };
+        if let Some(response) = self.replayed(&request)? {
+            return replayed_install(response, apk);
+        }
+
         let result = self
-            .shell(&format!("[ -f {} ] && echo 'exists'", path))
-            .await?;
-        Ok(result.contains("exists"))
-    }
+            .retrying(|adb| adb.install_staged(path, user, downgrade))
+            .await
+            .and_then(|output| install_result(apk, output));
 
-    pub async fn write_file(&mut self, path: &str, content: &str) -> Result<()> {
-        let escaped_content = content.replace('\'', "'\"'\"'");
-        self.shell(&format!("echo '{}' > {}", escaped_content, path))
-            .await?;
-        Ok(())
+        self.record(request, Response::from_done(&result));
+        result
     }
 
-    pub async fn list_packages(&mut self, pattern: &str) -> Result<Vec<String>> {
-        let output = self
-            .shell(&format!(
-                "pm list packages | grep {} | sed 's/package://'",
-                pattern
-            ))
-            .await?;
-
-        let packages: Vec<String> = output
-            .lines()
-            .filter(|line| !line.is_empty())
-            .map(|line| line.trim().to_string())
+    /// Installs the split APKs of one package at `paths`, base first, in a single package
+    /// manager session, so they replace any installed version together. `user` and `downgrade`
+    /// are as for `install_apk`
+    pub async fn install_apks(
+        &mut self,
+        paths: &[PathBuf],
+        user: Option<u32>,
+        downgrade: bool,
+    ) -> Result<()> {
+        let apks: Vec<String> = paths
+            .iter()
+            .map(|path| {
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            })
             .collect();
+        let Some(base) = apks.first().cloned() else {
+            return Ok(());
+        };
+        let request = Request::InstallSplits { apks };
+        if let Some(response) = self.replayed(&request)? {
+            return replayed_install(response, base);
+        }
 
-        Ok(packages)
+        // A session left open by a try that lost the connection, for the next try to abandon
+        let mut open = None;
+        let result = self
+            .retrying(|adb| adb.install_session(paths, user, downgrade, &mut open))
+            .await
+            .and_then(|output| install_result(base, output));
+
+        self.record(request, Response::from_done(&result));
+        result
     }
 
-    pub fn reboot(&mut self) -> Result<()> {
-        self.device
-            .reboot(adb_client::RebootType::System)
-            .map_err(|e| InstallerError::Adb(e.to_string()))?;
-        Ok(())
+    /// Stages each APK and writes it into a new `pm install-create` session, then commits it.
+    /// Returns `pm`'s output from the commit, or from the first step that didn't succeed, in
+    /// which case the session is abandoned. When the connection drops, the session is left in
+    /// `open`, and abandoned before the next try creates another
+    fn install_session(
+        &mut self,
+        paths: &[PathBuf],
+        user: Option<u32>,
+        downgrade: bool,
+        open: &mut Option<String>,
+    ) -> std::result::Result<String, Attempt> {
+        let mut files = Vec::new();
+        for path in paths {
+            files.push(std::fs::File::open(path).context("opening the APK")?);
+        }
+        let user = user.map(|user| user.to_string());
+        let mut create = vec!["pm", "install-create", "-r"];
+        if downgrade {
+            create.push("-d");
+        }
+        if let Some(user) = &user {
+            create.extend(["--user", user]);
+        }
+
+        let token = self.cancellation_token.as_ref();
+        let progress = self.transfer_progress.as_ref();
+        let device = self.device.as_mut().ok_or(InstallerError::NoDevice)?;
+
+        if let Some(previous) = open.as_deref() {
+            if let Err(e) = run_pm(device, &["pm", "install-abandon", previous]) {
+                return Err(self.transfer_error("Abandoning the earlier install session failed", e));
+            }
+            *open = None;
+        }
+
+        let mut result = run_pm(device, &create);
+        let session = match &result {
+            Ok(output) if output.contains("Success") => match created_session(output) {
+                Some(session) => Some(session),
+                None => {
+                    return Err(Attempt::Failed(InstallerError::Adb(format!(
+                        "pm install-create didn't give a session id: {}",
+                        output
+                    ))))
+                }
+            },
+            _ => None,
+        };
+        if let Some(session) = &session {
+            for (index, (path, file)) in paths.iter().zip(files).enumerate() {
+                let staged = format!("{}{}.apk", STAGED_SPLIT_PREFIX, index);
+                let file = Cancellable { inner: file, token };
+                let mut file = Reporting::new(file, progress, path, &staged);
+                let pushed = device.push(&mut file, &staged);
+                file.finish();
+
+                let name = format!("{}.apk", index);
+                result = pushed.and_then(|()| {
+                    run_pm(device, &["pm", "install-write", session, &name, &staged])
+                });
+                let _ = device.shell_command(&["rm", "-f", &staged], &mut Vec::new());
+                if !result
+                    .as_ref()
+                    .is_ok_and(|output| output.contains("Success"))
+                {
+                    break;
+                }
+            }
+
+            if result
+                .as_ref()
+                .is_ok_and(|output| output.contains("Success"))
+            {
+                result = run_pm(device, &["pm", "install-commit", session]);
+            }
+            if !result
+                .as_ref()
+                .is_ok_and(|output| output.contains("Success"))
+            {
+                let _ = run_pm(device, &["pm", "install-abandon", session]);
+            }
+        }
+
+        if result.is_err() {
+            *open = session;
+        }
+        result.map_err(|e| self.transfer_error("APK install failed", e))
+    }
+
+    /// Pushes the APK to a staging path and installs it from there, returning `pm`'s output
+    fn install_staged(
+        &mut self,
+        path: &Path,
+        user: Option<u32>,
+        downgrade: bool,
+    ) -> std::result::Result<String, Attempt> {
+        let file = std::fs::File::open(path).context("opening the APK")?;
+        let file = Cancellable {
+            inner: file,
+            token: self.cancellation_token.as_ref(),
+        };
+        let mut file = Reporting::new(file, self.transfer_progress.as_ref(), path, STAGED_APK);
+        let user = user.map(|user| user.to_string());
+        let mut command = vec!["pm", "install", "-r"];
+        if downgrade {
+            command.push("-d");
+        }
+        if let Some(user) = &user {
+            command.extend(["--user", user]);
+        }
+        command.push(STAGED_APK);
+        let mut output = Vec::new();
+
+        let device = self.device.as_mut().ok_or(InstallerError::NoDevice)?;
+        let pushed = device.push(&mut file, &STAGED_APK);
+        file.finish();
+        let result = pushed.and_then(|()| device.shell_command(&command, &mut output));
+        let _ = device.shell_command(&["rm", "-f", STAGED_APK], &mut Vec::new());
+
+        result
+            .map_err(|e| self.transfer_error("APK install failed", e))
+            .map(|()| String::from_utf8_lossy(&output).trim().to_string())
+    }
+
+    /// Uninstalls `package` for `user`, or for the default user and then entirely. Fails with
+    /// `pm`'s output when no uninstall succeeded
+    pub async fn uninstall_package(&mut self, package: &str, user: Option<u32>) -> Result<()> {
+        let commands = match user {
+            Some(user) => vec![format!("pm uninstall --user {} {}", user, package)],
+            None => vec![
+                format!("pm uninstall --user 0 {}", package),
+                format!("pm uninstall {}", package),
+            ],
+        };
+
+        let mut uninstalled = false;
+        let mut failures = Vec::new();
+        for command in commands {
+            match self.shell(&command).await {
+                Ok(output) if output.contains("Success") => uninstalled = true,
+                Ok(output) => failures.push(output),
+                Err(e) if matches!(e.root(), InstallerError::Cancelled) => return Err(e),
+                Err(e) => failures.push(e.to_string()),
+            }
+        }
+
+        if uninstalled {
+            Ok(())
+        } else {
+            Err(InstallerError::Adb(format!(
+                "Failed to uninstall {}: {}",
+                package,
+                failures.join("; ")
+            )))
+        }
+    }
+
+    pub async fn shell(&mut self, command: &str) -> Result<String> {
+        self.shell_stats.round_trips += 1;
+        self.shell_stats.commands += 1;
+
+        let request = Request::Shell {
+            command: command.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            return response
+                .into_output()
+                .with_context(|| format!("running `{}`", command));
+        }
+
+        let result = self
+            .retrying(|adb| {
+                let mut output = Cancellable {
+                    inner: Vec::new(),
+                    token: adb.cancellation_token.as_ref(),
+                };
+                // Sent whole, so quoted arguments and multi-line scripts reach the device's
+                // shell intact
+                adb.device
+                    .as_mut()
+                    .ok_or(InstallerError::NoDevice)?
+                    .shell_command(&[command], &mut output)
+                    .map_err(|e| adb.transfer_error("Failed to run shell command", e))
+                    .map(|()| String::from_utf8_lossy(&output.inner).trim().to_string())
+            })
+            .await;
+
+        self.record(request, Response::from_output(&result));
+        result.with_context(|| format!("running `{}`", command))
+    }
+
+    /// Writes `message` to the device log under `tag`. Best effort, and left out of sessions so
+    /// markers don't change what a replay expects. Does nothing when replaying
+    pub fn log_marker(&mut self, tag: &str, message: &str) {
+        if self.is_replaying() {
+            return;
+        }
+        let Some(device) = self.device.as_mut() else {
+            return;
+        };
+
+        let command = format!("log -t {} {}", quote(tag), quote(message));
+        if let Err(e) = device.shell_command(&[command.as_str()], &mut Vec::new()) {
+            debug!("Couldn't write log marker: {}", e);
+        }
+    }
+
+    /// Runs `command` as `run` does, without running it again when the connection drops, as it
+    /// may have taken effect already. For commands from configs, which may not be safe to repeat
+    pub async fn run_once(&mut self, command: &str) -> Result<String> {
+        let retry = std::mem::replace(&mut self.retry, RetryPolicy::none());
+        let result = self.run(command).await;
+        self.retry = retry;
+        result
+    }
+
+    /// Runs `command`, failing with its output, including stderr, when it exits non-zero
+    pub async fn run(&mut self, command: &str) -> Result<String> {
+        let output = self.shell(&checked(command)).await?;
+        exit_result(command, &output, self.is_replaying())
+    }
+
+    /// Runs `commands` in as few shell invocations as possible, returning the output of each.
+    /// A failing command doesn't stop the ones after it
+    pub async fn shell_batch(&mut self, commands: &[String]) -> Result<Vec<String>> {
+        Ok(self
+            .batch(commands)
+            .await?
+            .into_iter()
+            .map(|output| strip_exit_code(&output).0)
+            .collect())
+    }
+
+    /// Like `shell_batch`, but each command fails as `run` would
+    pub async fn run_batch(&mut self, commands: &[String]) -> Result<Vec<Result<String>>> {
+        let replaying = self.is_replaying();
+        Ok(commands
+            .iter()
+            .zip(self.batch(commands).await?)
+            .map(|(command, output)| exit_result(command, &output, replaying))
+            .collect())
+    }
+
+    /// Output of each of `commands`, ending with its exit code after `EXIT_MARKER`
+    async fn batch(&mut self, commands: &[String]) -> Result<Vec<String>> {
+        let mut outputs = Vec::with_capacity(commands.len());
+        for chunk in commands.chunks(MAX_BATCH_COMMANDS) {
+            let script = chunk
+                .iter()
+                .enumerate()
+                .map(|(index, command)| {
+                    format!("echo {}{}; {}", BATCH_MARKER, index, checked(command))
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            let output = self.shell(&script).await?;
+            self.shell_stats.commands += chunk.len() as u64 - 1;
+
+            let mut chunk_outputs = vec![String::new(); chunk.len()];
+            let mut current = None;
+            for line in output.lines() {
+                match line.trim().strip_prefix(BATCH_MARKER) {
+                    Some(index) => current = index.parse::<usize>().ok(),
+                    None => {
+                        if let Some(output) = current.and_then(|index| chunk_outputs.get_mut(index))
+                        {
+                            output.push_str(line);
+                            output.push('\n');
+                        }
+                    }
+                }
+            }
+            outputs.extend(
+                chunk_outputs
+                    .into_iter()
+                    .map(|output| output.trim().to_string()),
+            );
+        }
+        Ok(outputs)
+    }
+
+    /// Shell commands run over this connection so far
+    pub fn shell_stats(&self) -> ShellStats {
+        self.shell_stats
+    }
+
+    pub fn shell_stream<T>(&mut self, command: &str, writer: T) -> Result<()>
+    where
+        T: Write,
+    {
+        let mut line_buffer = LineBuffer::new(writer);
+
+        self.device
+            .as_mut()
+            .ok_or(InstallerError::NoDevice)?
+            .shell_command(&[command], &mut line_buffer)
+            .map_err(|e| InstallerError::Adb(format!("Failed to run shell command: {}", e)))
+            .with_context(|| format!("running `{}`", command))
+    }
+
+    pub async fn push_file(&mut self, local: &Path, remote: &str) -> Result<()> {
+        // Local paths differ between runs, so sessions only key on the file name
+        let request = Request::Push {
+            local: local
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            remote: remote.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            return response
+                .into_done()
+                .with_context(|| format!("pushing {} to {}", local.display(), remote));
+        }
+
+        // Sessions record the plain push, so only compress when not recording
+        let compressed = match self.session {
+            None if self.compress_pushes => self.push_compressed(local, remote).await,
+            _ => None,
+        };
+        let result = match compressed {
+            Some(result) => result,
+            None => self.push_stream(local, remote).await,
+        };
+
+        self.record(request, Response::from_done(&result));
+        result.with_context(|| format!("pushing {} to {}", local.display(), remote))
+    }
+
+    async fn push_stream(&mut self, local: &Path, remote: &str) -> Result<()> {
+        self.retrying(|adb| {
+            let file = std::fs::File::open(local).context("opening the file to push")?;
+            let file = Cancellable {
+                inner: file,
+                token: adb.cancellation_token.as_ref(),
+            };
+            let mut file = Reporting::new(file, adb.transfer_progress.as_ref(), local, remote);
+
+            let result = adb
+                .device
+                .as_mut()
+                .ok_or(InstallerError::NoDevice)?
+                .push(&mut file, &remote);
+            file.finish();
+            result.map_err(|e| adb.transfer_error("Push failed", e))
+        })
+        .await
+    }
+
+    /// Replaces how operations are retried when the connection drops, which defaults to
+    /// `RetryPolicy::from_env`
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Reports the progress of each push and APK upload to `sender`, at most every
+    /// `PROGRESS_INTERVAL` per file and once each finishes
+    pub fn set_transfer_progress(
+        &mut self,
+        sender: Option<mpsc::UnboundedSender<TransferProgress>>,
+    ) {
+        self.transfer_progress = sender;
+    }
+
+    /// Compresses large pushes on the host and unpacks them with a decompressor on the device, so
+    /// they take less time over the Pin's slow USB link. This is separate from the compression
+    /// in adb's own sync protocol, which the Pin's adbd doesn't offer
+    pub fn set_compress_pushes(&mut self, compress: bool) {
+        self.compress_pushes = compress;
+    }
+
+    /// Pushes `local` compressed and unpacks it on the device. `None` when it should be pushed
+    /// as is instead: it's small, doesn't compress well, or the device can't unpack it
+    async fn push_compressed(&mut self, local: &Path, remote: &str) -> Option<Result<()>> {
+        if std::fs::metadata(local).ok()?.len() < MIN_COMPRESSED_PUSH_BYTES {
+            return None;
+        }
+        let compression = self.push_compression().await?;
+
+        let staging = Platform::temp_dir();
+        let name = local.file_name()?.to_string_lossy().to_string();
+        let compressed = staging.join(format!("{}.{}", name, compression.extension()));
+        let size = std::fs::metadata(local).ok()?.len();
+        let compressed_size = std::fs::create_dir_all(&staging)
+            .and_then(|()| compression.compress(local, &compressed));
+
+        let result = match compressed_size {
+            Ok(compressed_size) if compressed_size * 10 <= size * 9 => {
+                debug!(
+                    "Pushing {} compressed with {} ({} of {} bytes)",
+                    name,
+                    compression.tool(),
+                    compressed_size,
+                    size
+                );
+                let mode = file_mode(local);
+                Some(
+                    self.push_and_unpack(&compressed, remote, compression, mode)
+                        .await,
+                )
+            }
+            Ok(_) => {
+                debug!("{} doesn't compress well, pushing it as is", name);
+                None
+            }
+            Err(e) => {
+                debug!("Failed to compress {}: {}", name, e);
+                None
+            }
+        };
+        let _ = std::fs::remove_dir_all(&staging);
+
+        match result {
+            Some(Err(e)) if !matches!(e.root(), InstallerError::Cancelled) => {
+                warn!(
+                    "Compressed push of {} failed, pushing uncompressed from now on: {}",
+                    name, e
+                );
+                self.push_compression = Some(None);
+                None
+            }
+            result => result,
+        }
+    }
+
+    /// Pushes `compressed` next to `remote` and unpacks it there with `mode`. The file is
+    /// unpacked to a temporary name and then moved into place, so `remote` is never left half
+    /// written
+    async fn push_and_unpack(
+        &mut self,
+        compressed: &Path,
+        remote: &str,
+        compression: PushCompression,
+        mode: u32,
+    ) -> Result<()> {
+        let staged = format!("{}.penumbra.{}", remote, compression.extension());
+        self.push_stream(compressed, &staged).await?;
+
+        let (staged, unpacked, remote) = (
+            quote(&staged),
+            quote(&format!("{}.penumbra.tmp", remote)),
+            quote(remote),
+        );
+        let output = self
+            .shell(&format!(
+                "{} -dc {staged} > {unpacked} && chmod {:o} {unpacked} && mv -f {unpacked} {remote} \
+                 && echo unpacked; rm -f {staged} {unpacked}",
+                compression.tool(),
+                mode,
+            ))
+            .await?;
+        if output.contains("unpacked") {
+            Ok(())
+        } else {
+            Err(InstallerError::Adb(format!(
+                "Failed to unpack {}: {}",
+                staged, output
+            )))
+        }
+    }
+
+    /// The decompressor compressed pushes are unpacked with, looked for on first use
+    async fn push_compression(&mut self) -> Option<PushCompression> {
+        if let Some(compression) = self.push_compression {
+            return compression;
+        }
+
+        let compression = match self
+            .shell("for tool in zstd gzip; do command -v $tool >/dev/null && echo $tool; done")
+            .await
+        {
+            Ok(output) => [PushCompression::Zstd, PushCompression::Gzip]
+                .into_iter()
+                .find(|compression| output.lines().any(|line| line.trim() == compression.tool())),
+            Err(e) => {
+                debug!("Failed to look for decompressors on the device: {}", e);
+                None
+            }
+        };
+        match compression {
+            Some(compression) => info!("Compressing large pushes with {}", compression.tool()),
+            None => info!("Device has no decompressor, so pushes aren't compressed"),
+        }
+
+        self.push_compression = Some(compression);
+        compression
+    }
+
+    /// Copies `remote` from the device to `local`, creating its parent directories
+    pub async fn pull_file(&mut self, remote: &str, local: &Path) -> Result<()> {
+        if let Some(parent) = local.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let request = Request::Pull {
+            remote: remote.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            // Pulled contents aren't recorded, so replays produce an empty file
+            std::fs::File::create(local)?;
+            return response
+                .into_done()
+                .with_context(|| format!("pulling {} to {}", remote, local.display()));
+        }
+
+        let result = self
+            .retrying(|adb| {
+                let file = std::fs::File::create(local).map_err(InstallerError::from)?;
+                let mut file = Cancellable {
+                    inner: file,
+                    token: adb.cancellation_token.as_ref(),
+                };
+
+                adb.device
+                    .as_mut()
+                    .ok_or(InstallerError::NoDevice)?
+                    .pull(&remote, &mut file)
+                    .map_err(|e| adb.transfer_error("Pull failed", e))
+            })
+            .await;
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(local);
+        }
+
+        self.record(request, Response::from_done(&result));
+        result.with_context(|| format!("pulling {} to {}", remote, local.display()))
+    }
+
+    pub async fn grant_permission(
+        &mut self,
+        package: &str,
+        permission: &str,
+        user: Option<u32>,
+    ) -> Result<()> {
+        let user = user.map_or(String::new(), |user| format!("--user {} ", user));
+        self.run(&format!("pm grant {}{} {}", user, package, permission))
+            .await?;
+        Ok(())
+    }
+
+    /// Grants each `(package, permission)` for `user`, in one shell invocation per batch.
+    /// Returns the result of each grant, so one failing doesn't hide the others
+    pub async fn grant_permissions(
+        &mut self,
+        grants: &[(&str, &str)],
+        user: Option<u32>,
+    ) -> Result<Vec<Result<()>>> {
+        let user = user.map_or(String::new(), |user| format!("--user {} ", user));
+        let commands: Vec<_> = grants
+            .iter()
+            .map(|(package, permission)| format!("pm grant {}{} {}", user, package, permission))
+            .collect();
+
+        let results = self.run_batch(&commands).await?;
+        Ok(grants
+            .iter()
+            .zip(results)
+            .map(|((package, permission), result)| {
+                let output = result?;
+                if !output.is_empty() {
+                    debug!("Granting {} to {}: {}", permission, package, output);
+                }
+                Ok(())
+            })
+            .collect())
+    }
+
+    pub async fn set_app_op(&mut self, package: &str, operation: &str, mode: &str) -> Result<()> {
+        self.shell(&format!("appops set {} {} {}", package, operation, mode))
+            .await?;
+        Ok(())
+    }
+
+    /// The mode `package` currently has for `operation`, as `appops get` prints it (such as
+    /// `allow`), or `None` if its output isn't recognized
+    pub async fn app_op_mode(&mut self, package: &str, operation: &str) -> Result<Option<String>> {
+        let output = self
+            .shell(&format!("appops get {} {}", package, operation))
+            .await?;
+        Ok(parse_app_op_mode(&output))
+    }
+
+    /// Sets each `(package, operation, mode)`, in one shell invocation per batch
+    pub async fn set_app_ops(&mut self, ops: &[(&str, &str, &str)]) -> Result<()> {
+        let commands: Vec<_> = ops
+            .iter()
+            .map(|(package, operation, mode)| {
+                format!("appops set {} {} {}", package, operation, mode)
+            })
+            .collect();
+        self.shell_batch(&commands).await?;
+        Ok(())
+    }
+
+    /// The mode of each `(package, operation)`, as `app_op_mode` reads it, in one shell
+    /// invocation per batch
+    pub async fn app_op_modes(&mut self, ops: &[(&str, &str)]) -> Result<Vec<Option<String>>> {
+        let commands: Vec<_> = ops
+            .iter()
+            .map(|(package, operation)| format!("appops get {} {}", package, operation))
+            .collect();
+        Ok(self
+            .shell_batch(&commands)
+            .await?
+            .iter()
+            .map(|output| parse_app_op_mode(output))
+            .collect())
+    }
+
+    pub async fn set_launcher(&mut self, component: &str) -> Result<()> {
+        self.run(&format!("cmd package set-home-activity {}", component))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn enable_package(&mut self, package: &str) -> Result<()> {
+        self.shell(&format!("pm enable --user 0 {}", package))
+            .await?;
+        Ok(())
+    }
+
+    /// Packages that are installed but disabled
+    pub async fn disabled_packages(&mut self) -> Result<Vec<String>> {
+        let output = self.shell("pm list packages -d").await?;
+        Ok(output
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("package:"))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The activity that currently handles the home intent, as `package/activity`
+    pub async fn home_activity(&mut self) -> Result<Option<String>> {
+        let output = self
+            .shell("cmd package resolve-activity --brief -a android.intent.action.MAIN -c android.intent.category.HOME")
+            .await?;
+        Ok(output
+            .lines()
+            .last()
+            .map(|line| line.trim().to_string())
+            .filter(|line| line.contains('/')))
+    }
+
+    /// Every activity able to handle the home intent, as `package/activity`
+    pub async fn home_activities(&mut self) -> Result<Vec<String>> {
+        let output = self
+            .shell("cmd package query-activities --brief -a android.intent.action.MAIN -c android.intent.category.HOME")
+            .await?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.contains('/') && !line.contains(' '))
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub async fn create_directory(&mut self, path: &str) -> Result<()> {
+        self.shell(&format!("mkdir -p {}", quote(path))).await?;
+        Ok(())
+    }
+
+    pub async fn remove_directory(&mut self, path: &str) -> Result<()> {
+        self.shell(&format!("rm -rf {}", quote(path))).await?;
+        Ok(())
+    }
+
+    pub async fn remove_file(&mut self, path: &str) -> Result<()> {
+        self.shell(&format!("rm -f {}", quote(path))).await?;
+        Ok(())
+    }
+
+    /// Metadata of `path`, or `None` if nothing exists there
+    pub async fn stat(&mut self, path: &str) -> Result<Option<RemoteStat>> {
+        let request = Request::Stat {
+            path: path.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            return response
+                .into_stat()
+                .with_context(|| format!("reading metadata of {}", path));
+        }
+
+        let result = self
+            .retrying(|adb| {
+                adb.device
+                    .as_mut()
+                    .ok_or(InstallerError::NoDevice)?
+                    .stat(path)
+                    .map(|stat| {
+                        RemoteStat::from_response(stat.file_perm, stat.file_size, stat.mod_time)
+                    })
+                    .map_err(|e| adb.transfer_error("Stat failed", e))
+            })
+            .await;
+
+        self.record(request, Response::from_stat(&result));
+        result.with_context(|| format!("reading metadata of {}", path))
+    }
+
+    /// Lowercase hex SHA-256 of the file at `path`, or `None` if the device couldn't hash it
+    pub async fn sha256(&mut self, path: &str) -> Result<Option<String>> {
+        let output = self
+            .shell(&format!("sha256sum {} 2>/dev/null", quote(path)))
+            .await?;
+        Ok(output
+            .split_whitespace()
+            .next()
+            .filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(str::to_lowercase))
+    }
+
+    pub async fn file_exists(&mut self, path: &str) -> Result<bool> {
+        Ok(self
+            .stat(path)
+            .await?
+            .is_some_and(|stat| stat.file_type == FileType::File))
+    }
+
+    pub async fn directory_exists(&mut self, path: &str) -> Result<bool> {
+        Ok(self
+            .stat(path)
+            .await?
+            .is_some_and(|stat| stat.file_type == FileType::Directory))
+    }
+
+    /// Replaces the file at `path` with `content`, ending in a newline
+    pub async fn write_file(&mut self, path: &str, content: &str) -> Result<()> {
+        let mut content = content.to_string();
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        self.write_file_bytes(path, content.as_bytes()).await
+    }
+
+    /// Replaces the file at `path` with `bytes`, pushed from a staged host file so nothing passes
+    /// through shell quoting. If the push fails, small files are sent base64 encoded through the
+    /// shell instead
+    pub async fn write_file_bytes(&mut self, path: &str, bytes: &[u8]) -> Result<()> {
+        let staging = Platform::temp_dir();
+        std::fs::create_dir_all(&staging)?;
+        let local = staging.join(path.rsplit('/').next().unwrap_or("file"));
+        std::fs::write(&local, bytes)?;
+
+        let result = self.push_file(&local, path).await;
+        let _ = std::fs::remove_dir_all(&staging);
+
+        match result {
+            Err(e)
+                if bytes.len() <= MAX_SHELL_WRITE_BYTES
+                    && !matches!(e.root(), InstallerError::Cancelled) =>
+            {
+                warn!("{}, writing {} through the shell instead", e, path);
+                self.write_file_base64(path, bytes).await
+            }
+            result => result,
+        }
+    }
+
+    async fn write_file_base64(&mut self, path: &str, bytes: &[u8]) -> Result<()> {
+        // The content, such as a config's, is kept out of recorded sessions
+        let encoded = STANDARD.encode(bytes);
+        self.add_secret(&encoded);
+
+        let output = self
+            .shell(&format!(
+                "echo {} | base64 -d > {} && echo written",
+                encoded,
+                quote(path)
+            ))
+            .await?;
+        if output.ends_with("written") {
+            Ok(())
+        } else {
+            Err(InstallerError::Adb(format!(
+                "Failed to write {}: {}",
+                path, output
+            )))
+        }
+    }
+
+    /// Contents of the file at `path`, without surrounding whitespace, or `None` if there is no
+    /// such file
+    pub async fn read_file(&mut self, path: &str) -> Result<Option<String>> {
+        if !self.file_exists(path).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.shell(&format!("cat {}", quote(path))).await?))
+    }
+
+    pub async fn list_packages(&mut self, pattern: &str) -> Result<Vec<String>> {
+        let output = self
+            .shell(&format!(
+                "pm list packages | grep {} | sed 's/package://'",
+                pattern
+            ))
+            .await?;
+
+        let packages: Vec<String> = output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.trim().to_string())
+            .collect();
+
+        Ok(packages)
+    }
+
+    /// The installed version and signing certificates of `package`, or `None` if it isn't
+    /// installed
+    pub async fn installed_package(&mut self, package: &str) -> Result<Option<InstalledPackage>> {
+        let output = self.shell(&format!("dumpsys package {}", package)).await?;
+        Ok(InstalledPackage::from_dumpsys(&output, package))
+    }
+
+    /// Paths of the APKs an installed package is made of, from `pm path`. Empty if the package
+    /// isn't installed
+    pub async fn package_apks(&mut self, package: &str) -> Result<Vec<String>> {
+        let output = self.shell(&format!("pm path {}", package)).await?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("package:"))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Bytes used by the directory tree at `path`, from `du`. Unreadable entries are left out
+    pub async fn disk_usage(&mut self, path: &str) -> Result<u64> {
+        let output = self
+            .shell(&format!("du -sk {} 2>/dev/null", quote(path)))
+            .await?;
+        parse_kilobytes(output.lines().next_back(), 0).ok_or_else(|| {
+            InstallerError::Adb(format!("Unexpected du output for {}: {}", path, output))
+        })
+    }
+
+    /// Bytes available to apps on the filesystem holding `path`, from `df`
+    pub async fn free_space(&mut self, path: &str) -> Result<u64> {
+        let output = self.shell(&format!("df -k {}", quote(path))).await?;
+        parse_kilobytes(output.lines().next_back(), 3).ok_or_else(|| {
+            InstallerError::Adb(format!("Unexpected df output for {}: {}", path, output))
+        })
+    }
+
+    pub fn reboot(&mut self) -> Result<()> {
+        self.reboot_into(RebootTarget::System, adb_client::RebootType::System)
+    }
+
+    /// Reboots the device into `target`. A soft reboot restarts Android's framework through
+    /// `am restart`, which keeps the kernel and the ADB connection up
+    pub async fn reboot_to(&mut self, target: RebootTarget) -> Result<()> {
+        let reboot_type = match target {
+            RebootTarget::Soft => return self.restart_framework().await,
+            RebootTarget::System => adb_client::RebootType::System,
+            RebootTarget::Recovery => adb_client::RebootType::Recovery,
+            RebootTarget::Bootloader => adb_client::RebootType::Bootloader,
+            RebootTarget::Sideload => adb_client::RebootType::Sideload,
+        };
+        self.reboot_into(target, reboot_type)
+    }
+
+    fn reboot_into(
+        &mut self,
+        target: RebootTarget,
+        reboot_type: adb_client::RebootType,
+    ) -> Result<()> {
+        let request = Request::Reboot { target };
+        if let Some(response) = self.replayed(&request)? {
+            return response.into_done();
+        }
+
+        let result = self
+            .device
+            .as_mut()
+            .ok_or(InstallerError::NoDevice)?
+            .reboot(reboot_type)
+            .map_err(|e| InstallerError::Adb(e.to_string()));
+
+        self.record(request, Response::from_done(&result));
+        result
+    }
+
+    /// Restarts Android's framework, returning once the running one has stopped, so that
+    /// [`wait_for_boot`](Self::wait_for_boot) can't mistake it for the restarted one
+    async fn restart_framework(&mut self) -> Result<()> {
+        let before = self.shell("pidof system_server").await?;
+        self.shell("am restart").await?;
+
+        let started = Instant::now();
+        let replaying = self.is_replaying();
+        loop {
+            if self
+                .cancellation_token
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled())
+            {
+                return Err(InstallerError::Cancelled);
+            }
+            if started.elapsed() > FRAMEWORK_STOP_TIMEOUT {
+                return Err(InstallerError::Adb(format!(
+                    "The framework didn't restart within {}s",
+                    FRAMEWORK_STOP_TIMEOUT.as_secs()
+                )));
+            }
+
+            // A new process, or none while it restarts
+            let retry = std::mem::replace(&mut self.retry, RetryPolicy::none());
+            let current = self.shell("pidof system_server").await;
+            self.retry = retry;
+            match current {
+                Ok(current) if current != before => return Ok(()),
+                Ok(_) => debug!("The framework is still running"),
+                Err(e) => debug!("Device not reachable: {}", e),
+            }
+
+            if !replaying {
+                sleep(BOOT_POLL_INTERVAL / 4).await;
+            }
+        }
+    }
+
+    /// Waits for the device to come back after `reboot`, report `sys.boot_completed` and run its
+    /// package service, reconnecting as needed. Returns the time from the call until the device
+    /// booted
+    pub async fn wait_for_boot(&mut self, timeout: Duration) -> Result<Duration> {
+        let started = Instant::now();
+        let replaying = self.is_replaying();
+
+        // The device keeps answering for a moment after the reboot is requested
+        if !replaying {
+            sleep(BOOT_POLL_INTERVAL * 2).await;
+        }
+
+        let mut connected = false;
+        loop {
+            if self
+                .cancellation_token
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled())
+            {
+                return Err(InstallerError::Cancelled);
+            }
+            if started.elapsed() > timeout {
+                return Err(InstallerError::BootTimeout {
+                    secs: timeout.as_secs(),
+                });
+            }
+
+            if !connected {
+                match self.reconnect() {
+                    Ok(()) => connected = true,
+                    Err(e) => debug!("Device not reachable yet: {}", e),
+                }
+            }
+
+            if connected {
+                // This loop does its own reconnecting, at its own pace
+                let retry = std::mem::replace(&mut self.retry, RetryPolicy::none());
+                let booted = self.shell(BOOT_CHECK).await;
+                self.retry = retry;
+                match booted {
+                    Ok(output) if has_booted(&output) => return Ok(started.elapsed()),
+                    Ok(_) => debug!("Device is still booting"),
+                    Err(e) => {
+                        debug!("Device not reachable yet: {}", e);
+                        connected = false;
+                    }
+                }
+            }
+
+            if !replaying {
+                sleep(BOOT_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// How the device is reached: `server` through the ADB server, `usb` directly, or `replay`
+    /// from a recorded session
+    pub fn backend(&self) -> &'static str {
+        if self.is_replaying() {
+            "replay"
+        } else if self.serial.is_some() {
+            "server"
+        } else {
+            "usb"
+        }
+    }
+
+    /// Whether device responses come from a recorded session
+    pub fn is_replaying(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| session.is_replay())
+    }
+
+    /// Runs `operation`, and when the connection fails partway through, reconnects and runs it
+    /// again from the start as the retry policy allows. Failures the device reported are
+    /// returned as they are. Operations that may have taken effect before the connection
+    /// dropped must be safe to repeat, or fail with `Attempt::Failed`, as `run_once` does
+    async fn retrying<T>(
+        &mut self,
+        mut operation: impl FnMut(&mut Self) -> std::result::Result<T, Attempt>,
+    ) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            let error = match operation(self) {
+                Ok(value) => return Ok(value),
+                Err(Attempt::Failed(error)) => return Err(error),
+                Err(Attempt::Disconnected(error)) => error,
+            };
+
+            if attempt >= self.retry.attempts || self.is_replaying() {
+                return Err(error);
+            }
+
+            let delay = self
+                .retry
+                .backoff
+                .checked_mul(2u32.saturating_pow(attempt - 1))
+                .map_or(MAX_RETRY_BACKOFF, |delay| delay.min(MAX_RETRY_BACKOFF));
+            warn!(
+                "{}. Reconnecting in {:.1}s (attempt {} of {})",
+                error,
+                delay.as_secs_f64(),
+                attempt + 1,
+                self.retry.attempts
+            );
+            sleep(delay).await;
+            if self
+                .cancellation_token
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled())
+            {
+                return Err(InstallerError::Cancelled);
+            }
+
+            if let Err(e) = self.reconnect() {
+                debug!("Reconnecting failed: {}", e);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Replaces the connection with a new one to the same device. Does nothing when replaying
+    fn reconnect(&mut self) -> Result<()> {
+        if self.is_replaying() {
+            return Ok(());
+        }
+
+        // A USB device can only be claimed by one connection at a time
+        self.device = None;
+
+        let device: Box<dyn ADBDeviceExt + Send> = match &self.serial {
+            Some(serial) => Box::new(
+                ADBServer::new(Self::server_address())
+                    .get_device_by_name(serial)
+                    .map_err(|e| InstallerError::Adb(format!("Failed to get device: {}", e)))?,
+            ),
+            None => Box::new(
+                ADBUSBDevice::autodetect(self.remote_auth_url.clone()).map_err(|e| {
+                    InstallerError::Adb(format!("Failed to connect to USB device: {}", e))
+                })?,
+            ),
+        };
+
+        self.device = Some(device);
+        Ok(())
+    }
+}
+
+/// The mode in `appops get` output, or `None` if it isn't recognized
+fn parse_app_op_mode(output: &str) -> Option<String> {
+    if output.starts_with("No operations") {
+        return Some("default".to_string());
+    }
+
+    // Lines read `OP_NAME: mode; time=...`. `Uid mode:` lines describe the uid, rather than
+    // the package mode that `appops set` changes
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("Uid mode:"))
+        .find_map(|line| line.split_once(": "))
+        .and_then(|(_, rest)| rest.split(';').next())
+        .map(|mode| mode.trim().to_lowercase())
+}
+
+fn apk_error(apk: String, reason: String) -> InstallerError {
+    InstallerError::ApkInstallation {
+        failure: ApkFailure::from_output(&reason),
+        apk,
+        reason,
+    }
+}
+
+/// Fails with the package manager's `output` unless it reports success
+fn install_result(apk: String, output: String) -> Result<()> {
+    if output.contains("Success") {
+        Ok(())
+    } else {
+        Err(apk_error(apk, output))
+    }
+}
+
+fn replayed_install(response: Response, apk: String) -> Result<()> {
+    response.into_done().map_err(|e| match e {
+        // Recorded as this error's message, which ends with the package manager's output
+        InstallerError::Adb(message) => match message.split_once(", reason: ") {
+            Some((_, reason)) => apk_error(apk, reason.to_string()),
+            None => apk_error(apk, message),
+        },
+        e => e,
+    })
+}
+
+/// Runs a `pm` command given as separate arguments, returning its trimmed output
+fn run_pm(
+    device: &mut Box<dyn ADBDeviceExt + Send>,
+    command: &[&str],
+) -> std::result::Result<String, adb_client::RustADBError> {
+    let mut output = Vec::new();
+    device.shell_command(command, &mut output)?;
+    Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// The session id in `pm install-create` output, such as `Success: created install session [42]`
+fn created_session(output: &str) -> Option<String> {
+    let (_, rest) = output.split_once('[')?;
+    let (session, _) = rest.split_once(']')?;
+    let valid = !session.is_empty() && session.chars().all(|c| c.is_ascii_digit());
+    valid.then(|| session.to_string())
+}
+
+/// Permission bits of the host file at `path`, which compressed pushes give the unpacked file.
+/// Files on hosts without Unix permissions get `0644`
+fn file_mode(path: &Path) -> u32 {
+    #[cfg(unix)]
+    if let Ok(metadata) = std::fs::metadata(path) {
+        use std::os::unix::fs::PermissionsExt;
+        return metadata.permissions().mode() & 0o777;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    0o644
+}
+
+/// `arg` quoted for the device's shell, so spaces, quotes, and other special characters reach
+/// the command as they are. Arguments of only safe characters are left bare, keeping logged
+/// commands readable
+pub fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Whether the output of `BOOT_CHECK` shows a booted device with its framework running
+fn has_booted(output: &str) -> bool {
+    let mut lines = output.lines().map(str::trim);
+    lines.next() == Some("1") && lines.next().is_some_and(|line| line.ends_with(": found"))
+}
+
+/// `command` run in a subshell with its stderr captured, followed by its exit code after
+/// `EXIT_MARKER` on a line of its own. The subshell keeps an `exit` in `command` from skipping
+/// the exit code, and the newline before it keeps output without a trailing newline apart
+fn checked(command: &str) -> String {
+    format!(
+        "(\n{}\n) 2>&1; printf '\\n%s%d\\n' {} $?",
+        command.trim().trim_end_matches(';'),
+        EXIT_MARKER
+    )
+}
+
+/// Output of a command run with `EXIT_MARKER`, and its exit code if the marker was found
+fn strip_exit_code(output: &str) -> (String, Option<i32>) {
+    let mut code = None;
+    let lines: Vec<&str> = output
+        .lines()
+        .filter(|line| match line.trim().strip_prefix(EXIT_MARKER) {
+            Some(exit) => {
+                code = exit.parse().ok();
+                false
+            }
+            None => true,
+        })
+        .collect();
+    (lines.join("\n").trim().to_string(), code)
+}
+
+/// The output of `command`, or an error with it when it exited non-zero. A missing exit code
+/// means the shell stopped before reporting it, so it's a failure too, except when `replaying`
+/// sessions recorded before commands were checked
+fn exit_result(command: &str, output: &str, replaying: bool) -> Result<String> {
+    match strip_exit_code(output) {
+        (output, Some(0)) => Ok(output),
+        (output, None) if replaying => Ok(output),
+        (output, Some(code)) => Err(InstallerError::Adb(format!(
+            "`{}` exited with {}: {}",
+            command, code, output
+        ))),
+        (output, None) => Err(InstallerError::Adb(format!(
+            "`{}` stopped without reporting an exit code: {}",
+            command, output
+        ))),
+    }
+}
+
+/// The kilobyte count in whitespace separated `column` of a `du` or `df` line, in bytes
+fn parse_kilobytes(line: Option<&str>, column: usize) -> Option<u64> {
+    let kilobytes = line?.split_whitespace().nth(column)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_the_package_service_to_have_booted() {
+        assert!(has_booted("1\nService package: found"));
+        assert!(!has_booted("1\nService package: not found"));
+        assert!(!has_booted("\nService package: found"));
+        assert!(!has_booted(""));
+    }
+
+    #[test]
+    fn parses_app_op_modes() {
+        assert_eq!(
+            parse_app_op_mode("SYSTEM_ALERT_WINDOW: allow; time=+2d3h4m ago\n"),
+            Some("allow".to_string())
+        );
+        assert_eq!(
+            parse_app_op_mode("MANAGE_EXTERNAL_STORAGE: allow\n"),
+            Some("allow".to_string())
+        );
+        assert_eq!(
+            parse_app_op_mode(
+                "Uid mode: LEGACY_STORAGE: allow\nLEGACY_STORAGE: ignore; rejectTime=+1h ago\n"
+            ),
+            Some("ignore".to_string())
+        );
+        assert_eq!(
+            parse_app_op_mode("RUN_IN_BACKGROUND: DEFAULT"),
+            Some("default".to_string())
+        );
+        assert_eq!(
+            parse_app_op_mode("No operations."),
+            Some("default".to_string())
+        );
+        assert_eq!(parse_app_op_mode(""), None);
+    }
+
+    #[test]
+    fn reads_exit_codes_after_output_without_a_newline() {
+        let output = "x\n@@penumbra-exit:1\n";
+        assert_eq!(strip_exit_code(output), ("x".to_string(), Some(1)));
+        assert!(exit_result("printf x; false", output, false).is_err());
+    }
+
+    #[test]
+    fn checks_exit_codes() {
+        assert_eq!(
+            exit_result("echo ok", "ok\n\n@@penumbra-exit:0\n", false).unwrap(),
+            "ok"
+        );
+        assert!(exit_result("exit 3", "err\n\n@@penumbra-exit:3\n", false).is_err());
+    }
+
+    #[test]
+    fn fails_without_an_exit_code_unless_replaying() {
+        assert!(exit_result("test -f /x || exit 1", "", false).is_err());
+        assert_eq!(exit_result("echo ok", "ok\n", true).unwrap(), "ok");
+    }
+
+    #[test]
+    fn runs_checked_commands_in_a_subshell() {
+        assert_eq!(
+            checked("test -f /x || exit 1;"),
+            "(\ntest -f /x || exit 1\n) 2>&1; printf '\\n%s%d\\n' @@penumbra-exit: $?"
+        );
+    }
+
+    #[test]
+    fn reads_created_install_sessions() {
+        assert_eq!(
+            created_session("Success: created install session [1234567]"),
+            Some("1234567".to_string())
+        );
+        assert_eq!(
+            created_session("Success: created install session [42]\n"),
+            Some("42".to_string())
+        );
+        assert_eq!(created_session("Success: created install session []"), None);
+        assert_eq!(
+            created_session("Error: java.lang.SecurityException: [denied]"),
+            None
+        );
+        assert_eq!(
+            created_session("Failure [INSTALL_FAILED_INTERNAL_ERROR]"),
+            None
+        );
+        assert_eq!(created_session(""), None);
     }
 }
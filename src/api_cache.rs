@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::platform::Platform;
+use crate::Result;
+
+/// How long cached responses are used, unless overridden by `PENUMBRA_API_CACHE_TTL` (seconds)
+const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedResponse {
+    url: String,
+    /// Seconds since the Unix epoch
+    fetched_at: u64,
+    status: u16,
+    body: Value,
+    /// Next page URL from the response's `Link` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+/// On-disk cache of successful GitHub API responses, so commands run in quick succession don't
+/// each spend API quota on the same release metadata
+#[derive(Debug, Clone)]
+pub struct ApiCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ApiCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// The default cache, in the temp directory. `None` when disabled by setting
+    /// `PENUMBRA_API_CACHE_TTL` to 0
+    pub fn from_env() -> Option<Self> {
+        let ttl = std::env::var("PENUMBRA_API_CACHE_TTL")
+            .ok()
+            .and_then(|seconds| seconds.parse::<u64>().ok())
+            .map_or(DEFAULT_TTL, Duration::from_secs);
+
+        if ttl.is_zero() {
+            return None;
+        }
+        Some(Self::new(Platform::api_cache_dir(), ttl))
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}.json", Sha256::digest(url.as_bytes())))
+    }
+
+    /// The status, body and next page URL cached for `url`, if it was fetched within the TTL
+    pub fn get(&self, url: &str) -> Option<(u16, Value, Option<String>)> {
+        let contents = fs::read(self.path(url)).ok()?;
+        let cached: CachedResponse = serde_json::from_slice(&contents).ok()?;
+
+        let age = now().saturating_sub(cached.fetched_at);
+        if cached.url != url || age > self.ttl.as_secs() {
+            return None;
+        }
+
+        debug!("Using cached response for {} ({}s old)", url, age);
+        Some((cached.status, cached.body, cached.next))
+    }
+
+    /// Caches a response. Failures are logged, as the cache is only an optimization
+    pub fn put(&self, url: &str, status: u16, body: &Value, next: Option<&str>) {
+        if let Err(e) = self.write(url, status, body, next) {
+            warn!("Failed to cache response for {}: {}", url, e);
+        }
+    }
+
+    fn write(&self, url: &str, status: u16, body: &Value, next: Option<&str>) -> Result<()> {
+        let cached = CachedResponse {
+            url: url.to_string(),
+            fetched_at: now(),
+            status,
+            body: body.clone(),
+            next: next.map(str::to_string),
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(url), serde_json::to_vec(&cached)?)?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,680 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::{ApkFailure, InstallerError};
+
+const MANIFEST_ENTRY: &str = "AndroidManifest.xml";
+
+// Binary XML chunk types
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const UTF8_FLAG: u32 = 1 << 8;
+
+// Attribute value types
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+
+// `android:` attribute resource ids, used when attribute names are stripped
+const VERSION_CODE_ATTR: u32 = 0x0101_021b;
+const VERSION_NAME_ATTR: u32 = 0x0101_021c;
+const VERSION_CODE_MAJOR_ATTR: u32 = 0x0101_0576;
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+/// End of central directory record without its comment
+const EOCD_SIZE: u64 = 22;
+const SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const SIGNATURE_SCHEME_V2_ID: u32 = 0x7109_871a;
+const SIGNATURE_SCHEME_V3_ID: u32 = 0xf053_68c0;
+/// v3 signed data attribute holding the key rotation lineage
+const PROOF_OF_ROTATION_ATTR_ID: u32 = 0x3ba0_6f8c;
+
+/// Package identity of an APK file, read on the host before installing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApkInfo {
+    pub package: String,
+    pub version_code: u64,
+    pub version_name: Option<String>,
+    /// Hash of the signing certificate, in the form `dumpsys package` shows. `None` for APKs
+    /// only signed with the JAR scheme
+    pub signature: Option<String>,
+    /// Hashes of the certificates in the v3 key rotation lineage, oldest first. An installed
+    /// package signed with any of them can be updated
+    pub lineage: Vec<String>,
+    /// Name of the configuration or feature split this APK is. `None` for base APKs
+    pub split: Option<String>,
+}
+
+/// The installed version of a package, from `dumpsys package`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledPackage {
+    pub version_code: u64,
+    pub signatures: Vec<String>,
+}
+
+impl ApkInfo {
+    pub fn read(path: &Path) -> std::result::Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+
+        let mut manifest = Vec::new();
+        archive
+            .by_name(MANIFEST_ENTRY)
+            .map_err(|e| format!("{}: {}", MANIFEST_ENTRY, e))?
+            .read_to_end(&mut manifest)
+            .map_err(|e| format!("{}: {}", MANIFEST_ENTRY, e))?;
+
+        let mut info =
+            parse_manifest(&manifest).ok_or_else(|| format!("Unreadable {}", MANIFEST_ENTRY))?;
+
+        let mut file = archive.into_inner();
+        if let Some((certificate, lineage)) = signing_certificates(&mut file) {
+            info.signature = Some(signature_hash(&certificate));
+            info.lineage = lineage.iter().map(|cert| signature_hash(cert)).collect();
+        }
+        Ok(info)
+    }
+
+    /// Why installing this APK over `installed` would fail, as the package manager would report
+    /// it. Downgrades are allowed when `allow_downgrade` is set
+    pub fn conflict(
+        &self,
+        installed: &InstalledPackage,
+        allow_downgrade: bool,
+    ) -> Option<(ApkFailure, String)> {
+        if let Some(signature) = &self.signature {
+            let trusted = installed
+                .signatures
+                .iter()
+                .any(|installed| installed == signature || self.lineage.contains(installed));
+            if !installed.signatures.is_empty() && !trusted {
+                return Some((
+                    ApkFailure::SignatureMismatch,
+                    format!(
+                        "{} is installed signed with a different key (certificate {}, the APK has {})",
+                        self.package,
+                        installed.signatures.join(", "),
+                        signature
+                    ),
+                ));
+            }
+        }
+
+        if self.version_code < installed.version_code && !allow_downgrade {
+            return Some((
+                ApkFailure::VersionDowngrade,
+                format!(
+                    "version code {} of {} is older than the installed {}",
+                    self.version_code, self.package, installed.version_code
+                ),
+            ));
+        }
+
+        None
+    }
+
+    /// Whether installing over `installed` needs `pm install -d`
+    pub fn is_downgrade(&self, installed: &InstalledPackage) -> bool {
+        self.version_code < installed.version_code
+    }
+}
+
+impl InstalledPackage {
+    /// Reads `package` from `dumpsys package` output. `None` if it isn't installed
+    pub fn from_dumpsys(output: &str, package: &str) -> Option<Self> {
+        let section = &output[output.find(&format!("Package [{}]", package))?..];
+
+        let version_code = section
+            .split_whitespace()
+            .find_map(|token| token.strip_prefix("versionCode="))?
+            .parse()
+            .ok()?;
+
+        // `signatures=PackageSignatures{... signatures:[a1b2c3d4], past signatures:[]}`
+        let signatures = section
+            .find("signatures:[")
+            .and_then(|start| {
+                let list = &section[start + "signatures:[".len()..];
+                list.find(']').map(|end| &list[..end])
+            })
+            .map(|list| {
+                list.split(',')
+                    .map(|signature| signature.trim().to_string())
+                    .filter(|signature| !signature.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            version_code,
+            signatures,
+        })
+    }
+}
+
+/// The reason reported for an APK whose installation was predicted to fail
+pub fn preflight_error(apk: String, failure: ApkFailure, reason: String) -> InstallerError {
+    InstallerError::ApkInstallation {
+        apk,
+        failure,
+        reason: format!("{} (checked before installing)", reason),
+    }
+}
+
+/// `apks` grouped for installation, keeping their order: each base APK with the split APKs of
+/// its package. Splits without a base come last, grouped by package, and APKs that can't be
+/// read are left on their own
+pub fn group_splits(apks: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let packages: Vec<_> = apks
+        .iter()
+        .map(|apk| {
+            let info = ApkInfo::read(apk).ok()?;
+            let split = info.split.is_some();
+            Some((info.package, split))
+        })
+        .collect();
+    group_by_package(apks, &packages)
+}
+
+/// `group_splits` for APKs whose package, and whether they're a split, is already known
+fn group_by_package(apks: &[PathBuf], packages: &[Option<(String, bool)>]) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<(Option<&str>, Vec<PathBuf>)> = Vec::new();
+    for (apk, package) in apks.iter().zip(packages) {
+        match package {
+            Some((_, true)) => {}
+            package => groups.push((
+                package.as_ref().map(|(package, _)| package.as_str()),
+                vec![apk.clone()],
+            )),
+        }
+    }
+    for (apk, package) in apks.iter().zip(packages) {
+        let Some((package, true)) = package else {
+            continue;
+        };
+        let package = Some(package.as_str());
+        match groups.iter_mut().find(|(base, _)| *base == package) {
+            Some((_, group)) => group.push(apk.clone()),
+            None => groups.push((package, vec![apk.clone()])),
+        }
+    }
+
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Reads the `<manifest>` element's package and version from binary XML
+fn parse_manifest(data: &[u8]) -> Option<ApkInfo> {
+    let mut strings = Vec::new();
+    let mut resource_ids = Vec::new();
+    let mut offset = u16_at(data, 2)? as usize;
+
+    while offset < data.len() {
+        let chunk_type = u16_at(data, offset)?;
+        let header_size = u16_at(data, offset + 2)? as usize;
+        let chunk_size = u32_at(data, offset + 4)? as usize;
+        let chunk = data.get(offset..offset + chunk_size)?;
+
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = parse_string_pool(chunk)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = chunk
+                    .get(header_size..)?
+                    .chunks_exact(4)
+                    .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+                    .collect();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                return parse_manifest_element(chunk, header_size, &strings, &resource_ids);
+            }
+            _ => {}
+        }
+
+        if chunk_size == 0 {
+            return None;
+        }
+        offset += chunk_size;
+    }
+
+    None
+}
+
+fn parse_manifest_element(
+    chunk: &[u8],
+    header_size: usize,
+    strings: &[String],
+    resource_ids: &[u32],
+) -> Option<ApkInfo> {
+    let string = |index: u32| strings.get(index as usize).cloned();
+    if string(u32_at(chunk, header_size + 4)?)? != "manifest" {
+        return None;
+    }
+
+    let attribute_start = u16_at(chunk, header_size + 8)? as usize;
+    let attribute_size = u16_at(chunk, header_size + 10)? as usize;
+    let attribute_count = u16_at(chunk, header_size + 12)? as usize;
+
+    let mut package = None;
+    let mut version_code = 0u64;
+    let mut version_code_major = 0u64;
+    let mut version_name = None;
+    let mut split = None;
+
+    for index in 0..attribute_count {
+        let attribute = header_size + attribute_start + index * attribute_size;
+        let name_index = u32_at(chunk, attribute + 4)?;
+        let raw_value = u32_at(chunk, attribute + 8)?;
+        let data_type = *chunk.get(attribute + 15)?;
+        let value = u32_at(chunk, attribute + 16)?;
+
+        let name = string(name_index).unwrap_or_default();
+        let resource_id = resource_ids.get(name_index as usize).copied();
+        let string_value = || match data_type {
+            TYPE_STRING => string(value),
+            _ => string(raw_value),
+        };
+        let int_value = || match data_type {
+            TYPE_INT_DEC | TYPE_INT_HEX => Some(value as u64),
+            _ => string_value()?.parse().ok(),
+        };
+
+        if name == "package" {
+            package = string_value();
+        } else if name == "split" {
+            split = string_value();
+        } else if name == "versionCode" || resource_id == Some(VERSION_CODE_ATTR) {
+            version_code = int_value().unwrap_or_default();
+        } else if name == "versionCodeMajor" || resource_id == Some(VERSION_CODE_MAJOR_ATTR) {
+            version_code_major = int_value().unwrap_or_default();
+        } else if name == "versionName" || resource_id == Some(VERSION_NAME_ATTR) {
+            // References to string resources aren't resolved
+            version_name = (data_type == TYPE_STRING).then(string_value).flatten();
+        }
+    }
+
+    Some(ApkInfo {
+        package: package?,
+        version_code: (version_code_major << 32) | version_code,
+        version_name,
+        signature: None,
+        lineage: Vec::new(),
+        split,
+    })
+}
+
+fn parse_string_pool(chunk: &[u8]) -> Option<Vec<String>> {
+    let header_size = u16_at(chunk, 2)? as usize;
+    let count = u32_at(chunk, 8)? as usize;
+    let flags = u32_at(chunk, 16)?;
+    let strings_start = u32_at(chunk, 20)? as usize;
+    let utf8 = flags & UTF8_FLAG != 0;
+
+    (0..count)
+        .map(|index| {
+            let offset = strings_start + u32_at(chunk, header_size + index * 4)? as usize;
+            if utf8 {
+                read_utf8(chunk, offset)
+            } else {
+                read_utf16(chunk, offset)
+            }
+        })
+        .collect()
+}
+
+/// A UTF-8 pool string: its UTF-16 length, its byte length, then the bytes
+fn read_utf8(chunk: &[u8], offset: usize) -> Option<String> {
+    let utf16_length_size = if chunk.get(offset)? & 0x80 != 0 { 2 } else { 1 };
+    let mut offset = offset + utf16_length_size;
+
+    let mut length = *chunk.get(offset)? as usize;
+    if length & 0x80 != 0 {
+        length = ((length & 0x7f) << 8) | *chunk.get(offset + 1)? as usize;
+        offset += 2;
+    } else {
+        offset += 1;
+    }
+
+    Some(String::from_utf8_lossy(chunk.get(offset..offset + length)?).into_owned())
+}
+
+/// A UTF-16 pool string: its length in code units, then the code units
+fn read_utf16(chunk: &[u8], offset: usize) -> Option<String> {
+    let mut length = u16_at(chunk, offset)? as usize;
+    let mut offset = offset + 2;
+    if length & 0x8000 != 0 {
+        length = ((length & 0x7fff) << 16) | u16_at(chunk, offset)? as usize;
+        offset += 2;
+    }
+
+    let units: Vec<u16> = chunk
+        .get(offset..offset + length * 2)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// The first signer's certificate from the APK Signing Block, preferring the v3 scheme, which
+/// reflects key rotation, along with the certificates of its rotation lineage
+fn signing_certificates<R: Read + Seek>(file: &mut R) -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+    let length = file.seek(SeekFrom::End(0)).ok()?;
+    let tail_length = length.min(EOCD_SIZE + u16::MAX as u64);
+    let mut tail = vec![0; tail_length as usize];
+    file.seek(SeekFrom::Start(length - tail_length)).ok()?;
+    file.read_exact(&mut tail).ok()?;
+
+    let eocd = (0..=tail.len().checked_sub(EOCD_SIZE as usize)?)
+        .rev()
+        .find(|&offset| u32_at(&tail, offset) == Some(EOCD_SIGNATURE))?;
+    let central_directory = u32_at(&tail, eocd + 16)? as u64;
+
+    // The block ends with its size and magic, right before the central directory
+    let mut footer = [0; 24];
+    file.seek(SeekFrom::Start(central_directory.checked_sub(24)?))
+        .ok()?;
+    file.read_exact(&mut footer).ok()?;
+    if &footer[8..] != SIGNING_BLOCK_MAGIC {
+        return None;
+    }
+    // The size leaves out the leading copy of itself. Checking that the block fits before the
+    // central directory bounds the allocation by the file's size
+    let block_size = u64_at(&footer, 0)?;
+    let block_start = central_directory.checked_sub(block_size.checked_add(8)?)?;
+
+    let mut block = vec![0; usize::try_from(block_size).ok()?.checked_sub(24)?];
+    file.seek(SeekFrom::Start(block_start + 8)).ok()?;
+    file.read_exact(&mut block).ok()?;
+
+    let mut schemes = Vec::new();
+    let mut offset = 0;
+    while offset + 12 <= block.len() {
+        let pair_length = usize::try_from(u64_at(&block, offset)?).ok()?;
+        let end = offset.checked_add(8)?.checked_add(pair_length)?;
+        let id = u32_at(&block, offset + 8)?;
+        let value = block.get(offset + 12..end)?;
+        schemes.push((id, value));
+        offset = end;
+    }
+    let scheme = |wanted: u32| {
+        schemes
+            .iter()
+            .find(|(id, _)| *id == wanted)
+            .map(|(_, value)| *value)
+    };
+
+    if let Some(signed_data) = scheme(SIGNATURE_SCHEME_V3_ID).and_then(first_signed_data) {
+        if let Some(certificate) = first_certificate(signed_data) {
+            let lineage = rotation_lineage(signed_data).unwrap_or_default();
+            return Some((certificate, lineage));
+        }
+    }
+    let signed_data = first_signed_data(scheme(SIGNATURE_SCHEME_V2_ID)?)?;
+    Some((first_certificate(signed_data)?, Vec::new()))
+}
+
+/// A length prefixed value within a signature scheme block, and the offset after it
+fn length_prefixed(data: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let length = u32_at(data, offset)? as usize;
+    let start = offset.checked_add(4)?;
+    let end = start.checked_add(length)?;
+    Some((data.get(start..end)?, end))
+}
+
+/// The signed data of the first signer in a v2 or v3 signature scheme block
+fn first_signed_data(scheme: &[u8]) -> Option<&[u8]> {
+    let (signers, _) = length_prefixed(scheme, 0)?;
+    let (signer, _) = length_prefixed(signers, 0)?;
+    let (signed_data, _) = length_prefixed(signer, 0)?;
+    Some(signed_data)
+}
+
+/// The first certificate in a signer's signed data
+fn first_certificate(signed_data: &[u8]) -> Option<Vec<u8>> {
+    let (_digests, next) = length_prefixed(signed_data, 0)?;
+    let (certificates, _) = length_prefixed(signed_data, next)?;
+    let (certificate, _) = length_prefixed(certificates, 0)?;
+    Some(certificate.to_vec())
+}
+
+/// Certificates of the proof-of-rotation attribute in v3 signed data, oldest first. Empty when
+/// the signing key was never rotated
+fn rotation_lineage(signed_data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let (_digests, next) = length_prefixed(signed_data, 0)?;
+    let (_certificates, next) = length_prefixed(signed_data, next)?;
+    // Skips the minimum and maximum SDK versions
+    let (attributes, _) = length_prefixed(signed_data, next.checked_add(8)?)?;
+
+    let mut offset = 0;
+    while offset < attributes.len() {
+        let (attribute, next) = length_prefixed(attributes, offset)?;
+        if u32_at(attribute, 0)? == PROOF_OF_ROTATION_ATTR_ID {
+            // Each node is its signed certificate and algorithm, then flags and a signature
+            let (nodes, _) = length_prefixed(attribute, 8)?;
+            let mut lineage = Vec::new();
+            let mut offset = 0;
+            while offset < nodes.len() {
+                let (node, next) = length_prefixed(nodes, offset)?;
+                let (node_signed_data, _) = length_prefixed(node, 0)?;
+                let (certificate, _) = length_prefixed(node_signed_data, 0)?;
+                lineage.push(certificate.to_vec());
+                offset = next;
+            }
+            return Some(lineage);
+        }
+        offset = next;
+    }
+    Some(Vec::new())
+}
+
+/// Android's `Signature.hashCode()` of a certificate, as printed by `dumpsys package`
+fn signature_hash(certificate: &[u8]) -> String {
+    let hash = certificate.iter().fold(1i32, |hash, &byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as i8 as i32)
+    });
+    format!("{:x}", hash as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn base(package: &str) -> Option<(String, bool)> {
+        Some((package.to_string(), false))
+    }
+
+    fn split(package: &str) -> Option<(String, bool)> {
+        Some((package.to_string(), true))
+    }
+
+    #[test]
+    fn groups_splits_with_their_base() {
+        let apks = paths(&["a.apk", "b.apk", "a-arm64.apk", "a-en.apk"]);
+        let packages = [
+            base("com.example.a"),
+            base("com.example.b"),
+            split("com.example.a"),
+            split("com.example.a"),
+        ];
+
+        assert_eq!(
+            group_by_package(&apks, &packages),
+            vec![
+                paths(&["a.apk", "a-arm64.apk", "a-en.apk"]),
+                paths(&["b.apk"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_splits_without_a_base_last() {
+        let apks = paths(&["c-arm64.apk", "a.apk", "c-en.apk"]);
+        let packages = [
+            split("com.example.c"),
+            base("com.example.a"),
+            split("com.example.c"),
+        ];
+
+        assert_eq!(
+            group_by_package(&apks, &packages),
+            vec![paths(&["a.apk"]), paths(&["c-arm64.apk", "c-en.apk"])]
+        );
+    }
+
+    #[test]
+    fn leaves_unreadable_apks_on_their_own() {
+        let apks = paths(&["broken.apk", "other.apk", "a.apk"]);
+        let packages = [None, None, base("com.example.a")];
+
+        assert_eq!(
+            group_by_package(&apks, &packages),
+            vec![
+                paths(&["broken.apk"]),
+                paths(&["other.apk"]),
+                paths(&["a.apk"])
+            ]
+        );
+    }
+
+    fn prefixed(parts: &[&[u8]]) -> Vec<u8> {
+        let value = parts.concat();
+        [(value.len() as u32).to_le_bytes().to_vec(), value].concat()
+    }
+
+    /// A signature scheme block with one signer
+    fn scheme(certificate: &[u8], attributes: &[u8]) -> Vec<u8> {
+        let signed_data = [
+            prefixed(&[]),
+            prefixed(&[&prefixed(&[certificate])]),
+            vec![0; 8],
+            prefixed(&[attributes]),
+        ]
+        .concat();
+        prefixed(&[&prefixed(&[&prefixed(&[&signed_data])])])
+    }
+
+    fn lineage_attribute(certificates: &[&[u8]]) -> Vec<u8> {
+        let nodes: Vec<Vec<u8>> = certificates
+            .iter()
+            .map(|&certificate| {
+                let signed_data = prefixed(&[&prefixed(&[certificate]), &[0; 4]]);
+                prefixed(&[&signed_data, &[0; 8], &prefixed(&[])])
+            })
+            .collect();
+        let nodes: Vec<&[u8]> = nodes.iter().map(Vec::as_slice).collect();
+        prefixed(&[
+            &PROOF_OF_ROTATION_ATTR_ID.to_le_bytes(),
+            &1u32.to_le_bytes(),
+            &prefixed(&nodes),
+        ])
+    }
+
+    /// Zip entries, a signing block with `pairs`, then an empty central directory
+    fn signed_apk(pairs: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        for (id, value) in pairs {
+            block.extend((value.len() as u64 + 4).to_le_bytes());
+            block.extend(id.to_le_bytes());
+            block.extend(value);
+        }
+        let block_size = block.len() as u64 + 24;
+
+        let mut apk = b"local file entries".to_vec();
+        apk.extend(block_size.to_le_bytes());
+        apk.extend(block);
+        apk.extend(block_size.to_le_bytes());
+        apk.extend(SIGNING_BLOCK_MAGIC);
+        let central_directory = apk.len() as u32;
+        apk.extend(EOCD_SIGNATURE.to_le_bytes());
+        apk.extend([0; 12]);
+        apk.extend(central_directory.to_le_bytes());
+        apk.extend([0; 2]);
+        apk
+    }
+
+    fn certificates(apk: Vec<u8>) -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+        signing_certificates(&mut std::io::Cursor::new(apk))
+    }
+
+    #[test]
+    fn reads_the_v2_certificate() {
+        let apk = signed_apk(&[(SIGNATURE_SCHEME_V2_ID, scheme(b"old", &[]))]);
+        assert_eq!(certificates(apk), Some((b"old".to_vec(), vec![])));
+    }
+
+    #[test]
+    fn prefers_the_v3_certificate_and_reads_its_lineage() {
+        let apk = signed_apk(&[
+            (SIGNATURE_SCHEME_V2_ID, scheme(b"old", &[])),
+            (
+                SIGNATURE_SCHEME_V3_ID,
+                scheme(b"new", &lineage_attribute(&[b"old", b"new"])),
+            ),
+        ]);
+        assert_eq!(
+            certificates(apk),
+            Some((b"new".to_vec(), vec![b"old".to_vec(), b"new".to_vec()]))
+        );
+    }
+
+    #[test]
+    fn rejects_sizes_past_the_file() {
+        let apk = signed_apk(&[(SIGNATURE_SCHEME_V2_ID, scheme(b"old", &[]))]);
+        let footer = apk.len() - EOCD_SIZE as usize - 24;
+        for block_size in [u64::MAX, 1 << 40] {
+            let mut apk = apk.clone();
+            apk[footer..footer + 8].copy_from_slice(&block_size.to_le_bytes());
+            assert_eq!(certificates(apk), None);
+        }
+
+        // The first pair's length, right after the block's leading size
+        let pair = b"local file entries".len() + 8;
+        let mut apk = apk;
+        apk[pair..pair + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(certificates(apk), None);
+    }
+
+    #[test]
+    fn accepts_installed_signatures_from_the_lineage() {
+        let apk = ApkInfo {
+            package: "com.example.a".to_string(),
+            version_code: 2,
+            version_name: None,
+            signature: Some("b".to_string()),
+            lineage: vec!["a".to_string(), "b".to_string()],
+            split: None,
+        };
+        let installed = |signature: &str| InstalledPackage {
+            version_code: 1,
+            signatures: vec![signature.to_string()],
+        };
+
+        assert_eq!(apk.conflict(&installed("a"), false), None);
+        assert!(matches!(
+            apk.conflict(&installed("c"), false),
+            Some((ApkFailure::SignatureMismatch, _))
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::adb::{quote, AdbManager};
+use crate::platform::Platform;
+use crate::{task, InstallerError, Result, ResultExt};
+
+const MANIFEST_NAME: &str = "manifest.json";
+/// Archive directory holding the pulled files, laid out by their device paths
+const FILES_DIR: &str = "files";
+
+/// Describes the contents of a data backup archive
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BackupManifest {
+    pub installer_version: String,
+    pub config: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    /// Device paths of the backed up files
+    pub files: Vec<String>,
+}
+
+/// Default location for data backups
+pub fn backup_dir() -> PathBuf {
+    Platform::state_dir().join("backups")
+}
+
+/// Pulls every file under `paths` from the device into a timestamped `.tar.gz` archive in
+/// `output_dir`. Paths missing from the device are skipped
+pub async fn backup_data(
+    adb: &mut AdbManager,
+    config_name: &str,
+    paths: &[String],
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let staging = Platform::temp_dir();
+    let result = backup_into(adb, config_name, paths, output_dir, &staging).await;
+    let _ = fs::remove_dir_all(&staging);
+    let (archive, files) = result?;
+
+    info!("Backed up {} files to {}", files, archive.display());
+    Ok(archive)
+}
+
+async fn backup_into(
+    adb: &mut AdbManager,
+    config_name: &str,
+    paths: &[String],
+    output_dir: &Path,
+    staging: &Path,
+) -> Result<(PathBuf, usize)> {
+    let files = pull_files(adb, paths, staging).await?;
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let manifest = BackupManifest {
+        installer_version: env!("CARGO_PKG_VERSION").to_string(),
+        config: config_name.to_string(),
+        created_at,
+        files,
+    };
+
+    fs::create_dir_all(output_dir)?;
+    let (archive, file) = create_archive(output_dir, created_at)?;
+    let staging = staging.to_path_buf();
+    let written = task::spawn_blocking(move || write_archive(&manifest, &staging, file)).await?;
+    match written {
+        Ok(files) => Ok((archive, files)),
+        Err(e) => {
+            let _ = fs::remove_file(&archive);
+            Err(e)
+        }
+    }
+}
+
+/// Creates a new archive readable only by the current user, named for `created_at`. Backups made
+/// within the same second are numbered rather than replacing each other
+fn create_archive(output_dir: &Path, created_at: u64) -> Result<(PathBuf, File)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut attempt = 0;
+    loop {
+        let name = match attempt {
+            0 => format!("penumbra_data_backup_{created_at}.tar.gz"),
+            n => format!("penumbra_data_backup_{created_at}_{n}.tar.gz"),
+        };
+        let path = output_dir.join(name);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Pushes every file in a backup archive back to its original device path. Every file must be
+/// within one of `data_paths`, so an archive can't write elsewhere on the device
+pub async fn restore_data(
+    adb: &mut AdbManager,
+    archive: &Path,
+    data_paths: &[String],
+) -> Result<BackupManifest> {
+    let staging = Platform::temp_dir();
+    let result = restore_from(adb, archive, data_paths, &staging).await;
+    let _ = fs::remove_dir_all(&staging);
+    let manifest = result?;
+
+    info!(
+        "Restored {} files from {}",
+        manifest.files.len(),
+        archive.display()
+    );
+    Ok(manifest)
+}
+
+async fn restore_from(
+    adb: &mut AdbManager,
+    archive: &Path,
+    data_paths: &[String],
+    staging: &Path,
+) -> Result<BackupManifest> {
+    let source = archive.to_path_buf();
+    let destination = staging.to_path_buf();
+    let manifest = task::spawn_blocking(move || read_archive(&source, &destination))
+        .await?
+        .with_context(|| format!("reading backup {}", archive.display()))?;
+
+    let outside: Vec<&str> = manifest
+        .files
+        .iter()
+        .filter(|remote| !is_within(remote, data_paths))
+        .map(String::as_str)
+        .collect();
+    if !outside.is_empty() {
+        return Err(InstallerError::Config(format!(
+            "{} lists files outside the config's data paths: {}",
+            archive.display(),
+            outside.join(", ")
+        )));
+    }
+
+    for remote in &manifest.files {
+        let local = staging_path(staging, remote);
+        if !local.exists() {
+            warn!("{} is listed in the backup but missing from it", remote);
+            continue;
+        }
+
+        if let Some((parent, _)) = remote.rsplit_once('/') {
+            if !parent.is_empty() {
+                adb.create_directory(parent).await?;
+            }
+        }
+        info!("Restoring {}", remote);
+        adb.push_file(&local, remote).await?;
+    }
+
+    Ok(manifest)
+}
+
+/// Pulls the files under `paths` into `staging`, returning the device paths that were pulled
+async fn pull_files(adb: &mut AdbManager, paths: &[String], staging: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if adb.directory_exists(path).await? {
+            let output = adb.shell(&format!("find {} -type f", quote(path))).await?;
+            files.extend(
+                output
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty()),
+            );
+        } else if adb.file_exists(path).await? {
+            files.push(path.clone());
+        } else {
+            info!("Skipping {}, as it isn't on the device", path);
+        }
+    }
+
+    files.sort();
+    files.dedup();
+
+    for remote in &files {
+        info!("Backing up {}", remote);
+        adb.pull_file(remote, &staging_path(staging, remote))
+            .await?;
+    }
+
+    Ok(files)
+}
+
+/// Whether the device path `remote` is one of `data_paths` or inside one. Relative paths and
+/// `..` components are never within
+fn is_within(remote: &str, data_paths: &[String]) -> bool {
+    if !remote.starts_with('/') || remote.split('/').any(|part| part == "..") {
+        return false;
+    }
+    data_paths.iter().any(|path| {
+        let path = path.trim_end_matches('/');
+        !path.is_empty()
+            && (remote == path
+                || remote
+                    .strip_prefix(path)
+                    .is_some_and(|rest| rest.starts_with('/')))
+    })
+}
+
+fn staging_path(staging: &Path, remote: &str) -> PathBuf {
+    staging.join(FILES_DIR).join(remote.trim_start_matches('/'))
+}
+
+/// Writes the manifest and staged files to a gzipped tarball, returning the number of files
+fn write_archive(manifest: &BackupManifest, staging: &Path, file: File) -> Result<usize> {
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let manifest_json = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_at);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+
+    let files_dir = staging.join(FILES_DIR);
+    if files_dir.exists() {
+        builder.append_dir_all(FILES_DIR, &files_dir)?;
+    }
+
+    builder.into_inner()?.finish()?;
+    Ok(manifest.files.len())
+}
+
+fn read_archive(archive: &Path, staging: &Path) -> Result<BackupManifest> {
+    let file = File::open(archive)?;
+    fs::create_dir_all(staging)?;
+    // `unpack` refuses entries that would escape `staging`
+    tar::Archive::new(GzDecoder::new(file)).unpack(staging)?;
+
+    let manifest = fs::read(staging.join(MANIFEST_NAME)).map_err(|_| {
+        InstallerError::Config(format!(
+            "{} is not a PenumbraOS data backup",
+            archive.display()
+        ))
+    })?;
+    Ok(serde_json::from_slice(&manifest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_only_within_data_paths() {
+        let data_paths = vec![
+            "/sdcard/penumbra/etc/".to_string(),
+            "/data/local/tmp/settings.json".to_string(),
+        ];
+        assert!(is_within("/sdcard/penumbra/etc/a.json", &data_paths));
+        assert!(is_within("/data/local/tmp/settings.json", &data_paths));
+
+        assert!(!is_within("/sdcard/penumbra/etcetera", &data_paths));
+        assert!(!is_within("/sdcard/penumbra/etc/../../x", &data_paths));
+        assert!(!is_within("sdcard/penumbra/etc/a.json", &data_paths));
+        assert!(!is_within("/data/local/tmp/settings.json.bak", &data_paths));
+        assert!(!is_within("/system/bin/sh", &data_paths));
+    }
+
+    #[test]
+    fn numbers_archives_made_in_the_same_second() {
+        let dir = std::env::temp_dir().join(format!("penumbra-backup-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let (first, _) = create_archive(&dir, 1_700_000_000).unwrap();
+        let (second, _) = create_archive(&dir, 1_700_000_000).unwrap();
+        assert_eq!(first, dir.join("penumbra_data_backup_1700000000.tar.gz"));
+        assert_eq!(second, dir.join("penumbra_data_backup_1700000000_1.tar.gz"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
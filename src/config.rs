@@ -1,7 +1,36 @@
-use crate::{InstallerError, Result};
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use crate::notify::NotifyConfig;
+use crate::overrides::RepositoryOverrides;
+use crate::platform::Platform;
+use crate::redact::REDACTED;
+use crate::version::{Fixed, LatestRelease, LatestTag, SemverRange, VersionResolver};
+use crate::{InstallerError, Result, ResultExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Path that stands for the built-in config when layering configs
+pub const BUILTIN_CONFIG_LAYER: &str = "builtin";
+
+const BUILTIN_CONFIG: &str = include_str!("../configs/penumbra.yml");
+
+/// Largest remote config accepted
+const MAX_REMOTE_CONFIG_BYTES: u64 = 1024 * 1024;
+
+/// Content types accepted for remote configs: YAML, JSON (which is also YAML) and plain text.
+/// Anything else, such as the HTML of a login page, is rejected before parsing
+const REMOTE_CONFIG_CONTENT_TYPES: [&str; 6] = [
+    "application/yaml",
+    "application/x-yaml",
+    "text/yaml",
+    "text/x-yaml",
+    "text/plain",
+    "application/json",
+];
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InstallConfig {
@@ -9,6 +38,193 @@ pub struct InstallConfig {
     repositories: Vec<Repository>,
     #[serde(default)]
     pub global_setup: Vec<InstallStep>,
+    #[serde(default)]
+    pub firmware_compatibility: Vec<FirmwareCompatibility>,
+    #[serde(default)]
+    pub reset: ResetConfig,
+    #[serde(default)]
+    pub clock: ClockCheck,
+    #[serde(default)]
+    pub battery: BatteryCheck,
+    #[serde(default)]
+    pub reboot: RebootWait,
+    /// Command and webhook told when an installation finishes
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub transfer: TransferConfig,
+    /// Set for configs shipped with the installer, which only target the Ai Pin
+    #[serde(skip)]
+    builtin: bool,
+    /// Repositories the user disabled, left out unless explicitly selected
+    #[serde(skip)]
+    disabled: BTreeSet<String>,
+    /// Repositories the user held at a version
+    #[serde(skip)]
+    held: BTreeSet<String>,
+    /// Where the config was loaded from, recorded in install reports
+    #[serde(skip)]
+    source: Option<ConfigSource>,
+}
+
+/// Where a config was loaded from, and a hash of its text
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConfigSource {
+    /// URL, file path, or `builtin`. Layered configs list each layer, joined by ` + `
+    pub location: String,
+    /// SHA-256 of the config text, or of each layer's text in order
+    pub sha256: String,
+}
+
+impl ConfigSource {
+    fn new<'a>(location: String, contents: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut hasher = Sha256::new();
+        for text in contents {
+            hasher.update(text.as_bytes());
+        }
+        Self {
+            location,
+            sha256: format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// How a factory reset returns the device to stock, beyond each repository's cleanup steps
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResetConfig {
+    /// Stock packages disabled during installation
+    #[serde(default)]
+    pub enable_packages: Vec<String>,
+    /// Stock home activity to restore, as `package/activity`
+    #[serde(default)]
+    pub launcher: Option<String>,
+    /// Device paths to remove, in addition to those created by `CreateDirectories` and
+    /// `CreateConfig` steps
+    #[serde(default)]
+    pub remove_paths: Vec<String>,
+}
+
+/// Checks the device clock before installing. HTTPS from the device, such as to OpenAI or
+/// SearXNG, fails certificate validation when it is far off
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClockCheck {
+    /// Largest difference from the host clock allowed before warning, in seconds
+    #[serde(default = "default_max_skew_secs")]
+    pub max_skew_secs: u64,
+    /// Steps that correct the device clock, offered when it is off
+    #[serde(default)]
+    pub sync: Vec<InstallStep>,
+}
+
+impl Default for ClockCheck {
+    fn default() -> Self {
+        Self {
+            max_skew_secs: default_max_skew_secs(),
+            sync: Vec::new(),
+        }
+    }
+}
+
+/// Certificates are commonly only valid from an hour before they were issued
+fn default_max_skew_secs() -> u64 {
+    60 * 60
+}
+
+/// Checks the device battery before installing, as a device that dies mid-install can be left
+/// with a partial installation
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatteryCheck {
+    /// Lowest battery percentage an install starts at. `0` disables the check
+    #[serde(default = "default_min_battery_level")]
+    pub min_level: u8,
+}
+
+impl Default for BatteryCheck {
+    fn default() -> Self {
+        Self {
+            min_level: default_min_battery_level(),
+        }
+    }
+}
+
+fn default_min_battery_level() -> u8 {
+    30
+}
+
+/// What to do after a repository's `reboot_after_completion` reboots the device
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RebootWait {
+    /// Wait for the device to finish booting and reconnect to it
+    #[serde(default)]
+    pub wait: bool,
+    /// Longest time to wait for the device to boot, in seconds
+    #[serde(default = "default_boot_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Steps run once the device has booted, to check that the installation came up
+    #[serde(default)]
+    pub verify: Vec<InstallStep>,
+}
+
+impl Default for RebootWait {
+    fn default() -> Self {
+        Self {
+            wait: false,
+            timeout_secs: default_boot_timeout_secs(),
+            verify: Vec::new(),
+        }
+    }
+}
+
+/// How files are sent to the device
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TransferConfig {
+    /// Compress pushes of large files, and unpack them with `zstd` or `gzip` on the device.
+    /// Pushes are sent as is when the device has neither
+    #[serde(default)]
+    pub compress: bool,
+}
+
+fn default_boot_timeout_secs() -> u64 {
+    5 * 60
+}
+
+/// Device state that steps of the selected repositories would write with differing content
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StepConflict {
+    /// Device path, or `launcher` for `SetLauncher` steps
+    pub target: String,
+    /// Repositories writing it, or `global` for the global setup
+    pub repositories: Vec<String>,
+}
+
+/// Component versions known to work on a device firmware build
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FirmwareCompatibility {
+    /// Device build fingerprint (`ro.build.fingerprint`). `*` matches any run of characters
+    pub fingerprint: String,
+    /// Allowed version patterns per repository name. Unlisted repositories are unrestricted
+    pub versions: BTreeMap<String, Vec<String>>,
+    /// Refuse to install other versions, rather than only warning
+    #[serde(default)]
+    pub block: bool,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+impl FirmwareCompatibility {
+    /// Whether this rule applies to `fingerprint` and doesn't allow `version` of `repo_name`
+    pub fn forbids(&self, fingerprint: &str, repo_name: &str, version: &str) -> bool {
+        let Some(allowed) = self.versions.get(repo_name) else {
+            return false;
+        };
+
+        matches_glob(&self.fingerprint, fingerprint)
+            && !allowed.iter().any(|pattern| matches_glob(pattern, version))
+    }
+}
+
+fn matches_glob(pattern: &str, value: &str) -> bool {
+    glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(value))
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -22,6 +238,17 @@ pub struct ConfigVariable {
     pub default: Option<String>,
 }
 
+/// A repository's variable and the steps using it, for documenting what a config expects
+#[derive(Debug, Clone, Serialize)]
+pub struct VariableUsage {
+    pub repository: String,
+    #[serde(flatten)]
+    pub variable: ConfigVariable,
+    /// Steps with a `{{ name }}` placeholder for the variable, such as `installation step 2
+    /// (CreateConfig)`
+    pub references: Vec<String>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Repository {
     pub name: String,
@@ -39,19 +266,211 @@ pub struct Repository {
     #[serde(default)]
     pub reboot_after_completion: bool,
 
+    /// Other repositories stop working without this one, such as the init daemon, so it is only
+    /// uninstalled when forced
+    #[serde(default)]
+    pub protected: bool,
+
+    /// Repositories with a higher priority install first, such as services that later
+    /// repositories rely on. Equal priorities keep their config order
+    #[serde(default)]
+    pub priority: i32,
+
     #[serde(default)]
     pub cleanup: Vec<CleanupStep>,
     #[serde(rename = "releaseAssets")]
-    pub release_assets: Vec<String>,
+    pub release_assets: Vec<AssetPattern>,
     #[serde(default, rename = "repoFiles")]
-    pub repo_files: Vec<String>,
+    pub repo_files: Vec<RepoFilePattern>,
+    /// Changes made to downloaded release assets before any installation step sees them
+    #[serde(default, rename = "assetTransforms")]
+    pub asset_transforms: Vec<AssetTransforms>,
     pub installation: Vec<InstallStep>,
+
+    /// Device files or directories holding user data, saved by data backups along with the
+    /// repository's `CreateConfig` files
+    #[serde(default, rename = "dataPaths")]
+    pub data_paths: Vec<String>,
+}
+
+/// A `releaseAssets` entry: an asset name pattern, optionally only for devices supporting an ABI
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AssetPattern {
+    Universal(String),
+    Abi { pattern: String, abi: String },
+}
+
+impl AssetPattern {
+    pub fn pattern(&self) -> &str {
+        match self {
+            AssetPattern::Universal(pattern) | AssetPattern::Abi { pattern, .. } => pattern,
+        }
+    }
+
+    pub fn abi(&self) -> Option<&str> {
+        match self {
+            AssetPattern::Universal(_) => None,
+            AssetPattern::Abi { abi, .. } => Some(abi),
+        }
+    }
+}
+
+impl fmt::Display for AssetPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetPattern::Universal(pattern) => write!(f, "{}", pattern),
+            AssetPattern::Abi { pattern, abi } => write!(f, "{} ({})", pattern, abi),
+        }
+    }
 }
 
+/// A `repoFiles` entry: a repository path, or a glob where `**` matches any number of
+/// directories. Glob matches are saved directly in the asset directory unless `preservePaths` is
+/// set, which keeps each file's path below the glob's first wildcard directory
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
+pub enum RepoFilePattern {
+    Flat(String),
+    Tree {
+        pattern: String,
+        #[serde(default, rename = "preservePaths")]
+        preserve_paths: bool,
+    },
+}
+
+impl RepoFilePattern {
+    pub fn pattern(&self) -> &str {
+        match self {
+            RepoFilePattern::Flat(pattern) | RepoFilePattern::Tree { pattern, .. } => pattern,
+        }
+    }
+
+    pub fn preserve_paths(&self) -> bool {
+        matches!(
+            self,
+            RepoFilePattern::Tree {
+                preserve_paths: true,
+                ..
+            }
+        )
+    }
+}
+
+impl fmt::Display for RepoFilePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.preserve_paths() {
+            write!(f, "{} (preserving paths)", self.pattern())
+        } else {
+            write!(f, "{}", self.pattern())
+        }
+    }
+}
+
+/// An `assetTransforms` entry: steps run in order on each downloaded release asset whose name
+/// matches the glob `pattern`, each working on the file the previous one left
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AssetTransforms {
+    pub pattern: String,
+    pub steps: Vec<AssetTransform>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum AssetTransform {
+    /// Renames the file within the asset directory
+    Rename { to: String },
+    /// Replaces a zip archive with its entry at `member`, named after the entry or `to`
+    Unzip {
+        member: String,
+        #[serde(default)]
+        to: Option<String>,
+    },
+    /// Replaces a gzip file with its content, named without the `.gz` extension or `to`
+    Gunzip {
+        #[serde(default)]
+        to: Option<String>,
+    },
+    /// Sets the file's permissions to the octal `mode`, such as `755`. Does nothing on Windows
+    Chmod { mode: String },
+}
+
+impl AssetTransform {
+    pub fn describe(&self) -> String {
+        match self {
+            AssetTransform::Rename { to } => format!("Rename to {}", to),
+            AssetTransform::Unzip { member, .. } => format!("Unzip {}", member),
+            AssetTransform::Gunzip { .. } => "Gunzip".to_string(),
+            AssetTransform::Chmod { mode } => format!("Chmod {}", mode),
+        }
+    }
+
+    /// The new file name, which must stay within the asset directory
+    fn target(&self) -> Option<&str> {
+        match self {
+            AssetTransform::Rename { to } => Some(to),
+            AssetTransform::Unzip { to, .. } | AssetTransform::Gunzip { to } => to.as_deref(),
+            AssetTransform::Chmod { .. } => None,
+        }
+    }
+}
+
+/// How a repository's version is chosen: a tag, `latest` for the newest release, or a
+/// `strategy`
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
 pub enum VersionSpec {
     Version(String),
+    Strategy(VersionStrategy),
+}
+
+impl<'de> Deserialize<'de> for VersionSpec {
+    /// Strategies are parsed directly, so a mistyped one is reported rather than as matching no
+    /// variant
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(version) => Ok(VersionSpec::Version(version)),
+            value => VersionStrategy::deserialize(value)
+                .map(VersionSpec::Strategy)
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "strategy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum VersionStrategy {
+    LatestRelease,
+    /// The newest tag, for repositories that don't publish releases
+    LatestTag,
+    /// The highest version within `range`, such as `^1.2`, among the releases or, with `tags`,
+    /// among the tags
+    Semver {
+        range: String,
+        #[serde(default)]
+        tags: bool,
+    },
+    Fixed {
+        version: String,
+    },
+}
+
+impl VersionSpec {
+    /// The resolver picking the version. Fails for an invalid semver range
+    pub fn resolver(&self) -> Result<Box<dyn VersionResolver>> {
+        Ok(match self {
+            VersionSpec::Version(version) if version == "latest" => Box::new(LatestRelease),
+            VersionSpec::Version(version) => Box::new(Fixed(version.clone())),
+            VersionSpec::Strategy(VersionStrategy::LatestRelease) => Box::new(LatestRelease),
+            VersionSpec::Strategy(VersionStrategy::LatestTag) => Box::new(LatestTag),
+            VersionSpec::Strategy(VersionStrategy::Semver { range, tags }) => {
+                Box::new(SemverRange::new(range, *tags)?)
+            }
+            VersionSpec::Strategy(VersionStrategy::Fixed { version }) => {
+                Box::new(Fixed(version.clone()))
+            }
+        })
+    }
 }
 
 impl Default for VersionSpec {
@@ -61,16 +480,28 @@ impl Default for VersionSpec {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum CleanupStep {
-    UninstallPackages { patterns: Vec<String> },
-    RemoveDirectories { paths: Vec<String> },
-    RemoveDirectoriesIfEmpty { paths: Vec<String> },
-    RemoveFiles { paths: Vec<String> },
+    UninstallPackages {
+        patterns: Vec<String>,
+        /// Android user to uninstall for, rather than removing the package entirely
+        #[serde(default)]
+        user: Option<u32>,
+    },
+    RemoveDirectories {
+        paths: Vec<String>,
+    },
+    RemoveDirectoriesIfEmpty {
+        paths: Vec<String>,
+    },
+    RemoveFiles {
+        paths: Vec<String>,
+    },
 }
 
+// Deserialized through the impl below, which picks between the built-in steps and `Custom`
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(tag = "type")]
+#[serde(remote = "Self", tag = "type", deny_unknown_fields)]
 pub enum InstallStep {
     CreateDirectories {
         paths: Vec<String>,
@@ -81,12 +512,28 @@ pub enum InstallStep {
         allow_failures: bool,
         #[serde(default)]
         exclude_patterns: Vec<String>,
+        /// Android user to install for, rather than every user
+        #[serde(default)]
+        user: Option<u32>,
+        /// Installs APKs older than the installed version with `pm install -d` rather than
+        /// failing. Android only allows this for debuggable apps or on debuggable builds
+        #[serde(default)]
+        allow_downgrade: bool,
+        /// Installs split APKs together with the base APK of their package, in one session
+        #[serde(default)]
+        group_splits: bool,
     },
     PushFiles {
         files: Vec<FilePush>,
     },
     GrantPermissions {
         grants: Vec<PermissionGrant>,
+        /// Android user to grant for, rather than the default user
+        #[serde(default)]
+        user: Option<u32>,
+        /// Warns about permissions that can't be granted rather than failing
+        #[serde(default)]
+        ignore_failure: bool,
     },
     SetAppOps {
         ops: Vec<AppOpGrant>,
@@ -104,17 +551,654 @@ pub enum InstallStep {
         content: String,
         #[serde(default)]
         only_if_missing: bool,
+        /// Leaves an existing file alone when its content already matches
+        #[serde(default)]
+        update_if_changed: bool,
+        /// Adds the keys of `content` missing from an existing file, keeping the values already
+        /// there, rather than replacing it
+        #[serde(default)]
+        merge: Option<ConfigFormat>,
+    },
+    /// Pushes pinitd unit files (a pattern in the repository's assets) to pinitd's unit
+    /// directory, optionally enabling them so they start at boot
+    InstallUnit {
+        file: String,
+        #[serde(default)]
+        enable: bool,
+    },
+    /// Sends a command for a unit to the running pinitd
+    ControlService {
+        unit: String,
+        action: ServiceAction,
+        #[serde(default)]
+        ignore_failure: bool,
+    },
+    /// Parallel-safe steps run concurrently, each group member over its own device connection
+    Parallel {
+        steps: Vec<InstallStep>,
     },
+    /// Pauses the install until the user confirms `message`. Unattended installs take `default`,
+    /// and declining aborts the repository's installation
+    Prompt {
+        message: String,
+        #[serde(default)]
+        default: bool,
+    },
+    /// Reboots the device into `target`. System and soft reboots wait for Android to boot
+    /// before the next step; the other targets leave Android, so must be a repository's last step
+    RebootDevice {
+        #[serde(default)]
+        target: RebootTarget,
+    },
+    /// Any other step type, handled by a `StepExecutor` registered with the engine
+    #[serde(skip)]
+    Custom(CustomStep),
+}
+
+impl Serialize for InstallStep {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            InstallStep::Custom(step) => step.serialize(serializer),
+            step => InstallStep::serialize(step, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for InstallStep {
+    /// Built-in step types are parsed strictly, so a mistyped or unknown field is reported rather
+    /// than the step falling through to `Custom`
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let step_type = value
+            .get("type")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or_default();
+
+        if InstallStep::BUILTIN_TYPES.contains(&step_type) {
+            return InstallStep::deserialize(value).map_err(D::Error::custom);
+        }
+        if let Some(builtin) = closest_match(step_type, &InstallStep::BUILTIN_TYPES) {
+            return Err(D::Error::custom(format!(
+                "unknown step type `{}`, did you mean `{}`?",
+                step_type, builtin
+            )));
+        }
+
+        CustomStep::deserialize(value)
+            .map(InstallStep::Custom)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Format of a config file merged by `CreateConfig`
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Parses either format into a YAML value, which keeps the order of keys
+    pub fn parse(&self, content: &str) -> Result<serde_yaml::Value> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_str(content)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+        })
+    }
+
+    pub fn render(&self, value: &serde_yaml::Value) -> Result<String> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(value)?,
+        })
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Enable,
+    Disable,
+}
+
+impl ServiceAction {
+    /// The `pinitd-cli` subcommand performing the action
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        }
+    }
+}
+
+/// Mode the device is rebooted into
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RebootTarget {
+    #[default]
+    System,
+    /// Restarts Android's framework without rebooting the kernel
+    Soft,
+    Recovery,
+    Bootloader,
+    /// Recovery, waiting for an OTA package from `adb sideload`
+    Sideload,
+}
+
+impl RebootTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RebootTarget::System => "system",
+            RebootTarget::Soft => "soft",
+            RebootTarget::Recovery => "recovery",
+            RebootTarget::Bootloader => "bootloader",
+            RebootTarget::Sideload => "sideload",
+        }
+    }
+
+    /// Whether the device comes back to Android, where ADB can continue the install
+    pub fn returns_to_android(&self) -> bool {
+        matches!(self, RebootTarget::System | RebootTarget::Soft)
+    }
 }
 
+/// A step type not built into the installer. Its fields are kept as generic values for the
+/// registered executor to interpret
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CustomStep {
+    #[serde(rename = "type")]
+    pub step_type: String,
+    #[serde(flatten)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Repository {
+    /// Whether any release asset is specific to an ABI
+    pub fn has_abi_assets(&self) -> bool {
+        self.release_assets
+            .iter()
+            .any(|asset| asset.abi().is_some())
+    }
+
+    /// The ABI whose asset variants suit a device supporting `device_abis` (most preferred
+    /// first): the first one this repository has variants for
+    pub fn preferred_abi(&self, device_abis: &[String]) -> Option<&str> {
+        device_abis.iter().find_map(|abi| {
+            self.release_assets
+                .iter()
+                .filter_map(AssetPattern::abi)
+                .find(|asset_abi| asset_abi == abi)
+        })
+    }
+
+    /// Release asset patterns to download, each with the patterns it must not match. Given the
+    /// device's ABIs, only variants for its preferred ABI are included; without them, all are.
+    /// Universal patterns never match ABI-specific assets
+    pub fn asset_selection(&self, device_abis: Option<&[String]>) -> Vec<(&str, Vec<String>)> {
+        let excluded = self.exclusion_patterns();
+        let abi_specific: Vec<String> = self
+            .release_assets
+            .iter()
+            .filter(|asset| asset.abi().is_some())
+            .map(|asset| asset.pattern().to_string())
+            .collect();
+        let preferred = device_abis.and_then(|abis| self.preferred_abi(abis));
+
+        self.release_assets
+            .iter()
+            .filter_map(|asset| match asset.abi() {
+                None => Some((
+                    asset.pattern(),
+                    excluded.iter().chain(&abi_specific).cloned().collect(),
+                )),
+                Some(abi) if device_abis.is_none() || preferred == Some(abi) => {
+                    Some((asset.pattern(), excluded.clone()))
+                }
+                Some(_) => None,
+            })
+            .collect()
+    }
+
+    /// Patterns of the ABI-specific assets that don't suit a device supporting `device_abis`
+    pub fn unsupported_assets(&self, device_abis: &[String]) -> Vec<String> {
+        let preferred = self.preferred_abi(device_abis);
+        self.release_assets
+            .iter()
+            .filter(|asset| asset.abi().is_some() && asset.abi() != preferred)
+            .map(|asset| asset.pattern().to_string())
+            .collect()
+    }
+
+    /// Asset patterns excluded by this repository's `InstallApks` step, which are never downloaded
+    pub fn exclusion_patterns(&self) -> Vec<String> {
+        for step in &self.installation {
+            if let InstallStep::InstallApks {
+                exclude_patterns, ..
+            } = step
+            {
+                return exclude_patterns.clone();
+            }
+        }
+        Vec::new()
+    }
+
+    /// Package name patterns this repository's cleanup uninstalls, which are the packages it
+    /// installs
+    pub fn package_patterns(&self) -> Vec<&str> {
+        self.cleanup
+            .iter()
+            .flat_map(|step| match step {
+                CleanupStep::UninstallPackages { patterns, .. } => patterns.as_slice(),
+                _ => &[],
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Device files and directories this repository writes or removes on uninstall, leaving out
+    /// paths inside another listed path
+    pub fn device_paths(&self) -> Vec<String> {
+        let written = self
+            .installation
+            .iter()
+            .flat_map(InstallStep::flatten)
+            .flat_map(|step| match step {
+                InstallStep::CreateDirectories { paths } => paths.clone(),
+                InstallStep::CreateConfig { path, .. } => vec![path.clone()],
+                InstallStep::PushFiles { files } => files
+                    .iter()
+                    .map(|file| file.remote_location().to_string())
+                    .collect(),
+                _ => Vec::new(),
+            });
+        let removed = self.cleanup.iter().flat_map(|step| match step {
+            CleanupStep::RemoveDirectories { paths }
+            | CleanupStep::RemoveDirectoriesIfEmpty { paths }
+            | CleanupStep::RemoveFiles { paths } => paths.clone(),
+            CleanupStep::UninstallPackages { .. } => Vec::new(),
+        });
+
+        let mut paths: Vec<String> = Vec::new();
+        for path in written
+            .chain(removed)
+            .chain(self.data_paths.iter().cloned())
+        {
+            let path = path.trim_end_matches('/').to_string();
+            if !path.is_empty() && !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+
+        let is_nested = |path: &str| {
+            paths
+                .iter()
+                .any(|parent| path.starts_with(&format!("{}/", parent)))
+        };
+        paths
+            .iter()
+            .filter(|path| !is_nested(path))
+            .cloned()
+            .collect()
+    }
+
+    /// The repository with its variables' defaults, its configs' content and its commands'
+    /// arguments replaced, for files shared with others
+    pub fn redacted(&self) -> Repository {
+        let mut repo = self.clone();
+        for variable in &mut repo.variables {
+            if variable.default.is_some() {
+                variable.default = Some(REDACTED.to_string());
+            }
+        }
+        for step in &mut repo.installation {
+            step.redact();
+        }
+        repo
+    }
+
+    /// Values of the repository's variable defaults, to be redacted from text shared with others
+    pub fn secrets(&self) -> Vec<String> {
+        self.variables
+            .iter()
+            .filter_map(|variable| variable.default.clone())
+            .collect()
+    }
+}
+
+impl CleanupStep {
+    /// Short human readable summary of the step
+    pub fn describe(&self) -> String {
+        match self {
+            CleanupStep::UninstallPackages { patterns, user } => format!(
+                "Uninstall packages: {}{}",
+                patterns.join(", "),
+                for_user(*user)
+            ),
+            CleanupStep::RemoveDirectories { paths } => {
+                format!("Remove directories: {}", paths.join(", "))
+            }
+            CleanupStep::RemoveDirectoriesIfEmpty { paths } => {
+                format!("Remove directories if empty: {}", paths.join(", "))
+            }
+            CleanupStep::RemoveFiles { paths } => format!("Remove files: {}", paths.join(", ")),
+        }
+    }
+}
+
+impl InstallStep {
+    /// Step types handled by the engine itself, which cannot be registered as custom steps
+    pub const BUILTIN_TYPES: [&'static str; 13] = [
+        "CreateDirectories",
+        "InstallApks",
+        "PushFiles",
+        "GrantPermissions",
+        "SetAppOps",
+        "RunCommand",
+        "SetLauncher",
+        "CreateConfig",
+        "InstallUnit",
+        "ControlService",
+        "Parallel",
+        "Prompt",
+        "RebootDevice",
+    ];
+
+    /// The step's `type`, as written in the config
+    pub fn step_type(&self) -> &str {
+        match self {
+            InstallStep::CreateDirectories { .. } => "CreateDirectories",
+            InstallStep::InstallApks { .. } => "InstallApks",
+            InstallStep::PushFiles { .. } => "PushFiles",
+            InstallStep::GrantPermissions { .. } => "GrantPermissions",
+            InstallStep::SetAppOps { .. } => "SetAppOps",
+            InstallStep::RunCommand { .. } => "RunCommand",
+            InstallStep::SetLauncher { .. } => "SetLauncher",
+            InstallStep::CreateConfig { .. } => "CreateConfig",
+            InstallStep::InstallUnit { .. } => "InstallUnit",
+            InstallStep::ControlService { .. } => "ControlService",
+            InstallStep::Parallel { .. } => "Parallel",
+            InstallStep::Prompt { .. } => "Prompt",
+            InstallStep::RebootDevice { .. } => "RebootDevice",
+            InstallStep::Custom(step) => &step.step_type,
+        }
+    }
+
+    /// Whether the step only writes files, and so can run alongside other such steps
+    pub fn is_parallel_safe(&self) -> bool {
+        matches!(
+            self,
+            InstallStep::CreateDirectories { .. }
+                | InstallStep::PushFiles { .. }
+                | InstallStep::CreateConfig { .. }
+                | InstallStep::InstallUnit { .. }
+        )
+    }
+
+    /// Names of the variables the step has `{{ name }}` placeholders for, leaving out those
+    /// `PushFiles` fills in per file
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Ok(value) = serde_json::to_value(self) {
+            collect_placeholders(&value, &mut names);
+        }
+        names.retain(|name| !FILE_PLACEHOLDERS.contains(&name.as_str()));
+        names
+    }
+
+    /// The step with the placeholders for `values` filled in, keeping any others
+    pub fn with_values(&self, values: &HashMap<String, String>) -> Result<InstallStep> {
+        let mut values = values.clone();
+        for name in self.placeholders() {
+            let placeholder = format!("{{{{ {} }}}}", name);
+            values.entry(name).or_insert(placeholder);
+        }
+
+        let mut step = self.clone();
+        substitute_install_step(&mut step, &values)?;
+        Ok(step)
+    }
+
+    /// The steps of a `Parallel` group, or the step itself
+    pub fn flatten(&self) -> &[InstallStep] {
+        match self {
+            InstallStep::Parallel { steps } => steps,
+            step => std::slice::from_ref(step),
+        }
+    }
+
+    /// Short human readable summary of the step, without any potentially sensitive content
+    pub fn describe(&self) -> String {
+        match self {
+            InstallStep::CreateDirectories { paths } => {
+                format!("Create directories: {}", paths.join(", "))
+            }
+            InstallStep::InstallApks {
+                priority_order,
+                user,
+                ..
+            } => {
+                if priority_order.is_empty() {
+                    format!("Install APKs{}", for_user(*user))
+                } else {
+                    format!(
+                        "Install APKs{} (priority: {})",
+                        for_user(*user),
+                        priority_order.join(", ")
+                    )
+                }
+            }
+            InstallStep::PushFiles { files } => format!(
+                "Push files: {}",
+                files
+                    .iter()
+                    .map(|file| format!("{} -> {}", file.local, file.remote))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            InstallStep::GrantPermissions { grants, user, .. } => {
+                format!("Grant {} permission(s){}", grants.len(), for_user(*user))
+            }
+            InstallStep::SetAppOps { ops } => format!("Set {} app op(s)", ops.len()),
+            // Only the program is named, as its arguments may hold tokens or other secrets
+            InstallStep::RunCommand { command, .. } => {
+                match command.trim().split_once(char::is_whitespace) {
+                    Some((program, _)) => format!("Run command: {} ...", program),
+                    None => format!("Run command: {}", command.trim()),
+                }
+            }
+            InstallStep::SetLauncher { component } => format!("Set launcher: {}", component),
+            InstallStep::CreateConfig { path, .. } => format!("Create config: {}", path),
+            InstallStep::InstallUnit { file, enable } => {
+                if *enable {
+                    format!("Install and enable unit: {}", file)
+                } else {
+                    format!("Install unit: {}", file)
+                }
+            }
+            InstallStep::ControlService { unit, action, .. } => {
+                let action = match action {
+                    ServiceAction::Start => "Start",
+                    ServiceAction::Stop => "Stop",
+                    ServiceAction::Restart => "Restart",
+                    ServiceAction::Enable => "Enable",
+                    ServiceAction::Disable => "Disable",
+                };
+                format!("{} service: {}", action, unit)
+            }
+            InstallStep::Parallel { steps } => format!(
+                "In parallel: {}",
+                steps
+                    .iter()
+                    .map(|step| step.describe())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            InstallStep::Prompt { message, .. } => format!("Ask to confirm: {}", message),
+            InstallStep::RebootDevice { target } => match target {
+                RebootTarget::System => "Reboot".to_string(),
+                RebootTarget::Soft => "Soft reboot".to_string(),
+                target => format!("Reboot to {}", target.as_str()),
+            },
+            InstallStep::Custom(step) => format!("Custom step: {}", step.step_type),
+        }
+    }
+
+    /// Replaces the content of the step that may hold secrets
+    pub fn redact(&mut self) {
+        match self {
+            InstallStep::RunCommand { command, .. } => {
+                let program = command.split_whitespace().next().unwrap_or_default();
+                *command = if program.len() < command.trim().len() {
+                    format!("{} {}", program, REDACTED)
+                } else {
+                    program.to_string()
+                };
+            }
+            InstallStep::CreateConfig { content, .. } => *content = REDACTED.to_string(),
+            InstallStep::Parallel { steps } => steps.iter_mut().for_each(InstallStep::redact),
+            InstallStep::Custom(step) => {
+                for value in step.params.values_mut() {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Device state written by `step`, and what it is written with. Pushed files are identified by
+/// their repository, as each repository's assets differ. Paths only known once assets are
+/// downloaded are left out
+fn step_writes(step: &InstallStep, repo_name: &str) -> Vec<(String, String)> {
+    match step {
+        // Merges into a file compose, so only conflict with plain writes
+        InstallStep::CreateConfig {
+            path,
+            merge: Some(_),
+            ..
+        } => vec![(path.clone(), "merged content".to_string())],
+        InstallStep::CreateConfig { path, content, .. } => vec![(path.clone(), content.clone())],
+        InstallStep::PushFiles { files } => files
+            .iter()
+            .filter(|file| !file.remote.contains("{{") && !file.remote.ends_with('/'))
+            .map(|file| {
+                (
+                    file.remote.clone(),
+                    format!("{}: {}", repo_name, file.local),
+                )
+            })
+            .collect(),
+        InstallStep::SetLauncher { component } => {
+            vec![("launcher".to_string(), component.clone())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `step` reboots into a mode other than Android, after which no step can run
+fn leaves_android(step: &InstallStep) -> bool {
+    matches!(step, InstallStep::RebootDevice { target } if !target.returns_to_android())
+}
+
+/// ` for user <id>` when a step targets a specific Android user
+fn for_user(user: Option<u32>) -> String {
+    user.map_or(String::new(), |user| format!(" for user {}", user))
+}
+
+/// Placeholders filled in per file in a `FilePush` remote path
+const FILE_PLACEHOLDERS: [&str; 3] = ["filename", "stem", "path"];
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FilePush {
-    pub local: String,
+    pub local: LocalPatterns,
+    /// Device path. A trailing `/` pushes into the directory under the local file name.
+    /// `{{ filename }}`, `{{ stem }}` (the file name up to its first `.`), and `{{ path }}` (the
+    /// file's path within the assets) are replaced per file
     pub remote: String,
+    /// File name patterns to leave out, even if matched by `local`
+    #[serde(default)]
+    pub exclude: Vec<String>,
     #[serde(default)]
     pub chmod: Option<String>,
 }
 
+/// Glob patterns relative to a repository's assets: one, or a list
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LocalPatterns {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl LocalPatterns {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            LocalPatterns::One(pattern) => std::slice::from_ref(pattern),
+            LocalPatterns::Many(patterns) => patterns,
+        }
+    }
+
+    fn patterns_mut(&mut self) -> &mut [String] {
+        match self {
+            LocalPatterns::One(pattern) => std::slice::from_mut(pattern),
+            LocalPatterns::Many(patterns) => patterns,
+        }
+    }
+}
+
+impl fmt::Display for LocalPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.patterns().join(", "))
+    }
+}
+
+impl FilePush {
+    /// The part of `remote` that is the same for every file: the whole path, or the directory
+    /// before the first placeholder
+    pub fn remote_location(&self) -> &str {
+        match self.remote.find("{{") {
+            Some(start) => self.remote[..start]
+                .rsplit_once('/')
+                .map_or("", |(dir, _)| dir),
+            None => self.remote.trim_end_matches('/'),
+        }
+    }
+
+    /// Device path for the local file at `path`, relative to the assets and `/` separated
+    pub fn remote_path(&self, path: &str) -> Result<String> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let stem = name.split('.').next().unwrap_or(name);
+        let values = HashMap::from([
+            ("filename".to_string(), name.to_string()),
+            ("stem".to_string(), stem.to_string()),
+            ("path".to_string(), path.to_string()),
+        ]);
+
+        let remote = replace_placeholders(&self.remote, &values)?;
+        if remote.ends_with('/') {
+            Ok(format!("{}{}", remote, name))
+        } else {
+            Ok(remote)
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PermissionGrant {
     pub package: String,
@@ -130,12 +1214,132 @@ pub struct AppOpGrant {
 
 pub struct ConfigLoader;
 
+/// Sidecar file read from the directory given to `install-local`
+pub const LOCAL_INSTALL_FILE: &str = "penumbra-local.yml";
+
+/// How to install a host directory of APKs and files, read from its optional sidecar file
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LocalInstall {
+    #[serde(default)]
+    pub priority_order: Vec<String>,
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+    /// As for `InstallApks`
+    #[serde(default)]
+    pub group_splits: bool,
+    #[serde(default)]
+    pub permissions: Vec<PermissionGrant>,
+    #[serde(default)]
+    pub app_ops: Vec<AppOpGrant>,
+    /// Further steps, such as `PushFiles` with paths relative to the directory, run after the
+    /// APKs are installed
+    #[serde(default)]
+    pub steps: Vec<InstallStep>,
+}
+
+impl LocalInstall {
+    /// Reads the sidecar file from `dir`, or the defaults if there isn't one
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCAL_INSTALL_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let local: Self = parse_yaml(&tokio::fs::read_to_string(&path).await?)?;
+        for step in &local.steps {
+            ConfigLoader::validate_step(step, LOCAL_INSTALL_FILE)?;
+        }
+        Ok(local)
+    }
+
+    /// A config with a single repository, `name`, whose assets are already in a directory of
+    /// that name
+    pub fn into_config(self, name: &str) -> InstallConfig {
+        let mut installation = vec![InstallStep::InstallApks {
+            priority_order: self.priority_order,
+            allow_failures: false,
+            exclude_patterns: self.exclude_patterns,
+            user: None,
+            allow_downgrade: false,
+            group_splits: self.group_splits,
+        }];
+        if !self.permissions.is_empty() {
+            installation.push(InstallStep::GrantPermissions {
+                grants: self.permissions,
+                user: None,
+                ignore_failure: false,
+            });
+        }
+        if !self.app_ops.is_empty() {
+            installation.push(InstallStep::SetAppOps { ops: self.app_ops });
+        }
+        installation.extend(self.steps);
+
+        InstallConfig {
+            name: format!("Local ({})", name),
+            repositories: vec![Repository {
+                name: name.to_string(),
+                owner: "local".to_string(),
+                repo: name.to_string(),
+                version: VersionSpec::Version("local".to_string()),
+                variables: Vec::new(),
+                optional: false,
+                reboot_after_completion: false,
+                protected: false,
+                priority: 0,
+                cleanup: Vec::new(),
+                release_assets: Vec::new(),
+                repo_files: Vec::new(),
+                asset_transforms: Vec::new(),
+                installation,
+                data_paths: Vec::new(),
+            }],
+            global_setup: Vec::new(),
+            firmware_compatibility: Vec::new(),
+            reset: ResetConfig::default(),
+            clock: ClockCheck::default(),
+            battery: BatteryCheck::default(),
+            reboot: RebootWait::default(),
+            notify: NotifyConfig::default(),
+            transfer: TransferConfig::default(),
+            builtin: false,
+            disabled: BTreeSet::new(),
+            held: BTreeSet::new(),
+            source: None,
+        }
+    }
+}
+
+/// Credentials sent when fetching a remote config
+#[derive(Debug, Clone, Default)]
+pub struct RemoteConfigAuth {
+    pub bearer_token: Option<String>,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl RemoteConfigAuth {
+    /// Parses a `Name: value` header
+    pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue)> {
+        let invalid = || InstallerError::Config(format!("Invalid header '{}'", header));
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+        Ok((name, value))
+    }
+}
+
 impl ConfigLoader {
     pub fn load_builtin(name: &str) -> Result<InstallConfig> {
         match name {
             "penumbra" => {
-                let config_str = include_str!("../configs/penumbra.yml");
-                Self::load_from_str(config_str)
+                let config_str = BUILTIN_CONFIG;
+                let mut config = Self::load_from_str(config_str)?;
+                config.builtin = true;
+                config.source = Some(ConfigSource::new(
+                    BUILTIN_CONFIG_LAYER.to_string(),
+                    [config_str],
+                ));
+                Ok(config)
             }
             _ => Err(InstallerError::Config(format!(
                 "Unknown built-in config: {}",
@@ -146,19 +1350,152 @@ impl ConfigLoader {
 
     pub async fn load_from_file(path: &Path) -> Result<InstallConfig> {
         let config_str = tokio::fs::read_to_string(path).await?;
-        Self::load_from_str(&config_str)
+        let mut config = Self::load_from_str(&config_str)?;
+        config.source = Some(ConfigSource::new(
+            path.display().to_string(),
+            [config_str.as_str()],
+        ));
+        Ok(config)
+    }
+
+    /// Loads configs and merges each over the ones before it, so a file of personal tweaks can
+    /// be layered over a shared config. A path of `builtin` stands for the built-in config.
+    ///
+    /// Repositories are matched by name and their fields merged, other mappings are merged key
+    /// by key, and any other value, including a list of steps, replaces the earlier one. Only
+    /// the merged config has to be complete
+    pub async fn load_layered(paths: &[PathBuf]) -> Result<InstallConfig> {
+        let builtin = |path: &PathBuf| path.as_os_str() == BUILTIN_CONFIG_LAYER;
+        match paths {
+            [] => {
+                return Err(InstallerError::Config(
+                    "No config files given to layer".to_string(),
+                ))
+            }
+            [path] if builtin(path) => return Self::load_builtin("penumbra"),
+            [path] => return Self::load_from_file(path).await,
+            _ => {}
+        }
+
+        let mut merged = serde_yaml::Value::Null;
+        let mut layers = Vec::new();
+        for path in paths {
+            let contents = if builtin(path) {
+                BUILTIN_CONFIG.to_string()
+            } else {
+                tokio::fs::read_to_string(path).await?
+            };
+            let layer = parse_layer(&contents)
+                .with_context(|| format!("reading config {}", path.display()))?;
+            merge_config_layer(&mut merged, layer);
+            layers.push(contents);
+        }
+
+        let mut config: InstallConfig = serde_yaml::from_value(merged).map_err(|e| {
+            InstallerError::Config(format!("{} (in the layered config)", without_location(&e)))
+        })?;
+        Self::validate_config(&config)?;
+        sort_by_priority(&mut config.repositories);
+        config.builtin = builtin(&paths[0]);
+        let location = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" + ");
+        config.source = Some(ConfigSource::new(
+            location,
+            layers.iter().map(String::as_str),
+        ));
+        Ok(config)
     }
 
     pub fn load_from_str(config_str: &str) -> Result<InstallConfig> {
-        let config: InstallConfig = serde_yaml::from_str(config_str)?;
+        let mut config: InstallConfig = parse_yaml(config_str)?;
         Self::validate_config(&config)?;
+        sort_by_priority(&mut config.repositories);
         Ok(config)
     }
 
-    pub async fn load_from_url(url: &str) -> Result<InstallConfig> {
-        let client = reqwest::Client::new();
-        let config_str = client.get(url).send().await?.text().await?;
-        Self::load_from_str(&config_str)
+    pub async fn load_from_url(url: &str) -> Result<InstallConfig> {
+        Self::load_from_url_with_auth(url, &RemoteConfigAuth::default()).await
+    }
+
+    /// Fetches a config that may be behind authentication. The response must be a successful
+    /// YAML, JSON or plain text response of at most 1 MiB. Credentials aren't sent on to other
+    /// hosts the URL redirects to, and the config's `notify.command` is dropped
+    pub async fn load_from_url_with_auth(
+        url: &str,
+        auth: &RemoteConfigAuth,
+    ) -> Result<InstallConfig> {
+        let client = Platform::http_client()?;
+
+        let mut credentials = HeaderMap::new();
+        if let Some(token) = &auth.bearer_token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| InstallerError::Config("Invalid bearer token".to_string()))?;
+            credentials.insert(AUTHORIZATION, value);
+        }
+        for (name, value) in &auth.headers {
+            credentials.append(name, value.clone());
+        }
+
+        let mut response = Platform::get_with_credentials(&client, url, &credentials).await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(InstallerError::Config(format!(
+                "Failed to fetch config from {}: HTTP {}",
+                url, status
+            )));
+        }
+
+        if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
+            let mime = content_type
+                .to_str()
+                .unwrap_or_default()
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            if !REMOTE_CONFIG_CONTENT_TYPES.contains(&mime.as_str()) {
+                return Err(InstallerError::Config(format!(
+                    "Config at {} has unexpected content type '{}'",
+                    url, mime
+                )));
+            }
+        }
+
+        let too_large = || {
+            InstallerError::Config(format!(
+                "Config at {} is larger than {} bytes",
+                url, MAX_REMOTE_CONFIG_BYTES
+            ))
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_REMOTE_CONFIG_BYTES)
+        {
+            return Err(too_large());
+        }
+
+        // The length header is optional, so enforce the limit while reading too
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() as u64 > MAX_REMOTE_CONFIG_BYTES {
+                return Err(too_large());
+            }
+        }
+
+        let config_str = String::from_utf8(body)
+            .map_err(|_| InstallerError::Config(format!("Config at {} is not valid UTF-8", url)))?;
+        let mut config = Self::load_from_str(&config_str)?;
+        // Only a local config or a flag may run commands on this computer
+        if config.notify.command.take().is_some() {
+            warn!("Ignoring the notification command of the config at {}", url);
+        }
+        config.source = Some(ConfigSource::new(url.to_string(), [config_str.as_str()]));
+        Ok(config)
     }
 
     fn validate_config(config: &InstallConfig) -> Result<()> {
@@ -191,6 +1528,37 @@ impl ConfigLoader {
                 )));
             }
 
+            repo.version
+                .resolver()
+                .with_context(|| format!("version of '{}'", repo.name))?;
+
+            for file in &repo.repo_files {
+                glob::Pattern::new(file.pattern()).map_err(|e| {
+                    InstallerError::Config(format!(
+                        "Invalid repo file pattern '{}' in '{}': {}",
+                        file.pattern(),
+                        repo.name,
+                        e
+                    ))
+                })?;
+            }
+
+            for transforms in &repo.asset_transforms {
+                Self::validate_transforms(transforms, &repo.name)?;
+            }
+
+            for step in &repo.installation {
+                Self::validate_step(step, &repo.name)?;
+            }
+
+            let leaving = repo.installation.iter().position(leaves_android);
+            if leaving.is_some_and(|index| index + 1 < repo.installation.len()) {
+                return Err(InstallerError::Config(format!(
+                    "Repository '{}' reboots out of Android before its last step",
+                    repo.name
+                )));
+            }
+
             for variable in &repo.variables {
                 if !variable.required && variable.default.is_none() {
                     return Err(InstallerError::Config(format!(
@@ -201,11 +1569,189 @@ impl ConfigLoader {
             }
         }
 
+        let global_steps = config
+            .global_setup
+            .iter()
+            .chain(&config.clock.sync)
+            .chain(&config.reboot.verify);
+        for step in global_steps {
+            Self::validate_step(step, "global")?;
+            if leaves_android(step) {
+                return Err(InstallerError::Config(format!(
+                    "Global steps can't reboot out of Android: {}",
+                    step.describe()
+                )));
+            }
+        }
+
+        for rule in &config.firmware_compatibility {
+            let patterns =
+                std::iter::once(&rule.fingerprint).chain(rule.versions.values().flatten());
+            for pattern in patterns {
+                glob::Pattern::new(pattern).map_err(|e| {
+                    InstallerError::Config(format!(
+                        "Invalid firmware compatibility pattern '{}': {}",
+                        pattern, e
+                    ))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_step(step: &InstallStep, repo_name: &str) -> Result<()> {
+        for step in step.flatten() {
+            if let InstallStep::CreateConfig {
+                path,
+                only_if_missing: true,
+                update_if_changed,
+                merge,
+                ..
+            } = step
+            {
+                if *update_if_changed || merge.is_some() {
+                    return Err(InstallerError::Config(format!(
+                        "Config {} in repository '{}' can't both be created only if missing and updated",
+                        path, repo_name
+                    )));
+                }
+            }
+
+            if let InstallStep::PushFiles { files } = step {
+                for pattern in files.iter().flat_map(|file| &file.exclude) {
+                    glob::Pattern::new(pattern).map_err(|e| {
+                        InstallerError::Config(format!(
+                            "Invalid exclude pattern '{}' in repository '{}': {}",
+                            pattern, repo_name, e
+                        ))
+                    })?;
+                }
+            }
+        }
+
+        if let InstallStep::Parallel { steps } = step {
+            if let Some(step) = steps.iter().find(|step| !step.is_parallel_safe()) {
+                return Err(InstallerError::Config(format!(
+                    "Parallel group in repository '{}' contains a step that must run on its own: {}",
+                    repo_name,
+                    step.describe()
+                )));
+            }
+        }
+        Ok(())
+    }
+    fn validate_transforms(transforms: &AssetTransforms, repo_name: &str) -> Result<()> {
+        glob::Pattern::new(&transforms.pattern).map_err(|e| {
+            InstallerError::Config(format!(
+                "Invalid asset transform pattern '{}' in '{}': {}",
+                transforms.pattern, repo_name, e
+            ))
+        })?;
+
+        for transform in &transforms.steps {
+            let invalid = match transform {
+                AssetTransform::Chmod { mode } => u32::from_str_radix(mode, 8).is_err(),
+                transform => transform.target().is_some_and(|name| {
+                    matches!(name, "" | "." | "..") || name.contains(['/', '\\'])
+                }),
+            };
+            if invalid {
+                return Err(InstallerError::Config(format!(
+                    "Invalid asset transform for '{}' in '{}': {}",
+                    transforms.pattern,
+                    repo_name,
+                    transform.describe()
+                )));
+            }
+        }
         Ok(())
     }
 }
 
 impl InstallConfig {
+    pub fn is_builtin(&self) -> bool {
+        self.builtin
+    }
+
+    /// Where the config was loaded from. `None` for configs built from a string
+    pub fn source(&self) -> Option<&ConfigSource> {
+        self.source.as_ref()
+    }
+
+    /// The config with its repositories and global steps redacted, as by
+    /// [`Repository::redacted`], and without notifications, whose targets may hold credentials
+    pub fn redacted(&self) -> InstallConfig {
+        let mut config = self.clone();
+        config.repositories = self.repositories.iter().map(Repository::redacted).collect();
+        config.global_setup.iter_mut().for_each(InstallStep::redact);
+        config.notify = NotifyConfig::default();
+        config
+    }
+
+    /// Values of every repository's variable defaults, to be redacted from text shared with
+    /// others
+    pub fn secrets(&self) -> Vec<String> {
+        self.repositories
+            .iter()
+            .flat_map(Repository::secrets)
+            .collect()
+    }
+
+    /// Device state that `repos` and the global setup would write with differing content, such
+    /// as two repositories pushing files to the same path. Identical writes, such as two
+    /// repositories creating the same directory, aren't conflicts
+    pub fn step_conflicts(&self, repos: &[Repository]) -> Vec<StepConflict> {
+        let steps =
+            self.global_setup
+                .iter()
+                .map(|step| ("global", step))
+                .chain(repos.iter().flat_map(|repo| {
+                    repo.installation
+                        .iter()
+                        .map(move |step| (repo.name.as_str(), step))
+                }));
+
+        let mut writes: BTreeMap<String, Vec<(&str, String)>> = BTreeMap::new();
+        for (repo_name, step) in steps {
+            for step in step.flatten() {
+                for (target, content) in step_writes(step, repo_name) {
+                    writes.entry(target).or_default().push((repo_name, content));
+                }
+            }
+        }
+
+        writes
+            .into_iter()
+            .filter(|(_, writes)| writes.iter().any(|(_, content)| *content != writes[0].1))
+            .map(|(target, writes)| {
+                let mut repositories: Vec<String> = Vec::new();
+                for (repo_name, _) in writes {
+                    if !repositories.iter().any(|name| name == repo_name) {
+                        repositories.push(repo_name.to_string());
+                    }
+                }
+                StepConflict {
+                    target,
+                    repositories,
+                }
+            })
+            .collect()
+    }
+
+    /// The first firmware compatibility rule that forbids `version` of `repo_name` on a device
+    /// with the given build fingerprint
+    pub fn incompatible_firmware(
+        &self,
+        fingerprint: &str,
+        repo_name: &str,
+        version: &str,
+    ) -> Option<&FirmwareCompatibility> {
+        self.firmware_compatibility
+            .iter()
+            .find(|rule| rule.forbids(fingerprint, repo_name, version))
+    }
+
     pub fn resolve_and_apply_variables(
         &mut self,
         active_repos: &mut Vec<Repository>,
@@ -273,23 +1819,159 @@ impl InstallConfig {
         values: &HashMap<String, HashMap<String, String>>,
     ) -> Result<()> {
         for repo in active_repos {
-            if let Some(repo_substitutions) = values.get(&repo.name) {
-                substitute_repository(repo, repo_substitutions)?;
+            // Optional variables without a value keep their placeholders, for the engine to ask
+            // for when a step needs them
+            let mut repo_substitutions: HashMap<String, String> = repo
+                .variables
+                .iter()
+                .map(|var| (var.name.clone(), format!("{{{{ {} }}}}", var.name)))
+                .collect();
+            if let Some(resolved) = values.get(&repo.name) {
+                repo_substitutions.extend(resolved.clone());
             }
+            substitute_repository(repo, &repo_substitutions)?;
         }
 
         Ok(())
     }
 
+    /// Every repository variable, with the steps referencing it
+    pub fn variable_usage(&self) -> Vec<VariableUsage> {
+        let mut usage = Vec::new();
+
+        for repo in &self.repositories {
+            let steps =
+                repo.cleanup
+                    .iter()
+                    .enumerate()
+                    .map(|(index, step)| ("cleanup", index, serde_json::to_value(step)))
+                    .chain(
+                        repo.installation.iter().enumerate().map(|(index, step)| {
+                            ("installation", index, serde_json::to_value(step))
+                        }),
+                    );
+
+            let mut placeholders: Vec<(String, Vec<String>)> = Vec::new();
+            for (list, index, step) in steps {
+                let Ok(step) = step else {
+                    continue;
+                };
+
+                let mut names = Vec::new();
+                collect_placeholders(&step, &mut names);
+                let location = format!(
+                    "{} step {} ({})",
+                    list,
+                    index + 1,
+                    step["type"].as_str().unwrap_or("unknown")
+                );
+                placeholders.push((location, names));
+            }
+
+            for variable in &repo.variables {
+                let references = placeholders
+                    .iter()
+                    .filter(|(_, names)| names.contains(&variable.name))
+                    .map(|(location, _)| location.clone())
+                    .collect();
+                usage.push(VariableUsage {
+                    repository: repo.name.clone(),
+                    variable: variable.clone(),
+                    references,
+                });
+            }
+        }
+
+        usage
+    }
+
     pub fn get_repository(&self, name: &str) -> Option<&Repository> {
         self.repositories.iter().find(|r| r.name == name)
     }
 
-    /// Returns all repository names in the config
+    /// Device paths holding user data for `repos`: each repository's `dataPaths`, and every file
+    /// written by a `CreateConfig` step
+    pub fn data_paths(&self, repos: &[Repository]) -> Vec<String> {
+        let steps = self
+            .global_setup
+            .iter()
+            .chain(repos.iter().flat_map(|repo| &repo.installation))
+            .flat_map(InstallStep::flatten);
+        let created = steps.filter_map(|step| match step {
+            InstallStep::CreateConfig { path, .. } => Some(path),
+            _ => None,
+        });
+
+        let mut paths = Vec::new();
+        for path in repos
+            .iter()
+            .flat_map(|repo| &repo.data_paths)
+            .chain(created)
+        {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+        paths
+    }
+
+    /// Device paths removed by a factory reset: everything created by `CreateDirectories` and
+    /// `CreateConfig` steps, and the reset's `remove_paths`
+    pub fn created_paths(&self) -> Vec<String> {
+        let steps = self
+            .global_setup
+            .iter()
+            .chain(self.repositories.iter().flat_map(|repo| &repo.installation))
+            .flat_map(InstallStep::flatten);
+        let created = steps.flat_map(|step| match step {
+            InstallStep::CreateDirectories { paths } => paths.iter().collect(),
+            InstallStep::CreateConfig { path, .. } => vec![path],
+            _ => Vec::new(),
+        });
+
+        let mut paths = Vec::new();
+        for path in created.chain(&self.reset.remove_paths) {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+        paths
+    }
+
+    /// Returns all repositories in the config, in installation order for loaded configs
     pub fn all_repositories(&self) -> &[Repository] {
         &self.repositories
     }
 
+    /// Leaves the repositories the user disabled out of the default selection, and pins those
+    /// the user held to their held version
+    pub fn apply_overrides(&mut self, overrides: &RepositoryOverrides) {
+        self.disabled = self
+            .repositories
+            .iter()
+            .filter(|repo| !overrides.is_enabled(&repo.name))
+            .map(|repo| repo.name.clone())
+            .collect();
+
+        self.held = BTreeSet::new();
+        for repo in &mut self.repositories {
+            if let Some(version) = overrides.held.get(&repo.name) {
+                repo.version = VersionSpec::Version(version.clone());
+                self.held.insert(repo.name.clone());
+            }
+        }
+    }
+
+    /// Whether the user held the repository at a version, which replaced its configured one
+    pub fn is_held(&self, repo: &Repository) -> bool {
+        self.held.contains(&repo.name)
+    }
+
+    /// Whether the repository is selected by default, being neither optional nor disabled
+    pub fn is_default_selected(&self, repo: &Repository) -> bool {
+        !repo.optional && !self.disabled.contains(&repo.name)
+    }
+
     pub fn filter_repositories(&self, filter: Option<Vec<String>>) -> Result<Vec<Repository>> {
         let mut filtered = Vec::new();
 
@@ -302,53 +1984,334 @@ impl InstallConfig {
                 }
             }
 
-            Ok(filtered.into_iter().cloned().collect())
+            let mut filtered: Vec<Repository> = filtered.into_iter().cloned().collect();
+            sort_by_priority(&mut filtered);
+            Ok(filtered)
         } else {
             Ok(self
                 .repositories
                 .iter()
-                .filter(|repo| !repo.optional)
+                .filter(|repo| self.is_default_selected(repo))
                 .cloned()
                 .collect())
         }
     }
 }
 
-fn replace_placeholders(input: &str, values: &HashMap<String, String>) -> Result<String> {
-    if !input.contains("{{") {
-        return Ok(input.to_string());
+/// Puts repositories in installation order. The sort is stable, so equal priorities keep their
+/// order
+fn sort_by_priority(repos: &mut [Repository]) {
+    repos.sort_by_key(|repo| std::cmp::Reverse(repo.priority));
+}
+
+/// Parses one config of a layered config, expanding its `<<` merge keys
+fn parse_layer(contents: &str) -> Result<serde_yaml::Value> {
+    let mut value: serde_yaml::Value =
+        serde_yaml::from_str(contents).map_err(|e| syntax_error(e, contents))?;
+    value.apply_merge()?;
+    Ok(value)
+}
+
+/// Merges a config over `base`, matching repositories by name
+fn merge_config_layer(base: &mut serde_yaml::Value, mut layer: serde_yaml::Value) {
+    let layer_repos = layer
+        .as_mapping_mut()
+        .and_then(|layer| layer.remove("repositories"));
+    merge_values(base, layer);
+
+    let Some(layer_repos) = layer_repos else {
+        return;
+    };
+    match (base.get_mut("repositories"), layer_repos) {
+        (Some(serde_yaml::Value::Sequence(repos)), serde_yaml::Value::Sequence(layer_repos)) => {
+            for repo in layer_repos {
+                let name = repo.get("name").cloned();
+                let existing = repos
+                    .iter_mut()
+                    .find(|existing| name.is_some() && existing.get("name") == name.as_ref());
+                match existing {
+                    Some(existing) => merge_values(existing, repo),
+                    None => repos.push(repo),
+                }
+            }
+        }
+        // Let deserialization report whatever is wrong with the layer's repositories
+        (_, layer_repos) => {
+            if let Some(base) = base.as_mapping_mut() {
+                base.insert("repositories".into(), layer_repos);
+            }
+        }
     }
+}
 
-    let mut output = String::with_capacity(input.len());
+/// Merges mappings key by key. Any other value in `layer` replaces the one in `base`
+fn merge_values(base: &mut serde_yaml::Value, layer: serde_yaml::Value) {
+    match (base, layer) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Parses a YAML config, expanding `<<` merge keys, which serde_yaml doesn't apply itself
+fn parse_yaml<T: DeserializeOwned>(contents: &str) -> Result<T> {
+    let mut value: serde_yaml::Value =
+        serde_yaml::from_str(contents).map_err(|e| syntax_error(e, contents))?;
+    if !has_merge_keys(&value) {
+        return serde_yaml::from_str(contents).map_err(|e| syntax_error(e, contents));
+    }
+
+    value.apply_merge()?;
+    // Reparsing the expanded document keeps the path to the offending entry in errors
+    let expanded = serde_yaml::to_string(&value)?;
+    serde_yaml::from_str(&expanded).map_err(|e| expanded_error(e, contents, &expanded))
+}
+
+/// Quotes the line of `contents` that `error` points at
+fn syntax_error(error: serde_yaml::Error, contents: &str) -> InstallerError {
+    match error.location() {
+        Some(location) => config_syntax(
+            without_location(&error),
+            contents,
+            location.line(),
+            location.column(),
+        ),
+        None => error.into(),
+    }
+}
+
+/// Quotes the line of `contents` that an error in the `expanded` document came from. Merged
+/// entries are copies of their anchor, so this is usually where the anchor is defined
+fn expanded_error(error: serde_yaml::Error, contents: &str, expanded: &str) -> InstallerError {
+    let message = format!(
+        "{} (after expanding `<<` merge keys)",
+        without_location(&error)
+    );
+    let source = error
+        .location()
+        .and_then(|location| source_location(contents, expanded, location.line()));
+
+    match source {
+        Some((line, column)) => config_syntax(message, contents, line, column),
+        None => InstallerError::Config(message),
+    }
+}
+
+fn config_syntax(message: String, contents: &str, line: usize, column: usize) -> InstallerError {
+    let text = contents.lines().nth(line - 1).unwrap_or_default();
+    let number = line.to_string();
+    let snippet = format!(
+        "{number} | {text}\n{:width$} | {:>column$}",
+        "",
+        "^",
+        width = number.len(),
+    );
+
+    InstallerError::ConfigSyntax {
+        message,
+        line,
+        column,
+        snippet,
+    }
+}
+
+/// Line and column in `contents` of the entry at `line` of the `expanded` document. Entries are
+/// compared along with the one after them, ignoring indentation, list dashes, and quotes, and
+/// the first match wins, since anchors come before the aliases merging them
+fn source_location(contents: &str, expanded: &str, line: usize) -> Option<(usize, usize)> {
+    let wanted: Vec<String> = expanded
+        .lines()
+        .skip(line - 1)
+        .filter_map(yaml_entry)
+        .take(2)
+        .map(|(entry, _)| entry)
+        .collect();
+    if wanted.is_empty() {
+        return None;
+    }
+
+    let entries: Vec<(usize, String, usize)> = contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let (entry, column) = yaml_entry(line)?;
+            Some((index + 1, entry, column))
+        })
+        .collect();
+
+    let matching = |len: usize| {
+        entries.windows(len).find_map(|window| {
+            let found = window
+                .iter()
+                .zip(&wanted)
+                .all(|((_, entry, _), wanted)| entry == wanted);
+            found.then(|| (window[0].0, window[0].2))
+        })
+    };
+    matching(wanted.len()).or_else(|| matching(1))
+}
+
+/// A YAML line without its indentation, list dashes, or quotes, and the column it starts at.
+/// `None` for blank and comment lines
+fn yaml_entry(line: &str) -> Option<(String, usize)> {
+    let mut entry = line.trim_start();
+    while let Some(rest) = entry.strip_prefix("- ") {
+        entry = rest.trim_start();
+    }
+    let column = line.len() - entry.len() + 1;
+
+    let entry = entry.trim_end();
+    if entry.is_empty() || entry == "-" || entry.starts_with('#') {
+        return None;
+    }
+    Some((entry.replace(['"', '\''], ""), column))
+}
+
+/// The error's message without the trailing position serde_yaml appends
+fn without_location(error: &serde_yaml::Error) -> String {
+    let message = error.to_string();
+    match error.location() {
+        Some(location) => message.replacen(
+            &format!(" at line {} column {}", location.line(), location.column()),
+            "",
+            1,
+        ),
+        None => message,
+    }
+}
+
+/// The candidate that `name` is most likely a typo of: one differing only in case, or by at most two
+/// edits
+fn closest_match<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let name = name.to_lowercase();
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn has_merge_keys(value: &serde_yaml::Value) -> bool {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => mapping
+            .iter()
+            .any(|(key, value)| key.as_str() == Some("<<") || has_merge_keys(value)),
+        serde_yaml::Value::Sequence(sequence) => sequence.iter().any(has_merge_keys),
+        serde_yaml::Value::Tagged(tagged) => has_merge_keys(&tagged.value),
+        _ => false,
+    }
+}
+
+/// Part of a string split at its `{{ name }}` placeholders
+enum Segment<'a> {
+    Text(&'a str),
+    /// A placeholder's name, trimmed
+    Placeholder(&'a str),
+}
+
+/// Splits `input` at its `{{ name }}` placeholders. Both finding and filling in placeholders go
+/// through here, so they always agree on what a placeholder is
+fn placeholder_segments(input: &str) -> Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
     let mut rest = input;
-    let mut missing = HashSet::new();
 
     while let Some(start) = rest.find("{{") {
-        output.push_str(&rest[..start]);
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
         rest = &rest[start + 2..];
 
         let end = rest.find("}}").ok_or_else(|| {
             InstallerError::Config("Unterminated variable placeholder".to_string())
         })?;
 
-        let key_raw = &rest[..end];
-        rest = &rest[end + 2..];
-
-        let key = key_raw.trim();
-        if key.is_empty() {
+        let name = rest[..end].trim();
+        if name.is_empty() {
             return Err(InstallerError::Config(
                 "Variable placeholder cannot be empty".to_string(),
             ));
         }
+        segments.push(Segment::Placeholder(name));
+        rest = &rest[end + 2..];
+    }
 
-        if let Some(value) = values.get(key) {
-            output.push_str(value);
-        } else {
-            missing.insert(key.to_string());
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    Ok(segments)
+}
+
+/// Names of the `{{ name }}` placeholders in every string within `value`
+fn collect_placeholders(value: &serde_json::Value, names: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(string) => {
+            // Malformed placeholders are reported when the string is filled in
+            for segment in placeholder_segments(string).unwrap_or_default() {
+                if let Segment::Placeholder(name) = segment {
+                    if !names.iter().any(|known| known == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_placeholders(item, names);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values() {
+                collect_placeholders(value, names);
+            }
         }
+        _ => {}
     }
+}
 
-    output.push_str(rest);
+fn replace_placeholders(input: &str, values: &HashMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut missing = HashSet::new();
+
+    for segment in placeholder_segments(input)? {
+        match segment {
+            Segment::Text(text) => output.push_str(text),
+            Segment::Placeholder(name) => match values.get(name) {
+                Some(value) => output.push_str(value),
+                None => {
+                    missing.insert(name.to_string());
+                }
+            },
+        }
+    }
 
     if !missing.is_empty() {
         return Err(InstallerError::Config(format!(
@@ -374,7 +2337,7 @@ fn substitute_repository(repo: &mut Repository, values: &HashMap<String, String>
 
 fn substitute_cleanup_step(step: &mut CleanupStep, values: &HashMap<String, String>) -> Result<()> {
     match step {
-        CleanupStep::UninstallPackages { patterns }
+        CleanupStep::UninstallPackages { patterns, .. }
         | CleanupStep::RemoveDirectories { paths: patterns }
         | CleanupStep::RemoveDirectoriesIfEmpty { paths: patterns }
         | CleanupStep::RemoveFiles { paths: patterns } => substitute_strings(patterns, values),
@@ -393,16 +2356,25 @@ fn substitute_install_step(step: &mut InstallStep, values: &HashMap<String, Stri
             substitute_strings(exclude_patterns, values)
         }
         InstallStep::PushFiles { files } => {
+            // File placeholders are kept for the engine to fill in
+            let mut remote_values = values.clone();
+            for key in FILE_PLACEHOLDERS {
+                remote_values.insert(key.to_string(), format!("{{{{ {} }}}}", key));
+            }
+
             for file in files {
-                substitute_string(&mut file.local, values)?;
-                substitute_string(&mut file.remote, values)?;
+                for pattern in file.local.patterns_mut() {
+                    substitute_string(pattern, values)?;
+                }
+                substitute_strings(&mut file.exclude, values)?;
+                substitute_string(&mut file.remote, &remote_values)?;
                 if let Some(chmod) = &mut file.chmod {
                     substitute_string(chmod, values)?;
                 }
             }
             Ok(())
         }
-        InstallStep::GrantPermissions { grants } => {
+        InstallStep::GrantPermissions { grants, .. } => {
             for grant in grants {
                 substitute_string(&mut grant.package, values)?;
                 substitute_string(&mut grant.permission, values)?;
@@ -423,6 +2395,44 @@ fn substitute_install_step(step: &mut InstallStep, values: &HashMap<String, Stri
             substitute_string(path, values)?;
             substitute_string(content, values)
         }
+        InstallStep::InstallUnit { file, .. } => substitute_string(file, values),
+        InstallStep::ControlService { unit, .. } => substitute_string(unit, values),
+        InstallStep::Prompt { message, .. } => substitute_string(message, values),
+        InstallStep::RebootDevice { .. } => Ok(()),
+        InstallStep::Parallel { steps } => {
+            for step in steps {
+                substitute_install_step(step, values)?;
+            }
+            Ok(())
+        }
+        InstallStep::Custom(step) => {
+            for value in step.params.values_mut() {
+                substitute_value(value, values)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn substitute_value(
+    target: &mut serde_json::Value,
+    values: &HashMap<String, String>,
+) -> Result<()> {
+    match target {
+        serde_json::Value::String(string) => substitute_string(string, values),
+        serde_json::Value::Array(items) => {
+            for item in items {
+                substitute_value(item, values)?;
+            }
+            Ok(())
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                substitute_value(value, values)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
     }
 }
 
@@ -437,3 +2447,321 @@ fn substitute_strings(strings: &mut Vec<String>, values: &HashMap<String, String
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with(global_setup: &str, installation: &str) -> String {
+        format!(
+            r#"name: "test"
+global_setup: {global_setup}
+repositories:
+  - name: "pinitd"
+    owner: "PenumbraOS"
+    repo: "pinitd"
+    version: "latest"
+    releaseAssets: ["*.apk"]
+    installation: {installation}
+"#
+        )
+    }
+
+    #[test]
+    fn allows_leaving_android_as_the_last_step() {
+        let config = config_with(
+            "[]",
+            r#"[{type: "InstallApks", priority_order: []}, {type: "RebootDevice", target: "bootloader"}]"#,
+        );
+        assert!(InstallConfig::load_from_str(&config).is_ok());
+    }
+
+    #[test]
+    fn allows_rebooting_back_into_android_before_other_steps() {
+        let config = config_with(
+            "[]",
+            r#"[{type: "RebootDevice", target: "soft"}, {type: "InstallApks", priority_order: []}]"#,
+        );
+        assert!(InstallConfig::load_from_str(&config).is_ok());
+    }
+
+    #[test]
+    fn refuses_steps_after_leaving_android() {
+        let config = config_with(
+            "[]",
+            r#"[{type: "RebootDevice", target: "recovery"}, {type: "InstallApks", priority_order: []}]"#,
+        );
+        let error = InstallConfig::load_from_str(&config).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("reboots out of Android before its last step"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn refuses_global_steps_leaving_android() {
+        let config = config_with(
+            r#"[{type: "RebootDevice", target: "sideload"}]"#,
+            r#"[{type: "InstallApks", priority_order: []}]"#,
+        );
+        let error = InstallConfig::load_from_str(&config).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Global steps can't reboot out of Android"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn expands_merge_keys_in_tagged_steps() {
+        let config: InstallConfig = parse_yaml(
+            r#"
+name: "test"
+x-repo: &repo
+  owner: "PenumbraOS"
+  version: "latest"
+  releaseAssets: []
+x-push: &push
+  type: "PushFiles"
+  files:
+    - local: "*.unit"
+      remote: "/sdcard/penumbra/etc/"
+x-settings: &settings
+  type: "CreateConfig"
+  path: "/sdcard/penumbra/settings.json"
+  content: "{}"
+repositories:
+  - <<: *repo
+    name: "pinitd"
+    repo: "pinitd"
+    installation:
+      - <<: *push
+      - <<: *settings
+        path: "/sdcard/penumbra/other.json"
+        only_if_missing: true
+"#,
+        )
+        .unwrap();
+
+        let repo = &config.repositories[0];
+        assert_eq!(repo.owner, "PenumbraOS");
+        assert_eq!(repo.name, "pinitd");
+        assert!(matches!(
+            &repo.installation[0],
+            InstallStep::PushFiles { files } if files[0].remote == "/sdcard/penumbra/etc/"
+        ));
+        match &repo.installation[1] {
+            InstallStep::CreateConfig {
+                path,
+                content,
+                only_if_missing,
+                ..
+            } => {
+                assert_eq!(path, "/sdcard/penumbra/other.json");
+                assert_eq!(content, "{}");
+                assert!(only_if_missing);
+            }
+            step => panic!("expected CreateConfig, got {:?}", step),
+        }
+    }
+
+    #[test]
+    fn aliases_whole_step_lists() {
+        let config: InstallConfig = parse_yaml(
+            r#"
+name: "test"
+x-common: &common
+  owner: "PenumbraOS"
+  version: "latest"
+  releaseAssets: []
+x-steps: &install
+  - type: "CreateDirectories"
+    paths: ["/sdcard/penumbra"]
+repositories:
+  - <<: *common
+    name: "first"
+    repo: "first"
+    installation: *install
+  - <<: *common
+    name: "second"
+    repo: "second"
+    installation: *install
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.repositories.len(), 2);
+        for repo in &config.repositories {
+            assert!(matches!(
+                &repo.installation[..],
+                [InstallStep::CreateDirectories { paths }] if paths == &["/sdcard/penumbra"]
+            ));
+        }
+    }
+
+    #[test]
+    fn points_merged_step_errors_at_the_anchor() {
+        let error = parse_yaml::<InstallConfig>(
+            r#"name: "test"
+x-step: &broken
+  type: "PushFilez"
+  files: []
+repositories:
+  - name: "pinitd"
+    owner: "PenumbraOS"
+    repo: "pinitd"
+    version: "latest"
+    releaseAssets: []
+    installation:
+      - <<: *broken
+"#,
+        )
+        .unwrap_err();
+
+        match error {
+            InstallerError::ConfigSyntax {
+                message,
+                line,
+                column,
+                snippet,
+            } => {
+                assert!(message.contains("did you mean `PushFiles`"), "{}", message);
+                assert!(message.contains("merge keys"), "{}", message);
+                assert_eq!((line, column), (3, 3));
+                assert!(
+                    snippet.starts_with("3 |   type: \"PushFilez\""),
+                    "{}",
+                    snippet
+                );
+            }
+            error => panic!("expected a located error, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn points_step_errors_at_their_line() {
+        let error = parse_yaml::<InstallConfig>(
+            r#"name: "test"
+repositories:
+  - name: "pinitd"
+    owner: "PenumbraOS"
+    repo: "pinitd"
+    version: "latest"
+    releaseAssets: []
+    installation:
+      - type: "PushFilez"
+"#,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            error,
+            InstallerError::ConfigSyntax { line: 9, ref message, .. }
+                if message.contains("did you mean `PushFiles`")
+        ));
+    }
+
+    #[test]
+    fn matches_expanded_lines_to_their_source() {
+        let contents = "a: &a\n  key: \"value\"\n  other: 1\nb:\n  - <<: *a\n    key: value\n";
+        let expanded = "a:\n  key: value\n  other: 1\nb:\n- key: value\n  other: 1\n";
+        assert_eq!(source_location(contents, expanded, 2), Some((2, 3)));
+        assert_eq!(source_location(contents, expanded, 5), Some((2, 3)));
+        assert_eq!(source_location(contents, expanded, 4), Some((4, 1)));
+        assert_eq!(source_location(contents, "missing: true\n", 1), None);
+    }
+
+    #[test]
+    fn describes_commands_by_their_program() {
+        let step = |command: &str| InstallStep::RunCommand {
+            command: command.to_string(),
+            ignore_failure: false,
+        };
+        assert_eq!(
+            step("curl -H 'Authorization: token secret' https://example.com").describe(),
+            "Run command: curl ..."
+        );
+        assert_eq!(step("  reboot ").describe(), "Run command: reboot");
+    }
+
+    #[test]
+    fn redacts_command_arguments_and_config_content() {
+        let mut command = InstallStep::RunCommand {
+            command: "settings put secure token abc".to_string(),
+            ignore_failure: false,
+        };
+        command.redact();
+        match command {
+            InstallStep::RunCommand { command, .. } => assert_eq!(command, "settings <redacted>"),
+            step => panic!("expected RunCommand, got {:?}", step),
+        }
+
+        let mut config = InstallStep::CreateConfig {
+            path: "/sdcard/settings.json".to_string(),
+            content: "{\"key\": \"secret\"}".to_string(),
+            only_if_missing: false,
+            update_if_changed: false,
+            merge: None,
+        };
+        config.redact();
+        match config {
+            InstallStep::CreateConfig { path, content, .. } => {
+                assert_eq!(path, "/sdcard/settings.json");
+                assert_eq!(content, REDACTED);
+            }
+            step => panic!("expected CreateConfig, got {:?}", step),
+        }
+    }
+
+    #[test]
+    fn finds_the_placeholders_it_fills_in() {
+        let step = InstallStep::CreateDirectories {
+            paths: vec![
+                "/sdcard/{{ user }}/{{dir}}".to_string(),
+                "{{ user }}".to_string(),
+            ],
+        };
+        assert_eq!(step.placeholders(), vec!["user", "dir"]);
+
+        let values = HashMap::from([
+            ("user".to_string(), "me".to_string()),
+            ("dir".to_string(), "files".to_string()),
+        ]);
+        assert_eq!(
+            replace_placeholders("/sdcard/{{ user }}/{{dir}}", &values).unwrap(),
+            "/sdcard/me/files"
+        );
+        assert!(replace_placeholders("{{ user", &values).is_err());
+        assert!(replace_placeholders("{{ }}", &values).is_err());
+    }
+
+    #[test]
+    fn keeps_placeholders_of_optional_variables_without_values() {
+        let mut config = InstallConfig::load_from_str(&format!(
+            "{}    variables:\n      - {{name: user, required: true}}\n      - {{name: dir}}\n",
+            config_with(
+                "[]",
+                r#"[{type: "CreateDirectories", paths: ["/sdcard/{{ user }}/{{ dir }}"]}]"#
+            )
+        ))
+        .unwrap();
+        let mut active_repos = config.repositories.clone();
+        let overrides = HashMap::from([("user".to_string(), "me".to_string())]);
+        config
+            .resolve_and_apply_variables(&mut active_repos, &overrides)
+            .unwrap();
+
+        assert!(matches!(
+            &active_repos[0].installation[0],
+            InstallStep::CreateDirectories { paths } if paths[0] == "/sdcard/me/{{ dir }}"
+        ));
+        assert!(config
+            .resolve_and_apply_variables(&mut config.repositories.clone(), &HashMap::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use tracing::warn;
+
+use crate::{InstallerError, Result};
+
+/// Records which release each asset in a download directory came from
+const MANIFEST_NAME: &str = ".assets.json";
+const PATCH_EXTENSION: &str = "zstpatch";
+/// Largest zstd window accepted, so patches made with `--long` against large APKs decode
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Release version of each asset downloaded into a directory, so the next release can be
+/// patched from it
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AssetManifest {
+    assets: BTreeMap<String, String>,
+}
+
+impl AssetManifest {
+    /// Loads the manifest for `dir`. A missing or unreadable manifest is treated as empty
+    pub fn load(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(MANIFEST_NAME))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(MANIFEST_NAME), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn version(&self, asset: &str) -> Option<&str> {
+        self.assets.get(asset).map(String::as_str)
+    }
+
+    pub fn set(&mut self, asset: &str, version: &str) {
+        self.assets.insert(asset.to_string(), version.to_string());
+    }
+}
+
+/// Name of the release asset that patches `asset` from its `from_version` release, as created by
+/// `zstd --patch-from=<old asset> <new asset> -o <patch>`
+pub fn patch_name(asset: &str, from_version: &str) -> String {
+    format!("{}.from-{}.{}", asset, from_version, PATCH_EXTENSION)
+}
+
+pub fn is_patch(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|extension| extension == PATCH_EXTENSION)
+}
+
+/// Rebuilds an asset by applying `patch` to `base`, writing it to `output`. Fails, removing
+/// `output`, unless the result matches `digest` (`sha256:<hex>`, as published by GitHub)
+pub fn apply_patch(base: &Path, patch: &Path, output: &Path, digest: &str) -> Result<()> {
+    let result = write_patched(base, patch, output).and_then(|actual| {
+        if digest.eq_ignore_ascii_case(&actual) {
+            Ok(())
+        } else {
+            Err(InstallerError::GitHub(format!(
+                "Patched {} has digest {}, expected {}",
+                output.display(),
+                actual,
+                digest
+            )))
+        }
+    });
+
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(output) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove {}: {}", output.display(), e);
+            }
+        }
+    }
+    result
+}
+
+/// Decodes the patch into `output`, returning the result's digest
+fn write_patched(base: &Path, patch: &Path, output: &Path) -> Result<String> {
+    let base = fs::read(base)?;
+    let patch = BufReader::new(File::open(patch)?);
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch, &base)?;
+    decoder.window_log_max(MAX_WINDOW_LOG)?;
+
+    let mut output = File::create(output)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = decoder.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+    }
+    output.flush()?;
+
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
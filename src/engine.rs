@@ -1,24 +1,100 @@
 use glob::glob;
-use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs;
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, watch};
 use tokio::time::sleep;
 use tokio_util::sync::CancellationToken;
-
-use crate::adb::AdbManager;
-use crate::github::GitHubClient;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+
+use crate::adb::{self, quote, AdbManager, FileType, TransferProgress};
+use crate::apk::{self, ApkInfo};
+use crate::backup;
+use crate::github::{DownloadProgress, GitHubClient, RepoFile, LABEL_PATTERN_PREFIX};
+use crate::known_devices::KnownDevices;
+use crate::lock::InstallLock;
+use crate::notify::{Notification, NotifyEvent};
+use crate::phase::InstallPhase;
+use crate::pins::FilePins;
 use crate::platform::Platform;
+use crate::progress::ProgressTracker;
+use crate::report::{
+    InstallEnvironment, InstallReport, InstallWarning, RebootOutcome, RepositoryOutcome,
+    ResetReport, StepTiming, WarningKind,
+};
+use crate::resolve::{self, ResolutionProgress};
+use crate::session::Session;
+use crate::step::{InputRequest, Prompter, StepContext, StepExecutor, StepRegistry};
+use crate::task;
+use crate::transform;
+use crate::usage::DATA_PARTITION;
 use crate::{
-    CleanupStep, FilePush, InstallConfig, InstallStep, InstallerError, Repository, Result,
+    AppOpGrant, CleanupStep, ConfigFormat, ConfigVariable, FilePush, InstallConfig, InstallStep,
+    InstallerError, Repository, ResetConfig, Result, ResultExt,
 };
 
+/// Times each app op is set before it is reported as not applied
+const APP_OP_ATTEMPTS: u32 = 3;
+/// Wait before retrying app ops that didn't apply, multiplied by the attempt number
+const APP_OP_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Most device connections opened for a `Parallel` step group
+const MAX_PARALLEL_CONNECTIONS: usize = 4;
+/// Directory pinitd loads system units from. Units are enabled by files of the same name in its
+/// `enabled` subdirectory
+const PINITD_UNIT_DIR: &str = "/sdcard/penumbra/etc/pinitd/system";
+const PINITD_CLI: &str = "/data/local/tmp/bin/pinitd-cli";
+/// Logcat tag of the markers written at each phase, repository and step boundary
+pub const LOG_MARKER_TAG: &str = "PenumbraInstaller";
+
 pub struct InstallationEngine {
     pub config: InstallConfig,
     github: GitHubClient,
-    adb: AdbManager,
+    adb: Option<AdbManager>,
     temp_dir: PathBuf,
     cancellation_token: Option<CancellationToken>,
+    force_unlock: bool,
+    /// Installation lock held while installing, uninstalling, or resetting
+    lock: Option<InstallLock>,
+    allow_any_device: bool,
+    /// Warn rather than refuse when the battery is below the config's minimum
+    ignore_battery: bool,
+    /// Uninstall repositories marked `protected`
+    force_uninstall: bool,
+    /// Ask before installing to a device other than those installed to before
+    check_known_device: bool,
+    /// Wait for the device to boot after rebooting it, even if the config doesn't ask to
+    wait_for_reboot: bool,
+    /// Directory to back up user data into before installing
+    backup_dir: Option<PathBuf>,
+    /// Build fingerprint of the device, read when the config has firmware compatibility rules
+    firmware: Option<String>,
+    /// ABIs supported by the device, most preferred first, read when a repository has
+    /// ABI-specific assets
+    abis: Option<Vec<String>>,
+    steps: StepRegistry,
+    /// Answers `Prompt` steps. Without one, each prompt takes its default answer
+    prompter: Option<Arc<dyn Prompter>>,
+    /// Values the prompter gave for variables without one, by repository and variable name
+    entered_values: HashMap<String, HashMap<String, String>>,
+    /// Idempotent writes done in this run, so repeats by later repositories are skipped
+    done: HashSet<String>,
+    phase: watch::Sender<InstallPhase>,
+    /// Repository being installed or downloaded, which warnings are recorded against
+    repository: Option<String>,
+    /// Problems in the current operation that don't fail it
+    warnings: Vec<InstallWarning>,
+    /// Timings of the current repository's installation steps
+    step_timings: Vec<StepTiming>,
+    warning_sender: Option<mpsc::UnboundedSender<InstallWarning>>,
+    /// Versions resolved ahead of downloading, by repository
+    versions: HashMap<String, String>,
+    resolution_sender: Option<mpsc::UnboundedSender<ResolutionProgress>>,
 }
 
 impl InstallationEngine {
@@ -44,33 +120,307 @@ impl InstallationEngine {
         github_token: Option<String>,
         remote_auth_url: Option<String>,
         cancellation_token: Option<CancellationToken>,
+    ) -> Result<Self> {
+        let adb = AdbManager::connect(remote_auth_url).await?;
+
+        Self::new_with_device(config, cache_dir, github_token, adb, cancellation_token).await
+    }
+
+    /// Creates an engine using an already established device connection
+    pub async fn new_with_device(
+        config: InstallConfig,
+        cache_dir: PathBuf,
+        github_token: Option<String>,
+        mut adb: AdbManager,
+        cancellation_token: Option<CancellationToken>,
     ) -> Result<Self> {
         fs::create_dir_all(&cache_dir).await?;
 
-        let github = GitHubClient::new_with_token(github_token);
-        let adb = AdbManager::connect(remote_auth_url).await?;
+        let mut github = GitHubClient::new_with_token(github_token);
+        github.set_cancellation_token(cancellation_token.clone());
+        adb.set_cancellation_token(cancellation_token.clone());
+        adb.set_compress_pushes(config.transfer.compress);
+
+        Ok(Self {
+            config,
+            github,
+            adb: Some(adb),
+            temp_dir: cache_dir,
+            cancellation_token,
+            force_unlock: false,
+            lock: None,
+            allow_any_device: false,
+            ignore_battery: false,
+            force_uninstall: false,
+            check_known_device: false,
+            wait_for_reboot: false,
+            backup_dir: None,
+            firmware: None,
+            abis: None,
+            steps: StepRegistry::new(),
+            prompter: None,
+            entered_values: HashMap::new(),
+            done: HashSet::new(),
+            phase: watch::channel(InstallPhase::Idle).0,
+            repository: None,
+            warnings: Vec::new(),
+            step_timings: Vec::new(),
+            warning_sender: None,
+            versions: HashMap::new(),
+            resolution_sender: None,
+        })
+    }
+
+    /// Creates an engine without a device connection, which can only download assets
+    pub async fn new_offline(
+        config: InstallConfig,
+        cache_dir: PathBuf,
+        github_token: Option<String>,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Result<Self> {
+        fs::create_dir_all(&cache_dir).await?;
+
+        let mut github = GitHubClient::new_with_token(github_token);
+        github.set_cancellation_token(cancellation_token.clone());
 
         Ok(Self {
             config,
             github,
-            adb,
+            adb: None,
             temp_dir: cache_dir,
             cancellation_token,
+            force_unlock: false,
+            lock: None,
+            allow_any_device: false,
+            ignore_battery: false,
+            force_uninstall: false,
+            check_known_device: false,
+            wait_for_reboot: false,
+            backup_dir: None,
+            firmware: None,
+            abis: None,
+            steps: StepRegistry::new(),
+            prompter: None,
+            entered_values: HashMap::new(),
+            done: HashSet::new(),
+            phase: watch::channel(InstallPhase::Idle).0,
+            repository: None,
+            warnings: Vec::new(),
+            step_timings: Vec::new(),
+            warning_sender: None,
+            versions: HashMap::new(),
+            resolution_sender: None,
         })
     }
 
+    /// Ignore installation locks left by another installer, such as after a crash
+    pub fn set_force_unlock(&mut self, force_unlock: bool) {
+        self.force_unlock = force_unlock;
+    }
+
+    /// Ask before installing to a device other than those installed to before, and remember each
+    /// device installed to. The first device is trusted without asking
+    pub fn set_check_known_device(&mut self, check_known_device: bool) {
+        self.check_known_device = check_known_device;
+    }
+
+    /// Wait for the device to come back after an install reboots it, then run the config's
+    /// `reboot.verify` steps
+    pub fn set_wait_for_reboot(&mut self, wait_for_reboot: bool) {
+        self.wait_for_reboot = wait_for_reboot;
+    }
+
+    /// Go ahead with installs when the device battery is below the config's minimum, warning
+    /// instead
+    pub fn set_ignore_battery(&mut self, ignore_battery: bool) {
+        self.ignore_battery = ignore_battery;
+    }
+
+    /// Let `uninstall` remove repositories marked `protected`, rather than refusing
+    pub fn set_force_uninstall(&mut self, force_uninstall: bool) {
+        self.force_uninstall = force_uninstall;
+    }
+
+    /// Allow installing built-in configs onto devices other than the Ai Pin
+    pub fn set_allow_any_device(&mut self, allow_any_device: bool) {
+        self.allow_any_device = allow_any_device;
+    }
+
+    /// Back up the repositories' user data into a timestamped archive in `dir` before installing.
+    /// The installation is aborted if the backup fails
+    pub fn set_backup_dir(&mut self, dir: Option<PathBuf>) {
+        self.backup_dir = dir;
+    }
+
+    /// Records every ADB command and HTTP request to `session`, or answers them from it when
+    /// replaying
+    pub fn set_session(&mut self, session: Arc<Session>) {
+        self.github.set_session(Some(session.clone()));
+        if let Some(adb) = self.adb.as_mut() {
+            adb.set_session(Some(session));
+        }
+    }
+
+    /// Asks `prompter` to confirm `Prompt` steps, rather than taking their default answers
+    pub fn set_prompter(&mut self, prompter: impl Prompter + 'static) {
+        self.prompter = Some(Arc::new(prompter));
+    }
+
+    /// What the engine is doing in the current or last operation. A failed operation stays in
+    /// the phase it failed in
+    pub fn current_phase(&self) -> InstallPhase {
+        *self.phase.borrow()
+    }
+
+    /// Follows `current_phase` from another task while an operation runs
+    pub fn subscribe_phase(&self) -> watch::Receiver<InstallPhase> {
+        self.phase.subscribe()
+    }
+
+    /// Follows the progress of each release asset download. Replaces any earlier subscriber
+    pub fn subscribe_downloads(&mut self) -> mpsc::UnboundedReceiver<DownloadProgress> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.github.set_progress(Some(sender));
+        receiver
+    }
+
+    /// Follows the progress of each file push and APK upload to the device. Replaces any earlier
+    /// subscriber
+    pub fn subscribe_transfers(&mut self) -> mpsc::UnboundedReceiver<TransferProgress> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Some(adb) = self.adb.as_mut() {
+            adb.set_transfer_progress(Some(sender));
+        }
+        receiver
+    }
+
+    /// Records the progress of downloads and pushes in `tracker` while the returned future runs,
+    /// which ends when the engine is dropped. Replaces any earlier download or transfer
+    /// subscriber
+    pub fn track_progress(
+        &mut self,
+        tracker: &ProgressTracker,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        tracker
+            .clone()
+            .follow(self.subscribe_downloads(), self.subscribe_transfers())
+    }
+
+    /// Follows the resolution of the repositories' versions and release assets, which happens
+    /// for all of them at once before downloading. Replaces any earlier subscriber
+    pub fn subscribe_resolution(&mut self) -> mpsc::UnboundedReceiver<ResolutionProgress> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.resolution_sender = Some(sender);
+        receiver
+    }
+
+    /// Follows the warnings of each operation as they happen. Replaces any earlier subscriber
+    pub fn subscribe_warnings(&mut self) -> mpsc::UnboundedReceiver<InstallWarning> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.warning_sender = Some(sender);
+        receiver
+    }
+
+    /// Registers a handler for a custom install step type
+    pub fn register_step(
+        &mut self,
+        step_type: impl Into<String>,
+        executor: impl StepExecutor + 'static,
+    ) -> Result<()> {
+        self.steps.register(step_type, executor)
+    }
+
+    /// Installs the given repositories, returning a per-repository report. Failures of optional
+    /// repositories are recorded and installation continues; a required repository failing
+    /// skips everything after it.
     pub async fn install(
         &mut self,
         active_repos: &Vec<Repository>,
         with_cache: bool,
-    ) -> Result<()> {
+    ) -> Result<InstallReport> {
+        let result = self.run_install(active_repos, with_cache).await;
+        self.notify(&result).await;
+        result
+    }
+
+    async fn run_install(
+        &mut self,
+        active_repos: &Vec<Repository>,
+        with_cache: bool,
+    ) -> Result<InstallReport> {
+        self.set_phase(InstallPhase::Connecting);
+        self.check_custom_steps(active_repos)?;
+        if let Some(conflict) = self.config.step_conflicts(active_repos).into_iter().next() {
+            return Err(InstallerError::StepConflict {
+                target: conflict.target,
+                repositories: conflict.repositories,
+            });
+        }
+        self.check_device().await?;
+        self.check_known_device().await?;
+
+        self.lock_device().await?;
+        let result = self.install_locked(active_repos, with_cache).await;
+        self.unlock_device().await;
+        result
+    }
+
+    async fn lock_device(&mut self) -> Result<()> {
+        let force_unlock = self.force_unlock;
+        let lock = InstallLock::acquire(self.adb()?, force_unlock).await?;
+        self.lock = Some(lock);
+        Ok(())
+    }
+
+    /// Releases the installation lock if it's held. Called before rebooting, since the device
+    /// marker can't be removed while the device restarts. A failure is only logged, so it
+    /// doesn't replace the result of the operation that held the lock
+    async fn unlock_device(&mut self) {
+        let Some(lock) = self.lock.take() else {
+            return;
+        };
+        let Some(adb) = self.adb.as_mut() else {
+            return;
+        };
+        if let Err(e) = lock.release(adb).await {
+            warn!("Failed to release the device installation lock: {}", e);
+        }
+    }
+
+    async fn install_locked(
+        &mut self,
+        active_repos: &Vec<Repository>,
+        with_cache: bool,
+    ) -> Result<InstallReport> {
         info!("Starting {} installation", self.config.name);
+        self.warnings.clear();
+        self.repository = None;
+        self.mark(&format!("BEGIN install {}", self.config.name));
+        self.check_battery().await?;
+
+        if let Some(dir) = self.backup_dir.clone() {
+            info!("Backing up user data");
+            let paths = self.config.data_paths(active_repos);
+            let config_name = self.config.name.clone();
+            backup::backup_data(self.adb()?, &config_name, &paths, &dir)
+                .await
+                .context("backing up user data")?;
+        }
+
+        self.check_clock().await?;
 
         if !self.config.global_setup.is_empty() {
             info!("Running global setup");
+            self.set_phase(InstallPhase::Installing);
             let global_setup = self.config.global_setup.clone();
-            for step in &global_setup {
-                self.execute_install_step(step, "global").await?;
+            for (index, step) in global_setup.iter().enumerate() {
+                self.execute_install_step(step, "global", &[])
+                    .instrument(step_span(index, &step.describe()))
+                    .instrument(debug_span!("repo", repo = "global"))
+                    .await
+                    .with_context(|| {
+                        format!("global setup step {} ({})", index + 1, step.describe())
+                    })?;
             }
         }
 
@@ -79,15 +429,79 @@ impl InstallationEngine {
         }
 
         info!("Installing {} repositories", active_repos.len());
+        self.resolve_ahead(active_repos, with_cache).await;
+        self.check_free_storage(active_repos, with_cache).await?;
+
+        let mut report = InstallReport {
+            environment: Some(self.capture_environment().await),
+            ..Default::default()
+        };
+        let mut failed_required = None;
 
         for repo in active_repos {
-            if self.is_cancelled() {
-                break;
+            let skip_reason = if self.is_cancelled() {
+                Some("cancelled".to_string())
+            } else {
+                failed_required
+                    .as_ref()
+                    .map(|name| format!("required repository '{}' failed", name))
+            };
+
+            if let Some(reason) = skip_reason {
+                report.record(
+                    &repo.name,
+                    repo.optional,
+                    RepositoryOutcome::Skipped { reason },
+                    Duration::ZERO,
+                );
+                continue;
             }
 
             info!("Installing repository: {}", repo.name);
-            self.install_repository(repo, with_cache).await?;
+            self.repository = Some(repo.name.clone());
+            let marker = match self.versions.get(&repo.name) {
+                Some(version) => format!("repo {} {}", repo.name, version),
+                None => format!("repo {}", repo.name),
+            };
+            self.mark(&format!("BEGIN {}", marker));
+            let started = Instant::now();
+            let outcome = match self
+                .install_repository(repo, with_cache)
+                .instrument(debug_span!("repo", repo = %repo.name))
+                .await
+                .with_context(|| format!("installing '{}'", repo.name))
+            {
+                Ok((version, files)) => RepositoryOutcome::Installed {
+                    version,
+                    files,
+                    warnings: self.repository_warnings(&repo.name),
+                },
+                Err(e) if matches!(e.root(), InstallerError::Cancelled) => {
+                    warn!("Cancelled while installing {}", repo.name);
+                    RepositoryOutcome::Skipped {
+                        reason: "cancelled".to_string(),
+                    }
+                }
+                Err(e) => {
+                    error!("Failed {}", e);
+                    if !repo.optional {
+                        failed_required = Some(repo.name.clone());
+                    }
+                    RepositoryOutcome::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            let end = match &outcome {
+                RepositoryOutcome::Installed { .. } => "END",
+                RepositoryOutcome::Failed { .. } => "FAIL",
+                _ => "CANCEL",
+            };
+            self.mark(&format!("{} {}", end, marker));
+            report.record(&repo.name, repo.optional, outcome, started.elapsed());
+            report.record_steps(std::mem::take(&mut self.step_timings));
         }
+        self.repository = None;
 
         if !with_cache {
             info!("Cleaning up temporary files");
@@ -95,34 +509,291 @@ impl InstallationEngine {
         }
 
         info!("Installation complete");
+        report.log_summary();
 
-        if !self.is_cancelled() && active_repos.iter().any(|r| r.reboot_after_completion) {
+        let needs_reboot = active_repos.iter().any(|repo| {
+            repo.reboot_after_completion
+                && report.repositories.iter().any(|r| {
+                    r.name == repo.name && matches!(r.outcome, RepositoryOutcome::Installed { .. })
+                })
+        });
+
+        if !self.is_cancelled() && needs_reboot {
             info!("Rebooting device");
-            self.adb.reboot()?;
+            self.set_phase(InstallPhase::Rebooting);
+            self.unlock_device().await;
+            self.adb()?.reboot()?;
+
+            let outcome = if self.wait_for_reboot || self.config.reboot.wait {
+                self.wait_and_verify().await?
+            } else {
+                RebootOutcome::Rebooted
+            };
+            report.reboot = Some(outcome);
+            report.log_reboot();
         }
 
-        Ok(())
+        report.warnings = std::mem::take(&mut self.warnings);
+        report.log_warnings();
+
+        self.set_phase(InstallPhase::Done);
+        self.mark(&format!("END install {}", self.config.name));
+        Ok(report)
+    }
+
+    /// Tells the config's notification command and webhook how an installation ended. Replayed
+    /// installations don't notify
+    async fn notify(&mut self, result: &Result<InstallReport>) {
+        let notify = self.config.notify.clone();
+        let replaying = self.adb.as_ref().is_some_and(AdbManager::is_replaying);
+        if notify.is_empty() || replaying {
+            return;
+        }
+
+        let device = match self.adb.as_mut() {
+            Some(adb) => adb.serial_number().await.ok(),
+            None => None,
+        };
+        let (event, report, error) = match result {
+            Ok(report) if report.succeeded() => {
+                (NotifyEvent::Completed, Some(report.clone()), None)
+            }
+            Ok(report) => (NotifyEvent::Failed, Some(report.clone()), None),
+            Err(e) => (NotifyEvent::Failed, None, Some(e.details())),
+        };
+
+        let notification = Notification {
+            event,
+            config_name: self.config.name.clone(),
+            device,
+            report,
+            error,
+        };
+        notify.send(&notification).await;
+    }
+
+    /// Waits for the rebooted device to boot, then runs the config's verification steps.
+    /// Only cancellation is returned as an error; other failures are part of the outcome
+    async fn wait_and_verify(&mut self) -> Result<RebootOutcome> {
+        let timeout = Duration::from_secs(self.config.reboot.timeout_secs);
+        info!("Waiting for the device to boot");
+
+        let boot_time = match self.adb()?.wait_for_boot(timeout).await {
+            Ok(boot_time) => boot_time,
+            Err(e) if matches!(e.root(), InstallerError::Cancelled) => return Err(e),
+            Err(e) => {
+                return Ok(RebootOutcome::Failed {
+                    error: e.to_string(),
+                });
+            }
+        };
+        let boot_ms = boot_time.as_millis() as u64;
+        info!("Device booted in {:.1}s", boot_time.as_secs_f64());
+
+        let verify = self.config.reboot.verify.clone();
+        if verify.is_empty() {
+            return Ok(RebootOutcome::Verified { boot_ms });
+        }
+
+        info!("Verifying the installation after reboot");
+        self.set_phase(InstallPhase::Verifying);
+        for (index, step) in verify.iter().enumerate() {
+            let result = self
+                .execute_install_step(step, "global", &[])
+                .instrument(step_span(index, &step.describe()))
+                .instrument(debug_span!("repo", repo = "global"))
+                .await
+                .with_context(|| format!("post-reboot step {} ({})", index + 1, step.describe()));
+
+            match result {
+                Ok(()) => {}
+                Err(e) if matches!(e.root(), InstallerError::Cancelled) => return Err(e),
+                Err(e) => {
+                    return Ok(RebootOutcome::VerificationFailed {
+                        boot_ms,
+                        error: e.to_string(),
+                    })
+                }
+            }
+        }
+
+        Ok(RebootOutcome::Verified { boot_ms })
     }
 
     pub async fn uninstall(&mut self, active_repos: &Vec<Repository>) -> Result<()> {
+        self.set_phase(InstallPhase::Connecting);
+        self.lock_device().await?;
+        let result = self.uninstall_locked(active_repos).await;
+        self.unlock_device().await;
+        result
+    }
+
+    async fn uninstall_locked(&mut self, active_repos: &[Repository]) -> Result<()> {
         info!("Starting {} uninstall", self.config.name);
 
         if active_repos.is_empty() {
             return Err(InstallerError::NoRepositoriesFound);
         }
 
+        if !self.force_uninstall {
+            if let Some(repo) = active_repos.iter().find(|repo| repo.protected) {
+                return Err(InstallerError::ProtectedRepository {
+                    repo: repo.name.clone(),
+                });
+            }
+        }
+
         info!("Uninstalling {} repositories", active_repos.len());
+        self.set_phase(InstallPhase::Cleaning);
 
         for repo in active_repos.iter().rev() {
             info!("Uninstalling repository: {}", repo.name);
-            self.uninstall_repository(repo).await?;
+            self.uninstall_repository(repo)
+                .instrument(debug_span!("repo", repo = %repo.name))
+                .await
+                .with_context(|| format!("uninstalling '{}'", repo.name))?;
         }
 
         info!("Uninstallation complete");
+        self.set_phase(InstallPhase::Done);
+        Ok(())
+    }
+
+    /// Uninstalls every repository in the config, restores the stock launcher and packages,
+    /// removes created files and directories, then checks that nothing is left behind
+    pub async fn reset(&mut self) -> Result<ResetReport> {
+        self.set_phase(InstallPhase::Connecting);
+        self.lock_device().await?;
+        let result = self.reset_locked().await;
+        self.unlock_device().await;
+        result
+    }
+
+    async fn reset_locked(&mut self) -> Result<ResetReport> {
+        info!("Resetting {} to stock", self.config.name);
+
+        let repos = self.config.all_repositories().to_vec();
+        let reset = self.config.reset.clone();
+        let paths = self.config.created_paths();
+        let mut report = ResetReport::default();
+        self.set_phase(InstallPhase::Cleaning);
+
+        for repo in repos.iter().rev() {
+            info!("Uninstalling repository: {}", repo.name);
+            if let Err(e) = self
+                .uninstall_repository(repo)
+                .instrument(debug_span!("repo", repo = %repo.name))
+                .await
+            {
+                error!("Failed to uninstall {}: {}", repo.name, e);
+                report
+                    .errors
+                    .push(format!("Uninstalling {} failed: {}", repo.name, e));
+            }
+        }
+
+        for path in &paths {
+            info!("Removing {}", path);
+            if let Err(e) = self.adb()?.remove_directory(path).await {
+                report
+                    .errors
+                    .push(format!("Removing {} failed: {}", path, e));
+            }
+        }
+
+        for package in &reset.enable_packages {
+            info!("Enabling package: {}", package);
+            if let Err(e) = self.adb()?.enable_package(package).await {
+                report
+                    .errors
+                    .push(format!("Enabling {} failed: {}", package, e));
+            }
+        }
+
+        if let Some(launcher) = &reset.launcher {
+            info!("Restoring launcher: {}", launcher);
+            if let Err(e) = self.adb()?.set_launcher(launcher).await {
+                report
+                    .errors
+                    .push(format!("Restoring launcher {} failed: {}", launcher, e));
+            }
+        }
+
+        info!("Verifying device state");
+        self.set_phase(InstallPhase::Verifying);
+        self.verify_reset(&repos, &paths, &reset, &mut report)
+            .await?;
+
+        report.log_summary();
+
+        if repos.iter().any(|repo| repo.reboot_after_completion) {
+            info!("Rebooting device");
+            self.set_phase(InstallPhase::Rebooting);
+            self.unlock_device().await;
+            self.adb()?.reboot()?;
+        }
+
+        self.set_phase(InstallPhase::Done);
+        Ok(report)
+    }
+
+    /// Records anything the reset should have removed or restored that is still in place
+    async fn verify_reset(
+        &mut self,
+        repos: &[Repository],
+        paths: &[String],
+        reset: &ResetConfig,
+        report: &mut ResetReport,
+    ) -> Result<()> {
+        for repo in repos {
+            for step in &repo.cleanup {
+                let CleanupStep::UninstallPackages { patterns, .. } = step else {
+                    continue;
+                };
+                for pattern in patterns {
+                    for package in self.find_packages_matching_pattern(pattern).await? {
+                        report
+                            .remaining
+                            .push(format!("Package {} is still installed", package));
+                    }
+                }
+            }
+        }
+
+        for path in paths {
+            let adb = self.adb()?;
+            if adb.directory_exists(path).await? || adb.file_exists(path).await? {
+                report.remaining.push(format!("{} still exists", path));
+            }
+        }
+
+        let disabled = self.adb()?.disabled_packages().await?;
+        for package in &reset.enable_packages {
+            if disabled.contains(package) {
+                report
+                    .remaining
+                    .push(format!("Package {} is still disabled", package));
+            }
+        }
+
+        if let Some(launcher) = &reset.launcher {
+            let home = self.adb()?.home_activity().await?;
+            if !home
+                .as_deref()
+                .is_some_and(|home| adb::same_component(home, launcher))
+            {
+                report.remaining.push(format!(
+                    "Launcher is {} rather than {}",
+                    home.as_deref().unwrap_or("unset"),
+                    launcher
+                ));
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn download(&mut self, active_repos: &Vec<Repository>) -> Result<()> {
+    pub async fn download(&mut self, active_repos: &Vec<Repository>) -> Result<InstallReport> {
         info!("Starting {} asset download", self.config.name);
 
         if active_repos.is_empty() {
@@ -131,48 +802,123 @@ impl InstallationEngine {
 
         info!("Downloading {} repositories", active_repos.len());
 
+        let mut report = InstallReport {
+            environment: Some(self.capture_environment().await),
+            ..Default::default()
+        };
+        self.warnings.clear();
+        self.resolve_ahead(active_repos, false).await;
+
         for repo in active_repos {
+            if self.is_cancelled() {
+                break;
+            }
+
             info!("Downloading repository: {}", repo.name);
-            self.download_repository(repo).await?;
+            self.repository = Some(repo.name.clone());
+            let started = Instant::now();
+            let (version, files) = match self
+                .download_repository(repo)
+                .instrument(debug_span!("repo", repo = %repo.name))
+                .await
+            {
+                Err(e) if matches!(e.root(), InstallerError::Cancelled) => break,
+                result => result.with_context(|| format!("downloading '{}'", repo.name))?,
+            };
+            report.record(
+                &repo.name,
+                repo.optional,
+                RepositoryOutcome::Downloaded { version, files },
+                started.elapsed(),
+            );
         }
 
+        self.repository = None;
+
         info!("Download complete - assets cached for installation");
-        Ok(())
+        report.warnings = std::mem::take(&mut self.warnings);
+        report.log_warnings();
+        self.set_phase(InstallPhase::Done);
+        Ok(report)
     }
 
-    async fn install_repository(&mut self, repo: &Repository, with_cache: bool) -> Result<()> {
-        if with_cache {
-            let repo_temp_dir = self.temp_dir.join(&repo.name);
-
-            if !repo_temp_dir.exists() {
-                self.download_repository_assets(repo).await?;
-            }
-        } else {
-            self.download_repository_assets(repo).await?;
+    /// Installs a single repository, returning the version and repository files downloaded (if
+    /// not already cached)
+    async fn install_repository(
+        &mut self,
+        repo: &Repository,
+        with_cache: bool,
+    ) -> Result<(Option<String>, Vec<RepoFile>)> {
+        let mut version = None;
+        let mut files = Vec::new();
+
+        if !with_cache || !self.temp_dir.join(&repo.name).exists() {
+            let (downloaded, fetched) = self.download_repository_assets(repo).await?;
+            version = Some(downloaded);
+            files = fetched;
         }
 
         if !repo.cleanup.is_empty() {
             info!("Running cleanup for {}", repo.name);
-            for cleanup in &repo.cleanup {
+            self.set_phase(InstallPhase::Cleaning);
+            for (index, cleanup) in repo.cleanup.iter().enumerate() {
                 if self.is_cancelled() {
                     break;
                 }
 
-                self.execute_cleanup_step(cleanup).await?;
+                self.execute_cleanup_step(cleanup)
+                    .instrument(step_span(index, &cleanup.describe()))
+                    .await
+                    .with_context(|| {
+                        format!("cleanup step {} ({})", index + 1, cleanup.describe())
+                    })?;
             }
         }
 
+        // Cached assets may include variants for every ABI, such as after an offline download
+        let unsupported = match self.device_abis(repo).await? {
+            Some(abis) => repo.unsupported_assets(&abis),
+            None => Vec::new(),
+        };
+
         info!("Running installation steps for {}", repo.name);
-        for step in &repo.installation {
+        self.set_phase(InstallPhase::Installing);
+        for (index, step) in repo.installation.iter().enumerate() {
             if self.is_cancelled() {
                 break;
             }
 
-            self.execute_install_step(step, &repo.name).await?;
+            // Only the step's type, as the device log is readable by other apps and descriptions
+            // can name paths, packages and commands
+            self.mark(&format!(
+                "STEP repo {} {}: {}",
+                repo.name,
+                index + 1,
+                step.step_type()
+            ));
+            let step = self
+                .fill_variables(step, repo)
+                .await
+                .with_context(|| format!("step {} ({})", index + 1, step.describe()))?;
+
+            let started = Instant::now();
+            let before = self.adb()?.shell_stats();
+            let result = self
+                .execute_install_step(&step, &repo.name, &unsupported)
+                .instrument(step_span(index, &step.describe()))
+                .await;
+            let after = self.adb().map(|adb| adb.shell_stats()).unwrap_or(before);
+            self.step_timings.push(StepTiming {
+                step: step.describe(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                round_trips: after.round_trips.saturating_sub(before.round_trips),
+                commands: after.commands.saturating_sub(before.commands),
+            });
+            result.with_context(|| format!("step {} ({})", index + 1, step.describe()))?;
         }
 
         info!("{} installation complete", repo.name);
-        Ok(())
+        Ok((version, files))
     }
 
     async fn uninstall_repository(&mut self, repo: &Repository) -> Result<()> {
@@ -182,76 +928,135 @@ impl InstallationEngine {
         }
 
         info!("Running cleanup steps for {}", repo.name);
-        for cleanup in &repo.cleanup {
-            self.execute_cleanup_step(cleanup).await?;
+        self.set_phase(InstallPhase::Cleaning);
+        for (index, cleanup) in repo.cleanup.iter().enumerate() {
+            self.execute_cleanup_step(cleanup)
+                .instrument(step_span(index, &cleanup.describe()))
+                .await
+                .with_context(|| format!("cleanup step {} ({})", index + 1, cleanup.describe()))?;
         }
 
         info!("{} uninstallation complete", repo.name);
         Ok(())
     }
 
-    async fn download_repository(&mut self, repo: &Repository) -> Result<()> {
-        self.download_repository_assets(repo).await?;
+    async fn download_repository(&mut self, repo: &Repository) -> Result<(String, Vec<RepoFile>)> {
+        let downloaded = self.download_repository_assets(repo).await?;
         info!("{} download complete", repo.name);
-        Ok(())
+        Ok(downloaded)
     }
 
     async fn execute_cleanup_step(&mut self, step: &CleanupStep) -> Result<()> {
         match step {
-            CleanupStep::UninstallPackages { patterns } => {
+            CleanupStep::UninstallPackages { patterns, user } => {
                 for pattern in patterns {
                     let packages = self.find_packages_matching_pattern(pattern).await?;
                     for package in packages {
                         info!("Uninstalling package: {}", package);
-                        self.adb.uninstall_package(&package).await?;
+                        match self.adb()?.uninstall_package(&package, *user).await {
+                            Ok(()) => {}
+                            Err(e) if matches!(e.root(), InstallerError::Cancelled) => {
+                                return Err(e)
+                            }
+                            Err(e) => self.add_warning(WarningKind::UninstallFailed, e.to_string()),
+                        }
                     }
                 }
             }
             CleanupStep::RemoveDirectories { paths } => {
                 for path in paths {
                     info!("Removing directory: {}", path);
-                    self.adb.remove_directory(path).await?;
+                    self.adb()?.remove_directory(path).await?;
                 }
             }
             CleanupStep::RemoveDirectoriesIfEmpty { paths } => {
                 for path in paths {
                     if self.is_directory_empty(path).await? {
                         info!("Removing empty directory: {}", path);
-                        self.adb.remove_directory(path).await?;
+                        self.adb()?.remove_directory(path).await?;
                     } else {
-                        warn!("Directory not empty, skipping: {}", path);
+                        self.add_warning(
+                            WarningKind::DirectoryNotEmpty,
+                            format!("Directory not empty, skipping: {}", path),
+                        );
                     }
                 }
             }
             CleanupStep::RemoveFiles { paths } => {
                 for path in paths {
                     info!("Removing file: {}", path);
-                    self.adb.remove_file(path).await?;
+                    self.adb()?.remove_file(path).await?;
                 }
             }
         }
         Ok(())
     }
 
-    async fn execute_install_step(&mut self, step: &InstallStep, repo_name: &str) -> Result<()> {
+    /// Runs an install step. APKs matching `unsupported_assets` are skipped
+    async fn execute_install_step(
+        &mut self,
+        step: &InstallStep,
+        repo_name: &str,
+        unsupported_assets: &[String],
+    ) -> Result<()> {
         match step {
-            InstallStep::CreateDirectories { paths } => {
-                for path in paths {
-                    info!("Creating directory: {}", path);
-                    self.adb.create_directory(path).await?;
+            InstallStep::CreateDirectories { .. }
+            | InstallStep::PushFiles { .. }
+            | InstallStep::CreateConfig { .. }
+            | InstallStep::InstallUnit { .. } => {
+                let Some(step) = self.without_repeats(step) else {
+                    info!(
+                        "Skipping {}, as an earlier step already did it",
+                        step.describe()
+                    );
+                    return Ok(());
+                };
+                let asset_dir = self.asset_dir(repo_name);
+                let token = self.cancellation_token.clone();
+                execute_file_step(self.adb()?, &step, &asset_dir, token.as_ref()).await?;
+                self.done.extend(idempotent_writes(&step));
+            }
+
+            InstallStep::ControlService {
+                unit,
+                action,
+                ignore_failure,
+            } => {
+                info!("Sending {} to service {}", action.as_str(), unit);
+                match self
+                    .adb()?
+                    .run(&format!("{} {} {}", PINITD_CLI, action.as_str(), unit))
+                    .await
+                {
+                    Ok(output) => {
+                        if !output.is_empty() {
+                            info!("pinitd: {}", output);
+                        }
+                    }
+                    Err(e) if *ignore_failure => {
+                        self.add_warning(
+                            WarningKind::CommandFailed,
+                            format!("Service command failed (ignoring): {}", e),
+                        );
+                    }
+                    Err(e) => return Err(e),
                 }
             }
 
+            InstallStep::Parallel { steps } => {
+                let asset_dir = self.asset_dir(repo_name);
+                self.execute_parallel(steps, asset_dir).await?;
+            }
+
             InstallStep::InstallApks {
                 priority_order,
                 allow_failures,
                 exclude_patterns,
+                user,
+                allow_downgrade,
+                group_splits,
             } => {
-                let repo_temp_dir = if repo_name == "global" {
-                    self.temp_dir.clone()
-                } else {
-                    self.temp_dir.join(repo_name)
-                };
+                let repo_temp_dir = self.asset_dir(repo_name);
 
                 let mut apks = self.find_apk_files_in_dir(&repo_temp_dir)?;
 
@@ -259,6 +1064,7 @@ impl InstallationEngine {
                     let filename = apk.file_name().unwrap().to_string_lossy();
                     !exclude_patterns
                         .iter()
+                        .chain(unsupported_assets)
                         .any(|pattern| self.matches_priority_pattern(&filename, pattern))
                 });
 
@@ -270,72 +1076,136 @@ impl InstallationEngine {
                 let sorted_apks = self.sort_apks_by_priority(&apks, priority_order);
 
                 info!("Installing {} APKs", sorted_apks.len());
-                for apk in sorted_apks {
+                let groups = if *group_splits {
+                    apk::group_splits(&sorted_apks)
+                } else {
+                    sorted_apks.into_iter().map(|apk| vec![apk]).collect()
+                };
+                for group in groups {
                     if self.is_cancelled() {
                         break;
                     }
 
+                    let apk = &group[0];
                     let apk_name = apk.file_name().unwrap().to_string_lossy();
-                    info!("Installing APK: {}", apk_name);
+                    if group.len() > 1 {
+                        info!(
+                            "Installing APK: {} with {} splits",
+                            apk_name,
+                            group.len() - 1
+                        );
+                    } else {
+                        info!("Installing APK: {}", apk_name);
+                    }
 
-                    match self.adb.install_apk(&apk).await {
+                    // Splits share the base APK's package, version, and signing key
+                    let installed = match self.preflight_apk(apk, *allow_downgrade).await {
+                        Ok(downgrade) if group.len() > 1 => {
+                            self.adb()?.install_apks(&group, *user, downgrade).await
+                        }
+                        Ok(downgrade) => self.adb()?.install_apk(apk, *user, downgrade).await,
+                        Err(e) => Err(e),
+                    };
+                    match installed {
                         Ok(()) => info!("Installed APK: {}", apk_name),
                         Err(e) if *allow_failures => {
-                            warn!("Failed to install {} (continuing): {}", apk_name, e);
+                            self.add_warning(
+                                WarningKind::ApkFailed,
+                                format!("Failed to install {} (continuing): {}", apk_name, e),
+                            );
                         }
                         Err(e) => return Err(e),
                     }
                 }
             }
 
-            InstallStep::PushFiles { files } => {
-                let repo_temp_dir = if repo_name == "global" {
-                    self.temp_dir.clone()
-                } else {
-                    self.temp_dir.join(repo_name)
-                };
-
-                for file_push in files {
-                    if self.is_cancelled() {
-                        break;
-                    }
-
-                    self.push_files(&repo_temp_dir, file_push).await?;
-                }
-            }
-
-            InstallStep::GrantPermissions { grants } => {
+            InstallStep::GrantPermissions {
+                grants,
+                user,
+                ignore_failure,
+            } => {
                 for grant in grants {
                     info!(
                         "Granting permission: {} to {}",
                         grant.permission, grant.package
                     );
-                    self.adb
-                        .grant_permission(&grant.package, &grant.permission)
-                        .await?;
                 }
-            }
-
+                let pairs: Vec<_> = grants
+                    .iter()
+                    .map(|grant| (grant.package.as_str(), grant.permission.as_str()))
+                    .collect();
+                let results = self.adb()?.grant_permissions(&pairs, *user).await?;
+
+                for (grant, result) in grants.iter().zip(results) {
+                    let Err(e) = result.with_context(|| {
+                        format!("granting {} to {}", grant.permission, grant.package)
+                    }) else {
+                        continue;
+                    };
+                    if !*ignore_failure {
+                        return Err(e);
+                    }
+                    self.add_warning(
+                        WarningKind::CommandFailed,
+                        format!("Permission not granted (ignoring): {}", e),
+                    );
+                }
+            }
+
             InstallStep::SetAppOps { ops } => {
-                for i in 0..3 {
-                    if i != 0 {
-                        info!("Delaying 5s to ensure app op changes succeed");
-                        sleep(Duration::from_secs(5)).await;
+                // Ops set right after an install can be reset as the package finishes
+                // registering, so read each back and retry only those that didn't stick
+                let mut pending: Vec<&AppOpGrant> = ops.iter().collect();
+                for attempt in 1..=APP_OP_ATTEMPTS {
+                    if attempt > 1 {
+                        info!("Retrying {} app ops that didn't apply", pending.len());
+                        sleep(APP_OP_RETRY_DELAY * (attempt - 1)).await;
                     }
 
-                    for op in ops {
-                        if self.is_cancelled() {
-                            break;
-                        }
+                    if self.is_cancelled() {
+                        return Ok(());
+                    }
 
+                    for op in &pending {
                         info!(
                             "Setting app op: {} {} {}",
                             op.package, op.operation, op.mode
                         );
-                        self.adb
-                            .set_app_op(&op.package, &op.operation, &op.mode)
-                            .await?;
                     }
+                    let changes: Vec<_> = pending
+                        .iter()
+                        .map(|op| (op.package.as_str(), op.operation.as_str(), op.mode.as_str()))
+                        .collect();
+                    self.adb()?.set_app_ops(&changes).await?;
+
+                    let keys: Vec<_> = pending
+                        .iter()
+                        .map(|op| (op.package.as_str(), op.operation.as_str()))
+                        .collect();
+                    let modes = self.adb()?.app_op_modes(&keys).await?;
+                    // Modes that can't be read back are assumed to have applied
+                    pending = pending
+                        .into_iter()
+                        .zip(modes)
+                        .filter(|(op, mode)| {
+                            mode.as_ref()
+                                .is_some_and(|mode| !same_app_op_mode(mode, &op.mode))
+                        })
+                        .map(|(op, _)| op)
+                        .collect();
+                    if pending.is_empty() {
+                        break;
+                    }
+                }
+
+                for op in pending {
+                    self.add_warning(
+                        WarningKind::AppOpNotApplied,
+                        format!(
+                            "App op {} for {} did not change to {}",
+                            op.operation, op.package, op.mode
+                        ),
+                    );
                 }
             }
 
@@ -344,14 +1214,17 @@ impl InstallationEngine {
                 ignore_failure,
             } => {
                 info!("Running command: {}", command);
-                match self.adb.shell(command).await {
+                match self.adb()?.run_once(command).await {
                     Ok(output) => {
                         if !output.is_empty() {
                             info!("Command output: {}", output);
                         }
                     }
                     Err(e) if *ignore_failure => {
-                        warn!("Command failed (ignoring): {}", e);
+                        self.add_warning(
+                            WarningKind::CommandFailed,
+                            format!("Command failed (ignoring): {}", e),
+                        );
                     }
                     Err(e) => return Err(e),
                 }
@@ -359,59 +1232,304 @@ impl InstallationEngine {
 
             InstallStep::SetLauncher { component } => {
                 info!("Setting launcher: {}", component);
-                self.adb.set_launcher(component).await?;
+                self.adb()?.set_launcher(component).await?;
+                self.verify_launcher(component).await?;
             }
 
-            InstallStep::CreateConfig {
-                path,
-                content,
-                only_if_missing,
-            } => {
-                if *only_if_missing && self.adb.file_exists(path).await? {
-                    info!("Config already exists: {}", path);
-                    return Ok(());
+            InstallStep::Prompt { message, default } => {
+                if !self.confirm(message, *default).await? {
+                    return Err(InstallerError::Declined {
+                        prompt: message.clone(),
+                    });
                 }
+            }
 
-                info!("Creating config: {}", path);
-                self.adb.write_file(path, content).await?;
+            InstallStep::RebootDevice { target } => {
+                info!("{}", step.describe());
+                if !target.returns_to_android() {
+                    self.unlock_device().await;
+                }
+                self.adb()?.reboot_to(*target).await?;
+
+                if target.returns_to_android() {
+                    let timeout = Duration::from_secs(self.config.reboot.timeout_secs);
+                    let boot_time = self.adb()?.wait_for_boot(timeout).await?;
+                    info!("Device booted in {:.1}s", boot_time.as_secs_f64());
+                } else {
+                    info!(
+                        "Device left Android for {}, so it can't be reached until it reboots",
+                        target.as_str()
+                    );
+                }
+            }
+
+            InstallStep::Custom(step) => {
+                let executor = self.steps.get(&step.step_type).ok_or_else(|| {
+                    InstallerError::Config(format!(
+                        "No executor registered for step type '{}'",
+                        step.step_type
+                    ))
+                })?;
+                let asset_dir = self.asset_dir(repo_name);
+
+                info!("Running custom step: {}", step.step_type);
+                let context = StepContext {
+                    adb: self.adb()?,
+                    repo_name,
+                    asset_dir: &asset_dir,
+                };
+                executor.execute(step, context).await?;
             }
         }
         Ok(())
     }
 
-    async fn push_files(&mut self, repo_temp_dir: &Path, file_push: &FilePush) -> Result<()> {
-        let local_pattern = repo_temp_dir.join(&file_push.local);
-        let pattern_str = local_pattern.to_string_lossy();
+    /// `step` without the directories and configs an earlier step already created in this run,
+    /// or `None` if that leaves nothing to do
+    fn without_repeats(&self, step: &InstallStep) -> Option<InstallStep> {
+        match step {
+            InstallStep::CreateDirectories { paths } => {
+                let paths: Vec<String> = paths
+                    .iter()
+                    .zip(idempotent_writes(step))
+                    .filter(|(_, write)| !self.done.contains(write))
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                (!paths.is_empty()).then_some(InstallStep::CreateDirectories { paths })
+            }
+            InstallStep::CreateConfig { .. } => {
+                let repeated = idempotent_writes(step)
+                    .iter()
+                    .all(|write| self.done.contains(write));
+                (!repeated).then(|| step.clone())
+            }
+            step => Some(step.clone()),
+        }
+    }
 
-        for entry in glob(&pattern_str)? {
-            let local_file = entry?;
+    /// `step` with its placeholders for `repo`'s variables filled in. Variables still without a
+    /// value, such as when the frontend resolves none up front, take their default or are asked
+    /// for the first time a step needs them
+    async fn fill_variables<'a>(
+        &mut self,
+        step: &'a InstallStep,
+        repo: &Repository,
+    ) -> Result<Cow<'a, InstallStep>> {
+        let variables: Vec<&ConfigVariable> = step
+            .placeholders()
+            .iter()
+            .filter_map(|name| repo.variables.iter().find(|var| &var.name == name))
+            .collect();
+        if variables.is_empty() {
+            return Ok(Cow::Borrowed(step));
+        }
 
-            let remote_path = if file_push.remote.ends_with('/') {
-                format!(
-                    "{}{}",
-                    file_push.remote,
-                    local_file.file_name().unwrap().to_string_lossy()
-                )
-            } else {
-                file_push.remote.clone()
+        let mut values = HashMap::new();
+        for variable in variables {
+            let entered = self
+                .entered_values
+                .get(&repo.name)
+                .and_then(|values| values.get(&variable.name));
+            let value = match entered.or(variable.default.as_ref()) {
+                Some(value) => value.clone(),
+                None => {
+                    let value = self.ask_variable(repo, variable, step).await?;
+                    if let Some(adb) = &self.adb {
+                        adb.add_secret(&value);
+                    }
+                    self.entered_values
+                        .entry(repo.name.clone())
+                        .or_default()
+                        .insert(variable.name.clone(), value.clone());
+                    value
+                }
             };
+            values.insert(variable.name.clone(), value);
+        }
+
+        step.with_values(&values).map(Cow::Owned)
+    }
+
+    /// Asks the prompter for a value for `variable`, giving up if the installation is cancelled
+    /// while waiting. An optional variable left without one is empty
+    async fn ask_variable(
+        &self,
+        repo: &Repository,
+        variable: &ConfigVariable,
+        step: &InstallStep,
+    ) -> Result<String> {
+        let missing = || {
+            if variable.required {
+                Err(InstallerError::Config(format!(
+                    "Missing value for required variable '{}'",
+                    variable.name
+                )))
+            } else {
+                Ok(String::new())
+            }
+        };
+        let Some(prompter) = self.prompter.clone() else {
+            return missing();
+        };
+
+        let request = InputRequest {
+            repository: repo.name.clone(),
+            variable: variable.name.clone(),
+            description: variable.description.clone(),
+            step: step.describe(),
+        };
+        info!("Waiting for a value for {}", variable.name);
+        let value = match &self.cancellation_token {
+            Some(token) => token
+                .run_until_cancelled(prompter.input(&request))
+                .await
+                .unwrap_or(Err(InstallerError::Cancelled)),
+            None => prompter.input(&request).await,
+        }?;
+
+        value.map_or_else(missing, Ok)
+    }
 
+    /// Asks the prompter to confirm `message`, giving up if the installation is cancelled while
+    /// waiting
+    async fn confirm(&self, message: &str, default: bool) -> Result<bool> {
+        let Some(prompter) = self.prompter.clone() else {
             info!(
-                "Pushing: {} -> {}",
-                local_file.file_name().unwrap().to_string_lossy(),
-                remote_path
+                "{} (continuing with default answer: {})",
+                message,
+                if default { "yes" } else { "no" }
             );
+            return Ok(default);
+        };
 
-            self.adb.push_file(&local_file, &remote_path).await?;
+        info!("Waiting for confirmation: {}", message);
+        let answer = match &self.cancellation_token {
+            Some(token) => token
+                .run_until_cancelled(prompter.confirm(message, default))
+                .await
+                .unwrap_or(Err(InstallerError::Cancelled)),
+            None => prompter.confirm(message, default).await,
+        }?;
+
+        if answer {
+            info!("Confirmed: {}", message);
+        }
+        Ok(answer)
+    }
 
-            if let Some(chmod) = &file_push.chmod {
-                self.adb
-                    .shell(&format!("chmod {} {}", chmod, remote_path))
-                    .await?;
+    /// Checks that `component` is installed as a home activity and now handles the home intent,
+    /// as `cmd package set-home-activity` can fail without reporting an error
+    async fn verify_launcher(&mut self, component: &str) -> Result<()> {
+        let adb = self.adb()?;
+
+        let candidates = adb.home_activities().await?;
+        if !candidates
+            .iter()
+            .any(|candidate| adb::same_component(candidate, component))
+        {
+            return Err(InstallerError::LauncherNotSet {
+                component: component.to_string(),
+                reason: "it is not installed or cannot handle the home intent".to_string(),
+            });
+        }
+
+        match adb.home_activity().await? {
+            Some(home) if adb::same_component(&home, component) => {
+                info!("Verified launcher: {}", component);
+                Ok(())
             }
+            home => Err(InstallerError::LauncherNotSet {
+                component: component.to_string(),
+                reason: format!(
+                    "the home activity is {}",
+                    home.as_deref().unwrap_or("unresolved")
+                ),
+            }),
         }
+    }
 
-        Ok(())
+    /// Runs a group of parallel-safe steps spread across extra device connections, or in order
+    /// when the device only allows one connection
+    async fn execute_parallel(&mut self, steps: &[InstallStep], asset_dir: PathBuf) -> Result<()> {
+        let mut connections = Vec::new();
+        for _ in 0..steps.len().min(MAX_PARALLEL_CONNECTIONS) {
+            match self.adb()?.open_connection()? {
+                Some(connection) => connections.push(connection),
+                None => break,
+            }
+        }
+
+        let token = self.cancellation_token.clone();
+
+        if connections.is_empty() {
+            info!(
+                "Running {} steps in order, as the device allows only one connection",
+                steps.len()
+            );
+            for (index, step) in steps.iter().enumerate() {
+                if self.is_cancelled() {
+                    break;
+                }
+
+                execute_file_step(self.adb()?, step, &asset_dir, token.as_ref())
+                    .instrument(step_span(index, &step.describe()))
+                    .await
+                    .with_context(|| {
+                        format!("parallel step {} ({})", index + 1, step.describe())
+                    })?;
+            }
+            return Ok(());
+        }
+
+        info!(
+            "Running {} steps over {} connections",
+            steps.len(),
+            connections.len()
+        );
+
+        let mut queues = vec![Vec::new(); connections.len()];
+        for (index, step) in steps.iter().enumerate() {
+            queues[index % connections.len()].push((index, step.clone()));
+        }
+
+        let runtime = Handle::current();
+        let span = tracing::Span::current();
+        let jobs = connections.into_iter().zip(queues).map(|(mut adb, queue)| {
+            let runtime = runtime.clone();
+            let span = span.clone();
+            let asset_dir = asset_dir.clone();
+            let token = token.clone();
+
+            move || {
+                let run = async {
+                    for (index, step) in queue {
+                        if token.as_ref().is_some_and(|token| token.is_cancelled()) {
+                            break;
+                        }
+
+                        execute_file_step(&mut adb, &step, &asset_dir, token.as_ref())
+                            .instrument(step_span(index, &step.describe()))
+                            .await
+                            .with_context(|| {
+                                format!("parallel step {} ({})", index + 1, step.describe())
+                            })?;
+                    }
+                    Ok(())
+                };
+                runtime.block_on(run.instrument(span))
+            }
+        });
+
+        // Every connection finishes its queue before the first failure is reported
+        let mut result = Ok(());
+        for outcome in task::spawn_blocking_all(jobs).await {
+            if let Err(e) = outcome.and_then(|outcome| outcome) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
     }
 
     fn find_apk_files_in_dir(&self, dir: &Path) -> Result<Vec<PathBuf>> {
@@ -452,7 +1570,42 @@ impl InstallationEngine {
         sorted_apks
     }
 
+    /// Compares an APK with the installed version of its package, failing before the install on
+    /// a downgrade that isn't allowed or a different signing key. Returns whether the install
+    /// needs to allow a downgrade
+    async fn preflight_apk(&mut self, apk: &Path, allow_downgrade: bool) -> Result<bool> {
+        let info = match ApkInfo::read(apk) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Couldn't read {} before installing: {}", apk.display(), e);
+                return Ok(false);
+            }
+        };
+
+        let Some(installed) = self.adb()?.installed_package(&info.package).await? else {
+            return Ok(false);
+        };
+        if let Some((failure, reason)) = info.conflict(&installed, allow_downgrade) {
+            let apk = apk.file_name().unwrap_or_default().to_string_lossy();
+            return Err(apk::preflight_error(apk.to_string(), failure, reason));
+        }
+
+        let downgrade = info.is_downgrade(&installed);
+        if downgrade {
+            info!(
+                "Downgrading {} from version code {} to {}",
+                info.package, installed.version_code, info.version_code
+            );
+        }
+        Ok(downgrade)
+    }
+
     fn matches_priority_pattern(&self, filename: &str, pattern: &str) -> bool {
+        // Downloaded files keep only their asset name, so label patterns can't match them
+        if pattern.starts_with(LABEL_PATTERN_PREFIX) {
+            return false;
+        }
+
         if pattern.starts_with('*') && pattern.ends_with('*') && pattern.len() > 1 {
             let inner = &pattern[1..pattern.len() - 1];
             filename.to_lowercase().contains(&inner.to_lowercase())
@@ -474,37 +1627,134 @@ impl InstallationEngine {
             pattern.to_string()
         };
 
-        self.adb.list_packages(&search_pattern).await
+        self.adb()?.list_packages(&search_pattern).await
     }
 
     async fn is_directory_empty(&mut self, path: &str) -> Result<bool> {
-        let output = self.adb.shell(&format!("ls -A {}", path)).await?;
+        let output = self.adb()?.shell(&format!("ls -A {}", quote(path))).await?;
         Ok(output.trim().is_empty())
     }
 
-    fn get_exclusion_patterns(&self, repo: &Repository) -> Vec<String> {
-        for step in &repo.installation {
-            if let InstallStep::InstallApks {
-                exclude_patterns, ..
-            } = step
+    /// Resolves the versions and release assets of the repositories that will be downloaded, several
+    /// at once. A repository that fails here is retried, and fails with context, when its turn
+    /// comes
+    async fn resolve_ahead(&mut self, active_repos: &[Repository], with_cache: bool) {
+        self.versions.clear();
+        let repos: Vec<Repository> = active_repos
+            .iter()
+            .filter(|repo| !with_cache || !self.temp_dir.join(&repo.name).exists())
+            .cloned()
+            .collect();
+        if repos.is_empty() {
+            return;
+        }
+
+        info!("Resolving {} repositories", repos.len());
+        self.set_phase(InstallPhase::Resolving);
+        let versions =
+            resolve::resolve_versions(&self.github, &repos, self.resolution_sender.as_ref()).await;
+        for (repo, version) in repos.iter().zip(versions) {
+            match version {
+                Ok(version) => {
+                    self.versions.insert(repo.name.clone(), version);
+                }
+                Err(e) => debug!("Couldn't resolve {} ahead of downloading: {}", repo.name, e),
+            }
+        }
+    }
+
+    /// Fails before installing anything when the device's data partition can't hold the assets
+    /// about to be installed, plus a staged copy of the largest. Repositories whose assets can't
+    /// be sized are left out of the estimate
+    async fn check_free_storage(
+        &mut self,
+        active_repos: &[Repository],
+        with_cache: bool,
+    ) -> Result<()> {
+        let mut sizes = Vec::new();
+        for repo in active_repos {
+            let cached = self.temp_dir.join(&repo.name);
+            if with_cache && cached.exists() {
+                sizes.extend(file_sizes(&cached));
+                continue;
+            }
+
+            let Some(version) = self.versions.get(&repo.name).cloned() else {
+                continue;
+            };
+            let abis = self.device_abis(repo).await?;
+            match self
+                .github
+                .selected_assets(repo, &version, abis.as_deref())
+                .await
             {
-                return exclude_patterns.clone();
+                Ok(assets) => sizes.extend(assets.iter().map(|asset| asset.size)),
+                Err(e) => debug!("Couldn't size the assets of {}: {}", repo.name, e),
+            }
+        }
+
+        let total: u64 = sizes.iter().sum();
+        if total == 0 {
+            return Ok(());
+        }
+        let required = total + sizes.iter().max().copied().unwrap_or(0);
+
+        let available = match self.adb()?.free_space(DATA_PARTITION).await {
+            Ok(available) => available,
+            Err(e) => {
+                warn!("Couldn't read free storage on the device: {}", e);
+                return Ok(());
             }
+        };
+        debug!(
+            "Assets need about {} bytes, {} free on {}",
+            required, available, DATA_PARTITION
+        );
+
+        if available < required {
+            return Err(InstallerError::InsufficientStorage {
+                required,
+                available,
+            });
         }
-        Vec::new()
+        Ok(())
     }
 
-    async fn download_repository_assets(&mut self, repo: &Repository) -> Result<()> {
-        let version = self.github.get_version(repo).await?;
+    /// Downloads a repository's release assets and files, returning the resolved version and the
+    /// repository files fetched
+    async fn download_repository_assets(
+        &mut self,
+        repo: &Repository,
+    ) -> Result<(String, Vec<RepoFile>)> {
+        self.set_phase(InstallPhase::Resolving);
+        let version = match self.versions.remove(&repo.name) {
+            Some(version) => version,
+            None => self.github.get_version(repo).await?,
+        };
         info!("Version: {}", version);
+        self.check_firmware(repo, &version)?;
 
         let repo_temp_dir = self.temp_dir.join(&repo.name);
         fs::create_dir_all(&repo_temp_dir).await?;
 
-        let exclude_patterns = self.get_exclusion_patterns(repo);
+        let abis = self.device_abis(repo).await?;
+        if let Some(abis) = &abis {
+            if repo.preferred_abi(abis).is_none() {
+                let message = format!(
+                    "{} has no assets for the device ABIs ({}), installing only universal assets",
+                    repo.name,
+                    abis.join(", ")
+                );
+                self.add_warning(WarningKind::UnsupportedAbi, message);
+            }
+        }
 
         info!("Downloading release assets");
-        for pattern in &repo.release_assets {
+        self.set_phase(InstallPhase::Downloading);
+        // Patterns can overlap, so each asset is downloaded once for the first pattern matching it
+        let mut downloaded_assets = BTreeSet::new();
+        let mut excluded_assets = BTreeMap::new();
+        for (pattern, exclude_patterns) in repo.asset_selection(abis.as_deref()) {
             if self.is_cancelled() {
                 break;
             }
@@ -518,38 +1768,737 @@ impl InstallationEngine {
                     pattern,
                     &repo_temp_dir,
                     &exclude_patterns,
+                    &mut downloaded_assets,
                 )
-                .await?;
-
-            if downloaded.is_empty() {
-                warn!("No release assets found for pattern: {}", pattern);
+                .await
+                .with_context(|| format!("release asset '{}' at {}", pattern, version))?;
+
+            if downloaded.files.is_empty() {
+                self.add_warning(
+                    WarningKind::MissingAssets,
+                    format!("No release assets found for pattern: {}", pattern),
+                );
+            }
+            for excluded in downloaded.excluded {
+                excluded_assets
+                    .entry(excluded.name)
+                    .or_insert(excluded.pattern);
+            }
+        }
+        // ABI variants are excluded from universal patterns by design, so only the config's own
+        // exclusions of assets no other pattern downloaded are worth a warning
+        let exclusion_patterns = repo.exclusion_patterns();
+        for (asset, pattern) in excluded_assets {
+            if !downloaded_assets.contains(&asset) && exclusion_patterns.contains(&pattern) {
+                self.add_warning(
+                    WarningKind::ExcludedAsset,
+                    format!("Skipped {}, excluded by {}", asset, pattern),
+                );
             }
         }
+        if !downloaded_assets.is_empty() {
+            info!(
+                "Release assets: {}",
+                downloaded_assets
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        self.transform_assets(repo, &repo_temp_dir, &downloaded_assets)
+            .await?;
 
-        for filepath in &repo.repo_files {
+        let mut files = Vec::new();
+        for pattern in &repo.repo_files {
             if self.is_cancelled() {
                 break;
             }
 
+            let filepath = pattern.pattern();
             info!("Downloading repository file: {}", filepath);
-            if filepath.contains('*') {
-                self.github
-                    .download_file(&repo.owner, &repo.repo, &version, filepath, &repo_temp_dir)
-                    .await?;
+            let dest = if filepath.contains('*') {
+                repo_temp_dir.clone()
             } else {
-                let dest = repo_temp_dir.join(Path::new(filepath).file_name().unwrap());
+                repo_temp_dir.join(Path::new(filepath).file_name().unwrap())
+            };
+            files.extend(
                 self.github
-                    .download_file(&repo.owner, &repo.repo, &version, filepath, &dest)
-                    .await?;
+                    .download_file(
+                        &repo.owner,
+                        &repo.repo,
+                        &version,
+                        filepath,
+                        &dest,
+                        pattern.preserve_paths(),
+                    )
+                    .await?,
+            );
+        }
+
+        // Files are fetched from raw.githubusercontent.com by tag, so catch a tag that has since
+        // been moved to different content
+        if !files.is_empty() {
+            let mut pins = FilePins::load();
+            for file in &files {
+                pins.check(&repo.owner, &repo.repo, &version, file)?;
             }
+            pins.save()?;
         }
 
+        Ok((version, files))
+    }
+
+    /// Runs the first of the repository's `assetTransforms` matching each downloaded asset
+    async fn transform_assets(
+        &self,
+        repo: &Repository,
+        repo_temp_dir: &Path,
+        assets: &BTreeSet<String>,
+    ) -> Result<()> {
+        let mut rules = Vec::new();
+        for transforms in &repo.asset_transforms {
+            rules.push((glob::Pattern::new(&transforms.pattern)?, &transforms.steps));
+        }
+
+        for asset in assets {
+            if self.is_cancelled() {
+                break;
+            }
+            let Some((_, steps)) = rules.iter().find(|(pattern, _)| pattern.matches(asset)) else {
+                continue;
+            };
+
+            info!(
+                "Transforming {}: {}",
+                asset,
+                steps
+                    .iter()
+                    .map(|step| step.describe())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            let (path, steps) = (repo_temp_dir.join(asset), steps.to_vec());
+            let result = task::spawn_blocking(move || transform::apply(&path, &steps)).await?;
+            result.with_context(|| format!("transforming release asset {}", asset))?;
+        }
         Ok(())
     }
 
+    /// ABIs supported by the device, read on first use. `None` if `repo` has no ABI-specific
+    /// assets, or there is no device, in which case assets for every ABI are used
+    async fn device_abis(&mut self, repo: &Repository) -> Result<Option<Vec<String>>> {
+        if !repo.has_abi_assets() {
+            return Ok(None);
+        }
+
+        if self.abis.is_none() {
+            let Some(adb) = self.adb.as_mut() else {
+                return Ok(None);
+            };
+            let abis = adb.abis().await?;
+            info!("Device ABIs: {}", abis.join(", "));
+            self.abis = Some(abis);
+        }
+        Ok(self.abis.clone())
+    }
+
+    /// Refuses to install a built-in config onto anything but an Ai Pin unless the user confirms
+    /// at the prompt, and reads the firmware fingerprint for compatibility checks
+    async fn check_device(&mut self) -> Result<()> {
+        let require_pin = self.config.is_builtin() && !self.allow_any_device;
+        if !require_pin && self.config.firmware_compatibility.is_empty() {
+            return Ok(());
+        }
+
+        let identity = self.adb()?.device_identity().await?;
+        info!("Connected device: {}", identity);
+
+        if require_pin && !identity.is_pin() {
+            // Only someone at the prompt can override this, as the default answer is no
+            let prompt = format!(
+                "This device ({} {}) doesn't identify as an Ai Pin. Install to it anyway?",
+                identity.manufacturer, identity.model
+            );
+            if self.prompter.is_none() || !self.confirm(&prompt, false).await? {
+                return Err(InstallerError::UnsupportedDevice {
+                    device: identity.to_string(),
+                });
+            }
+        }
+
+        self.firmware = Some(identity.fingerprint);
+        Ok(())
+    }
+
+    /// Captures the host, device, and config of this run for its report. The device's
+    /// fingerprint is read if no check has read it already, except when replaying
+    async fn capture_environment(&mut self) -> InstallEnvironment {
+        let mut environment = InstallEnvironment::host(self.config.source());
+        if let Some(adb) = self.adb.as_mut() {
+            environment.adb_backend = Some(adb.backend().to_string());
+            environment.device_fingerprint = match &self.firmware {
+                Some(fingerprint) => Some(fingerprint.clone()),
+                None if adb.is_replaying() => None,
+                None => adb
+                    .device_identity()
+                    .await
+                    .ok()
+                    .map(|identity| identity.fingerprint),
+            };
+        }
+        environment
+    }
+
+    /// Asks before installing to a device other than those installed to before, remembering it if
+    /// the user agrees. Replays don't touch the known devices
+    async fn check_known_device(&mut self) -> Result<()> {
+        let replaying = self.adb.as_ref().is_some_and(AdbManager::is_replaying);
+        if !self.check_known_device || replaying {
+            return Ok(());
+        }
+
+        let adb = self.adb()?;
+        let serial = adb.serial_number().await?;
+        let identity = adb.device_identity().await?;
+
+        let mut known = KnownDevices::load();
+        if known.get(&serial).is_some() {
+            return Ok(());
+        }
+
+        if let Some(first) = known.devices.first() {
+            let prompt = format!(
+                "This device ({} {}) isn't one you've installed to before, such as {} {}. Install to it?",
+                identity.model, serial, first.model, first.serial
+            );
+            if !self.confirm(&prompt, false).await? {
+                return Err(InstallerError::Declined { prompt });
+            }
+        } else {
+            info!("Remembering {} {} as your device", identity.model, serial);
+        }
+
+        known.add(&serial, &identity.model, &identity.fingerprint);
+        known.save()
+    }
+
+    /// Refuses to start an install below the config's minimum battery level, or only warns when
+    /// the device is charging or told to ignore the battery
+    async fn check_battery(&mut self) -> Result<()> {
+        let min_level = self.config.battery.min_level;
+        if min_level == 0 {
+            return Ok(());
+        }
+
+        let battery = match self.adb()?.battery().await {
+            Ok(battery) if battery.level >= min_level => return Ok(()),
+            Ok(battery) => battery,
+            Err(e) => {
+                warn!("Couldn't read the device battery level: {}", e);
+                return Ok(());
+            }
+        };
+        let level = battery.level;
+
+        if battery.charging {
+            self.add_warning(
+                WarningKind::LowBattery,
+                format!(
+                    "Installing with the battery at {}%, below {}%, as the device is charging. Keep it connected to power until the install finishes",
+                    level, min_level
+                ),
+            );
+            return Ok(());
+        }
+        if !self.ignore_battery {
+            return Err(InstallerError::LowBattery { level, min_level });
+        }
+        self.add_warning(
+            WarningKind::LowBattery,
+            format!(
+                "Installing with the battery at {}%, below {}%. The device may shut down before finishing",
+                level, min_level
+            ),
+        );
+        Ok(())
+    }
+
+    /// Warns when the device clock is far enough off to break HTTPS from the device, offering to
+    /// run the config's clock sync steps
+    async fn check_clock(&mut self) -> Result<()> {
+        let max_skew = self.config.clock.max_skew_secs;
+        let skew = match self.adb()?.clock_skew().await {
+            Ok(skew) if skew.unsigned_abs() <= max_skew => return Ok(()),
+            Ok(skew) => skew,
+            Err(e) => {
+                warn!("Couldn't read the device clock: {}", e);
+                return Ok(());
+            }
+        };
+
+        let direction = if skew > 0 { "ahead of" } else { "behind" };
+        let message = format!(
+            "Device clock is {} {} this computer's. HTTPS from the device, such as to OpenAI or SearXNG, will fail until it is corrected",
+            format_skew(skew.unsigned_abs()),
+            direction
+        );
+        self.add_warning(WarningKind::ClockSkew, message);
+
+        let sync = self.config.clock.sync.clone();
+        if sync.is_empty() || !self.confirm("Sync the device clock now?", true).await? {
+            return Ok(());
+        }
+
+        info!("Syncing device clock");
+        for (index, step) in sync.iter().enumerate() {
+            self.execute_install_step(step, "global", &[])
+                .instrument(step_span(index, &step.describe()))
+                .instrument(debug_span!("repo", repo = "global"))
+                .await
+                .with_context(|| format!("clock sync step {} ({})", index + 1, step.describe()))?;
+        }
+
+        // Network time can take a moment to apply
+        sleep(Duration::from_secs(5)).await;
+        match self.adb()?.clock_skew().await {
+            Ok(skew) if skew.unsigned_abs() <= max_skew => info!("Device clock synced"),
+            Ok(skew) => self.add_warning(
+                WarningKind::ClockSkew,
+                format!(
+                    "Device clock is still {} off after syncing",
+                    format_skew(skew.unsigned_abs())
+                ),
+            ),
+            Err(e) => warn!("Couldn't read the device clock: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Warns about, or refuses, a version known not to work on the device firmware
+    fn check_firmware(&mut self, repo: &Repository, version: &str) -> Result<()> {
+        let Some(fingerprint) = self.firmware.clone() else {
+            return Ok(());
+        };
+        let Some(rule) = self
+            .config
+            .incompatible_firmware(&fingerprint, &repo.name, version)
+        else {
+            return Ok(());
+        };
+
+        if rule.block {
+            if let Some(note) = &rule.note {
+                warn!("{}", note);
+            }
+            return Err(InstallerError::IncompatibleFirmware {
+                repo: repo.name.clone(),
+                version: version.to_string(),
+                fingerprint,
+                note: rule.note.clone(),
+            });
+        }
+
+        let mut message = format!(
+            "{} {} is not known to work on firmware {}, continuing anyway",
+            repo.name, version, fingerprint
+        );
+        if let Some(note) = &rule.note {
+            message = format!("{}: {}", message, note);
+        }
+        self.add_warning(WarningKind::IncompatibleFirmware, message);
+        Ok(())
+    }
+
+    /// Fails before touching the device if any custom step has no registered executor
+    fn check_custom_steps(&self, active_repos: &[Repository]) -> Result<()> {
+        let steps = self
+            .config
+            .global_setup
+            .iter()
+            .chain(&self.config.clock.sync)
+            .chain(&self.config.reboot.verify)
+            .chain(active_repos.iter().flat_map(|repo| &repo.installation));
+
+        for step in steps {
+            if let InstallStep::Custom(step) = step {
+                if !self.steps.contains(&step.step_type) {
+                    return Err(InstallerError::Config(format!(
+                        "No executor registered for step type '{}'",
+                        step.step_type
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Directory holding the downloaded assets of `repo_name`
+    fn asset_dir(&self, repo_name: &str) -> PathBuf {
+        if repo_name == "global" {
+            self.temp_dir.clone()
+        } else {
+            self.temp_dir.join(repo_name)
+        }
+    }
+
+    /// Logs a problem that doesn't fail the operation, and keeps it for the report
+    fn add_warning(&mut self, kind: WarningKind, message: String) {
+        warn!("{}", message);
+        let warning = InstallWarning {
+            kind,
+            repository: self.repository.clone(),
+            message,
+        };
+        if let Some(sender) = &self.warning_sender {
+            // The subscriber may have gone away
+            let _ = sender.send(warning.clone());
+        }
+        self.warnings.push(warning);
+    }
+
+    /// Messages of the warnings recorded for `repository`
+    fn repository_warnings(&self, repository: &str) -> Vec<String> {
+        self.warnings
+            .iter()
+            .filter(|warning| warning.repository.as_deref() == Some(repository))
+            .map(|warning| warning.message.clone())
+            .collect()
+    }
+
+    fn set_phase(&mut self, phase: InstallPhase) {
+        let changed = self.phase.send_if_modified(|current| {
+            let changed = *current != phase;
+            *current = phase;
+            changed
+        });
+        if changed {
+            debug!(phase = %phase, "Entering {} phase", phase);
+            let marker = match &self.repository {
+                Some(repo) => format!("PHASE {} repo {}", phase, repo),
+                None => format!("PHASE {}", phase),
+            };
+            self.mark(&marker);
+        }
+    }
+
+    /// Writes `marker` to the device log under `LOG_MARKER_TAG`, so device-side events can be
+    /// lined up with what the installer was doing. Does nothing without a device
+    fn mark(&mut self, marker: &str) {
+        if let Some(adb) = self.adb.as_mut() {
+            adb.log_marker(LOG_MARKER_TAG, marker);
+        }
+    }
+
+    fn adb(&mut self) -> Result<&mut AdbManager> {
+        self.adb.as_mut().ok_or(InstallerError::NoDevice)
+    }
+
     fn is_cancelled(&self) -> bool {
         self.cancellation_token
             .as_ref()
             .map_or(false, |token| token.is_cancelled())
     }
 }
+
+/// Runs a parallel-safe step, which needs nothing from the engine beyond a device connection
+async fn execute_file_step(
+    adb: &mut AdbManager,
+    step: &InstallStep,
+    asset_dir: &Path,
+    token: Option<&CancellationToken>,
+) -> Result<()> {
+    match step {
+        InstallStep::CreateDirectories { paths } => {
+            for path in paths {
+                info!("Creating directory: {}", path);
+                adb.create_directory(path).await?;
+            }
+        }
+
+        InstallStep::PushFiles { files } => {
+            for file_push in files {
+                if token.is_some_and(|token| token.is_cancelled()) {
+                    break;
+                }
+
+                push_files(adb, asset_dir, file_push).await?;
+            }
+        }
+
+        InstallStep::CreateConfig {
+            path,
+            content,
+            only_if_missing,
+            update_if_changed,
+            merge,
+        } => {
+            if *only_if_missing && adb.file_exists(path).await? {
+                info!("Config already exists: {}", path);
+                return Ok(());
+            }
+
+            let existing = if *update_if_changed || merge.is_some() {
+                adb.read_file(path).await?
+            } else {
+                None
+            };
+            let Some(existing) = existing else {
+                info!("Creating config: {}", path);
+                adb.write_file(path, content).await?;
+                return Ok(());
+            };
+
+            let updated = match merge {
+                Some(format) => {
+                    let Some(merged) = merge_config(*format, &existing, content, path)? else {
+                        info!("Config already has every key: {}", path);
+                        return Ok(());
+                    };
+                    merged
+                }
+                None if existing == content.trim() => {
+                    info!("Config unchanged: {}", path);
+                    return Ok(());
+                }
+                None => content.clone(),
+            };
+
+            info!("Updating config: {}", path);
+            adb.write_file(path, &updated).await?;
+        }
+
+        InstallStep::InstallUnit { file, enable } => {
+            let pattern = asset_dir.join(file);
+            let units =
+                glob(&pattern.to_string_lossy())?.collect::<std::result::Result<Vec<_>, _>>()?;
+            if units.is_empty() {
+                return Err(InstallerError::Config(format!(
+                    "No unit files match '{}'",
+                    file
+                )));
+            }
+
+            if *enable {
+                adb.create_directory(&format!("{}/enabled", PINITD_UNIT_DIR))
+                    .await?;
+            }
+
+            for unit in units {
+                let name = unit.file_name().unwrap().to_string_lossy().to_string();
+                info!("Installing unit: {}", name);
+                adb.push_file(&unit, &format!("{}/{}", PINITD_UNIT_DIR, name))
+                    .await?;
+
+                if *enable {
+                    info!("Enabling unit: {}", name);
+                    adb.shell(&format!(
+                        "touch {}",
+                        quote(&format!("{}/enabled/{}", PINITD_UNIT_DIR, name))
+                    ))
+                    .await?;
+                }
+            }
+        }
+
+        step => {
+            return Err(InstallerError::Internal(format!(
+                "Step cannot run in parallel: {}",
+                step.describe()
+            )))
+        }
+    }
+    Ok(())
+}
+
+async fn push_files(
+    adb: &mut AdbManager,
+    repo_temp_dir: &Path,
+    file_push: &FilePush,
+) -> Result<()> {
+    let mut local_files = Vec::new();
+    for pattern in file_push.local.patterns() {
+        for entry in glob(&repo_temp_dir.join(pattern).to_string_lossy())? {
+            let local_file = entry?;
+            // `**` also matches directories
+            if local_file.is_file() && !local_files.contains(&local_file) {
+                local_files.push(local_file);
+            }
+        }
+    }
+
+    let excluded = file_push
+        .exclude
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    for local_file in local_files {
+        let name = local_file
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        if excluded.iter().any(|pattern| pattern.matches(&name)) {
+            info!("Skipping excluded file: {}", name);
+            continue;
+        }
+
+        let path = local_file
+            .strip_prefix(repo_temp_dir)
+            .unwrap_or(&local_file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let remote_path = file_push.remote_path(&path)?;
+        if is_up_to_date(adb, &local_file, &remote_path).await? {
+            info!("Up to date: {}", remote_path);
+        } else {
+            info!("Pushing: {} -> {}", path, remote_path);
+            adb.push_file(&local_file, &remote_path).await?;
+        }
+
+        if let Some(chmod) = &file_push.chmod {
+            adb.shell(&format!("chmod {} {}", quote(chmod), quote(&remote_path)))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `remote` already holds `local`: a file of the same size and SHA-256. Modification
+/// times aren't compared, as the device clock is often wrong
+async fn is_up_to_date(adb: &mut AdbManager, local: &Path, remote: &str) -> Result<bool> {
+    let Some(stat) = adb.stat(remote).await? else {
+        return Ok(false);
+    };
+    if stat.file_type != FileType::File || stat.size != fs::metadata(local).await?.len() {
+        return Ok(false);
+    }
+    let Some(remote_hash) = adb.sha256(remote).await? else {
+        return Ok(false);
+    };
+    let local = local.to_path_buf();
+    let local_hash = task::spawn_blocking(move || file_sha256(&local)).await??;
+    Ok(local_hash == remote_hash)
+}
+
+/// Sizes of the files under `dir`
+fn file_sizes(dir: &Path) -> Vec<u64> {
+    glob(&dir.join("**/*").to_string_lossy())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|path| path.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .collect()
+}
+
+fn file_sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// `existing` with the keys of `content` it lacks added, or `None` if it has them all. An
+/// existing file that doesn't parse is left alone, as it may hold the user's edits
+fn merge_config(
+    format: ConfigFormat,
+    existing: &str,
+    content: &str,
+    path: &str,
+) -> Result<Option<String>> {
+    let defaults = format.parse(content).map_err(|e| {
+        InstallerError::Config(format!(
+            "Config for {} is not valid {}: {}",
+            path, format, e
+        ))
+    })?;
+    let original = match format.parse(existing) {
+        Ok(original) => original,
+        Err(e) => {
+            warn!(
+                "Leaving {} unchanged, as it is not valid {}: {}",
+                path, format, e
+            );
+            return Ok(None);
+        }
+    };
+
+    let mut merged = original.clone();
+    add_missing_keys(&mut merged, defaults);
+    if merged == original {
+        return Ok(None);
+    }
+    format.render(&merged).map(Some)
+}
+
+/// Adds the entries of `defaults` missing from `target`, recursing into mappings present in both
+fn add_missing_keys(target: &mut serde_yaml::Value, defaults: serde_yaml::Value) {
+    let (serde_yaml::Value::Mapping(target), serde_yaml::Value::Mapping(defaults)) =
+        (target, defaults)
+    else {
+        return;
+    };
+
+    for (key, value) in defaults {
+        match target.get_mut(&key) {
+            Some(existing) => add_missing_keys(existing, value),
+            None => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Keys for the writes of `step` that are safe to skip when repeated: one per directory created,
+/// or the config written
+fn idempotent_writes(step: &InstallStep) -> Vec<String> {
+    match step {
+        InstallStep::CreateDirectories { paths } => {
+            paths.iter().map(|path| format!("mkdir {}", path)).collect()
+        }
+        InstallStep::CreateConfig {
+            path,
+            content,
+            only_if_missing,
+            update_if_changed,
+            merge,
+        } => vec![format!(
+            "config {} {} {:?} {} {}",
+            only_if_missing, update_if_changed, merge, path, content
+        )],
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the mode `appops get` printed is the `wanted` mode, which may be given by name or by
+/// number
+fn same_app_op_mode(mode: &str, wanted: &str) -> bool {
+    let wanted = match wanted.trim() {
+        "0" => "allow",
+        "1" => "ignore",
+        "2" => "deny",
+        "3" => "default",
+        "4" => "foreground",
+        name => name,
+    };
+    mode.eq_ignore_ascii_case(wanted)
+}
+
+/// Renders a clock difference in its largest whole unit
+fn format_skew(secs: u64) -> String {
+    match secs {
+        0..=119 => format!("{} seconds", secs),
+        120..=7199 => format!("{} minutes", secs / 60),
+        7200..=172_799 => format!("{} hours", secs / 3600),
+        _ => format!("{} days", secs / 86_400),
+    }
+}
+
+/// Span for a single cleanup or install step. `index` is zero-based
+fn step_span(index: usize, description: &str) -> tracing::Span {
+    debug_span!("step", index = index + 1, step = %description)
+}
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,9 +10,28 @@ pub enum InstallerError {
     #[error("GitHub API error: {0}")]
     GitHub(String),
 
+    #[error(
+        "{file} at {version} changed since it was last fetched (blob {expected}, now {actual})"
+    )]
+    RepoFileChanged {
+        file: String,
+        version: String,
+        expected: String,
+        actual: String,
+    },
+
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Configuration error at line {line} column {column}: {message}\n{snippet}")]
+    ConfigSyntax {
+        message: String,
+        line: usize,
+        column: usize,
+        /// The offending line, with a caret under the column
+        snippet: String,
+    },
+
     #[error("Network error: {0}")]
     Network(#[from] reqwest::Error),
 
@@ -35,6 +56,30 @@ pub enum InstallerError {
     #[error("Multiple devices connected (exactly one required)")]
     MultipleDevices,
 
+    #[error("This configuration only supports the Ai Pin, but the connected device is {device}")]
+    UnsupportedDevice { device: String },
+
+    #[error("{repo} {version} is not compatible with the device firmware ({fingerprint})")]
+    IncompatibleFirmware {
+        repo: String,
+        version: String,
+        fingerprint: String,
+        note: Option<String>,
+    },
+
+    #[error("Device did not finish booting within {secs} seconds")]
+    BootTimeout { secs: u64 },
+
+    #[error(
+        "Not enough free storage on the device: about {} MB needed, {} MB free",
+        required / 1_048_576,
+        available / 1_048_576
+    )]
+    InsufficientStorage { required: u64, available: u64 },
+
+    #[error("Device battery is at {level}%, below the {min_level}% needed to install")]
+    LowBattery { level: u8, min_level: u8 },
+
     #[error("No repositories found matching filter")]
     NoRepositoriesFound,
 
@@ -45,7 +90,20 @@ pub enum InstallerError {
     InstallationStep { step: String, reason: String },
 
     #[error("APK installation failed: {apk}, reason: {reason}")]
-    ApkInstallation { apk: String, reason: String },
+    ApkInstallation {
+        apk: String,
+        failure: ApkFailure,
+        reason: String,
+    },
+
+    #[error("Launcher {component} was not set: {reason}")]
+    LauncherNotSet { component: String, reason: String },
+
+    #[error("Declined prompt: {prompt}")]
+    Declined { prompt: String },
+
+    #[error("Repository '{repo}' is protected, as other components stop working without it")]
+    ProtectedRepository { repo: String },
 
     #[error("File not found: {path}")]
     FileNotFound { path: String },
@@ -53,8 +111,458 @@ pub enum InstallerError {
     #[error("Invalid version format: {version}")]
     InvalidVersion { version: String },
 
+    #[error("{} write different content to {target}", repositories.join(" and "))]
+    StepConflict {
+        target: String,
+        repositories: Vec<String>,
+    },
+
+    #[error("Another installation is in progress ({holder}). If it is no longer running, retry with --force-unlock")]
+    Locked { holder: String },
+
+    #[error("Cancelled")]
+    Cancelled,
+
     #[error("CLI error: {0}")]
     CLI(String),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+
+    /// An error annotated with what was being done when it occurred
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        source: Box<InstallerError>,
+    },
+}
+
+/// Stable, machine-readable description of an error for scripts and frontends
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ErrorDetails {
+    pub code: String,
+    pub numeric_code: u16,
+    pub message: String,
+    /// Structured fields of the error, such as the repository or path involved
+    pub context: Value,
+    /// Whether retrying the same operation may succeed
+    pub retryable: bool,
+    /// Suggested fix, shown separately from the message
+    pub hint: Option<String>,
+}
+
+impl InstallerError {
+    /// The underlying error, without any added context
+    pub fn root(&self) -> &InstallerError {
+        match self {
+            InstallerError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Contexts added to this error, outermost first
+    pub fn context_chain(&self) -> Vec<&str> {
+        let mut chain = Vec::new();
+        let mut error = self;
+        while let InstallerError::Context { context, source } = error {
+            chain.push(context.as_str());
+            error = source;
+        }
+        chain
+    }
+
+    /// Stable string identifier. Never change an existing code
+    pub fn code(&self) -> &'static str {
+        match self {
+            InstallerError::Adb(_) => "adb",
+            InstallerError::NoDevice => "no_device",
+            InstallerError::MultipleDevices => "multiple_devices",
+            InstallerError::UnsupportedDevice { .. } => "unsupported_device",
+            InstallerError::IncompatibleFirmware { .. } => "incompatible_firmware",
+            InstallerError::BootTimeout { .. } => "boot_timeout",
+            InstallerError::InsufficientStorage { .. } => "insufficient_storage",
+            InstallerError::LowBattery { .. } => "low_battery",
+            InstallerError::GitHub(_) => "github",
+            InstallerError::Network(_) => "network",
+            InstallerError::RepoFileChanged { .. } => "repo_file_changed",
+            InstallerError::Config(_) => "config",
+            InstallerError::ConfigSyntax { .. } => "config_syntax",
+            InstallerError::Yaml(_) => "yaml",
+            InstallerError::Json(_) => "json",
+            InstallerError::Glob(_) => "glob_pattern",
+            InstallerError::GlobMatch(_) => "glob_match",
+            InstallerError::NoRepositoriesFound => "no_repositories_found",
+            InstallerError::RepositoryNotFound { .. } => "repository_not_found",
+            InstallerError::InvalidVersion { .. } => "invalid_version",
+            InstallerError::StepConflict { .. } => "step_conflict",
+            InstallerError::InstallationStep { .. } => "installation_step",
+            InstallerError::ApkInstallation { .. } => "apk_installation",
+            InstallerError::Locked { .. } => "locked",
+            InstallerError::Cancelled => "cancelled",
+            InstallerError::LauncherNotSet { .. } => "launcher_not_set",
+            InstallerError::Declined { .. } => "declined",
+            InstallerError::ProtectedRepository { .. } => "protected_repository",
+            InstallerError::Io(_) => "io",
+            InstallerError::FileNotFound { .. } => "file_not_found",
+            InstallerError::CLI(_) => "cli",
+            InstallerError::Internal(_) => "internal",
+            InstallerError::Context { source, .. } => source.code(),
+        }
+    }
+
+    /// Stable numeric identifier, grouped by area: 1xx device, 2xx network, 3xx configuration,
+    /// 4xx installation, 5xx filesystem, 6xx CLI, 9xx internal
+    pub fn numeric_code(&self) -> u16 {
+        match self {
+            InstallerError::Adb(_) => 100,
+            InstallerError::NoDevice => 101,
+            InstallerError::MultipleDevices => 102,
+            InstallerError::UnsupportedDevice { .. } => 103,
+            InstallerError::IncompatibleFirmware { .. } => 104,
+            InstallerError::BootTimeout { .. } => 105,
+            InstallerError::InsufficientStorage { .. } => 106,
+            InstallerError::LowBattery { .. } => 107,
+            InstallerError::GitHub(_) => 200,
+            InstallerError::Network(_) => 201,
+            InstallerError::RepoFileChanged { .. } => 202,
+            InstallerError::Config(_) => 300,
+            InstallerError::Yaml(_) => 301,
+            InstallerError::Json(_) => 302,
+            InstallerError::Glob(_) => 303,
+            InstallerError::GlobMatch(_) => 304,
+            InstallerError::NoRepositoriesFound => 305,
+            InstallerError::RepositoryNotFound { .. } => 306,
+            InstallerError::InvalidVersion { .. } => 307,
+            InstallerError::StepConflict { .. } => 308,
+            InstallerError::ConfigSyntax { .. } => 309,
+            InstallerError::InstallationStep { .. } => 400,
+            InstallerError::ApkInstallation { .. } => 401,
+            InstallerError::Locked { .. } => 402,
+            InstallerError::Cancelled => 403,
+            InstallerError::LauncherNotSet { .. } => 404,
+            InstallerError::Declined { .. } => 405,
+            InstallerError::ProtectedRepository { .. } => 406,
+            InstallerError::Io(_) => 500,
+            InstallerError::FileNotFound { .. } => 501,
+            InstallerError::CLI(_) => 600,
+            InstallerError::Internal(_) => 900,
+            InstallerError::Context { source, .. } => source.numeric_code(),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.root(),
+            InstallerError::Adb(_)
+                | InstallerError::NoDevice
+                | InstallerError::MultipleDevices
+                | InstallerError::BootTimeout { .. }
+                | InstallerError::LowBattery { .. }
+                | InstallerError::GitHub(_)
+                | InstallerError::Network(_)
+                | InstallerError::Locked { .. }
+        )
+    }
+
+    /// A suggested fix for the user, where there is an obvious one
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.root() {
+            InstallerError::GitHub(message) if is_rate_limited(message) => Some(
+                "GitHub API rate limit reached. Pass --github-token (or add a token in the installer settings)",
+            ),
+            InstallerError::Adb(message) if message.to_lowercase().contains("unauthorized") => {
+                Some("Accept the RSA key prompt on the device, then retry")
+            }
+            InstallerError::NoDevice => {
+                Some("Connect the device over USB and make sure USB debugging is enabled")
+            }
+            InstallerError::MultipleDevices => {
+                Some("Use --serial to choose a device (see `adb devices`)")
+            }
+            InstallerError::UnsupportedDevice { .. } => Some(
+                "Check that the Ai Pin is the selected device, or pass --force-device to install anyway",
+            ),
+            InstallerError::IncompatibleFirmware { .. } => {
+                Some("Update the device firmware, or install a compatible version of the component")
+            }
+            InstallerError::BootTimeout { .. } => Some(
+                "The device may still be booting. Wait for it to start, check that it is connected, then retry",
+            ),
+            InstallerError::InsufficientStorage { .. } => Some(
+                "Free up space on the device, such as by removing unused apps or files, or install fewer repositories",
+            ),
+            InstallerError::LowBattery { .. } => Some(
+                "Charge the device and retry, or pass --ignore-battery to install anyway",
+            ),
+            InstallerError::RepoFileChanged { .. } => Some(
+                "The file was changed upstream without a new release. If the change is expected, remove its entry from repo_files.lock.json in the installer state directory",
+            ),
+            InstallerError::LauncherNotSet { .. } => Some(
+                "Check that the launcher app installed and is enabled, then retry. Until then, the device may have no working home screen",
+            ),
+            InstallerError::ProtectedRepository { .. } => Some(
+                "Uninstall the components relying on it first, or pass --force to uninstall it anyway",
+            ),
+            InstallerError::ApkInstallation { failure, .. } => failure.hint(),
+            _ => None,
+        }
+    }
+
+    fn context(&self) -> Value {
+        let mut context = match self.root() {
+            InstallerError::RepositoryNotFound { repo } => json!({ "repo": repo }),
+            InstallerError::InstallationStep { step, reason } => {
+                json!({ "step": step, "reason": reason })
+            }
+            InstallerError::ApkInstallation {
+                apk,
+                failure,
+                reason,
+            } => json!({ "apk": apk, "failure": failure, "reason": reason }),
+            InstallerError::RepoFileChanged {
+                file,
+                version,
+                expected,
+                actual,
+            } => json!({
+                "file": file,
+                "version": version,
+                "expected": expected,
+                "actual": actual,
+            }),
+            InstallerError::LauncherNotSet { component, reason } => {
+                json!({ "component": component, "reason": reason })
+            }
+            InstallerError::Declined { prompt } => json!({ "prompt": prompt }),
+            InstallerError::ProtectedRepository { repo } => json!({ "repo": repo }),
+            InstallerError::ConfigSyntax {
+                message,
+                line,
+                column,
+                snippet,
+            } => json!({
+                "message": message,
+                "line": line,
+                "column": column,
+                "snippet": snippet,
+            }),
+            InstallerError::FileNotFound { path } => json!({ "path": path }),
+            InstallerError::InvalidVersion { version } => json!({ "version": version }),
+            InstallerError::StepConflict {
+                target,
+                repositories,
+            } => json!({ "target": target, "repositories": repositories }),
+            InstallerError::Locked { holder } => json!({ "holder": holder }),
+            InstallerError::UnsupportedDevice { device } => json!({ "device": device }),
+            InstallerError::BootTimeout { secs } => json!({ "secs": secs }),
+            InstallerError::InsufficientStorage {
+                required,
+                available,
+            } => json!({ "required": required, "available": available }),
+            InstallerError::LowBattery { level, min_level } => {
+                json!({ "level": level, "min_level": min_level })
+            }
+            InstallerError::IncompatibleFirmware {
+                repo,
+                version,
+                fingerprint,
+                note,
+            } => json!({
+                "repo": repo,
+                "version": version,
+                "fingerprint": fingerprint,
+                "note": note,
+            }),
+            _ => json!({}),
+        };
+
+        let chain = self.context_chain();
+        if !chain.is_empty() {
+            context["chain"] = json!(chain);
+        }
+        context
+    }
+
+    pub fn details(&self) -> ErrorDetails {
+        ErrorDetails {
+            code: self.code().to_string(),
+            numeric_code: self.numeric_code(),
+            message: self.to_string(),
+            context: self.context(),
+            retryable: self.is_retryable(),
+            hint: self.hint().map(str::to_string),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self.details())
+    }
+}
+
+fn is_rate_limited(message: &str) -> bool {
+    message.contains("HTTP 403") || message.contains("HTTP 429") || message.contains("rate limit")
+}
+
+/// Why the package manager refused an APK, from the `INSTALL_FAILED_*` code in its output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApkFailure {
+    /// A newer version of the package is already installed
+    VersionDowngrade,
+    /// The installed package was signed with a different key
+    SignatureMismatch,
+    InsufficientStorage,
+    /// The APK needs a newer Android version than the device runs
+    OlderSdk,
+    /// The APK has no native code for the device's ABIs
+    NoMatchingAbis,
+    /// The file is corrupt or isn't an APK
+    InvalidApk,
+    Other,
+}
+
+impl ApkFailure {
+    /// Classifies `pm install` output
+    pub fn from_output(output: &str) -> Self {
+        match failure_code(output) {
+            Some("INSTALL_FAILED_VERSION_DOWNGRADE") => ApkFailure::VersionDowngrade,
+            Some(
+                "INSTALL_FAILED_UPDATE_INCOMPATIBLE"
+                | "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE"
+                | "INSTALL_PARSE_FAILED_INCONSISTENT_CERTIFICATES",
+            ) => ApkFailure::SignatureMismatch,
+            Some("INSTALL_FAILED_INSUFFICIENT_STORAGE") => ApkFailure::InsufficientStorage,
+            Some("INSTALL_FAILED_OLDER_SDK") => ApkFailure::OlderSdk,
+            Some("INSTALL_FAILED_NO_MATCHING_ABIS") => ApkFailure::NoMatchingAbis,
+            Some(code)
+                if code == "INSTALL_FAILED_INVALID_APK"
+                    || code.starts_with("INSTALL_PARSE_FAILED_") =>
+            {
+                ApkFailure::InvalidApk
+            }
+            _ => ApkFailure::Other,
+        }
+    }
+
+    fn hint(self) -> Option<&'static str> {
+        match self {
+            ApkFailure::VersionDowngrade => Some(
+                "A newer version is already installed. Uninstall the app from the device, install a newer release, or set `allow_downgrade` on the InstallApks step",
+            ),
+            ApkFailure::SignatureMismatch => Some(
+                "The installed app was signed with a different key, such as a development build. Uninstall it from the device, then retry",
+            ),
+            ApkFailure::InsufficientStorage => {
+                Some("The device is out of storage. Free up space on it, then retry")
+            }
+            ApkFailure::OlderSdk => {
+                Some("This release needs a newer Android version than the device runs")
+            }
+            ApkFailure::NoMatchingAbis => {
+                Some("This release has no build for the device's CPU architecture")
+            }
+            ApkFailure::InvalidApk => {
+                Some("The download may be corrupt. Clear the download cache, then retry")
+            }
+            ApkFailure::Other => None,
+        }
+    }
+}
+
+/// The first `INSTALL_*` code in `pm install` output, such as `INSTALL_FAILED_OLDER_SDK`
+fn failure_code(output: &str) -> Option<&str> {
+    let code = &output[output.find("INSTALL_")?..];
+    let end = code
+        .find(|c: char| !(c.is_ascii_uppercase() || c == '_'))
+        .unwrap_or(code.len());
+    Some(&code[..end])
 }
 
 pub type Result<T> = std::result::Result<T, InstallerError>;
+
+/// Adds context to errors as they propagate, e.g. which repository and step was running
+pub trait ResultExt<T> {
+    fn context(self, context: impl Into<String>) -> Result<T>;
+
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T>;
+}
+
+impl<T, E: Into<InstallerError>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Into<String>) -> Result<T> {
+        self.with_context(|| context.into())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T> {
+        self.map_err(|e| InstallerError::Context {
+            context: f(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_pm_install_failures() {
+        let cases = [
+            (
+                "Performing Streamed Install\nFailure [INSTALL_FAILED_VERSION_DOWNGRADE: Downgrade detected: Update version code 3 is older than current 5]",
+                ApkFailure::VersionDowngrade,
+            ),
+            (
+                "Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: Package com.penumbra.launcher signatures do not match previously installed version; ignoring!]",
+                ApkFailure::SignatureMismatch,
+            ),
+            (
+                "Failure [INSTALL_PARSE_FAILED_INCONSISTENT_CERTIFICATES: Scanning Failed.: No signature found in package of version 2 or newer]",
+                ApkFailure::SignatureMismatch,
+            ),
+            (
+                "Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]",
+                ApkFailure::InsufficientStorage,
+            ),
+            (
+                "Failure [INSTALL_FAILED_OLDER_SDK: Failed parse during installPackageLI: /data/app/vmdl123.tmp/base.apk (at Binary XML file line #7): Requires newer sdk version #33 (current version is #32)]",
+                ApkFailure::OlderSdk,
+            ),
+            (
+                "Failure [INSTALL_FAILED_NO_MATCHING_ABIS: Failed to extract native libraries, res=-113]",
+                ApkFailure::NoMatchingAbis,
+            ),
+            (
+                "Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES: Failed collecting certificates for /data/app/vmdl123.tmp/base.apk]",
+                ApkFailure::InvalidApk,
+            ),
+            ("Failure [INSTALL_FAILED_INVALID_APK]", ApkFailure::InvalidApk),
+            (
+                "Failure [INSTALL_FAILED_ABORTED: User rejected permissions]",
+                ApkFailure::Other,
+            ),
+            ("Error: Unable to open file: /data/local/tmp/app.apk", ApkFailure::Other),
+            ("", ApkFailure::Other),
+        ];
+
+        for (output, expected) in cases {
+            assert_eq!(ApkFailure::from_output(output), expected, "{}", output);
+        }
+    }
+
+    #[test]
+    fn failure_code_stops_at_the_code() {
+        assert_eq!(
+            failure_code("Failure [INSTALL_FAILED_OLDER_SDK: Requires newer sdk]"),
+            Some("INSTALL_FAILED_OLDER_SDK")
+        );
+        assert_eq!(
+            failure_code("Failure [INSTALL_FAILED_INSUFFICIENT_STORAGE]"),
+            Some("INSTALL_FAILED_INSUFFICIENT_STORAGE")
+        );
+        assert_eq!(
+            failure_code("INSTALL_FAILED_INVALID_APK"),
+            Some("INSTALL_FAILED_INVALID_APK")
+        );
+        assert_eq!(failure_code("Success"), None);
+    }
+}
@@ -1,16 +1,207 @@
-use log::{info, warn};
-use reqwest::{Client, Response};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug_span, error, info, warn, Instrument};
 
-use crate::config::VersionSpec;
+use crate::api_cache::ApiCache;
+use crate::delta::{self, AssetManifest};
 use crate::platform::Platform;
-use crate::{InstallerError, Repository, Result};
+use crate::session::{Request, Response, Session};
+use crate::{task, InstallerError, Repository, Result, ResultExt};
 
+/// Shortest time between progress reports for one download
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Prefix of asset patterns matched against an asset's label instead of its name
+pub const LABEL_PATTERN_PREFIX: &str = "label:";
+/// Most pages fetched for one list, unless overridden by `PENUMBRA_GITHUB_MAX_PAGES`
+const DEFAULT_MAX_PAGES: usize = 10;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    /// Display label set by the uploader, shown on the release page in place of the name
+    #[serde(default)]
+    pub label: Option<String>,
+    pub size: u64,
+    #[serde(rename = "browser_download_url")]
+    pub download_url: String,
+    /// `sha256:<hex>` digest of the asset, published by GitHub for newer releases
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// Release assets downloaded for one pattern
+#[derive(Debug, Clone)]
+pub struct AssetDownload {
+    pub files: Vec<PathBuf>,
+    /// Assets matching the pattern that an exclusion pattern left out
+    pub excluded: Vec<ExcludedAsset>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExcludedAsset {
+    pub name: String,
+    /// The exclusion pattern matching the asset
+    pub pattern: String,
+}
+
+/// The release assets one `releaseAssets` pattern selects
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternMatch {
+    pub pattern: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abi: Option<String>,
+    pub matched: Vec<String>,
+    pub excluded: Vec<ExcludedAsset>,
+}
+
+/// A repository's release asset patterns matched against the assets of its resolved release,
+/// without downloading anything
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetMatchReport {
+    pub repository: String,
+    pub version: String,
+    /// Every asset of the release except delta patches
+    pub assets: Vec<String>,
+    pub patterns: Vec<PatternMatch>,
+}
+
+impl AssetMatchReport {
+    /// Patterns matching no assets, which an install warns about
+    pub fn unmatched_patterns(&self) -> impl Iterator<Item = &PatternMatch> {
+        self.patterns
+            .iter()
+            .filter(|pattern| pattern.matched.is_empty())
+    }
+
+    /// Assets of the release that no pattern selects
+    pub fn unused_assets(&self) -> Vec<&str> {
+        self.assets
+            .iter()
+            .filter(|asset| {
+                !self
+                    .patterns
+                    .iter()
+                    .any(|pattern| pattern.matched.contains(asset))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    pub fn log_summary(&self) {
+        info!(
+            "{} {} has {} release assets",
+            self.repository,
+            self.version,
+            self.assets.len()
+        );
+        for pattern in &self.patterns {
+            let abi = match &pattern.abi {
+                Some(abi) => format!(" ({})", abi),
+                None => String::new(),
+            };
+            if pattern.matched.is_empty() {
+                error!("  {}{}: matched nothing", pattern.pattern, abi);
+            } else {
+                info!("  {}{}:", pattern.pattern, abi);
+            }
+            for asset in &pattern.matched {
+                info!("    {}", asset);
+            }
+            for excluded in &pattern.excluded {
+                info!("    {} (excluded by {})", excluded.name, excluded.pattern);
+            }
+        }
+
+        let unused = self.unused_assets();
+        if !unused.is_empty() {
+            info!("  Not selected: {}", unused.join(", "));
+        }
+    }
+}
+
+/// A repository file fetched at a release tag, identified by its git blob SHA
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RepoFile {
+    /// Path within the repository
+    pub path: String,
+    pub sha: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    pub html_url: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// Progress of a release asset download
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DownloadProgress {
+    /// GitHub repository the asset is from
+    pub repo: String,
+    pub asset: String,
+    pub downloaded_bytes: u64,
+    /// Size GitHub reports for the asset
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+impl DownloadProgress {
+    fn new(repo: &str, asset: &ReleaseAsset) -> Self {
+        Self {
+            repo: repo.to_string(),
+            asset: asset.name.clone(),
+            downloaded_bytes: 0,
+            total_bytes: asset.size,
+            done: false,
+        }
+    }
+}
+
+/// API quota left, and GitHub's clock when the quota was read
+#[derive(Debug, Clone)]
+pub struct ApiStatus {
+    pub limit: u64,
+    pub remaining: u64,
+    pub server_time: Option<SystemTime>,
+}
+
+#[derive(Clone)]
 pub struct GitHubClient {
     client: Client,
     auth_header: Option<String>,
+    cancellation_token: Option<CancellationToken>,
+    session: Option<Arc<Session>>,
+    cache: Option<ApiCache>,
+    progress: Option<mpsc::UnboundedSender<DownloadProgress>>,
+    /// Asset lists of the releases this client and its clones have fetched, by `owner/repo@tag`
+    releases: Arc<Mutex<HashMap<String, Vec<ReleaseAsset>>>>,
+    /// Most pages fetched for one paginated list
+    max_pages: usize,
+}
+
+/// Status and JSON body of a GitHub API response
+struct ApiResponse {
+    status: StatusCode,
+    body: Option<Value>,
+    /// URL of the next page, from the `Link` header of a paginated response
+    next: Option<String>,
 }
 
 impl GitHubClient {
@@ -19,80 +210,453 @@ impl GitHubClient {
     }
 
     pub fn new_with_token(token: Option<String>) -> Self {
-        let client = Client::builder()
-            .user_agent(Platform::user_agent())
-            .build()
-            .unwrap();
+        let client = Platform::http_client().unwrap();
 
         let auth_header = token.map(|t| format!("Bearer {}", t));
 
         Self {
             client,
             auth_header,
+            cancellation_token: None,
+            session: None,
+            cache: ApiCache::from_env(),
+            progress: None,
+            releases: Arc::default(),
+            max_pages: std::env::var("PENUMBRA_GITHUB_MAX_PAGES")
+                .ok()
+                .and_then(|pages| pages.parse::<usize>().ok())
+                .filter(|pages| *pages > 0)
+                .unwrap_or(DEFAULT_MAX_PAGES),
         }
     }
 
-    pub async fn get_version(&self, repo: &Repository) -> Result<String> {
-        match &repo.version {
-            VersionSpec::Version(v) if v == "latest" => {
-                self.get_latest_version(&repo.owner, &repo.repo).await
+    /// Headers that authenticate API requests
+    fn credentials(&self) -> HeaderMap {
+        let mut credentials = HeaderMap::new();
+        if let Some(value) = self
+            .auth_header
+            .as_deref()
+            .and_then(|auth| HeaderValue::from_str(auth).ok())
+        {
+            credentials.insert(AUTHORIZATION, value);
+        }
+        credentials
+    }
+
+    /// Aborts in-flight downloads with `InstallerError::Cancelled` once `token` is cancelled
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.cancellation_token = token;
+    }
+
+    /// Replaces the API response cache. `None` always queries GitHub
+    pub fn set_api_cache(&mut self, cache: Option<ApiCache>) {
+        self.cache = cache;
+    }
+
+    /// Limits paginated lists, such as a repository's releases, to `max_pages` pages of 100
+    /// entries. Lists with more are cut off with a warning
+    pub fn set_max_pages(&mut self, max_pages: usize) {
+        self.max_pages = max_pages.max(1);
+    }
+
+    /// Reports the progress of release asset downloads to `sender`, at most every
+    /// `PROGRESS_INTERVAL` per asset and once each finishes
+    pub fn set_progress(&mut self, sender: Option<mpsc::UnboundedSender<DownloadProgress>>) {
+        self.progress = sender;
+    }
+
+    /// Records every request and its response metadata to `session`, or replays them from it
+    pub fn set_session(&mut self, session: Option<Arc<Session>>) {
+        self.session = session;
+    }
+
+    fn replayed(&self, request: &Request) -> Result<Option<Response>> {
+        match &self.session {
+            Some(session) => session.replayed(request),
+            None => Ok(None),
+        }
+    }
+
+    fn record(&self, request: Request, response: Response) {
+        if let Some(session) = &self.session {
+            session.log(request, response);
+        }
+    }
+
+    /// Sends an authenticated GET to the GitHub API
+    async fn api_get(&self, url: &str) -> Result<ApiResponse> {
+        let request = Request::Http {
+            url: url.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            let (status, body, next) = response.into_http()?;
+            return Ok(ApiResponse {
+                status: StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+                body,
+                next,
+            });
+        }
+
+        if let Some((status, body, next)) = self.cache.as_ref().and_then(|cache| cache.get(url)) {
+            // Recorded like a network response, so replays of this session don't diverge
+            self.record(
+                request,
+                Response::Http {
+                    status,
+                    bytes: 0,
+                    body: Some(body.clone()),
+                    next: next.clone(),
+                },
+            );
+            return Ok(ApiResponse {
+                status: StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+                body: Some(body),
+                next,
+            });
+        }
+
+        let response =
+            match Platform::get_with_credentials(&self.client, url, &self.credentials()).await {
+                Ok(response) => response,
+                Err(e) => {
+                    self.record(request, Response::Error(e.to_string()));
+                    return Err(e);
+                }
+            };
+
+        let status = response.status();
+        let next = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page_url);
+        let bytes = response.bytes().await?;
+        let body = serde_json::from_slice::<Value>(&bytes).ok();
+
+        if let (Some(cache), Some(body)) = (&self.cache, &body) {
+            if status.is_success() {
+                cache.put(url, status.as_u16(), body, next.as_deref());
+            }
+        }
+
+        self.record(
+            request,
+            Response::Http {
+                status: status.as_u16(),
+                bytes: bytes.len() as u64,
+                body: body.clone(),
+                next: next.clone(),
+            },
+        );
+
+        Ok(ApiResponse { status, body, next })
+    }
+
+    /// Fetches every page of a GitHub API list, following `Link` headers from `url` up to the
+    /// page limit
+    async fn api_get_list(&self, url: &str, action: &str) -> Result<Vec<Value>> {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let mut url = Some(format!("{}{}per_page=100", url, separator));
+        let mut items = Vec::new();
+        let mut pages = 0;
+
+        while let Some(page_url) = url.take() {
+            if pages == self.max_pages {
+                warn!(
+                    "Stopped after {} pages of results while trying to {}, so some may be missing",
+                    pages, action
+                );
+                break;
             }
-            VersionSpec::Version(v) => Ok(v.clone()),
+
+            let response = self.api_get(&page_url).await?;
+            let next = response.next.clone();
+            let json = validate_response(response, action, self.auth_header.is_some())?;
+            let Value::Array(page) = json else {
+                return Err(InstallerError::GitHub(format!(
+                    "Failed to {action}: expected a list"
+                )));
+            };
+
+            items.extend(page);
+            pages += 1;
+            url = next;
         }
+
+        Ok(items)
     }
 
-    async fn get_latest_version(&self, owner: &str, repo: &str) -> Result<String> {
+    /// Reads the API quota, which doesn't count against it. Goes to the network even when
+    /// responses are cached, so it also shows whether GitHub can be reached
+    pub async fn api_status(&self) -> Result<ApiStatus> {
+        let response = Platform::get_with_credentials(
+            &self.client,
+            "https://api.github.com/rate_limit",
+            &self.credentials(),
+        )
+        .await?;
+        let server_time = response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(parse_http_date);
+        let status = response.status();
+        let body = serde_json::from_slice::<Value>(&response.bytes().await?).ok();
+        let body = validate_response(
+            ApiResponse {
+                status,
+                body,
+                next: None,
+            },
+            "read the API rate limit",
+            self.auth_header.is_some(),
+        )?;
+
+        let rate = &body["rate"];
+        Ok(ApiStatus {
+            limit: rate["limit"].as_u64().unwrap_or(0),
+            remaining: rate["remaining"].as_u64().unwrap_or(0),
+            server_time,
+        })
+    }
+
+    /// Resolves the tag to install for `repo` with the resolver its `version` chooses
+    pub async fn get_version(&self, repo: &Repository) -> Result<String> {
+        repo.version
+            .resolver()?
+            .resolve(self, &repo.owner, &repo.repo)
+            .await
+    }
+
+    /// Fetches the latest published (non-prerelease) release of a repository
+    pub async fn get_latest_release(&self, owner: &str, repo: &str) -> Result<Release> {
         let url = format!(
             "https://api.github.com/repos/{}/{}/releases/latest",
             owner, repo
         );
-        let mut request = self.client.get(&url);
+        let response = self.api_get(&url).await?;
+        let json = validate_response(
+            response,
+            &format!("fetch latest '{repo}' release"),
+            self.auth_header.is_some(),
+        )?;
 
-        if let Some(ref auth) = self.auth_header {
-            request = request.header("Authorization", auth);
-        }
+        serde_json::from_value(json)
+            .map_err(|e| InstallerError::GitHub(format!("Malformed release: {}", e)))
+    }
 
-        let response = request.send().await?;
+    pub(crate) async fn get_latest_version(&self, owner: &str, repo: &str) -> Result<String> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/releases/latest",
+            owner, repo
+        );
+        let response = self.api_get(&url).await?;
 
-        if response.status().is_success() {
-            let json: Value = response.json().await?;
-            if let Some(tag_name) = json["tag_name"].as_str() {
+        if response.status.is_success() {
+            if let Some(tag_name) = response
+                .body
+                .as_ref()
+                .and_then(|json| json["tag_name"].as_str())
+            {
                 return Ok(tag_name.to_string());
             }
         }
 
-        let url = format!("https://api.github.com/repos/{}/{}/releases", owner, repo);
-        let mut request = self.client.get(&url);
-
-        if let Some(ref auth) = self.auth_header {
-            request = request.header("Authorization", auth);
-        }
-
-        let response = request.send().await?;
-
+        // Only prereleases, which `/releases/latest` skips, so take the newest of any kind. That
+        // is the first on the first page, so there's no need to list them all
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/releases?per_page=1",
+            owner, repo
+        );
+        let response = self.api_get(&url).await?;
         let json = validate_response(
             response,
             &format!("fetch '{repo}' releases"),
             self.auth_header.is_some(),
-        )
-        .await?;
+        )?;
+        json.as_array()
+            .into_iter()
+            .flatten()
+            .filter(|release| !release["draft"].as_bool().unwrap_or(false))
+            .find_map(|release| release["tag_name"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| InstallerError::GitHub("No releases found".to_string()))
+    }
 
-        let releases = json
-            .as_array()
-            .ok_or_else(|| InstallerError::GitHub("Expected array of releases".to_string()))?;
+    /// Tag names of a repository's releases, newest first, leaving out drafts
+    pub async fn list_release_tags(&self, owner: &str, repo: &str) -> Result<Vec<String>> {
+        let url = format!("https://api.github.com/repos/{}/{}/releases", owner, repo);
+        let releases = self
+            .api_get_list(&url, &format!("fetch '{repo}' releases"))
+            .await?;
+
+        Ok(releases
+            .iter()
+            .filter(|release| !release["draft"].as_bool().unwrap_or(false))
+            .filter_map(|release| release["tag_name"].as_str())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Names of a repository's tags, whether or not they have releases
+    pub async fn list_tags(&self, owner: &str, repo: &str) -> Result<Vec<String>> {
+        let url = format!("https://api.github.com/repos/{}/{}/tags", owner, repo);
+        let tags = self
+            .api_get_list(&url, &format!("fetch '{repo}' tags"))
+            .await?;
+
+        Ok(tags
+            .iter()
+            .filter_map(|tag| tag["name"].as_str())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Matches each of `repo`'s release asset patterns, for every ABI, against the assets of
+    /// its resolved release
+    pub async fn match_assets(&self, repo: &Repository) -> Result<AssetMatchReport> {
+        let version = self.get_version(repo).await?;
+        let assets = self
+            .release_assets(&repo.owner, &repo.repo, &version)
+            .await?;
+
+        // Without device ABIs, the selection has one entry per `releaseAssets` entry, in order
+        let patterns = repo
+            .asset_selection(None)
+            .into_iter()
+            .zip(&repo.release_assets)
+            .map(|((pattern, exclude_patterns), asset_pattern)| {
+                let (matched, excluded) = self.select_assets(&assets, pattern, &exclude_patterns);
+                PatternMatch {
+                    pattern: pattern.to_string(),
+                    abi: asset_pattern.abi().map(str::to_string),
+                    matched: matched.into_iter().map(|asset| asset.name).collect(),
+                    excluded,
+                }
+            })
+            .collect();
+
+        Ok(AssetMatchReport {
+            repository: repo.name.clone(),
+            version,
+            assets: assets
+                .into_iter()
+                .map(|asset| asset.name)
+                .filter(|name| !delta::is_patch(name))
+                .collect(),
+            patterns,
+        })
+    }
+
+    /// The release assets `repo` downloads at `version` for a device supporting `device_abis`,
+    /// each once even when several patterns match it
+    pub async fn selected_assets(
+        &self,
+        repo: &Repository,
+        version: &str,
+        device_abis: Option<&[String]>,
+    ) -> Result<Vec<ReleaseAsset>> {
+        let assets = self
+            .release_assets(&repo.owner, &repo.repo, version)
+            .await?;
+        let selection = repo.asset_selection(device_abis);
+
+        Ok(assets
+            .into_iter()
+            .filter(|asset| !delta::is_patch(&asset.name))
+            .filter(|asset| {
+                selection.iter().any(|(pattern, exclude_patterns)| {
+                    self.matches_asset(asset, pattern)
+                        && !exclude_patterns
+                            .iter()
+                            .any(|exclude_pattern| self.matches_asset(asset, exclude_pattern))
+                })
+            })
+            .collect())
+    }
 
-        if releases.is_empty() {
-            return Err(InstallerError::GitHub("No releases found".to_string()));
+    /// Resolves the release assets matching `pattern`, minus any matching `exclude_patterns`
+    pub async fn find_release_assets(
+        &self,
+        owner: &str,
+        repo: &str,
+        version: &str,
+        pattern: &str,
+        exclude_patterns: &[String],
+    ) -> Result<Vec<ReleaseAsset>> {
+        let assets = self.release_assets(owner, repo, version).await?;
+        Ok(self.select_assets(&assets, pattern, exclude_patterns).0)
+    }
+
+    /// Parsed assets of a release. Tagged releases are only fetched once
+    pub(crate) async fn release_assets(
+        &self,
+        owner: &str,
+        repo: &str,
+        version: &str,
+    ) -> Result<Vec<ReleaseAsset>> {
+        let key = format!("{}/{}@{}", owner, repo, version);
+        if let Some(assets) = self.releases.lock().unwrap().get(&key) {
+            return Ok(assets.clone());
         }
 
-        let latest_release = &releases[0];
-        let tag_name = latest_release["tag_name"]
-            .as_str()
-            .ok_or_else(|| InstallerError::GitHub("No tag_name found in release".to_string()))?;
+        let assets = self
+            .get_release_assets(owner, repo, version)
+            .await?
+            .into_iter()
+            .map(|asset| {
+                serde_json::from_value(asset)
+                    .map_err(|e| InstallerError::GitHub(format!("Malformed release asset: {}", e)))
+            })
+            .collect::<Result<Vec<ReleaseAsset>>>()?;
+
+        // `latest` moves with each release
+        if version != "latest" {
+            self.releases.lock().unwrap().insert(key, assets.clone());
+        }
+        Ok(assets)
+    }
+
+    /// Assets matching `pattern`, minus patches and any matching `exclude_patterns`, and the
+    /// excluded assets with the pattern excluding each. Patterns starting with `label:` match
+    /// the asset's label, the rest its name
+    fn select_assets(
+        &self,
+        assets: &[ReleaseAsset],
+        pattern: &str,
+        exclude_patterns: &[String],
+    ) -> (Vec<ReleaseAsset>, Vec<ExcludedAsset>) {
+        let mut matched = Vec::new();
+        let mut excluded = Vec::new();
+
+        for asset in assets {
+            if !self.matches_asset(asset, pattern) || delta::is_patch(&asset.name) {
+                continue;
+            }
+
+            let exclusion = exclude_patterns
+                .iter()
+                .find(|exclude_pattern| self.matches_asset(asset, exclude_pattern));
+
+            if let Some(exclusion) = exclusion {
+                info!("  Skipping excluded asset: {}", asset.name);
+                excluded.push(ExcludedAsset {
+                    name: asset.name.clone(),
+                    pattern: exclusion.clone(),
+                });
+                continue;
+            }
+
+            matched.push(asset.clone());
+        }
 
-        Ok(tag_name.to_string())
+        (matched, excluded)
     }
 
+    /// Downloads the release assets matching `pattern` to `dest_dir`, returning their paths and
+    /// the assets left out by `exclude_patterns`. Assets named in `downloaded` were already
+    /// fetched by an earlier pattern and are only returned; the rest are added to it
+    #[allow(clippy::too_many_arguments)]
     pub async fn download_asset(
         &self,
         owner: &str,
@@ -101,47 +665,118 @@ impl GitHubClient {
         pattern: &str,
         dest_dir: &Path,
         exclude_patterns: &[String],
-    ) -> Result<Vec<PathBuf>> {
+        downloaded: &mut BTreeSet<String>,
+    ) -> Result<AssetDownload> {
         fs::create_dir_all(dest_dir).await?;
 
-        let assets = self.get_release_assets(owner, repo, version).await?;
+        let release_assets = self.release_assets(owner, repo, version).await?;
+        let (assets, excluded) = self.select_assets(&release_assets, pattern, exclude_patterns);
+        let mut manifest = AssetManifest::load(dest_dir);
         let mut downloaded_files = Vec::new();
 
         for asset in assets {
-            let name = asset["name"]
-                .as_str()
-                .ok_or_else(|| InstallerError::GitHub("Asset has no name".to_string()))?;
+            let dest_path = dest_dir.join(&asset.name);
+            if !downloaded.insert(asset.name.clone()) {
+                info!("  Already downloaded for another pattern: {}", asset.name);
+                downloaded_files.push(dest_path);
+                continue;
+            }
 
-            if self.matches_pattern(name, pattern) {
-                let should_exclude = exclude_patterns
-                    .iter()
-                    .any(|exclude_pattern| self.matches_pattern(name, exclude_pattern));
+            let base_version = manifest
+                .version(&asset.name)
+                .filter(|base| *base != version && dest_path.exists())
+                .map(str::to_string);
 
-                if should_exclude {
-                    info!("  Skipping excluded asset: {}", name);
-                    continue;
+            let patched = match base_version {
+                Some(base) => {
+                    self.download_patched(repo, &asset, &release_assets, &base, &dest_path)
+                        .await?
                 }
+                None => false,
+            };
+            if !patched {
+                let progress = DownloadProgress::new(repo, &asset);
+                self.download_file_from_url(&asset.download_url, &dest_path, Some(progress))
+                    .await?;
+            }
 
-                let download_url = asset["browser_download_url"].as_str().ok_or_else(|| {
-                    InstallerError::GitHub("Asset has no download URL".to_string())
-                })?;
+            manifest.set(&asset.name, version);
+            manifest.save(dest_dir)?;
+            downloaded_files.push(dest_path);
 
-                let dest_path = dest_dir.join(name);
-                self.download_file_from_url(download_url, &dest_path)
-                    .await?;
-                downloaded_files.push(dest_path);
+            info!("  Downloaded: {}", asset.name);
+        }
 
-                info!("  Downloaded: {}", name);
+        Ok(AssetDownload {
+            files: downloaded_files,
+            excluded,
+        })
+    }
+
+    /// Updates `dest`, which holds `asset` from the `base_version` release, by downloading and
+    /// applying a published patch. Returns false, leaving `dest` untouched, if there is no
+    /// usable patch or it fails to apply, so the caller can fall back to a full download
+    async fn download_patched(
+        &self,
+        repo: &str,
+        asset: &ReleaseAsset,
+        release_assets: &[ReleaseAsset],
+        base_version: &str,
+        dest: &Path,
+    ) -> Result<bool> {
+        let patch_name = delta::patch_name(&asset.name, base_version);
+        let Some(patch) = release_assets.iter().find(|a| a.name == patch_name) else {
+            return Ok(false);
+        };
+        let Some(digest) = asset.digest.clone() else {
+            info!(
+                "  Ignoring patch {}, as {} has no digest",
+                patch.name, asset.name
+            );
+            return Ok(false);
+        };
+
+        info!(
+            "  Patching {} from {} ({} bytes instead of {})",
+            asset.name, base_version, patch.size, asset.size
+        );
+        let patch_path = dest.with_file_name(&patch.name);
+        let patched_path = dest.with_file_name(format!("{}.patched", asset.name));
+
+        let progress = DownloadProgress::new(repo, patch);
+        let result = match self
+            .download_file_from_url(&patch.download_url, &patch_path, Some(progress))
+            .await
+        {
+            Ok(()) => {
+                let (base, patch, output) =
+                    (dest.to_path_buf(), patch_path.clone(), patched_path.clone());
+                task::spawn_blocking(move || delta::apply_patch(&base, &patch, &output, &digest))
+                    .await
+                    .and_then(|result| result)
             }
-        }
+            Err(e) => Err(e),
+        };
+        let _ = fs::remove_file(&patch_path).await;
 
-        if downloaded_files.is_empty() {
-            warn!("  No assets found matching pattern: {}", pattern);
+        match result {
+            Ok(()) => {
+                fs::rename(&patched_path, dest).await?;
+                Ok(true)
+            }
+            Err(e) if matches!(e.root(), InstallerError::Cancelled) => Err(e),
+            Err(e) => {
+                warn!(
+                    "  Patching {} failed, downloading it in full: {}",
+                    asset.name, e
+                );
+                Ok(false)
+            }
         }
-
-        Ok(downloaded_files)
     }
 
+    /// Downloads a repository file, or the files matching a `*` pattern, at `version`. Each file
+    /// must match the blob SHA GitHub reports for it at that ref
     pub async fn download_file(
         &self,
         owner: &str,
@@ -149,21 +784,76 @@ impl GitHubClient {
         version: &str,
         filepath: &str,
         dest: &Path,
-    ) -> Result<()> {
+        preserve_paths: bool,
+    ) -> Result<Vec<RepoFile>> {
         if filepath.contains('*') {
             return self
-                .download_files_glob(owner, repo, version, filepath, dest)
+                .download_files_glob(owner, repo, version, filepath, dest, preserve_paths)
                 .await;
         }
 
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/contents/{}?ref={}",
+            owner, repo, filepath, version
+        );
+        let response = self.api_get(&url).await?;
+        let json = validate_response(
+            response,
+            &format!("look up '{filepath}' in '{repo}'"),
+            self.auth_header.is_some(),
+        )?;
+
+        let sha = json["sha"]
+            .as_str()
+            .ok_or_else(|| InstallerError::GitHub(format!("No blob SHA found for {}", filepath)))?;
+        let file = RepoFile {
+            path: filepath.to_string(),
+            sha: sha.to_string(),
+        };
+
+        self.download_repo_file(owner, repo, version, &file, dest)
+            .await?;
+        Ok(vec![file])
+    }
+
+    /// Downloads `file` from raw.githubusercontent.com, failing if its content doesn't match its
+    /// blob SHA
+    async fn download_repo_file(
+        &self,
+        owner: &str,
+        repo: &str,
+        version: &str,
+        file: &RepoFile,
+        dest: &Path,
+    ) -> Result<()> {
         let url = format!(
             "https://raw.githubusercontent.com/{}/{}/{}/{}",
-            owner, repo, version, filepath
+            owner, repo, version, file.path
         );
+        self.download_file_from_url(&url, dest, None).await?;
+
+        // Replayed downloads are empty placeholders
+        if self
+            .session
+            .as_ref()
+            .is_some_and(|session| session.is_replay())
+        {
+            return Ok(());
+        }
 
-        self.download_file_from_url(&url, dest).await
+        let actual = git_blob_sha(&fs::read(dest).await?);
+        if actual != file.sha {
+            let _ = fs::remove_file(dest).await;
+            return Err(InstallerError::GitHub(format!(
+                "{} at {} has blob {}, expected {}",
+                file.path, version, actual, file.sha
+            )));
+        }
+        Ok(())
     }
 
+    /// Downloads the files matching the glob `filepath` into `dest_dir`, directly or, with
+    /// `preserve_paths`, at their path below the glob's first wildcard directory
     async fn download_files_glob(
         &self,
         owner: &str,
@@ -171,55 +861,121 @@ impl GitHubClient {
         version: &str,
         filepath: &str,
         dest_dir: &Path,
-    ) -> Result<()> {
-        let base_path = filepath.split('*').next().unwrap_or("");
-        let pattern = filepath.split('/').last().unwrap_or("*");
+        preserve_paths: bool,
+    ) -> Result<Vec<RepoFile>> {
+        let pattern = glob::Pattern::new(filepath).map_err(|e| {
+            InstallerError::Config(format!("Invalid repo file pattern '{}': {}", filepath, e))
+        })?;
+        // Only `**` matches across directories
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let base: String = filepath
+            .split('/')
+            .take_while(|component| !component.contains('*'))
+            .map(|component| format!("{}/", component))
+            .collect();
 
-        let url = format!(
-            "https://api.github.com/repos/{}/{}/contents/{}",
-            owner,
-            repo,
-            base_path.trim_end_matches('/')
-        );
+        let entries = self.list_files(owner, repo, version, &base).await?;
+
+        fs::create_dir_all(dest_dir).await?;
 
-        let mut request = self.client.get(&url);
+        let mut downloaded = Vec::new();
+        for entry in entries {
+            let (Some(path), Some(sha)) = (entry["path"].as_str(), entry["sha"].as_str()) else {
+                continue;
+            };
+            if entry["type"] != "blob" || !pattern.matches_with(path, options) {
+                continue;
+            }
 
-        if let Some(ref auth) = self.auth_header {
-            request = request.header("Authorization", auth);
+            let relative = if preserve_paths {
+                path.strip_prefix(&base).unwrap_or(path)
+            } else {
+                path.rsplit('/').next().unwrap_or(path)
+            };
+            let dest = dest_dir.join(relative);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            let repo_file = RepoFile {
+                path: path.to_string(),
+                sha: sha.to_string(),
+            };
+            self.download_repo_file(owner, repo, version, &repo_file, &dest)
+                .await?;
+            info!("  Downloaded: {}", relative);
+            downloaded.push(repo_file);
         }
 
-        let response = request.send().await?;
-        let json = validate_response(
-            response,
-            &format!("list contents of '{repo}'"),
-            self.auth_header.is_some(),
-        )
-        .await?;
+        Ok(downloaded)
+    }
 
-        let files = json
-            .as_array()
-            .ok_or_else(|| InstallerError::GitHub("Expected array of files".to_string()))?;
+    /// Entries of the repository tree at `version`, with paths from its root. When GitHub
+    /// truncates the recursive listing, walks it a directory at a time instead, only into
+    /// directories at or below `base`
+    async fn list_files(
+        &self,
+        owner: &str,
+        repo: &str,
+        version: &str,
+        base: &str,
+    ) -> Result<Vec<Value>> {
+        let json = self
+            .get_tree(owner, repo, &format!("{}?recursive=1", version))
+            .await?;
+        if json["truncated"].as_bool() != Some(true) {
+            return tree_entries(json);
+        }
 
-        fs::create_dir_all(dest_dir).await?;
+        warn!(
+            "File listing of '{}' is too large to fetch at once, listing it a directory at a time",
+            repo
+        );
+        let mut entries = Vec::new();
+        let mut directories = vec![(String::new(), version.to_string())];
+        while let Some((prefix, tree)) = directories.pop() {
+            let json = self.get_tree(owner, repo, &tree).await?;
+            if json["truncated"].as_bool() == Some(true) {
+                return Err(InstallerError::GitHub(format!(
+                    "File listing of '{}/{}' is truncated",
+                    repo, prefix
+                )));
+            }
 
-        for file in files {
-            let name = file["name"].as_str().unwrap_or("");
-            if self.matches_pattern(name, pattern) {
-                let file_url = format!(
-                    "https://raw.githubusercontent.com/{}/{}/{}/{}/{}",
-                    owner,
-                    repo,
-                    version,
-                    base_path.trim_end_matches('/'),
-                    name
-                );
-                let dest_path = dest_dir.join(name);
-                self.download_file_from_url(&file_url, &dest_path).await?;
-                info!("  Downloaded: {}", name);
+            for mut entry in tree_entries(json)? {
+                let Some(name) = entry["path"].as_str() else {
+                    continue;
+                };
+                let path = format!("{}{}", prefix, name);
+                let dir = format!("{}/", path);
+                if entry["type"] == "tree" && (base.starts_with(&dir) || dir.starts_with(base)) {
+                    if let Some(sha) = entry["sha"].as_str() {
+                        directories.push((dir, sha.to_string()));
+                    }
+                }
+                entry["path"] = Value::String(path);
+                entries.push(entry);
             }
         }
 
-        Ok(())
+        Ok(entries)
+    }
+
+    /// The git tree `tree`, a commit-ish or tree SHA with any query
+    async fn get_tree(&self, owner: &str, repo: &str, tree: &str) -> Result<Value> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/git/trees/{}",
+            owner, repo, tree
+        );
+        let response = self.api_get(&url).await?;
+        validate_response(
+            response,
+            &format!("list files of '{repo}'"),
+            self.auth_header.is_some(),
+        )
     }
 
     async fn get_release_assets(
@@ -240,45 +996,140 @@ impl GitHubClient {
             )
         };
 
-        let mut request = self.client.get(&url);
-
-        if let Some(ref auth) = self.auth_header {
-            request = request.header("Authorization", auth);
-        }
-
-        let response = request.send().await?;
+        let response = self.api_get(&url).await?;
         let json = validate_response(
             response,
             &format!("fetch '{repo}'"),
             self.auth_header.is_some(),
-        )
-        .await?;
+        )?;
 
         let assets = json["assets"]
             .as_array()
             .ok_or_else(|| InstallerError::GitHub("No assets found in release".to_string()))?;
 
+        // Releases only embed their first 100 assets; the rest are listed page by page
+        if assets.len() >= 100 {
+            if let Some(assets_url) = json["assets_url"].as_str() {
+                return self
+                    .api_get_list(assets_url, &format!("fetch '{repo}' release assets"))
+                    .await;
+            }
+        }
+
         Ok(assets.clone())
     }
 
-    async fn download_file_from_url(&self, url: &str, dest: &Path) -> Result<()> {
-        let response = self.client.get(url).send().await?;
+    /// Downloads `url` to `dest`, reporting progress when `progress` describes a release asset
+    async fn download_file_from_url(
+        &self,
+        url: &str,
+        dest: &Path,
+        progress: Option<DownloadProgress>,
+    ) -> Result<()> {
+        let request = Request::Http {
+            url: url.to_string(),
+        };
+        if let Some(response) = self.replayed(&request)? {
+            // Downloads aren't recorded, so replays leave an empty placeholder file
+            response.into_http()?;
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(dest, b"").await?;
+            return Ok(());
+        }
+
+        let download = self
+            .stream_to_file(url, dest, progress)
+            .instrument(debug_span!("download", url));
+        let result = match &self.cancellation_token {
+            // Dropping the download future aborts the request
+            Some(token) => token
+                .run_until_cancelled(download)
+                .await
+                .unwrap_or(Err(InstallerError::Cancelled)),
+            None => download.await,
+        };
+
+        if result.is_err() {
+            let _ = fs::remove_file(dest).await;
+        }
+
+        let response = match &result {
+            Ok(bytes) => Response::Http {
+                status: StatusCode::OK.as_u16(),
+                bytes: *bytes,
+                body: None,
+                next: None,
+            },
+            Err(e) => Response::Error(e.to_string()),
+        };
+        self.record(request, response);
+
+        result.map(|_| ())
+    }
+
+    /// Streams `url` into `dest`, returning the number of bytes written
+    async fn stream_to_file(
+        &self,
+        url: &str,
+        dest: &Path,
+        mut progress: Option<DownloadProgress>,
+    ) -> Result<u64> {
+        let mut response =
+            Platform::get_with_credentials(&self.client, url, &HeaderMap::new()).await?;
 
         if !response.status().is_success() {
             return Err(InstallerError::GitHub(format!(
                 "Failed to download file: HTTP {}",
                 response.status()
-            )));
+            )))
+            .with_context(|| format!("downloading {}", url));
         }
 
-        let bytes = response.bytes().await?;
-
         if let Some(parent) = dest.parent() {
             fs::create_dir_all(parent).await?;
         }
 
-        fs::write(dest, bytes).await?;
-        Ok(())
+        let mut file = fs::File::create(dest).await?;
+        let mut bytes = 0;
+        let mut reported = Instant::now();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("downloading {}", url))?
+        {
+            file.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                reported = Instant::now();
+                self.report_progress(&mut progress, bytes, false);
+            }
+        }
+        file.flush().await?;
+        self.report_progress(&mut progress, bytes, true);
+
+        Ok(bytes)
+    }
+
+    fn report_progress(&self, progress: &mut Option<DownloadProgress>, bytes: u64, done: bool) {
+        if let (Some(sender), Some(progress)) = (&self.progress, progress) {
+            progress.downloaded_bytes = bytes;
+            progress.done = done;
+            let _ = sender.send(progress.clone());
+        }
+    }
+
+    fn matches_asset(&self, asset: &ReleaseAsset, pattern: &str) -> bool {
+        match pattern.strip_prefix(LABEL_PATTERN_PREFIX) {
+            Some(pattern) => asset
+                .label
+                .as_deref()
+                .filter(|label| !label.is_empty())
+                .is_some_and(|label| self.matches_pattern(label, pattern)),
+            None => self.matches_pattern(&asset.name, pattern),
+        }
     }
 
     fn matches_pattern(&self, filename: &str, pattern: &str) -> bool {
@@ -317,24 +1168,88 @@ impl Default for GitHubClient {
     }
 }
 
-async fn validate_response(response: Response, action: &str, has_auth: bool) -> Result<Value> {
-    if !response.status().is_success() {
+fn validate_response(response: ApiResponse, action: &str, has_auth: bool) -> Result<Value> {
+    if !response.status.is_success() {
         let auth_message = if has_auth {
             "using auth"
         } else {
             "without auth"
         };
 
-        let status_code = response.status();
-
-        let json: std::result::Result<Value, reqwest::Error> = response.json().await;
+        let status_code = response.status;
+        let json = response.body;
 
         Err(InstallerError::GitHub(format!(
             "Failed to {action} {auth_message}: HTTP {status_code}, body: {json:?}",
         )))
     } else {
-        let json: Value = response.json().await?;
+        response.body.ok_or_else(|| {
+            InstallerError::GitHub(format!("Failed to {action}: response was not valid JSON"))
+        })
+    }
+}
 
-        Ok(json)
+/// The entries of a git tree response
+fn tree_entries(mut json: Value) -> Result<Vec<Value>> {
+    match json["tree"].take() {
+        Value::Array(entries) => Ok(entries),
+        _ => Err(InstallerError::GitHub("Expected tree of files".to_string())),
     }
 }
+
+/// The `rel="next"` URL of a `Link` header such as
+/// `<https://api.github.com/...&page=2>; rel="next", <https://api.github.com/...&page=5>; rel="last"`
+fn next_page_url(link: &str) -> Option<String> {
+    link.split(',').find_map(|entry| {
+        let (url, params) = entry.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// Parses an HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let mut parts = date.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = MONTHS
+        .iter()
+        .position(|month| Some(*month) == parts.next())? as i64
+        + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    // Days-from-civil conversion (Howard Hinnant)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+    let secs = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// The SHA-1 git uses to identify a blob with `content`
+fn git_blob_sha(content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+    format!("{:x}", hasher.finalize())
+}
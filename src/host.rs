@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+use crate::github::GitHubClient;
+use crate::platform::Platform;
+use crate::{task, AdbManager};
+
+/// Free space needed in the temp directory, which holds a run's downloads
+const MIN_FREE_TEMP_BYTES: u64 = 1024 * 1024 * 1024;
+/// Largest difference from GitHub's clock before warning
+const MAX_HOST_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+/// Oldest adb that can pair and connect over wireless debugging (platform tools 30)
+const MIN_ADB_REVISION: u32 = 41;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    /// Something may not work, but installing can still succeed
+    Warning,
+    Failed,
+    /// The check couldn't run, such as the clock check without network access
+    Skipped,
+}
+
+/// Result of one host prerequisite check
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HostCheck {
+    /// Stable identifier: `libusb`, `adb`, `disk_space`, `github`, or `clock`
+    pub id: String,
+    pub status: CheckStatus,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl HostCheck {
+    fn new(id: &str, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.to_string());
+        self
+    }
+}
+
+/// Checks of the computer running the installer, made before touching a device
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HostReport {
+    pub checks: Vec<HostCheck>,
+}
+
+impl HostReport {
+    /// Runs every check. `adb` is the adb binary to check, found on `PATH` when `None`
+    pub async fn run(github: &GitHubClient, adb: Option<PathBuf>) -> Self {
+        let adb = adb.or_else(find_adb);
+        let (libusb, adb) = task::spawn_blocking(move || (check_libusb(), check_adb(adb)))
+            .await
+            .unwrap_or_else(|e| {
+                let skipped = |id| HostCheck::new(id, CheckStatus::Skipped, e.to_string());
+                (skipped("libusb"), skipped("adb"))
+            });
+        let (github, clock) = check_github(github).await;
+
+        Self {
+            checks: vec![libusb, adb, check_disk_space(), github, clock],
+        }
+    }
+
+    /// True when no check failed. Warnings are allowed
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &HostCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+    }
+
+    pub fn log_summary(&self) {
+        for check in &self.checks {
+            match check.status {
+                CheckStatus::Passed => info!("  {}: {}", check.id, check.message),
+                CheckStatus::Skipped => info!("  {}: skipped ({})", check.id, check.message),
+                CheckStatus::Warning => warn!("  {}: {}", check.id, check.message),
+                CheckStatus::Failed => error!("  {}: {}", check.id, check.message),
+            }
+            if let Some(hint) = &check.hint {
+                if check.status != CheckStatus::Passed {
+                    warn!("    Hint: {}", hint);
+                }
+            }
+        }
+    }
+}
+
+/// Finds `adb` on `PATH`
+pub fn find_adb() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let name = format!("adb{}", Platform::executable_extension());
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// The USB backend talks to the device through libusb, without an ADB server
+fn check_libusb() -> HostCheck {
+    let version = rusb::version();
+    let version = format!(
+        "libusb {}.{}.{}",
+        version.major(),
+        version.minor(),
+        version.micro()
+    );
+
+    match rusb::Context::new() {
+        Ok(_) => HostCheck::new("libusb", CheckStatus::Passed, version),
+        // Connections through a running ADB server don't need libusb
+        Err(e) if AdbManager::server_version().is_ok() => HostCheck::new(
+            "libusb",
+            CheckStatus::Warning,
+            format!(
+                "{} is unavailable ({}), so devices can only be reached through the ADB server",
+                version, e
+            ),
+        ),
+        Err(e) => HostCheck::new(
+            "libusb",
+            CheckStatus::Failed,
+            format!("{} is unavailable: {}", version, e),
+        )
+        .with_hint(libusb_hint()),
+    }
+}
+
+fn libusb_hint() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "Install the WinUSB driver for the device (for example with Zadig), or start an ADB server"
+    } else if cfg!(target_os = "macos") {
+        "Install libusb (`brew install libusb`), or start an ADB server"
+    } else {
+        "Install libusb and make sure your user can access USB devices (udev rules), or start an ADB server"
+    }
+}
+
+fn check_adb(adb: Option<PathBuf>) -> HostCheck {
+    let server = AdbManager::server_version().ok();
+    let Some(adb) = adb else {
+        return match server {
+            Some(server) => HostCheck::new(
+                "adb",
+                CheckStatus::Passed,
+                format!("ADB server {} is running (adb binary not found)", server),
+            ),
+            None => HostCheck::new(
+                "adb",
+                CheckStatus::Warning,
+                "adb not found, so only USB connections are available",
+            )
+            .with_hint(
+                "Install the Android platform tools for wireless debugging and multiple devices",
+            ),
+        };
+    };
+
+    let version = match adb_version(&adb) {
+        Ok(version) => version,
+        Err(message) => return HostCheck::new("adb", CheckStatus::Warning, message),
+    };
+
+    let server = match server {
+        Some(server) => format!("server {} running", server),
+        None => "server not running".to_string(),
+    };
+    let message = format!("adb {} at {} ({})", version, adb.display(), server);
+
+    let revision = version
+        .rsplit('.')
+        .next()
+        .and_then(|revision| revision.parse::<u32>().ok());
+    if revision.is_some_and(|revision| revision < MIN_ADB_REVISION) {
+        return HostCheck::new("adb", CheckStatus::Warning, message)
+            .with_hint("Update the Android platform tools to use wireless debugging");
+    }
+
+    HostCheck::new("adb", CheckStatus::Passed, message)
+}
+
+/// Version from the first line of `adb version`, such as `1.0.41`
+fn adb_version(adb: &Path) -> std::result::Result<String, String> {
+    let output = Command::new(adb)
+        .arg("version")
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", adb.display(), e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("Android Debug Bridge version "))
+        .map(|version| version.trim().to_string())
+        .ok_or_else(|| format!("Unexpected `adb version` output: {}", stdout.trim()))
+}
+
+fn check_disk_space() -> HostCheck {
+    let temp_root = Platform::temp_root();
+    // The temp directory is created on first use, so measure the closest existing parent
+    let Some(existing) = temp_root.ancestors().find(|dir| dir.exists()) else {
+        return HostCheck::new(
+            "disk_space",
+            CheckStatus::Skipped,
+            format!("{} does not exist", temp_root.display()),
+        );
+    };
+
+    let available = match fs4::available_space(existing) {
+        Ok(available) => available,
+        Err(e) => {
+            return HostCheck::new(
+                "disk_space",
+                CheckStatus::Skipped,
+                format!("Couldn't read free space of {}: {}", existing.display(), e),
+            )
+        }
+    };
+
+    let message = format!(
+        "{:.1} GB free in {}",
+        available as f64 / 1_000_000_000.0,
+        temp_root.display()
+    );
+    if available < MIN_FREE_TEMP_BYTES {
+        return HostCheck::new("disk_space", CheckStatus::Failed, message)
+            .with_hint("Free up disk space, or point PENUMBRA_TEMP_DIR at a larger disk");
+    }
+
+    HostCheck::new("disk_space", CheckStatus::Passed, message)
+}
+
+/// Checks that GitHub is reachable over TLS, and compares the local clock with GitHub's
+async fn check_github(github: &GitHubClient) -> (HostCheck, HostCheck) {
+    let status = match github.api_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            return (
+                HostCheck::new(
+                    "github",
+                    CheckStatus::Failed,
+                    format!("Couldn't reach api.github.com: {}", e),
+                )
+                .with_hint("Check your network connection, proxy, and firewall. A wrong system clock also breaks TLS"),
+                HostCheck::new("clock", CheckStatus::Skipped, "GitHub is unreachable"),
+            )
+        }
+    };
+
+    let message = format!(
+        "api.github.com reachable, {} of {} API requests left",
+        status.remaining, status.limit
+    );
+    let github = if status.remaining == 0 {
+        HostCheck::new("github", CheckStatus::Warning, message)
+            .with_hint("Pass --github-token (or add a token in the installer settings) for a higher rate limit")
+    } else {
+        HostCheck::new("github", CheckStatus::Passed, message)
+    };
+
+    let clock = match status.server_time {
+        Some(server_time) => check_clock(server_time),
+        None => HostCheck::new("clock", CheckStatus::Skipped, "GitHub didn't send its time"),
+    };
+
+    (github, clock)
+}
+
+fn check_clock(server_time: SystemTime) -> HostCheck {
+    let now = SystemTime::now();
+    let (skew, direction) = match now.duration_since(server_time) {
+        Ok(ahead) => (ahead, "ahead of"),
+        Err(behind) => (behind.duration(), "behind"),
+    };
+
+    if skew <= MAX_HOST_CLOCK_SKEW {
+        return HostCheck::new("clock", CheckStatus::Passed, "Clock matches GitHub's");
+    }
+
+    HostCheck::new(
+        "clock",
+        CheckStatus::Warning,
+        format!("Clock is {}s {} GitHub's", skew.as_secs(), direction),
+    )
+    .with_hint("Enable automatic time synchronization on this computer")
+}
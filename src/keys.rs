@@ -0,0 +1,407 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+use crate::adb::{quote, AdbManager};
+use crate::host::find_adb;
+use crate::platform::Platform;
+use crate::{task, InstallerError, Result, ResultExt};
+
+/// The host keys adbd accepts without asking, one `<base64 key> <comment>` per line
+pub const AUTHORIZED_KEYS_PATH: &str = "/data/misc/adb/adb_keys";
+
+const PRIVATE_KEY: &str = "adbkey";
+const PUBLIC_KEY: &str = "adbkey.pub";
+/// Suffix of the previous key files, kept until the new key is known to work
+const OLD_SUFFIX: &str = ".old";
+
+/// Where the key the installer authenticates to devices with is kept
+#[derive(Debug, Clone)]
+pub enum KeyStore {
+    /// `adbkey` and `adbkey.pub` in a directory, normally `~/.android`
+    Local { dir: PathBuf },
+    /// A remote signing server, which generates and holds the key itself
+    Remote { url: String, client: Client },
+}
+
+/// A key generated to replace the current one, not yet in use
+#[derive(Debug, Clone)]
+pub struct PendingKey {
+    pub public_key: String,
+    /// `None` for keys held by a remote signing server
+    private_key: Option<String>,
+}
+
+/// Outcome of `rotate`
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRotation {
+    pub serial: String,
+    /// Public key now used to connect
+    pub public_key: String,
+    /// Whether the new key was added to the device's authorized keys, rather than accepted at
+    /// the USB debugging prompt
+    pub authorized_on_device: bool,
+    /// Whether the previous key was removed from the device's authorized keys
+    pub retired_on_device: bool,
+    /// The new private key, for local keys
+    #[serde(skip)]
+    pub private_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RemoteKey {
+    public_key: Option<String>,
+}
+
+impl KeyStore {
+    /// The key in `~/.android`, which adb and the USB transport use
+    pub fn local() -> Result<Self> {
+        let home = dirs::home_dir()
+            .ok_or_else(|| InstallerError::Adb("Could not find the home directory".into()))?;
+        Ok(KeyStore::Local {
+            dir: home.join(".android"),
+        })
+    }
+
+    pub fn remote(url: &str) -> Self {
+        KeyStore::Remote {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+        }
+    }
+
+    /// The signing server URL to connect with, for remote keys
+    pub fn remote_auth_url(&self) -> Option<String> {
+        match self {
+            KeyStore::Local { .. } => None,
+            KeyStore::Remote { url, .. } => Some(url.clone()),
+        }
+    }
+
+    /// Public key currently used to connect, if there is one
+    pub async fn current_public_key(&self) -> Result<Option<String>> {
+        match self {
+            KeyStore::Local { dir } => {
+                let public = dir.join(PUBLIC_KEY);
+                if public.is_file() {
+                    return Ok(Some(fs::read_to_string(public)?.trim().to_string()));
+                }
+                if !dir.join(PRIVATE_KEY).is_file() {
+                    return Ok(None);
+                }
+                let private = dir.join(PRIVATE_KEY);
+                task::spawn_blocking(move || adb_pubkey(&private))
+                    .await?
+                    .map(Some)
+            }
+            KeyStore::Remote { url, client } => {
+                let request = client.get(format!("{}/keys/current", url));
+                Ok(signing_server(request, "read").await?.public_key)
+            }
+        }
+    }
+
+    /// Generates a new key, leaving the current one in use
+    pub async fn generate(&self) -> Result<PendingKey> {
+        match self {
+            KeyStore::Local { .. } => task::spawn_blocking(adb_keygen).await?,
+            KeyStore::Remote { .. } => {
+                let public_key = self.post("rotate").await?.public_key.ok_or_else(|| {
+                    InstallerError::Adb("Signing server did not return the new public key".into())
+                })?;
+                Ok(PendingKey {
+                    public_key,
+                    private_key: None,
+                })
+            }
+        }
+    }
+
+    /// Starts connecting with `key`, keeping the previous key until `retire` or `rollback`
+    pub async fn activate(&self, key: &PendingKey) -> Result<()> {
+        match self {
+            KeyStore::Local { dir } => {
+                let private_key = key.private_key.as_deref().unwrap_or_default();
+                fs::create_dir_all(dir)?;
+                for name in [PRIVATE_KEY, PUBLIC_KEY] {
+                    let path = dir.join(name);
+                    if path.exists() {
+                        fs::rename(&path, old_path(&path))?;
+                    }
+                }
+                write_private(&dir.join(PRIVATE_KEY), private_key)?;
+                fs::write(dir.join(PUBLIC_KEY), format!("{}\n", key.public_key))?;
+                Ok(())
+            }
+            KeyStore::Remote { .. } => self.post("activate").await.map(|_| ()),
+        }
+    }
+
+    /// Goes back to the previous key after an `activate`
+    pub async fn rollback(&self) -> Result<()> {
+        match self {
+            KeyStore::Local { dir } => {
+                for name in [PRIVATE_KEY, PUBLIC_KEY] {
+                    let path = dir.join(name);
+                    let old = old_path(&path);
+                    if old.exists() {
+                        fs::rename(old, path)?;
+                    } else {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                Ok(())
+            }
+            KeyStore::Remote { .. } => self.post("rollback").await.map(|_| ()),
+        }
+    }
+
+    /// Discards the previous key after an `activate`
+    pub async fn retire(&self) -> Result<()> {
+        match self {
+            KeyStore::Local { dir } => {
+                for name in [PRIVATE_KEY, PUBLIC_KEY] {
+                    let old = old_path(&dir.join(name));
+                    if old.exists() {
+                        fs::remove_file(old)?;
+                    }
+                }
+                Ok(())
+            }
+            KeyStore::Remote { .. } => self.post("retire").await.map(|_| ()),
+        }
+    }
+
+    /// POSTs to `<url>/keys/<action>` on the signing server. Local keys have no server
+    async fn post(&self, action: &str) -> Result<RemoteKey> {
+        match self {
+            KeyStore::Local { .. } => Ok(RemoteKey { public_key: None }),
+            KeyStore::Remote { url, client } => {
+                let request = client.post(format!("{}/keys/{}", url, action));
+                signing_server(request, action).await
+            }
+        }
+    }
+}
+
+/// Sends a key request to the signing server. Responses without a body are accepted
+async fn signing_server(request: reqwest::RequestBuilder, action: &str) -> Result<RemoteKey> {
+    let response = request
+        .header(reqwest::header::USER_AGENT, Platform::user_agent())
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Signing server couldn't {} the key", action))?;
+
+    let body = response.text().await?;
+    if body.trim().is_empty() {
+        return Ok(RemoteKey { public_key: None });
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Replaces the key used to connect to the attached device: generates a new key, adds it to the
+/// device's authorized keys over the current connection, switches to it, reconnects to check
+/// that it works, and then removes the previous key from the device and from `store`. If the
+/// new key can't connect, `store` goes back to the previous key. Fails if `expected_serial` is
+/// given and isn't the attached device's
+pub async fn rotate(store: &KeyStore, expected_serial: Option<&str>) -> Result<KeyRotation> {
+    if AdbManager::server_version().is_ok() {
+        // The server loads the key once at startup and would keep connecting with the old one
+        return Err(InstallerError::Adb(
+            "Stop the ADB server (`adb kill-server`) before rotating keys".into(),
+        ));
+    }
+
+    let remote_auth_url = store.remote_auth_url();
+    let mut adb = AdbManager::connect_usb_device(remote_auth_url.clone()).await?;
+    let serial = adb.serial_number().await?;
+    if let Some(expected) = expected_serial.filter(|expected| *expected != serial) {
+        // Keys are rotated over USB without the ADB server, which can't pick between devices
+        return Err(InstallerError::Adb(format!(
+            "The attached device is {}, not {}",
+            serial, expected
+        )));
+    }
+
+    let previous = store.current_public_key().await?;
+    let key = store.generate().await?;
+    info!("Generated a new key for {}", serial);
+
+    let authorized_on_device = match authorize(&mut adb, &key.public_key).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                "Couldn't add the new key to {}: {}",
+                AUTHORIZED_KEYS_PATH, e
+            );
+            warn!("   Confirm the USB debugging prompt on your Pin's laser when it reconnects");
+            false
+        }
+    };
+    drop(adb);
+
+    store.activate(&key).await?;
+    info!("Reconnecting with the new key...");
+    let reconnected = match AdbManager::connect_usb_device(remote_auth_url).await {
+        Ok(mut adb) => match adb.serial_number().await {
+            Ok(reconnected) if reconnected == serial => Ok(adb),
+            Ok(reconnected) => Err(InstallerError::Adb(format!(
+                "Reconnected to {} instead of {}",
+                reconnected, serial
+            ))),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    let mut adb = match reconnected {
+        Ok(adb) => adb,
+        Err(e) => {
+            store.rollback().await?;
+            return Err(e).context("The new key couldn't connect, so the previous key is kept");
+        }
+    };
+
+    let retired_on_device = match &previous {
+        Some(previous) => match revoke(&mut adb, previous).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Couldn't remove the previous key from the device: {}", e);
+                warn!("   Revoke USB debugging authorizations in developer options to remove it");
+                false
+            }
+        },
+        None => false,
+    };
+    store.retire().await?;
+
+    Ok(KeyRotation {
+        serial,
+        public_key: key.public_key,
+        authorized_on_device,
+        retired_on_device,
+        private_key: key.private_key,
+    })
+}
+
+/// Adds `public_key` to the device's authorized keys, which usually needs root
+async fn authorize(adb: &mut AdbManager, public_key: &str) -> Result<()> {
+    let mut keys = authorized_keys(adb).await?;
+    if !keys.iter().any(|line| same_key(line, public_key)) {
+        keys.push(public_key.to_string());
+        write_authorized_keys(adb, &keys).await?;
+    }
+    Ok(())
+}
+
+/// Removes `public_key` from the device's authorized keys
+async fn revoke(adb: &mut AdbManager, public_key: &str) -> Result<()> {
+    let keys = authorized_keys(adb).await?;
+    let remaining: Vec<_> = keys
+        .iter()
+        .filter(|line| !same_key(line, public_key))
+        .cloned()
+        .collect();
+    if remaining.len() < keys.len() {
+        write_authorized_keys(adb, &remaining).await?;
+    }
+    Ok(())
+}
+
+/// Replaces the device's authorized keys, giving the file the owner, mode and SELinux label
+/// adbd reads it with
+async fn write_authorized_keys(adb: &mut AdbManager, keys: &[String]) -> Result<()> {
+    adb.write_file(AUTHORIZED_KEYS_PATH, &keys.join("\n"))
+        .await?;
+    let path = quote(AUTHORIZED_KEYS_PATH);
+    adb.run(&format!(
+        "chown system:shell {path} && chmod 640 {path} && restorecon {path}"
+    ))
+    .await?;
+    Ok(())
+}
+
+/// The device's authorized keys. Fails rather than returning an empty list when the file can't
+/// be read, so it's never replaced with only the new key
+async fn authorized_keys(adb: &mut AdbManager) -> Result<Vec<String>> {
+    let contents = adb
+        .read_file(AUTHORIZED_KEYS_PATH)
+        .await?
+        .ok_or_else(|| InstallerError::Adb(format!("{} is not readable", AUTHORIZED_KEYS_PATH)))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether two `<base64 key> <comment>` lines hold the same key, whatever their comments
+fn same_key(a: &str, b: &str) -> bool {
+    a.split_whitespace().next() == b.split_whitespace().next()
+}
+
+/// Generates a key pair with `adb keygen`, which writes Android's public key format
+fn adb_keygen() -> Result<PendingKey> {
+    let adb = find_adb().ok_or_else(|| {
+        InstallerError::Adb("adb is needed to generate keys, but was not found on PATH".into())
+    })?;
+
+    let dir = Platform::temp_dir();
+    fs::create_dir_all(&dir)?;
+    let private = dir.join(PRIVATE_KEY);
+    let output = Command::new(&adb).arg("keygen").arg(&private).output()?;
+
+    let key = if output.status.success() {
+        Ok(PendingKey {
+            private_key: Some(fs::read_to_string(&private)?),
+            public_key: fs::read_to_string(dir.join(PUBLIC_KEY))?.trim().to_string(),
+        })
+    } else {
+        Err(InstallerError::Adb(format!(
+            "adb keygen failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    };
+    let _ = fs::remove_dir_all(&dir);
+    key
+}
+
+/// The public key of an `adbkey` file that has lost its `adbkey.pub`
+fn adb_pubkey(private: &Path) -> Result<String> {
+    let adb = find_adb().ok_or_else(|| {
+        InstallerError::Adb(format!(
+            "adb is needed to read the public key of {}, but was not found on PATH",
+            private.display()
+        ))
+    })?;
+
+    let output = Command::new(&adb).arg("pubkey").arg(private).output()?;
+    if !output.status.success() {
+        return Err(InstallerError::Adb(format!(
+            "adb pubkey failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn old_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(OLD_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Writes a private key readable only by the current user
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    std::io::Write::write_all(&mut options.open(path)?, contents.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::platform::Platform;
+use crate::Result;
+
+/// A device the user has installed to, identified by its serial number. Build fingerprints
+/// change with each OTA, so they're only kept for reference
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KnownDevice {
+    pub serial: String,
+    pub model: String,
+    pub fingerprint: String,
+    /// Unix time the device was first installed to
+    pub first_seen: u64,
+}
+
+/// Devices trusted on first use, so an install to a different Pin, such as another one in the
+/// household, asks first
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KnownDevices {
+    #[serde(default)]
+    pub devices: Vec<KnownDevice>,
+}
+
+impl KnownDevices {
+    pub fn path() -> PathBuf {
+        Platform::config_dir().join("devices.json")
+    }
+
+    /// Loads the known devices. A missing or unreadable file is treated as empty
+    pub fn load() -> Self {
+        Platform::load_json(&Self::path(), "known devices")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Platform::save_json(&Self::path(), self)
+    }
+
+    pub fn get(&self, serial: &str) -> Option<&KnownDevice> {
+        self.devices.iter().find(|device| device.serial == serial)
+    }
+
+    pub fn add(&mut self, serial: &str, model: &str, fingerprint: &str) {
+        if self.get(serial).is_some() {
+            return;
+        }
+
+        self.devices.push(KnownDevice {
+            serial: serial.to_string(),
+            model: model.to_string(),
+            fingerprint: fingerprint.to_string(),
+            first_seen: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        });
+    }
+
+    /// Returns whether the device was known
+    pub fn remove(&mut self, serial: &str) -> bool {
+        let count = self.devices.len();
+        self.devices.retain(|device| device.serial != serial);
+        self.devices.len() != count
+    }
+}
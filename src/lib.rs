@@ -1,17 +1,61 @@
 pub mod adb;
+pub mod api_cache;
+pub mod apk;
+pub mod backup;
 pub mod config;
+pub mod delta;
 pub mod engine;
 pub mod error;
 pub mod github;
+pub mod host;
+pub mod keys;
+pub mod known_devices;
+pub mod lock;
 pub mod logs;
+pub mod notify;
+pub mod overrides;
+pub mod phase;
+pub mod pins;
+pub mod plan;
 pub mod platform;
+pub mod progress;
+pub mod redact;
+pub mod report;
+pub mod resolve;
+pub mod session;
+pub mod snapshot;
+pub mod step;
+pub mod task;
+pub mod telemetry;
+pub mod transform;
+pub mod update;
+pub mod usage;
+pub mod version;
 
-pub use adb::AdbManager;
-pub use config::{ConfigLoader, InstallConfig};
+pub use adb::{AdbManager, RetryPolicy};
+pub use config::{ConfigLoader, InstallConfig, RemoteConfigAuth};
 pub use engine::InstallationEngine;
-pub use error::{InstallerError, Result};
+pub use error::{ApkFailure, ErrorDetails, InstallerError, Result, ResultExt};
+pub use host::{CheckStatus, HostCheck, HostReport};
+pub use known_devices::KnownDevices;
+pub use notify::{Notification, NotifyConfig, NotifyEvent};
+pub use overrides::RepositoryOverrides;
+pub use phase::InstallPhase;
+pub use plan::{InstallPlan, UninstallPlan};
+pub use progress::{ProgressSnapshot, ProgressTracker, TaskKind, TaskProgress};
+pub use report::{
+    InstallEnvironment, InstallReport, InstallWarning, ProvisionReport, RebootOutcome, ResetReport,
+    StepTiming, WarningKind,
+};
+pub use session::Session;
+pub use snapshot::DeviceSnapshot;
+pub use step::{InputRequest, Prompter, StepContext, StepExecutor, StepRegistry};
+pub use usage::UsageReport;
 
 pub use config::{
-    AppOpGrant, CleanupStep, ConfigVariable, FilePush, InstallStep, PermissionGrant, Repository,
-    VersionSpec,
+    AppOpGrant, AssetPattern, AssetTransform, AssetTransforms, BatteryCheck, CleanupStep,
+    ClockCheck, ConfigFormat, ConfigSource, ConfigVariable, CustomStep, FilePush,
+    FirmwareCompatibility, InstallStep, LocalInstall, LocalPatterns, PermissionGrant, RebootTarget,
+    RebootWait, RepoFilePattern, Repository, ResetConfig, ServiceAction, StepConflict,
+    VariableUsage, VersionSpec, VersionStrategy,
 };
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::adb::AdbManager;
+use crate::platform::Platform;
+use crate::{InstallerError, Result};
+
+/// Marker left on the device for the duration of an installation
+const DEVICE_LOCK_PATH: &str = "/data/local/tmp/penumbra-installer.lock";
+
+/// The process holding an installation lock
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid {}, started at {}",
+            self.pid,
+            format_utc(self.started_at)
+        )
+    }
+}
+
+/// Prevents two installers from working on the same device at once, using an advisory lock on
+/// the host and a marker file on the device. Host locks are per device, so different devices
+/// can be installed to at the same time
+pub struct InstallLock {
+    /// Held open for the lifetime of the lock. The OS releases it if the process dies
+    _host_lock: Option<File>,
+}
+
+impl InstallLock {
+    /// Acquires both locks. With `force`, existing locks are ignored and replaced
+    pub async fn acquire(adb: &mut AdbManager, force: bool) -> Result<Self> {
+        let serial = adb.serial_number().await?;
+        let host_lock = Self::acquire_host(&serial, force)?;
+
+        let existing = adb
+            .shell(&format!("cat {} 2>/dev/null", DEVICE_LOCK_PATH))
+            .await?;
+        if let Ok(holder) = serde_json::from_str::<LockHolder>(&existing) {
+            if !force {
+                return Err(InstallerError::Locked {
+                    holder: holder.to_string(),
+                });
+            }
+            warn!("Overriding device installation lock held by {}", holder);
+        }
+
+        // Written beside the lock and moved into place, so another installer never reads a
+        // partly written marker
+        let marker = serde_json::to_string(&LockHolder::current())?;
+        let staged = format!("{}.tmp", DEVICE_LOCK_PATH);
+        adb.write_file(&staged, &marker).await?;
+        adb.run(&format!("mv -f {} {}", staged, DEVICE_LOCK_PATH))
+            .await?;
+
+        Ok(Self {
+            _host_lock: host_lock,
+        })
+    }
+
+    fn acquire_host(serial: &str, force: bool) -> Result<Option<File>> {
+        let path = Platform::runtime_dir().join(host_lock_name(serial));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut contents = String::new();
+                let holder = file
+                    .read_to_string(&mut contents)
+                    .ok()
+                    .and_then(|_| serde_json::from_str::<LockHolder>(&contents).ok())
+                    .map_or_else(|| "unknown process".to_string(), |h| h.to_string());
+
+                if !force {
+                    return Err(InstallerError::Locked { holder });
+                }
+
+                warn!("Ignoring host installation lock held by {}", holder);
+                return Ok(None);
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(serde_json::to_string(&LockHolder::current())?.as_bytes())?;
+
+        Ok(Some(file))
+    }
+
+    /// Removes the device marker. The host lock is released when `self` is dropped
+    pub async fn release(self, adb: &mut AdbManager) -> Result<()> {
+        adb.remove_file(DEVICE_LOCK_PATH).await
+    }
+}
+
+/// Name of the host lock file for the device with `serial`, keeping only characters safe in
+/// file names. When any had to be replaced, a hash of the serial keeps it apart from serials
+/// that read the same once replaced
+fn host_lock_name(serial: &str) -> String {
+    let serial = serial.trim();
+    let safe: String = serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if safe.is_empty() {
+        "install.lock".to_string()
+    } else if safe == serial {
+        format!("install-{}.lock", safe)
+    } else {
+        let hash = format!("{:x}", Sha256::digest(serial.as_bytes()));
+        format!("install-{}-{}.lock", safe, &hash[..8])
+    }
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20 UTC");
+        assert_eq!(format_utc(4_102_444_799), "2099-12-31 23:59:59 UTC");
+    }
+
+    #[test]
+    fn host_lock_names_are_safe_file_names() {
+        assert_eq!(host_lock_name("PIN123ABC\n"), "install-PIN123ABC.lock");
+        assert_eq!(host_lock_name("a_b"), "install-a_b.lock");
+        assert_eq!(host_lock_name(""), "install.lock");
+
+        let address = host_lock_name("192.168.1.20:5555");
+        assert!(
+            address.starts_with("install-192_168_1_20_5555-"),
+            "{}",
+            address
+        );
+        assert!(address.ends_with(".lock"), "{}", address);
+        assert_ne!(host_lock_name("a.b"), host_lock_name("a_b"));
+        assert_ne!(host_lock_name("a.b"), host_lock_name("a:b"));
+    }
+}
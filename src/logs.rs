@@ -1,14 +1,17 @@
-use std::fs::File;
 use std::io::{self, Write};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tokio::runtime::Handle;
-use tokio::signal::ctrl_c;
-use tokio::sync::watch::{self, Sender};
-use tokio::task::spawn_blocking;
-use tokio::time::sleep;
-
-use crate::{AdbManager, InstallerError};
+// Logcat dumping is only used by the CLI
+#[cfg(feature = "cli")]
+use {
+    crate::{task, AdbManager, InstallerError},
+    std::fs::File,
+    std::time::{Duration, SystemTime, UNIX_EPOCH},
+    tokio::runtime::Handle,
+    tokio::signal::ctrl_c,
+    tokio::sync::watch::{self, Sender},
+    tokio::task::spawn_blocking,
+    tokio::time::sleep,
+};
 
 // Taken from adb_client LogFilter
 pub struct LineBuffer<W: Write> {
@@ -56,12 +59,14 @@ impl<W: Write> Write for LineBuffer<W> {
     }
 }
 
+#[cfg(feature = "cli")]
 struct PrintFileWriter {
     file: File,
     line_count: usize,
     tx: Sender<usize>,
 }
 
+#[cfg(feature = "cli")]
 impl Write for PrintFileWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         // This isn't correct if there needs to be a retry, but assume it just works
@@ -80,6 +85,7 @@ impl Write for PrintFileWriter {
     }
 }
 
+#[cfg(feature = "cli")]
 pub async fn dump_logcat_and_exit(stream: bool, remote_auth_url: Option<String>) {
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -89,7 +95,7 @@ pub async fn dump_logcat_and_exit(stream: bool, remote_auth_url: Option<String>)
     let filename = format!("penumbra_log_dump_{timestamp}.log");
 
     let inner_filename = filename.clone();
-    match tokio::spawn(async move {
+    match task::spawn(async move {
         let mut adb = AdbManager::connect(remote_auth_url.clone()).await?;
 
         let mut file = File::create(inner_filename)?;
@@ -138,7 +144,7 @@ pub async fn dump_logcat_and_exit(stream: bool, remote_auth_url: Option<String>)
         }
     })
     .await
-    .unwrap()
+    .and_then(|result| result)
     {
         Ok(line_count) => {
             println!("\n\nWrote {line_count} lines to {filename}");
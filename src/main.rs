@@ -1,13 +1,38 @@
-use clap::{Parser, Subcommand};
-use log::{error, info, warn};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio;
+use tracing::{debug_span, error, info, warn, Instrument};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
 use penumbra_installer::{
-    logs::dump_logcat_and_exit, ConfigLoader, InstallationEngine, InstallerError, Result,
+    adb::ConnectedDevice,
+    backup,
+    github::GitHubClient,
+    keys::{self, KeyStore},
+    logs::dump_logcat_and_exit,
+    platform::{Platform, PrunePolicy},
+    session::SessionHeader,
+    step::PromptFuture,
+    task,
+    telemetry::{self, InstallEvent},
+    update::{UpdateCheck, UpdateState},
+    AdbManager, CheckStatus, ConfigLoader, DeviceSnapshot, HostReport, InstallConfig, InstallPlan,
+    InstallReport, InstallationEngine, InstallerError, KnownDevices, LocalInstall, Notification,
+    NotifyConfig, NotifyEvent, ProgressTracker, Prompter, ProvisionReport, RemoteConfigAuth,
+    Repository, RepositoryOverrides, Result, Session, TaskKind, UninstallPlan, UsageReport,
 };
 
+/// How often the update daemon looks for a connected device
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Parser)]
 #[command(name = "penumbra")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -17,11 +42,56 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
+    /// Format for results and errors written to stdout
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "PENUMBRA_OUTPUT",
+        default_value_t = OutputFormat::Text
+    )]
+    output: OutputFormat,
+
     #[arg(short, long, global = true)]
     verbose: bool,
 
     #[arg(long, global = true, env)]
     github_token: Option<String>,
+
+    /// Serial of the device to use when more than one is connected
+    #[arg(long, global = true, env = "ANDROID_SERIAL")]
+    serial: Option<String>,
+
+    /// Proceed even if another installation appears to be in progress, such as after a crash
+    #[arg(long, global = true)]
+    force_unlock: bool,
+
+    /// Install the built-in configuration even if the device doesn't identify as an Ai Pin
+    #[arg(long, global = true, visible_alias = "force-device")]
+    allow_any_device: bool,
+
+    /// Install even if the device battery is below the config's minimum, warning instead
+    #[arg(long, global = true)]
+    ignore_battery: bool,
+
+    /// Never ask on the terminal, such as which of several attached devices to use. `Prompt`
+    /// steps take their default answers
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// Send an anonymous install summary (config, versions, outcome, duration). Off by default
+    #[arg(long, global = true)]
+    telemetry: bool,
+
+    /// Endpoint that receives telemetry when `--telemetry` is set
+    #[arg(long, global = true, env = "PENUMBRA_TELEMETRY_ENDPOINT")]
+    telemetry_endpoint: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -31,57 +101,520 @@ enum Commands {
         repos: Option<Vec<String>>,
         #[arg(long)]
         cache_dir: Option<PathBuf>,
-        #[arg(long)]
-        config: Option<PathBuf>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
         #[arg(long)]
         config_url: Option<String>,
+        /// Bearer token sent when fetching `--config-url`
+        #[arg(long, env = "PENUMBRA_CONFIG_TOKEN", hide_env_values = true)]
+        config_token: Option<String>,
+        /// Extra `Name: value` header sent when fetching `--config-url`. Can be repeated
+        #[arg(long = "config-header", requires = "config_url")]
+        config_headers: Vec<String>,
         /// URL for remote ADB authentication
-        #[clap(short = 'a', long = "remote-auth-url")]
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
         remote_auth_url: Option<String>,
+        /// Record every ADB command and HTTP request to this file, for `penumbra replay`
+        #[arg(long, conflicts_with = "cache_dir")]
+        record_session: Option<PathBuf>,
+        /// Back up user data on the device before installing, aborting if the backup fails
+        #[arg(long)]
+        backup_data: bool,
+        /// After a reboot, wait for the device to boot and run the config's `reboot.verify` steps
+        #[arg(long)]
+        wait_for_reboot: bool,
+        /// Shell command run when the install finishes, given the result as JSON on stdin
+        #[arg(long)]
+        notify_command: Option<String>,
+        /// URL the install result is POSTed to as JSON when the install finishes
+        #[arg(long)]
+        notify_webhook: Option<String>,
 
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         variables: Vec<String>,
     },
+    /// Install onto several devices in turn, continuing past devices that fail
+    Provision {
+        /// Serials of the devices to provision
+        #[arg(
+            long,
+            value_delimiter = ',',
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        serials: Option<Vec<String>>,
+        /// Provision every ready device known to the ADB server
+        #[arg(long)]
+        all: bool,
+        /// Shell command run when each device's install finishes, given its result as JSON on stdin
+        #[arg(long)]
+        notify_command: Option<String>,
+        /// URL each device's install result is POSTed to as JSON
+        #[arg(long)]
+        notify_webhook: Option<String>,
+        #[arg(long, value_delimiter = ',')]
+        repos: Option<Vec<String>>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        variables: Vec<String>,
+    },
+    /// Install APKs and files from a host directory instead of a GitHub release. Options can also
+    /// be set in a `penumbra-local.yml` in the directory
+    InstallLocal {
+        dir: PathBuf,
+        /// APK name patterns to install first, in order. Overrides the sidecar file
+        #[arg(long, value_delimiter = ',')]
+        priority: Vec<String>,
+        /// APK name patterns to skip, in addition to those in the sidecar file
+        #[arg(long, value_delimiter = ',')]
+        exclude: Vec<String>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+    /// Install new releases onto the connected device, skipping releases it already has
+    Update {
+        /// Keep running, checking for new releases and for the device being reconnected
+        #[arg(long)]
+        daemon: bool,
+        /// How often the daemon checks for new releases, such as `30m`, `6h`, or `1d`
+        #[arg(long, default_value = "6h", value_parser = parse_interval)]
+        interval: Duration,
+        /// Shell command run when each update's install finishes, given its result as JSON on
+        /// stdin. Replaces the config's notification command
+        #[arg(long)]
+        notify: Option<String>,
+        #[arg(long, value_delimiter = ',')]
+        repos: Option<Vec<String>>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        variables: Vec<String>,
+    },
+    /// Re-run a recorded installation against its recorded device and network responses
+    Replay { session: PathBuf },
     Uninstall {
         #[arg(long, value_delimiter = ',')]
         repos: Option<Vec<String>>,
+        /// Uninstall protected repositories, such as pinitd, without asking to type their names
+        #[arg(long)]
+        force: bool,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+    /// Remove every PenumbraOS component, config, and directory, and restore the stock launcher
+    Reset {
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
         /// URL for remote ADB authentication
-        #[clap(short = 'a', long = "remote-auth-url")]
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
         remote_auth_url: Option<String>,
     },
     Download {
         #[arg(long, value_delimiter = ',')]
         repos: Option<Vec<String>>,
-        #[arg(long)]
+        #[arg(long, env = "PENUMBRA_CACHE_DIR")]
         cache_dir: PathBuf,
     },
     List {
-        config: Option<PathBuf>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+    /// Save PenumbraOS settings and configs from the device into a timestamped archive
+    BackupData {
+        #[arg(long, value_delimiter = ',')]
+        repos: Option<Vec<String>>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// Directory to write the archive to. Defaults to the installer's state directory
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+    /// Push the files in a `backup-data` archive back to the device
+    RestoreData {
+        archive: PathBuf,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config).
+        /// Only files within its data paths are restored
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+    /// Estimate the storage each repository takes up on the device: its APKs and the files it
+    /// wrote
+    Usage {
+        /// Repositories to measure. Defaults to every repository in the config
+        #[arg(long, value_delimiter = ',')]
+        repos: Option<Vec<String>>,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
     },
+    /// Check that this computer can install: libusb, adb, free disk space, access to GitHub, and
+    /// its clock
+    CheckHost,
     Devices {
         /// URL for remote ADB authentication
-        #[clap(short = 'a', long = "remote-auth-url")]
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
         remote_auth_url: Option<String>,
+        /// Forget a device installed to before, so installing to it asks again
+        #[arg(long, value_name = "SERIAL")]
+        forget: Option<String>,
+    },
+    /// Connect to a device over Wi-Fi, so it can be installed to without a USB cable
+    Connect {
+        /// `IP:PORT` shown under Wireless debugging, or the IP of a device put in TCP mode with
+        /// `adb tcpip` (port 5555)
+        address: String,
+        /// Pairing code from "Pair device with pairing code" (Android 11+), needed the first
+        /// time this computer connects
+        #[arg(long, requires = "pair_address")]
+        code: Option<String>,
+        /// `IP:PORT` shown with the pairing code, which differs from the port to connect to
+        #[arg(long, requires = "code")]
+        pair_address: Option<String>,
+        /// Disconnect from the device instead
+        #[arg(long, conflicts_with_all = ["code", "pair_address"])]
+        disconnect: bool,
     },
     DumpLogs {
         #[clap(short = 's', long = "stream")]
         stream: bool,
 
         /// URL for remote ADB authentication
-        #[clap(short = 'a', long = "remote-auth-url")]
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
         remote_auth_url: Option<String>,
     },
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Keep a repository at a version, so installs and updates don't move it to newer releases
+    Hold {
+        repo: String,
+        /// Version to hold at. Defaults to the version last installed by `update`, to the device
+        /// given with --serial or to every device
+        version: Option<String>,
+        /// Config files the repository is in, each layered over the ones before it
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+    /// Let a held repository follow its configured version again
+    Unhold { repo: String },
+    /// Change settings kept across runs
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Save the PenumbraOS state of a device, or bring another device to a saved state
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+    /// Manage the key used to authenticate to the device
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Write each repository's installed packages and pushed file hashes as JSON
+    Export {
+        /// File to write the snapshot to, instead of standard output
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Also record the content of the files `CreateConfig` steps wrote, which may contain
+        /// API keys and other secrets
+        #[arg(long)]
+        include_settings: bool,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+    /// Install the repositories of a snapshot that differ on the attached device, at the
+    /// snapshot's versions and with its settings
+    Apply {
+        file: PathBuf,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+        /// URL for remote ADB authentication
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        variables: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Replace the ADB key with a new one: authorize it on the attached device over the current
+    /// connection, check that it connects, then remove the old key from the device and this
+    /// computer
+    Rotate {
+        /// Rotate the key held by this remote signing server instead of `~/.android/adbkey`
+        #[clap(
+            short = 'a',
+            long = "remote-auth-url",
+            env = "PENUMBRA_REMOTE_AUTH_URL"
+        )]
+        remote_auth_url: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Include or leave out a repository when no `--repos` list is given. Disabled repositories
+    /// can still be installed by naming them in `--repos`
+    SetEnabled {
+        repo: String,
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+        /// Config files the repository is in, each layered over the ones before it
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+    /// List the variables each repository takes as `--<name> <value>` install flags, and the
+    /// steps using them
+    Vars {
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+    /// Show which assets of a repository's release each `releaseAssets` pattern matches or
+    /// excludes, without downloading or installing
+    Match {
+        /// Repository to match
+        #[arg(long)]
+        repo: String,
+        /// Config files, each layered over the ones before it (`builtin` for the built-in config)
+        #[arg(long, value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Show the cache location and per-repository usage
+    Info,
+    /// Remove stale or least recently used cache entries
+    Prune {
+        /// Remove entries not used within this many days
+        #[arg(long)]
+        max_age_days: Option<u64>,
+        /// Remove least recently used entries until the cache fits in this many megabytes
+        #[arg(long)]
+        max_size_mb: Option<u64>,
+    },
+    /// Remove the entire cache
+    Clear,
+}
+
+/// Defaults for command line flags, read from `cli.toml` in the config directory. Each is
+/// applied as the environment variable its flag falls back to, so set variables and flags still
+/// take precedence
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CliDefaults {
+    github_token: Option<String>,
+    remote_auth_url: Option<String>,
+    cache_dir: Option<PathBuf>,
+    serial: Option<String>,
+    output: Option<String>,
+}
+
+impl CliDefaults {
+    fn path() -> PathBuf {
+        Platform::config_dir().join("cli.toml")
+    }
+
+    /// Loads the defaults. A missing file is treated as empty
+    fn load() -> Result<Self> {
+        let path = Self::path();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        toml::from_str(&contents)
+            .map_err(|e| InstallerError::Config(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    fn apply(self) {
+        let defaults = [
+            ("GITHUB_TOKEN", self.github_token),
+            ("PENUMBRA_REMOTE_AUTH_URL", self.remote_auth_url),
+            (
+                "PENUMBRA_CACHE_DIR",
+                self.cache_dir.map(|dir| dir.display().to_string()),
+            ),
+            ("ANDROID_SERIAL", self.serial),
+            ("PENUMBRA_OUTPUT", self.output),
+        ];
+
+        for (var, value) in defaults {
+            if let Some(value) = value {
+                if std::env::var_os(var).is_none() {
+                    std::env::set_var(var, value);
+                }
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    // Defaults are set as environment variables, which is only sound before the runtime starts
+    // its worker threads
+    match CliDefaults::load() {
+        Ok(defaults) => defaults.apply(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run_cli())
+}
+
+async fn run_cli() -> Result<()> {
     let cli = Cli::parse();
 
-    if cli.verbose {
+    if cli.output == OutputFormat::Json {
+        // Structured logs on stderr, carrying the repository and step spans of each event
+        let level = if cli.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::INFO
+        };
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(false)
+                    .with_span_list(true)
+                    .with_writer(std::io::stderr)
+                    // Spans are debug level so they stay out of plain `log` output
+                    .with_filter(filter_fn(move |metadata| {
+                        metadata.is_span() || *metadata.level() <= level
+                    })),
+            )
+            .init();
+    } else if cli.verbose {
         env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug")).init();
     } else {
         env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     }
+    task::install_panic_hook();
+
+    Platform::clean_stale_temp_dirs();
+    if !matches!(cli.command, Commands::Cache { .. }) {
+        Platform::enforce_cache_limit();
+    }
+
+    let output = cli.output;
+    match run(cli).await {
+        Err(e) if output == OutputFormat::Json => {
+            println!("{}", e.to_json());
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("{}", e);
+            if let Some(hint) = e.hint() {
+                warn!("Hint: {}", hint);
+            }
+            std::process::exit(1);
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let interactive = is_interactive(cli.output, cli.non_interactive);
 
     match cli.command {
         Commands::Install {
@@ -89,109 +622,603 @@ async fn main() -> Result<()> {
             cache_dir,
             config,
             config_url,
+            config_token,
+            config_headers,
             remote_auth_url,
+            record_session,
+            backup_data,
+            wait_for_reboot,
+            notify_command,
+            notify_webhook,
             variables,
         } => {
             let variable_overrides = parse_variable_overrides(&variables)?;
 
-            let mut config = match (config, config_url) {
-                (None, None) => ConfigLoader::load_builtin("penumbra"),
-                (None, Some(config_url)) => ConfigLoader::load_from_url(&config_url).await,
-                (Some(config_path), None) => ConfigLoader::load_from_file(&config_path).await,
-                (Some(_), Some(_)) => {
+            let mut config = match (config.is_empty(), config_url) {
+                (_, None) => load_config(&config).await,
+                (true, Some(config_url)) => {
+                    let auth = RemoteConfigAuth {
+                        bearer_token: config_token,
+                        headers: config_headers
+                            .iter()
+                            .map(|header| RemoteConfigAuth::parse_header(header))
+                            .collect::<Result<_>>()?,
+                    };
+                    ConfigLoader::load_from_url_with_auth(&config_url, &auth)
+                        .await
+                        .map(|mut config| {
+                            config.apply_overrides(&RepositoryOverrides::load());
+                            config
+                        })
+                }
+                (false, Some(_)) => {
                     return Err(InstallerError::CLI(
                         "`config` and `config_url` options are mutually exclusive".into(),
                     ));
                 }
             }?;
 
+            apply_notify_flags(&mut config, notify_command, notify_webhook);
             let mut active_repos = config.filter_repositories(repos)?;
             config.resolve_and_apply_variables(&mut active_repos, &variable_overrides)?;
 
-            let mut engine = if let Some(ref cache_path) = cache_dir {
-                InstallationEngine::new_with_cache(
-                    config,
-                    cache_path.clone(),
-                    cli.github_token.clone(),
-                    remote_auth_url.clone(),
-                    None,
-                )
-                .await?
-            } else {
-                InstallationEngine::new_with_token(
-                    config,
+            let session = match record_session {
+                Some(path) => Some(Session::record(
+                    &path,
+                    SessionHeader {
+                        installer_version: env!("CARGO_PKG_VERSION").to_string(),
+                        config: config.clone(),
+                        repositories: active_repos.clone(),
+                    },
+                    variable_overrides.values().cloned().collect(),
+                )?),
+                None => None,
+            };
+
+            check_host_before_install(cli.github_token.clone()).await;
+
+            let config_name = config.name.clone();
+            let started = Instant::now();
+            let options = InstallOptions {
+                cache_dir,
+                github_token: cli.github_token.clone(),
+                force_unlock: cli.force_unlock,
+                allow_any_device: cli.allow_any_device,
+                ignore_battery: cli.ignore_battery,
+                check_known_device: cli.serial.is_none(),
+                backup_dir: backup_data.then(backup::backup_dir),
+                wait_for_reboot,
+                session,
+                interactive,
+            };
+            let notify = config.notify.clone();
+            let result =
+                match connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await {
+                    Ok(adb) => install(config, &active_repos, adb, options).await,
+                    Err(e) => {
+                        notify_connect_failure(&notify, &config_name, cli.serial.clone(), &e).await;
+                        Err(e)
+                    }
+                };
+
+            if cli.telemetry {
+                let event = InstallEvent::new(&config_name, result.as_ref(), started.elapsed());
+                match cli.telemetry_endpoint {
+                    Some(ref endpoint) => {
+                        if let Err(e) = telemetry::submit(endpoint, &event).await {
+                            warn!("Failed to send telemetry: {}", e);
+                        }
+                    }
+                    None => warn!("Telemetry enabled but no endpoint configured"),
+                }
+            }
+
+            check_report(&result?, cli.output)?;
+        }
+
+        Commands::Provision {
+            serials,
+            all: _,
+            notify_command,
+            notify_webhook,
+            repos,
+            config,
+            remote_auth_url,
+            variables,
+        } => {
+            let variable_overrides = parse_variable_overrides(&variables)?;
+            let mut config = load_config(&config).await?;
+            apply_notify_flags(&mut config, notify_command, notify_webhook);
+            let mut active_repos = config.filter_repositories(repos)?;
+            config.resolve_and_apply_variables(&mut active_repos, &variable_overrides)?;
+            check_host_before_install(cli.github_token.clone()).await;
+
+            let serials = match serials {
+                Some(serials) => serials,
+                None => AdbManager::list_devices()?
+                    .into_iter()
+                    .filter(|device| device.is_ready())
+                    .map(|device| device.serial)
+                    .collect(),
+            };
+            if serials.is_empty() {
+                return Err(InstallerError::NoDevice);
+            }
+
+            // Download once, then install every device from the shared cache
+            let cache_dir = Platform::temp_dir();
+            let downloaded = async {
+                InstallationEngine::new_offline(
+                    config.clone(),
+                    cache_dir.clone(),
                     cli.github_token.clone(),
-                    remote_auth_url.clone(),
                     None,
                 )
                 .await?
+                .download(&active_repos)
+                .await
+            }
+            .await;
+            if let Err(e) = downloaded {
+                let _ = std::fs::remove_dir_all(&cache_dir);
+                return Err(e);
+            }
+
+            let mut report = ProvisionReport::default();
+            for (index, serial) in serials.iter().enumerate() {
+                info!("Provisioning {} ({}/{})", serial, index + 1, serials.len());
+                let started = Instant::now();
+                let options = InstallOptions {
+                    cache_dir: Some(cache_dir.clone()),
+                    github_token: cli.github_token.clone(),
+                    force_unlock: cli.force_unlock,
+                    allow_any_device: cli.allow_any_device,
+                    ignore_battery: cli.ignore_battery,
+                    check_known_device: false,
+                    backup_dir: None,
+                    wait_for_reboot: false,
+                    session: None,
+                    interactive: false,
+                };
+
+                let result = async {
+                    let adb =
+                        match AdbManager::connect_device(Some(serial), remote_auth_url.clone())
+                            .await
+                        {
+                            Ok(adb) => adb,
+                            Err(e) => {
+                                notify_connect_failure(
+                                    &config.notify,
+                                    &config.name,
+                                    Some(serial.clone()),
+                                    &e,
+                                )
+                                .await;
+                                return Err(e);
+                            }
+                        };
+                    install(config.clone(), &active_repos, adb, options).await
+                }
+                .instrument(debug_span!("device", serial = %serial))
+                .await;
+
+                if let Err(e) = &result {
+                    error!("Provisioning {} failed: {}", serial, e);
+                }
+                report.record(serial, result, started.elapsed());
+            }
+
+            let _ = std::fs::remove_dir_all(&cache_dir);
+            report.log_table();
+
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&report)?);
+            }
+            let failed = report.failures().count();
+            if failed > 0 {
+                return Err(InstallerError::InstallationStep {
+                    step: "provision".into(),
+                    reason: format!("{} of {} devices failed", failed, serials.len()),
+                });
+            }
+        }
+
+        Commands::InstallLocal {
+            dir,
+            priority,
+            exclude,
+            remote_auth_url,
+        } => {
+            let dir = dir.canonicalize()?;
+            let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+                return Err(InstallerError::CLI(format!(
+                    "{} can't be used as an asset directory",
+                    dir.display()
+                )));
+            };
+
+            let mut local = LocalInstall::load(&dir).await?;
+            if !priority.is_empty() {
+                local.priority_order = priority;
+            }
+            local.exclude_patterns.extend(exclude);
+            let config = local.into_config(&name.to_string_lossy());
+            let active_repos = config.all_repositories().to_vec();
+
+            // The engine reads cached assets from `<cache dir>/<repository name>`, which is `dir`
+            let options = InstallOptions {
+                cache_dir: Some(parent.to_path_buf()),
+                github_token: None,
+                force_unlock: cli.force_unlock,
+                allow_any_device: cli.allow_any_device,
+                ignore_battery: cli.ignore_battery,
+                check_known_device: cli.serial.is_none(),
+                backup_dir: None,
+                wait_for_reboot: false,
+                session: None,
+                interactive,
+            };
+            let adb = connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+            let report = install(config, &active_repos, adb, options).await?;
+            check_report(&report, cli.output)?;
+        }
+
+        Commands::Update {
+            daemon,
+            interval,
+            notify,
+            repos,
+            config,
+            remote_auth_url,
+            variables,
+        } => {
+            let variable_overrides = parse_variable_overrides(&variables)?;
+            let mut config = load_config(&config).await?;
+            let mut active_repos = config.filter_repositories(repos)?;
+            config.resolve_and_apply_variables(&mut active_repos, &variable_overrides)?;
+            apply_notify_flags(&mut config, notify, None);
+
+            let options = || InstallOptions {
+                cache_dir: None,
+                github_token: cli.github_token.clone(),
+                force_unlock: cli.force_unlock,
+                allow_any_device: cli.allow_any_device,
+                ignore_battery: cli.ignore_battery,
+                check_known_device: false,
+                backup_dir: None,
+                wait_for_reboot: false,
+                session: None,
+                interactive: false,
             };
 
-            engine.install(&active_repos, cache_dir.is_some()).await?;
+            if !daemon {
+                let mut adb =
+                    connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+                let device = adb.serial_number().await?;
+                if let Some(report) =
+                    update(&config, &active_repos, adb, &device, options()).await?
+                {
+                    check_report(&report, cli.output)?;
+                }
+                return Ok(());
+            }
+
+            info!(
+                "Watching for devices, checking for new releases every {}s",
+                interval.as_secs()
+            );
+            let mut connected: Option<String> = None;
+            let mut last_checked: HashMap<String, Instant> = HashMap::new();
+            loop {
+                // Nobody is around to pick a device for the daemon
+                match connect_device(cli.serial.as_deref(), remote_auth_url.clone(), false).await {
+                    Ok(mut adb) => match adb.serial_number().await {
+                        Ok(device) => {
+                            let reconnected = connected.as_ref() != Some(&device);
+                            let due = last_checked
+                                .get(&device)
+                                .is_none_or(|checked| checked.elapsed() >= interval);
+
+                            if reconnected || due {
+                                info!("Checking for updates for {}", device);
+                                last_checked.insert(device.clone(), Instant::now());
+                                if let Err(e) =
+                                    update(&config, &active_repos, adb, &device, options()).await
+                                {
+                                    error!("Update of {} failed: {}", device, e);
+                                }
+                            }
+                            connected = Some(device);
+                        }
+                        Err(e) => warn!("Failed to identify device: {}", e),
+                    },
+                    Err(_) => connected = None,
+                }
+
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {
+                        info!("Stopping update daemon");
+                        break;
+                    }
+                    _ = tokio::time::sleep(DEVICE_POLL_INTERVAL) => {}
+                }
+            }
+        }
+
+        Commands::Replay { session } => {
+            let session = Session::replay(&session)?;
+            let header = session.header().clone();
+            info!(
+                "Replaying {} installation recorded with installer {}",
+                header.config.name, header.installer_version
+            );
+
+            let adb = AdbManager::replay(session.clone());
+            let options = InstallOptions {
+                cache_dir: None,
+                github_token: None,
+                force_unlock: cli.force_unlock,
+                allow_any_device: cli.allow_any_device,
+                ignore_battery: cli.ignore_battery,
+                check_known_device: false,
+                backup_dir: None,
+                wait_for_reboot: false,
+                session: Some(session),
+                interactive: false,
+            };
+            let report = install(header.config, &header.repositories, adb, options).await?;
+            check_report(&report, cli.output)?;
         }
 
         Commands::Uninstall {
             repos,
+            force,
             remote_auth_url,
         } => {
-            let config = ConfigLoader::load_builtin("penumbra")?;
-            let mut engine = InstallationEngine::new_with_token(
+            let config = load_config(&[]).await?;
+            let active_repos = config.filter_repositories(repos)?;
+            let protected: Vec<String> = active_repos
+                .iter()
+                .filter(|repo| repo.protected)
+                .map(|repo| repo.name.clone())
+                .collect();
+            let force = force
+                || (!protected.is_empty()
+                    && interactive
+                    && task::spawn_blocking(move || confirm_protected(&protected)).await??);
+
+            let adb = connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+            let mut engine = InstallationEngine::new_with_device(
                 config,
+                Platform::temp_dir(),
                 cli.github_token.clone(),
-                remote_auth_url,
+                adb,
                 None,
             )
             .await?;
-            let active_repos = engine.config.filter_repositories(repos)?;
+            engine.set_force_unlock(cli.force_unlock);
+            engine.set_force_uninstall(force);
             engine.uninstall(&active_repos).await?;
         }
 
-        Commands::Download { repos, cache_dir } => {
+        Commands::Reset {
+            yes,
+            remote_auth_url,
+        } => {
             let config = ConfigLoader::load_builtin("penumbra")?;
-            let mut engine = InstallationEngine::new_with_cache(
+            let mut adb =
+                connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+
+            if !yes {
+                let plan =
+                    UninstallPlan::resolve(&config, config.all_repositories(), &mut adb).await?;
+                info!("Reset will:");
+                for repo in &plan.repositories {
+                    for package in &repo.packages {
+                        info!("  Uninstall {} and its app data", package);
+                    }
+                }
+                for path in config.created_paths() {
+                    info!("  Remove {}", path);
+                }
+                for package in &config.reset.enable_packages {
+                    info!("  Re-enable {}", package);
+                }
+                if let Some(launcher) = &config.reset.launcher {
+                    info!("  Restore launcher {}", launcher);
+                }
+
+                if !confirm("Reset the device to stock?", false)? {
+                    info!("Reset cancelled");
+                    return Ok(());
+                }
+            }
+
+            let mut engine = InstallationEngine::new_with_device(
                 config,
-                cache_dir,
+                Platform::temp_dir(),
                 cli.github_token.clone(),
-                None,
+                adb,
                 None,
             )
             .await?;
+            engine.set_force_unlock(cli.force_unlock);
+            let report = engine.reset().await?;
+
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&report)?);
+            }
+            if !report.is_clean() {
+                return Err(InstallerError::InstallationStep {
+                    step: "reset".into(),
+                    reason: format!(
+                        "{} changes could not be undone",
+                        report.errors.len() + report.remaining.len()
+                    ),
+                });
+            }
+        }
+
+        Commands::Download { repos, cache_dir } => {
+            let config = load_config(&[]).await?;
+            let mut engine =
+                InstallationEngine::new_offline(config, cache_dir, cli.github_token.clone(), None)
+                    .await?;
             let active_repos = engine.config.filter_repositories(repos)?;
+            if std::io::stderr().is_terminal() {
+                show_progress(&mut engine);
+            }
             engine.download(&active_repos).await?;
         }
 
         Commands::List { config } => {
-            let config = if let Some(config_path) = config {
-                ConfigLoader::load_from_file(&config_path).await?
-            } else {
-                ConfigLoader::load_builtin("penumbra")?
-            };
+            let config = load_config(&config).await?;
 
-            info!("Available repositories in '{}':", config.name);
-            for repo in config.all_repositories() {
-                info!("  {}", repo.name);
+            info!(
+                "Available repositories in '{}', in installation order:",
+                config.name
+            );
+            for (index, repo) in config.all_repositories().iter().enumerate() {
+                info!("  {}. {}", index + 1, repo.name);
                 info!("     Repository: {}/{}", repo.owner, repo.repo);
                 info!("     Version: {:?}", repo.version);
                 if repo.optional {
                     info!("     Optional: true");
+                } else if !config.is_default_selected(repo) {
+                    info!("     Disabled: true");
+                }
+                if repo.protected {
+                    info!("     Protected: true");
+                }
+                if config.is_held(repo) {
+                    info!("     Held: true");
+                }
+                if repo.priority != 0 {
+                    info!("     Priority: {}", repo.priority);
                 }
                 if !repo.release_assets.is_empty() {
-                    info!("     Assets: {}", repo.release_assets.join(", "));
+                    let assets: Vec<String> = repo
+                        .release_assets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect();
+                    info!("     Assets: {}", assets.join(", "));
                 }
                 if !repo.repo_files.is_empty() {
-                    info!("     Files: {}", repo.repo_files.join(", "));
+                    let files: Vec<String> =
+                        repo.repo_files.iter().map(ToString::to_string).collect();
+                    info!("     Files: {}", files.join(", "));
                 }
             }
         }
 
-        Commands::Devices { remote_auth_url } => {
-            use penumbra_installer::adb::AdbManager;
+        Commands::BackupData {
+            repos,
+            config,
+            output_dir,
+            remote_auth_url,
+        } => {
+            let config = load_config(&config).await?;
+            let active_repos = config.filter_repositories(repos)?;
+            let paths = config.data_paths(&active_repos);
+            let output_dir = output_dir.unwrap_or_else(backup::backup_dir);
 
-            info!("Checking device connection...");
-            match AdbManager::connect(remote_auth_url).await {
-                Ok(_) => {
-                    info!("Single device connected and ready for installation");
+            let mut adb =
+                connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+            let archive = backup::backup_data(&mut adb, &config.name, &paths, &output_dir).await?;
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::json!({ "archive": archive }));
+            }
+        }
+
+        Commands::RestoreData {
+            archive,
+            config,
+            remote_auth_url,
+        } => {
+            let config = load_config(&config).await?;
+            let paths = config.data_paths(config.all_repositories());
+
+            let mut adb =
+                connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+            let manifest = backup::restore_data(&mut adb, &archive, &paths).await?;
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&manifest)?);
+            }
+        }
+
+        Commands::Usage {
+            repos,
+            config,
+            remote_auth_url,
+        } => {
+            let config = load_config(&config).await?;
+            // Optional and disabled repositories may still be installed, so measure them all
+            let active_repos = match repos {
+                Some(_) => config.filter_repositories(repos)?,
+                None => config.all_repositories().to_vec(),
+            };
+
+            let mut adb =
+                connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+            let report = UsageReport::resolve(&config, &active_repos, &mut adb).await?;
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&report)?);
+            } else {
+                report.log_summary();
+            }
+        }
+
+        Commands::CheckHost => {
+            let github = GitHubClient::new_with_token(cli.github_token.clone());
+            let report = HostReport::run(&github, None).await;
+
+            if cli.output == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&report)?);
+            } else {
+                info!("Host checks:");
+                report.log_summary();
+            }
+            if !report.passed() {
+                return Err(InstallerError::InstallationStep {
+                    step: "host checks".into(),
+                    reason: report
+                        .failures()
+                        .map(|check| check.id.clone())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                        + " failed",
+                });
+            }
+        }
+
+        Commands::Devices {
+            remote_auth_url,
+            forget,
+        } => {
+            if let Some(serial) = forget {
+                let mut known = KnownDevices::load();
+                if known.remove(&serial) {
+                    known.save()?;
+                    info!("Forgot {}", serial);
+                } else {
+                    warn!("{} isn't a device you've installed to", serial);
                 }
+                return Ok(());
+            }
+
+            info!("Checking device connection...");
+            match connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await {
+                Ok(mut adb) => match adb.serial_number().await {
+                    Ok(serial) => {
+                        info!("{} connected and ready for installation", serial);
+                        if KnownDevices::load().get(&serial).is_none() {
+                            info!("   You haven't installed to this device before");
+                        }
+                    }
+                    Err(_) => info!("Device connected and ready for installation"),
+                },
                 Err(InstallerError::NoDevice) => {
                     warn!("No Android device connected");
                     warn!("   Please connect a device and enable USB debugging");
@@ -199,7 +1226,15 @@ async fn main() -> Result<()> {
                 }
                 Err(InstallerError::MultipleDevices) => {
                     warn!("Multiple devices connected");
-                    warn!("   Please connect exactly one device for installation");
+                    for device in AdbManager::list_devices().unwrap_or_default() {
+                        warn!(
+                            "   {}  {}  ({})",
+                            device.serial,
+                            device.model.as_deref().unwrap_or("unknown model"),
+                            device.state
+                        );
+                    }
+                    warn!("   Please connect exactly one device, or choose one with --serial");
                     std::process::exit(1);
                 }
                 Err(e) => {
@@ -209,10 +1244,665 @@ async fn main() -> Result<()> {
             }
         }
 
+        Commands::Connect {
+            address,
+            code,
+            pair_address,
+            disconnect,
+        } => {
+            let address = AdbManager::parse_socket_address(&address)?;
+            if disconnect {
+                task::spawn_blocking(move || AdbManager::disconnect_wireless(address)).await??;
+                info!("Disconnected from {}", address);
+                return Ok(());
+            }
+
+            if let (Some(code), Some(pair_address)) = (code, pair_address) {
+                let pair_address = AdbManager::parse_socket_address(&pair_address)?;
+                task::spawn_blocking(move || AdbManager::pair_wireless(pair_address, code.trim()))
+                    .await??;
+                info!("Paired with {}", pair_address);
+            }
+
+            let serial =
+                task::spawn_blocking(move || AdbManager::connect_wireless(address)).await??;
+            let mut adb = AdbManager::connect_device(Some(&serial), None).await?;
+            let identity = adb.device_identity().await?;
+            info!("Connected to {} at {}", identity, serial);
+            info!("   Install to it with --serial {}", serial);
+        }
+
         Commands::DumpLogs {
             stream,
             remote_auth_url,
         } => dump_logcat_and_exit(stream, remote_auth_url).await,
+
+        Commands::Cache { command } => match command {
+            CacheCommand::Info => {
+                let usage = Platform::cache_usage()?;
+                info!("Cache: {}", usage.path.display());
+                info!(
+                    "  {} files, {:.1} MB",
+                    usage.file_count,
+                    usage.total_bytes as f64 / 1_048_576.0
+                );
+                for entry in usage.entries {
+                    info!(
+                        "  {}: {} files, {:.1} MB",
+                        entry.name,
+                        entry.file_count,
+                        entry.bytes as f64 / 1_048_576.0
+                    );
+                }
+            }
+            CacheCommand::Prune {
+                max_age_days,
+                max_size_mb,
+            } => {
+                if max_age_days.is_none() && max_size_mb.is_none() {
+                    return Err(InstallerError::CLI(
+                        "Specify `--max-age-days` and/or `--max-size-mb`".into(),
+                    ));
+                }
+
+                let policy = PrunePolicy {
+                    max_age: max_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    max_bytes: max_size_mb.map(|mb| mb * 1024 * 1024),
+                };
+                let removed = Platform::prune_cache(&policy)?;
+                info!("Removed {} cache entries", removed.len());
+            }
+            CacheCommand::Clear => {
+                let cache_dir = Platform::cache_dir();
+                if cache_dir.exists() {
+                    std::fs::remove_dir_all(&cache_dir)?;
+                }
+                info!("Cleared {}", cache_dir.display());
+            }
+        },
+
+        Commands::Hold {
+            repo,
+            version,
+            config,
+        } => {
+            let config = load_config(&config).await?;
+            if config.get_repository(&repo).is_none() {
+                return Err(InstallerError::RepositoryNotFound { repo });
+            }
+
+            // Holding at the release the config selects now could hold at one never installed
+            let version = match version {
+                Some(version) => version,
+                None => UpdateState::load()
+                    .installed_version(&repo, cli.serial.as_deref())
+                    .map(str::to_string)
+                    .ok_or_else(|| {
+                        InstallerError::CLI(format!(
+                            "The installed version of {} isn't known, so give the version to \
+                             hold it at",
+                            repo
+                        ))
+                    })?,
+            };
+
+            let mut overrides = RepositoryOverrides::load();
+            if overrides.hold(&repo, &version) {
+                overrides.save()?;
+            }
+            info!("{} is held at {}", repo, version);
+        }
+
+        Commands::Unhold { repo } => {
+            let mut overrides = RepositoryOverrides::load();
+            if overrides.unhold(&repo) {
+                overrides.save()?;
+                info!("{} is no longer held", repo);
+            } else {
+                info!("{} was not held", repo);
+            }
+        }
+
+        Commands::Snapshot { command } => match command {
+            SnapshotCommand::Export {
+                output,
+                include_settings,
+                config,
+                remote_auth_url,
+            } => {
+                let config = load_config(&config).await?;
+                let mut adb =
+                    connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+                let snapshot = DeviceSnapshot::capture(
+                    &config,
+                    config.all_repositories(),
+                    &mut adb,
+                    include_settings,
+                )
+                .await?;
+
+                match output {
+                    Some(path) => {
+                        snapshot.save(&path)?;
+                        info!(
+                            "Saved {} repositories of {} to {}",
+                            snapshot.repositories.len(),
+                            snapshot.device,
+                            path.display()
+                        );
+                    }
+                    None => println!("{}", serde_json::to_string_pretty(&snapshot)?),
+                }
+            }
+            SnapshotCommand::Apply {
+                file,
+                config,
+                remote_auth_url,
+                variables,
+            } => {
+                let snapshot: DeviceSnapshot =
+                    serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+                let variable_overrides = parse_variable_overrides(&variables)?;
+                let mut config = load_config(&config).await?;
+                if snapshot.config != config.name {
+                    warn!(
+                        "The snapshot was taken with the '{}' config, applying it with '{}'",
+                        snapshot.config, config.name
+                    );
+                }
+
+                let mut active_repos = snapshot.repositories(&config);
+                let mut adb =
+                    connect_device(cli.serial.as_deref(), remote_auth_url, interactive).await?;
+                // Settings are only compared when the snapshot recorded them
+                let current = DeviceSnapshot::capture(
+                    &config,
+                    &active_repos,
+                    &mut adb,
+                    snapshot.has_settings(),
+                )
+                .await?;
+                let changed = snapshot.differences(&current);
+                active_repos.retain(|repo| changed.contains(&repo.name));
+                if active_repos.is_empty() {
+                    info!("The device already matches the snapshot");
+                    return Ok(());
+                }
+
+                info!("Applying {} from the snapshot", changed.join(", "));
+                config.resolve_and_apply_variables(&mut active_repos, &variable_overrides)?;
+                let options = InstallOptions {
+                    cache_dir: None,
+                    github_token: cli.github_token.clone(),
+                    force_unlock: cli.force_unlock,
+                    allow_any_device: cli.allow_any_device,
+                    ignore_battery: cli.ignore_battery,
+                    check_known_device: cli.serial.is_none(),
+                    backup_dir: None,
+                    wait_for_reboot: false,
+                    session: None,
+                    interactive,
+                };
+                let report = install(config, &active_repos, adb, options).await?;
+                check_report(&report, cli.output)?;
+            }
+        },
+
+        Commands::Config { command } => match command {
+            ConfigCommand::SetEnabled {
+                repo,
+                enabled,
+                config,
+            } => {
+                let config = load_config(&config).await?;
+                if config.get_repository(&repo).is_none() {
+                    return Err(InstallerError::RepositoryNotFound { repo });
+                }
+
+                let mut overrides = RepositoryOverrides::load();
+                if overrides.set_enabled(&repo, enabled) {
+                    overrides.save()?;
+                }
+                info!(
+                    "{} is {}",
+                    repo,
+                    if enabled { "enabled" } else { "disabled" }
+                );
+            }
+            ConfigCommand::Vars { config } => {
+                let config = load_config(&config).await?;
+                let usage = config.variable_usage();
+
+                if cli.output == OutputFormat::Json {
+                    println!("{}", serde_json::to_string(&usage)?);
+                    return Ok(());
+                }
+                if usage.is_empty() {
+                    info!("'{}' has no variables", config.name);
+                    return Ok(());
+                }
+
+                info!("Variables in '{}':", config.name);
+                for entry in &usage {
+                    let variable = &entry.variable;
+                    info!(
+                        "  --{} ({}, {})",
+                        variable.name,
+                        entry.repository,
+                        if variable.required {
+                            "required"
+                        } else {
+                            "optional"
+                        }
+                    );
+                    if let Some(description) = &variable.description {
+                        info!("     {}", description);
+                    }
+                    if let Some(default) = &variable.default {
+                        info!("     Default: {}", default);
+                    }
+                    if entry.references.is_empty() {
+                        info!("     Not used by any step");
+                    } else {
+                        info!("     Used in: {}", entry.references.join(", "));
+                    }
+                }
+            }
+            ConfigCommand::Match { repo, config } => {
+                let config = load_config(&config).await?;
+                let Some(repository) = config.get_repository(&repo) else {
+                    return Err(InstallerError::RepositoryNotFound { repo });
+                };
+
+                let github = GitHubClient::new_with_token(cli.github_token.clone());
+                let report = github.match_assets(repository).await?;
+
+                if cli.output == OutputFormat::Json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    report.log_summary();
+                }
+
+                let unmatched: Vec<_> = report
+                    .unmatched_patterns()
+                    .map(|pattern| pattern.pattern.clone())
+                    .collect();
+                if !unmatched.is_empty() {
+                    return Err(InstallerError::Config(format!(
+                        "no release assets of {} {} match {}",
+                        report.repository,
+                        report.version,
+                        unmatched.join(", ")
+                    )));
+                }
+            }
+        },
+
+        Commands::Keys { command } => match command {
+            KeysCommand::Rotate { remote_auth_url } => {
+                let store = match &remote_auth_url {
+                    Some(url) => KeyStore::remote(url),
+                    None => KeyStore::local()?,
+                };
+                let rotation = keys::rotate(&store, cli.serial.as_deref()).await?;
+
+                if cli.output == OutputFormat::Json {
+                    println!("{}", serde_json::to_string(&rotation)?);
+                } else {
+                    info!("{} now accepts the new key", rotation.serial);
+                    if !rotation.retired_on_device {
+                        warn!("   The previous key may still be authorized on the device");
+                    }
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Loads the layered `--config` files, or the built-in config when there are none, leaving out
+/// the repositories the user disabled
+async fn load_config(paths: &[PathBuf]) -> Result<InstallConfig> {
+    let mut config = if paths.is_empty() {
+        ConfigLoader::load_builtin("penumbra")?
+    } else {
+        ConfigLoader::load_layered(paths).await?
+    };
+    config.apply_overrides(&RepositoryOverrides::load());
+    Ok(config)
+}
+
+/// Warns about problems with this computer before installing. They don't stop the install, as
+/// they may not affect it, such as missing libusb when an ADB server is running
+async fn check_host_before_install(github_token: Option<String>) {
+    let report = HostReport::run(&GitHubClient::new_with_token(github_token), None).await;
+    let problems = report
+        .checks
+        .iter()
+        .any(|check| matches!(check.status, CheckStatus::Warning | CheckStatus::Failed));
+
+    if problems {
+        warn!("Found problems with this computer (see `penumbra check-host`):");
+        report.log_summary();
+    }
+}
+
+struct InstallOptions {
+    cache_dir: Option<PathBuf>,
+    github_token: Option<String>,
+    force_unlock: bool,
+    allow_any_device: bool,
+    ignore_battery: bool,
+    /// Ask before installing to a device other than those installed to before
+    check_known_device: bool,
+    backup_dir: Option<PathBuf>,
+    /// Wait for the device to boot after an install reboots it
+    wait_for_reboot: bool,
+    session: Option<Arc<Session>>,
+    /// Ask on the terminal before `Prompt` steps, rather than taking their default answers
+    interactive: bool,
+}
+
+async fn install(
+    config: InstallConfig,
+    active_repos: &Vec<Repository>,
+    adb: AdbManager,
+    options: InstallOptions,
+) -> Result<InstallReport> {
+    let mut engine = InstallationEngine::new_with_device(
+        config,
+        options.cache_dir.clone().unwrap_or_else(Platform::temp_dir),
+        options.github_token,
+        adb,
+        None,
+    )
+    .await?;
+
+    engine.set_force_unlock(options.force_unlock);
+    engine.set_allow_any_device(options.allow_any_device);
+    engine.set_ignore_battery(options.ignore_battery);
+    engine.set_check_known_device(options.check_known_device);
+    engine.set_backup_dir(options.backup_dir);
+    engine.set_wait_for_reboot(options.wait_for_reboot);
+    if let Some(session) = options.session {
+        engine.set_session(session);
+    }
+    if options.interactive {
+        engine.set_prompter(TerminalPrompter);
+        if std::io::stderr().is_terminal() {
+            show_progress(&mut engine);
+        }
+    }
+    engine
+        .install(active_repos, options.cache_dir.is_some())
+        .await
+}
+
+/// Shows one progress line on the terminal for each batch of downloads and pushes totalling a
+/// megabyte or more, until the engine is dropped
+fn show_progress(engine: &mut InstallationEngine) {
+    let tracker = ProgressTracker::new();
+    let mut snapshots = tracker.subscribe();
+    tokio::spawn(engine.track_progress(&tracker));
+
+    tokio::spawn(async move {
+        while snapshots.changed().await.is_ok() {
+            let snapshot = snapshots.borrow_and_update().clone();
+            if snapshot.total_bytes < 1_048_576 {
+                continue;
+            }
+
+            // Named after the tasks still running, or once the batch is done, all of them
+            let done = snapshot.is_done();
+            let tasks: Vec<_> = if done {
+                snapshot.tasks.iter().collect()
+            } else {
+                snapshot.active().collect()
+            };
+            let action = if tasks.iter().all(|task| task.kind == TaskKind::Download) {
+                "Downloading"
+            } else if tasks.iter().all(|task| task.kind == TaskKind::Transfer) {
+                "Sending"
+            } else {
+                "Transferring"
+            };
+            let what = match tasks.as_slice() {
+                [task] => task.name.clone(),
+                tasks => format!("{} files", tasks.len()),
+            };
+
+            eprint!(
+                "\r\x1b[K  {} {}: {:.0}% ({:.1} of {:.1} MB)",
+                action,
+                what,
+                snapshot.percent,
+                snapshot.done_bytes as f64 / 1_048_576.0,
+                snapshot.total_bytes as f64 / 1_048_576.0
+            );
+            if done {
+                eprintln!();
+            }
+        }
+    });
+}
+
+/// Installs releases newer than those last applied to `device`, which notifies like any other
+/// install. Returns `None` when there was nothing to install
+async fn update(
+    config: &InstallConfig,
+    active_repos: &Vec<Repository>,
+    adb: AdbManager,
+    device: &str,
+    options: InstallOptions,
+) -> Result<Option<InstallReport>> {
+    let github = GitHubClient::new_with_token(options.github_token.clone());
+    let plan = InstallPlan::resolve(config, active_repos, &github).await?;
+
+    let mut state = UpdateState::load();
+    match state.check(device, &plan) {
+        UpdateCheck::UpToDate => {
+            info!("{} is up to date", device);
+            return Ok(None);
+        }
+        UpdateCheck::PreviouslyFailed => {
+            warn!(
+                "Skipping {}, as updating it to these releases failed before",
+                device
+            );
+            return Ok(None);
+        }
+        UpdateCheck::Available(versions) => {
+            for (name, version) in &versions {
+                info!("Update available for {}: {} {}", device, name, version);
+            }
+        }
+    }
+
+    let result = install(config.clone(), active_repos, adb, options).await;
+    let succeeded = result.as_ref().is_ok_and(|report| report.succeeded());
+
+    state.record(device, &plan, succeeded);
+    state.save()?;
+
+    result.map(Some)
+}
+
+/// Replaces the config's notification command and webhook with those given as flags
+fn apply_notify_flags(
+    config: &mut InstallConfig,
+    command: Option<String>,
+    webhook: Option<String>,
+) {
+    if command.is_some() {
+        config.notify.command = command;
+    }
+    if webhook.is_some() {
+        config.notify.webhook = webhook;
+    }
+}
+
+/// Reports an install that failed before the engine, which notifies for itself, could start
+async fn notify_connect_failure(
+    notify: &NotifyConfig,
+    config_name: &str,
+    device: Option<String>,
+    error: &InstallerError,
+) {
+    if notify.is_empty() {
+        return;
+    }
+
+    let notification = Notification {
+        event: NotifyEvent::Failed,
+        config_name: config_name.to_string(),
+        device,
+        report: None,
+        error: Some(error.details()),
+    };
+    notify.send(&notification).await;
+}
+
+/// Parses a duration such as `90s`, `30m`, `6h`, or `1d`
+fn parse_interval(value: &str) -> std::result::Result<Duration, String> {
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("'{}' doesn't start with a number", value))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit '{}', expected s, m, h, or d", unit)),
+    };
+    if amount == 0 {
+        return Err("Interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(amount * seconds))
+}
+
+/// Asks for the names of the `protected` repositories to be typed before uninstalling them,
+/// returning whether they all were
+fn confirm_protected(protected: &[String]) -> Result<bool> {
+    warn!(
+        "Other components stop working without these protected repositories: {}",
+        protected.join(", ")
+    );
+    for name in protected {
+        print!("Type '{}' to uninstall it anyway: ", name);
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim() != name {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Asks a yes/no question on stdin, taking `default` on an empty answer
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "yes"),
+    })
+}
+
+/// Whether questions such as `Prompt` steps can be asked on the terminal without corrupting JSON
+/// output
+fn is_interactive(output: OutputFormat, non_interactive: bool) -> bool {
+    !non_interactive && output == OutputFormat::Text && std::io::stdin().is_terminal()
+}
+
+/// Connects to the device `serial` names, or the only one attached. When several are attached
+/// and none was named, asks which to use if `interactive`
+async fn connect_device(
+    serial: Option<&str>,
+    remote_auth_url: Option<String>,
+    interactive: bool,
+) -> Result<AdbManager> {
+    match AdbManager::connect_device(serial, remote_auth_url.clone()).await {
+        Err(InstallerError::MultipleDevices) if serial.is_none() && interactive => {
+            // Devices found over USB without an ADB server can't be listed or chosen
+            let devices = AdbManager::list_devices().unwrap_or_default();
+            if devices.len() < 2 {
+                return Err(InstallerError::MultipleDevices);
+            }
+
+            let serial = task::spawn_blocking(move || pick_device(&devices)).await??;
+            AdbManager::connect_device(Some(&serial), remote_auth_url).await
+        }
+        result => result,
+    }
+}
+
+/// Lists `devices` and asks which to use, returning its serial
+fn pick_device(devices: &[ConnectedDevice]) -> Result<String> {
+    println!("Several devices are attached:");
+    for (index, device) in devices.iter().enumerate() {
+        println!(
+            "  {}) {}  {}  ({})",
+            index + 1,
+            device.serial,
+            device.model.as_deref().unwrap_or("unknown model"),
+            device.state
+        );
+    }
+
+    loop {
+        print!("Use which device? [1-{}] ", devices.len());
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Err(InstallerError::MultipleDevices);
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(choice) if (1..=devices.len()).contains(&choice) => {
+                return Ok(devices[choice - 1].serial.clone());
+            }
+            _ => println!("Enter a number from 1 to {}", devices.len()),
+        }
+    }
+}
+
+/// Asks `Prompt` steps on the terminal
+struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn confirm<'a>(&'a self, message: &'a str, default: bool) -> PromptFuture<'a> {
+        let message = message.to_string();
+        Box::pin(async move { task::spawn_blocking(move || confirm(&message, default)).await? })
+    }
+}
+
+/// Prints the report in JSON mode, failing if any required repository failed
+fn check_report(report: &InstallReport, output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string(report)?);
+    }
+
+    if !report.succeeded() {
+        return Err(InstallerError::InstallationStep {
+            step: "install".into(),
+            reason: report
+                .failures()
+                .map(|r| r.name.clone())
+                .collect::<Vec<_>>()
+                .join(", ")
+                + " failed",
+        });
     }
 
     Ok(())
@@ -0,0 +1,159 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::report::InstallReport;
+use crate::{task, ErrorDetails, InstallerError, Result};
+
+/// Longest wait for a webhook to respond
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest wait for the notification command to exit before it is killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the notification command is checked for having exited
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Host-side command and webhook told when an installation finishes, such as by provisioning
+/// rigs or CI
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NotifyConfig {
+    /// Shell command run with the notification as JSON on stdin, and `PENUMBRA_EVENT` and
+    /// `PENUMBRA_DEVICE` set. Killed if it runs longer than a minute. Configs loaded from a URL
+    /// can't set it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// URL the notification is POSTed to as JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    Completed,
+    Failed,
+}
+
+impl NotifyEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            NotifyEvent::Completed => "completed",
+            NotifyEvent::Failed => "failed",
+        }
+    }
+}
+
+/// Payload sent when an installation finishes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Notification {
+    pub event: NotifyEvent,
+    pub config_name: String,
+    /// Serial number of the device, if it could still be read
+    pub device: Option<String>,
+    /// Per-repository outcomes, for installations that got as far as installing
+    pub report: Option<InstallReport>,
+    /// Why the installation stopped, for installations that failed before finishing
+    pub error: Option<ErrorDetails>,
+}
+
+impl NotifyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none() && self.webhook.is_none()
+    }
+
+    /// Runs the command and calls the webhook. Failures are logged rather than returned, so a
+    /// broken hook doesn't fail the installation it reports on
+    pub async fn send(&self, notification: &Notification) {
+        let payload = match serde_json::to_string(notification) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Failed to encode notification: {}", e);
+                return;
+            }
+        };
+
+        if let Some(command) = self.command.clone() {
+            let event = notification.event.as_str();
+            let device = notification.device.clone().unwrap_or_default();
+            let payload = payload.clone();
+            let result =
+                task::spawn_blocking(move || run_command(&command, event, &device, &payload)).await;
+            match result.and_then(|result| result) {
+                Ok(()) => info!("Ran notification command"),
+                Err(e) => warn!("Notification command failed: {}", e),
+            }
+        }
+
+        if let Some(url) = &self.webhook {
+            match post_webhook(url, payload).await {
+                Ok(()) => info!("Sent notification to {}", url),
+                Err(e) => warn!("Notification webhook {} failed: {}", url, e),
+            }
+        }
+    }
+}
+
+fn run_command(command: &str, event: &str, device: &str, payload: &str) -> Result<()> {
+    #[cfg(windows)]
+    let mut process = Command::new("cmd");
+    #[cfg(windows)]
+    process.args(["/C", command]);
+    #[cfg(not(windows))]
+    let mut process = Command::new("sh");
+    #[cfg(not(windows))]
+    process.args(["-c", command]);
+
+    let mut child = process
+        .env("PENUMBRA_EVENT", event)
+        .env("PENUMBRA_DEVICE", device)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Written from another thread, as a command that doesn't read its input would otherwise
+        // block the write once the pipe fills. Errors are ignored for the same reason
+        let payload = payload.to_string();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(payload.as_bytes());
+        });
+    }
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(InstallerError::InstallationStep {
+                step: "notify".into(),
+                reason: format!(
+                    "command didn't finish within {} seconds",
+                    COMMAND_TIMEOUT.as_secs()
+                ),
+            });
+        }
+        std::thread::sleep(COMMAND_POLL_INTERVAL);
+    };
+    if !status.success() {
+        return Err(InstallerError::InstallationStep {
+            step: "notify".into(),
+            reason: format!("command exited with {}", status),
+        });
+    }
+    Ok(())
+}
+
+async fn post_webhook(url: &str, payload: String) -> Result<()> {
+    Client::new()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(payload)
+        .timeout(WEBHOOK_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
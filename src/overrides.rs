@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::platform::Platform;
+use crate::Result;
+
+/// The user's own choices about which repositories to install, kept across runs and applied to
+/// every config with [`InstallConfig::apply_overrides`](crate::InstallConfig::apply_overrides)
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RepositoryOverrides {
+    /// Repositories left out unless explicitly selected, like optional repositories
+    #[serde(default)]
+    pub disabled: BTreeSet<String>,
+    /// Versions repositories are held at, installed in place of their configured version
+    #[serde(default)]
+    pub held: BTreeMap<String, String>,
+}
+
+impl RepositoryOverrides {
+    pub fn path() -> PathBuf {
+        Platform::config_dir().join("repositories.json")
+    }
+
+    /// Loads the saved overrides. A missing or unreadable file is treated as empty
+    pub fn load() -> Self {
+        Platform::load_json(&Self::path(), "repository overrides")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Platform::save_json(&Self::path(), self)
+    }
+
+    pub fn is_enabled(&self, repo: &str) -> bool {
+        !self.disabled.contains(repo)
+    }
+
+    /// Returns whether this changed the overrides
+    pub fn set_enabled(&mut self, repo: &str, enabled: bool) -> bool {
+        if enabled {
+            self.disabled.remove(repo)
+        } else {
+            self.disabled.insert(repo.to_string())
+        }
+    }
+
+    /// Holds `repo` at `version`. Returns whether this changed the overrides
+    pub fn hold(&mut self, repo: &str, version: &str) -> bool {
+        self.held
+            .insert(repo.to_string(), version.to_string())
+            .as_deref()
+            != Some(version)
+    }
+
+    /// Returns whether `repo` was held
+    pub fn unhold(&mut self, repo: &str) -> bool {
+        self.held.remove(repo).is_some()
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What the engine is currently doing, so frontends can lay out their progress from real state
+/// rather than from log output. Phases repeat for each repository
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallPhase {
+    /// No operation has started
+    #[default]
+    Idle,
+    /// Checking and preparing the device, such as taking the installation lock
+    Connecting,
+    /// Finding the release to install
+    Resolving,
+    Downloading,
+    /// Running cleanup steps
+    Cleaning,
+    /// Running setup and installation steps
+    Installing,
+    /// Checking the device state after changing it
+    Verifying,
+    Rebooting,
+    /// The operation finished
+    Done,
+}
+
+impl InstallPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallPhase::Idle => "idle",
+            InstallPhase::Connecting => "connecting",
+            InstallPhase::Resolving => "resolving",
+            InstallPhase::Downloading => "downloading",
+            InstallPhase::Cleaning => "cleaning",
+            InstallPhase::Installing => "installing",
+            InstallPhase::Verifying => "verifying",
+            InstallPhase::Rebooting => "rebooting",
+            InstallPhase::Done => "done",
+        }
+    }
+}
+
+impl fmt::Display for InstallPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
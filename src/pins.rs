@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::github::RepoFile;
+use crate::platform::Platform;
+use crate::{InstallerError, Result};
+
+/// Git blob SHAs of the repository files fetched at each release tag. A file is pinned the first
+/// time it is fetched at a tag, so content that later changes for the same tag is detected
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FilePins {
+    /// `owner/repo@tag:path` to blob SHA
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+impl FilePins {
+    pub fn path() -> PathBuf {
+        Platform::state_dir().join("repo_files.lock.json")
+    }
+
+    /// Loads the saved pins. A missing or unreadable file is treated as empty
+    pub fn load() -> Self {
+        Platform::load_json(&Self::path(), "repository file pins")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Platform::save_json(&Self::path(), self)
+    }
+
+    /// Pins `file` as fetched from `owner/repo` at `tag`, failing if it was previously fetched
+    /// at that tag with different content
+    pub fn check(&mut self, owner: &str, repo: &str, tag: &str, file: &RepoFile) -> Result<()> {
+        let key = format!("{}/{}@{}:{}", owner, repo, tag, file.path);
+
+        match self.files.get(&key) {
+            Some(pinned) if *pinned != file.sha => Err(InstallerError::RepoFileChanged {
+                file: format!("{}/{}:{}", owner, repo, file.path),
+                version: tag.to_string(),
+                expected: pinned.clone(),
+                actual: file.sha.clone(),
+            }),
+            Some(_) => Ok(()),
+            None => {
+                info!("Pinned {} at {} ({})", file.path, tag, file.sha);
+                self.files.insert(key, file.sha.clone());
+                Ok(())
+            }
+        }
+    }
+}
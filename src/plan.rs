@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+
+use crate::adb::quote;
+use crate::config::{CleanupStep, StepConflict};
+use crate::github::{GitHubClient, ReleaseAsset};
+use crate::resolve;
+use crate::{AdbManager, InstallConfig, Repository, Result};
+
+/// The resolved set of actions an installation would perform, computed without a device
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstallPlan {
+    pub config_name: String,
+    pub global_setup: Vec<String>,
+    pub repositories: Vec<RepositoryPlan>,
+    pub reboot_after_completion: bool,
+    /// Versions known not to work on the device firmware. Empty until `check_firmware` is called
+    #[serde(default)]
+    pub firmware_issues: Vec<FirmwareIssue>,
+    /// Device state the repositories' steps would write with differing content, which stops the
+    /// installation
+    #[serde(default)]
+    pub conflicts: Vec<StepConflict>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FirmwareIssue {
+    pub repository: String,
+    pub version: String,
+    pub fingerprint: String,
+    /// The installation will be refused, rather than only warned about
+    pub blocking: bool,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryPlan {
+    pub name: String,
+    pub owner: String,
+    pub repo: String,
+    pub version: String,
+    pub assets: Vec<ReleaseAsset>,
+    pub repo_files: Vec<String>,
+    pub cleanup_steps: usize,
+    pub steps: Vec<String>,
+}
+
+impl InstallPlan {
+    pub async fn resolve(
+        config: &InstallConfig,
+        active_repos: &[Repository],
+        github: &GitHubClient,
+    ) -> Result<Self> {
+        let versions = resolve::resolve_versions(github, active_repos, None).await;
+        let mut repositories = Vec::new();
+
+        for (repo, version) in active_repos.iter().zip(versions) {
+            repositories.push(RepositoryPlan::resolve(repo, github, version?).await?);
+        }
+
+        Ok(Self {
+            config_name: config.name.clone(),
+            global_setup: config.global_setup.iter().map(|s| s.describe()).collect(),
+            repositories,
+            reboot_after_completion: active_repos.iter().any(|r| r.reboot_after_completion),
+            firmware_issues: Vec::new(),
+            conflicts: config.step_conflicts(active_repos),
+        })
+    }
+
+    /// Checks the resolved versions against the config's firmware compatibility rules for a
+    /// device with the given build fingerprint
+    pub fn check_firmware(&mut self, config: &InstallConfig, fingerprint: &str) {
+        self.firmware_issues = self
+            .repositories
+            .iter()
+            .filter_map(|repo| {
+                let rule = config.incompatible_firmware(fingerprint, &repo.name, &repo.version)?;
+                Some(FirmwareIssue {
+                    repository: repo.name.clone(),
+                    version: repo.version.clone(),
+                    fingerprint: fingerprint.to_string(),
+                    blocking: rule.block,
+                    note: rule.note.clone(),
+                })
+            })
+            .collect();
+    }
+
+    /// Whether a firmware incompatibility or step conflict would stop the installation
+    pub fn is_blocked(&self) -> bool {
+        !self.conflicts.is_empty() || self.firmware_issues.iter().any(|issue| issue.blocking)
+    }
+
+    /// Total size in bytes of all release assets that would be downloaded
+    pub fn download_size(&self) -> u64 {
+        self.repositories
+            .iter()
+            .flat_map(|repo| repo.assets.iter())
+            .map(|asset| asset.size)
+            .sum()
+    }
+}
+
+impl RepositoryPlan {
+    /// Lists the assets `repo` would download at `version`, from the release fetched while
+    /// resolving it
+    async fn resolve(repo: &Repository, github: &GitHubClient, version: String) -> Result<Self> {
+        // Without a device, every ABI variant is listed. Like the download, an asset matched by
+        // several patterns is listed once
+        let mut assets: Vec<ReleaseAsset> = Vec::new();
+        for (pattern, exclude_patterns) in repo.asset_selection(None) {
+            let matched = github
+                .find_release_assets(
+                    &repo.owner,
+                    &repo.repo,
+                    &version,
+                    pattern,
+                    &exclude_patterns,
+                )
+                .await?;
+
+            for asset in matched {
+                if !assets.iter().any(|a| a.name == asset.name) {
+                    assets.push(asset);
+                }
+            }
+        }
+
+        Ok(Self {
+            name: repo.name.clone(),
+            owner: repo.owner.clone(),
+            repo: repo.repo.clone(),
+            version,
+            assets,
+            repo_files: repo.repo_files.iter().map(ToString::to_string).collect(),
+            cleanup_steps: repo.cleanup.len(),
+            steps: repo.installation.iter().map(|s| s.describe()).collect(),
+        })
+    }
+}
+
+/// What an uninstall would remove, based on the cleanup steps and the current device contents
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UninstallPlan {
+    pub config_name: String,
+    pub repositories: Vec<RepositoryUninstallPlan>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryUninstallPlan {
+    pub name: String,
+    /// Only uninstalled when forced, as other repositories rely on it
+    #[serde(default)]
+    pub protected: bool,
+    pub packages: Vec<String>,
+    pub directories: Vec<String>,
+    pub files: Vec<String>,
+    /// Non-empty directories that are only removed when empty, and so will be left in place
+    pub kept_directories: Vec<String>,
+    /// Uninstalling a package also removes its app data
+    pub wipes_app_data: bool,
+}
+
+impl UninstallPlan {
+    /// Queries the device for what each repository's cleanup steps would remove, in uninstall order
+    pub async fn resolve(
+        config: &InstallConfig,
+        active_repos: &[Repository],
+        adb: &mut AdbManager,
+    ) -> Result<Self> {
+        let mut repositories = Vec::new();
+
+        for repo in active_repos.iter().rev() {
+            repositories.push(RepositoryUninstallPlan::resolve(repo, adb).await?);
+        }
+
+        Ok(Self {
+            config_name: config.name.clone(),
+            repositories,
+        })
+    }
+
+    pub fn wipes_app_data(&self) -> bool {
+        self.repositories.iter().any(|repo| repo.wipes_app_data)
+    }
+}
+
+impl RepositoryUninstallPlan {
+    async fn resolve(repo: &Repository, adb: &mut AdbManager) -> Result<Self> {
+        let mut plan = Self {
+            name: repo.name.clone(),
+            protected: repo.protected,
+            packages: Vec::new(),
+            directories: Vec::new(),
+            files: Vec::new(),
+            kept_directories: Vec::new(),
+            wipes_app_data: false,
+        };
+
+        for step in &repo.cleanup {
+            match step {
+                CleanupStep::UninstallPackages { patterns, .. } => {
+                    for pattern in patterns {
+                        for package in adb.list_packages(&pattern.replace('*', "")).await? {
+                            if !plan.packages.contains(&package) {
+                                plan.packages.push(package);
+                            }
+                        }
+                    }
+                }
+                CleanupStep::RemoveDirectories { paths } => {
+                    for path in paths {
+                        if adb.directory_exists(path).await? {
+                            plan.directories.push(path.clone());
+                        }
+                    }
+                }
+                CleanupStep::RemoveDirectoriesIfEmpty { paths } => {
+                    for path in paths {
+                        if !adb.directory_exists(path).await? {
+                            continue;
+                        }
+
+                        let contents = adb.shell(&format!("ls -A {}", quote(path))).await?;
+                        if contents.trim().is_empty() {
+                            plan.directories.push(path.clone());
+                        } else {
+                            plan.kept_directories.push(path.clone());
+                        }
+                    }
+                }
+                CleanupStep::RemoveFiles { paths } => {
+                    for path in paths {
+                        if adb.file_exists(path).await? {
+                            plan.files.push(path.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        plan.wipes_app_data = !plan.packages.is_empty();
+        Ok(plan)
+    }
+}
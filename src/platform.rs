@@ -1,18 +1,263 @@
-use std::path::PathBuf;
+use reqwest::header::{HeaderMap, LOCATION};
+use reqwest::{redirect, Client, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::{InstallerError, Result};
+
+/// Cache size above which the cache is pruned automatically, unless overridden by
+/// `PENUMBRA_CACHE_MAX_MB`
+const DEFAULT_CACHE_LIMIT_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+/// Most redirects followed for one request, as many as reqwest follows by default
+const MAX_REDIRECTS: usize = 10;
 
 pub struct Platform;
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheEntry {
+    pub name: String,
+    pub bytes: u64,
+    pub file_count: usize,
+    /// Most recent modification time of anything in the entry, in seconds since the Unix epoch
+    pub last_used: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheUsage {
+    pub path: PathBuf,
+    pub total_bytes: u64,
+    pub file_count: usize,
+    pub entries: Vec<CacheEntry>,
+}
+
+/// Which cache entries to remove. Entries older than `max_age` are removed first, then the
+/// least recently used entries until the cache fits in `max_bytes`
+#[derive(Debug, Clone, Default)]
+pub struct PrunePolicy {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl PrunePolicy {
+    /// The size cap applied automatically on startup
+    pub fn automatic() -> Self {
+        let max_bytes = std::env::var("PENUMBRA_CACHE_MAX_MB")
+            .ok()
+            .and_then(|mb| mb.parse::<u64>().ok())
+            .map_or(DEFAULT_CACHE_LIMIT_BYTES, |mb| mb * 1024 * 1024);
+
+        Self {
+            max_age: None,
+            max_bytes: Some(max_bytes),
+        }
+    }
+}
+
 impl Platform {
+    /// Download cache. Overridden by `PENUMBRA_CACHE_DIR`, otherwise `$XDG_CACHE_HOME` on Linux
     pub fn cache_dir() -> PathBuf {
-        if let Some(cache_dir) = dirs::cache_dir() {
+        if let Some(dir) = env_dir("PENUMBRA_CACHE_DIR") {
+            dir
+        } else if let Some(cache_dir) = dirs::cache_dir() {
             cache_dir.join("penumbra-installer")
         } else {
             std::env::temp_dir().join("penumbra-installer")
         }
     }
 
+    /// Settings and setup state. Overridden by `PENUMBRA_CONFIG_DIR`, otherwise
+    /// `$XDG_CONFIG_HOME` on Linux
+    pub fn config_dir() -> PathBuf {
+        if let Some(dir) = env_dir("PENUMBRA_CONFIG_DIR") {
+            dir
+        } else if let Some(config_dir) = dirs::config_dir() {
+            config_dir.join("penumbra-installer")
+        } else {
+            Self::cache_dir()
+        }
+    }
+
+    /// Persistent state that isn't configuration. `$XDG_STATE_HOME` on Linux,
+    /// and the config directory elsewhere
+    pub fn state_dir() -> PathBuf {
+        if let Some(dir) = env_dir("PENUMBRA_CONFIG_DIR") {
+            dir
+        } else if let Some(state_dir) = dirs::state_dir() {
+            state_dir.join("penumbra-installer")
+        } else {
+            Self::config_dir()
+        }
+    }
+
+    /// Files that only matter while the installer is running, such as locks.
+    /// `$XDG_RUNTIME_DIR` on Linux, and the state directory elsewhere
+    pub fn runtime_dir() -> PathBuf {
+        if let Some(runtime_dir) = dirs::runtime_dir() {
+            runtime_dir.join("penumbra-installer")
+        } else {
+            Self::state_dir()
+        }
+    }
+
+    /// Loads JSON state, such as the known devices, from `path`. A missing or unreadable file is
+    /// treated as empty, with a warning naming `what` when the file can't be parsed
+    pub fn load_json<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return T::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable {}: {}", what, e);
+            T::default()
+        })
+    }
+
+    /// Saves JSON state to `path`, creating its directory
+    pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(value)?)?;
+        Ok(())
+    }
+
+    /// Parent of the per-run temp directories. Overridden by `PENUMBRA_TEMP_DIR`
+    pub fn temp_root() -> PathBuf {
+        env_dir("PENUMBRA_TEMP_DIR")
+            .unwrap_or_else(|| std::env::temp_dir().join("penumbra-installer"))
+    }
+
+    /// Cached GitHub API responses. Kept out of the download cache, so it doesn't count as
+    /// downloaded assets
+    pub fn api_cache_dir() -> PathBuf {
+        Self::temp_root().join("github-api")
+    }
+
+    /// Returns a new temporary directory path unique to this run, so concurrent installers don't
+    /// share (and delete) each other's downloads. The directory is not created
     pub fn temp_dir() -> PathBuf {
-        std::env::temp_dir().join("penumbra-installer")
+        static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Self::temp_root().join(format!(
+            "run-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Removes run directories left behind by processes that crashed or were killed. Only
+    /// `run-<pid>-*` entries whose process has exited are removed, so other contents of the
+    /// temp root, such as the API cache, and the runs of long-lived processes stay
+    pub fn clean_stale_temp_dirs() {
+        let Ok(entries) = fs::read_dir(Self::temp_root()) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let stale = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("run-"))
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|pid| pid.parse::<u32>().ok())
+                .is_some_and(|pid| pid != std::process::id() && !process_alive(pid));
+
+            if stale {
+                let path = entry.path();
+                let result = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+
+                if let Err(e) = result {
+                    warn!("Failed to remove stale temp dir {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Sizes of each repository directory in the download cache
+    pub fn cache_usage() -> Result<CacheUsage> {
+        let cache_dir = Self::cache_dir();
+        let mut usage = CacheUsage {
+            path: cache_dir.clone(),
+            total_bytes: 0,
+            file_count: 0,
+            entries: Vec::new(),
+        };
+
+        if !cache_dir.exists() {
+            return Ok(usage);
+        }
+
+        for entry in fs::read_dir(&cache_dir)?.flatten() {
+            let path = entry.path();
+            let (bytes, file_count, modified) = dir_usage(&path);
+            usage.total_bytes += bytes;
+            usage.file_count += file_count;
+
+            // Loose files (such as state that falls back to the cache dir) are counted but never pruned
+            if path.is_dir() {
+                usage.entries.push(CacheEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    bytes,
+                    file_count,
+                    last_used: modified
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs()),
+                });
+            }
+        }
+
+        usage.entries.sort_by_key(|entry| entry.last_used);
+        Ok(usage)
+    }
+
+    /// Removes cache entries according to `policy`, returning the entries removed
+    pub fn prune_cache(policy: &PrunePolicy) -> Result<Vec<CacheEntry>> {
+        let usage = Self::cache_usage()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut total_bytes = usage.total_bytes;
+        let mut removed = Vec::new();
+
+        // Entries are sorted least recently used first
+        for entry in usage.entries {
+            let expired = policy
+                .max_age
+                .is_some_and(|max_age| now.saturating_sub(entry.last_used) > max_age.as_secs());
+            let over_limit = policy.max_bytes.is_some_and(|max| total_bytes > max);
+
+            if !expired && !over_limit {
+                continue;
+            }
+
+            info!("Pruning cached {} ({} bytes)", entry.name, entry.bytes);
+            fs::remove_dir_all(usage.path.join(&entry.name))?;
+            total_bytes -= entry.bytes;
+            removed.push(entry);
+        }
+
+        Ok(removed)
+    }
+
+    /// Applies the automatic size cap, logging rather than failing on errors
+    pub fn enforce_cache_limit() {
+        if let Err(e) = Self::prune_cache(&PrunePolicy::automatic()) {
+            warn!("Failed to prune cache: {}", e);
+        }
     }
 
     pub fn executable_extension() -> &'static str {
@@ -30,4 +275,105 @@ impl Platform {
             std::env::consts::OS
         )
     }
+
+    /// HTTP client that leaves redirects to [`Platform::get_with_credentials`]
+    pub fn http_client() -> reqwest::Result<Client> {
+        Client::builder()
+            .user_agent(Self::user_agent())
+            .redirect(redirect::Policy::none())
+            .build()
+    }
+
+    /// Fetches `url` with a client from [`Platform::http_client`], following redirects itself so
+    /// that `credentials` are only sent to the origin of `url`, never to a host it redirects to
+    pub async fn get_with_credentials(
+        client: &Client,
+        url: &str,
+        credentials: &HeaderMap,
+    ) -> Result<Response> {
+        let mut target = Url::parse(url)
+            .map_err(|e| InstallerError::Config(format!("Invalid URL '{}': {}", url, e)))?;
+        let origin = target.origin();
+
+        for _ in 0..=MAX_REDIRECTS {
+            let mut request = client.get(target.clone());
+            if target.origin() == origin {
+                request = request.headers(credentials.clone());
+            }
+            let response = request.send().await?;
+
+            // 304 responses to conditional requests are redirections without a location
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok());
+            let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+                return Ok(response);
+            };
+            target = target.join(location).map_err(|e| {
+                InstallerError::Config(format!("Invalid redirect from {}: {}", target, e))
+            })?;
+        }
+
+        Err(InstallerError::Config(format!(
+            "Too many redirects fetching {}",
+            url
+        )))
+    }
+}
+
+/// Directory named by an environment variable, ignoring empty values
+fn env_dir(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Total bytes, file count, and latest modification time under `path`
+fn dir_usage(path: &Path) -> (u64, usize, SystemTime) {
+    let Ok(metadata) = path.metadata() else {
+        return (0, 0, UNIX_EPOCH);
+    };
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+
+    if metadata.is_file() {
+        return (metadata.len(), 1, modified);
+    }
+
+    let Ok(entries) = fs::read_dir(path) else {
+        return (0, 0, modified);
+    };
+
+    entries
+        .flatten()
+        .map(|entry| dir_usage(&entry.path()))
+        .fold((0, 0, modified), |(bytes, files, latest), (b, f, m)| {
+            (bytes + b, files + f, latest.max(m))
+        })
+}
+
+/// Whether a process with `pid` is running. Assumes it is when that can't be determined
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("ps")
+        .args(["-p", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .map_or(true, |output| {
+            String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid))
+        })
 }
@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+
+use crate::adb::TransferProgress;
+use crate::github::DownloadProgress;
+
+/// What a tracked task moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskKind {
+    /// A release asset download
+    Download,
+    /// A file push or APK upload to the device
+    Transfer,
+}
+
+/// Byte progress of one download or transfer
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskProgress {
+    pub kind: TaskKind,
+    /// Tells concurrent tasks apart: the repository and asset, or the device path
+    pub key: String,
+    /// Asset name, or the name of the local file sent
+    pub name: String,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+impl From<&DownloadProgress> for TaskProgress {
+    fn from(progress: &DownloadProgress) -> Self {
+        Self {
+            kind: TaskKind::Download,
+            key: format!("{}/{}", progress.repo, progress.asset),
+            name: progress.asset.clone(),
+            done_bytes: progress.downloaded_bytes,
+            total_bytes: progress.total_bytes,
+            done: progress.done,
+        }
+    }
+}
+
+impl From<&TransferProgress> for TaskProgress {
+    fn from(progress: &TransferProgress) -> Self {
+        Self {
+            kind: TaskKind::Transfer,
+            key: progress.remote.clone(),
+            name: progress.file.clone(),
+            done_bytes: progress.transferred_bytes,
+            total_bytes: progress.total_bytes,
+            done: progress.done,
+        }
+    }
+}
+
+/// Combined progress of the tasks in the current batch
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ProgressSnapshot {
+    /// From 0 to 100, by bytes across the batch. Finished tasks count in full, even if they
+    /// failed partway
+    pub percent: f64,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+    /// Each task in the batch, in the order they started
+    pub tasks: Vec<TaskProgress>,
+}
+
+impl ProgressSnapshot {
+    /// Tasks still running
+    pub fn active(&self) -> impl Iterator<Item = &TaskProgress> {
+        self.tasks.iter().filter(|task| !task.done)
+    }
+
+    /// Whether every task in the batch has finished, as when there are none
+    pub fn is_done(&self) -> bool {
+        self.tasks.iter().all(|task| task.done)
+    }
+
+    fn update(&mut self, task: TaskProgress) {
+        // A task starting after everything finished begins a new batch
+        if !task.done && self.is_done() {
+            self.tasks.clear();
+        }
+        match self
+            .tasks
+            .iter_mut()
+            .find(|tracked| tracked.kind == task.kind && tracked.key == task.key)
+        {
+            Some(tracked) => *tracked = task,
+            None => self.tasks.push(task),
+        }
+
+        self.total_bytes = self.tasks.iter().map(|task| task.total_bytes).sum();
+        self.done_bytes = self
+            .tasks
+            .iter()
+            .map(|task| {
+                if task.done {
+                    task.total_bytes
+                } else {
+                    task.done_bytes.min(task.total_bytes)
+                }
+            })
+            .sum();
+        self.percent = match self.total_bytes {
+            0 if self.is_done() => 100.0,
+            0 => 0.0,
+            total => self.done_bytes as f64 * 100.0 / total as f64,
+        };
+    }
+}
+
+/// Aggregates the byte progress of concurrent downloads and transfers into one snapshot, so
+/// every frontend shows the same numbers. A batch lasts until all of its tasks finish, and the
+/// next task to start begins a new one. Clones share the same snapshot
+#[derive(Clone)]
+pub struct ProgressTracker {
+    snapshot: Arc<watch::Sender<ProgressSnapshot>>,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(ProgressSnapshot::default());
+        Self {
+            snapshot: Arc::new(sender),
+        }
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.snapshot.borrow().clone()
+    }
+
+    /// Follows the snapshot from another task, which changes with every update
+    pub fn subscribe(&self) -> watch::Receiver<ProgressSnapshot> {
+        self.snapshot.subscribe()
+    }
+
+    /// Records the latest progress of a task. Safe to call from any thread
+    pub fn update(&self, task: TaskProgress) {
+        self.snapshot.send_modify(|snapshot| snapshot.update(task));
+    }
+
+    /// Records download and transfer reports until both channels close, as they do when the
+    /// engine reporting them is dropped. Must run on a Tokio runtime
+    pub async fn follow(
+        self,
+        mut downloads: mpsc::UnboundedReceiver<DownloadProgress>,
+        mut transfers: mpsc::UnboundedReceiver<TransferProgress>,
+    ) {
+        let tracker = self.clone();
+        let following = tokio::spawn(async move {
+            while let Some(progress) = transfers.recv().await {
+                tracker.update(TaskProgress::from(&progress));
+            }
+        });
+
+        while let Some(progress) = downloads.recv().await {
+            self.update(TaskProgress::from(&progress));
+        }
+        let _ = following.await;
+    }
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(
+        kind: TaskKind,
+        key: &str,
+        done_bytes: u64,
+        total_bytes: u64,
+        done: bool,
+    ) -> TaskProgress {
+        TaskProgress {
+            kind,
+            key: key.to_string(),
+            name: key.to_string(),
+            done_bytes,
+            total_bytes,
+            done,
+        }
+    }
+
+    #[test]
+    fn combines_concurrent_tasks() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Download, "a", 25, 100, false));
+        snapshot.update(task(TaskKind::Transfer, "/sdcard/b", 50, 300, false));
+
+        assert_eq!(snapshot.tasks.len(), 2);
+        assert_eq!(snapshot.done_bytes, 75);
+        assert_eq!(snapshot.total_bytes, 400);
+        assert_eq!(snapshot.percent, 18.75);
+        assert_eq!(snapshot.active().count(), 2);
+    }
+
+    #[test]
+    fn replaces_reports_of_the_same_task() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Download, "a", 25, 100, false));
+        snapshot.update(task(TaskKind::Download, "a", 75, 100, false));
+        // The same key from another kind of task is a different task
+        snapshot.update(task(TaskKind::Transfer, "a", 0, 100, false));
+
+        assert_eq!(snapshot.tasks.len(), 2);
+        assert_eq!(snapshot.done_bytes, 75);
+    }
+
+    #[test]
+    fn counts_finished_tasks_in_full() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Download, "a", 40, 100, false));
+        snapshot.update(task(TaskKind::Download, "b", 0, 100, false));
+        snapshot.update(task(TaskKind::Download, "a", 40, 100, true));
+
+        assert_eq!(snapshot.done_bytes, 100);
+        assert_eq!(snapshot.percent, 50.0);
+        assert!(!snapshot.is_done());
+    }
+
+    #[test]
+    fn caps_tasks_at_their_total() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Download, "a", 150, 100, false));
+
+        assert_eq!(snapshot.done_bytes, 100);
+        assert_eq!(snapshot.percent, 100.0);
+    }
+
+    #[test]
+    fn starts_a_new_batch_after_everything_finished() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Download, "a", 100, 100, true));
+        assert!(snapshot.is_done());
+        assert_eq!(snapshot.percent, 100.0);
+
+        snapshot.update(task(TaskKind::Transfer, "/sdcard/b", 10, 40, false));
+        assert_eq!(snapshot.tasks.len(), 1);
+        assert_eq!(snapshot.done_bytes, 10);
+        assert_eq!(snapshot.percent, 25.0);
+    }
+
+    #[test]
+    fn empty_tasks_report_no_progress_until_done() {
+        let mut snapshot = ProgressSnapshot::default();
+        snapshot.update(task(TaskKind::Transfer, "/sdcard/empty", 0, 0, false));
+        assert_eq!(snapshot.percent, 0.0);
+
+        snapshot.update(task(TaskKind::Transfer, "/sdcard/empty", 0, 0, true));
+        assert_eq!(snapshot.percent, 100.0);
+    }
+
+    #[test]
+    fn tracker_publishes_each_update() {
+        let tracker = ProgressTracker::new();
+        let receiver = tracker.subscribe();
+        tracker.update(task(TaskKind::Download, "a", 50, 100, false));
+
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(tracker.snapshot().percent, 50.0);
+    }
+}
@@ -0,0 +1,429 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::adb::AdbManager;
+use crate::config::ConfigSource;
+use crate::github::RepoFile;
+use crate::Result;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RepositoryOutcome {
+    Installed {
+        version: Option<String>,
+        /// Repository files fetched for this install, pinned by blob SHA
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        files: Vec<RepoFile>,
+        /// Changes that didn't take effect, without failing the install
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+    },
+    Downloaded {
+        version: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        files: Vec<RepoFile>,
+    },
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Kind of problem that didn't stop the install
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A release asset pattern matched nothing
+    MissingAssets,
+    /// A release asset was left out by the config's exclusion patterns
+    ExcludedAsset,
+    /// The repository has no assets for the device's ABIs, so only universal ones were used
+    UnsupportedAbi,
+    /// A directory removed only when empty still had files in it
+    DirectoryNotEmpty,
+    /// An app op still had another mode after retrying
+    AppOpNotApplied,
+    /// An APK failed to install in a step allowing failures
+    ApkFailed,
+    /// A command failed in a step ignoring failures
+    CommandFailed,
+    /// A cleanup step couldn't uninstall a package
+    UninstallFailed,
+    /// The device clock is far enough off to break HTTPS from the device
+    ClockSkew,
+    /// The battery was below the configured level, and the install went ahead as the device was
+    /// charging or it was told to
+    LowBattery,
+    /// The version isn't known to work on the device firmware
+    IncompatibleFirmware,
+}
+
+/// A problem that didn't stop the install, collected for the end-of-run summary
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstallWarning {
+    pub kind: WarningKind,
+    /// Repository being installed, or `None` for global setup
+    pub repository: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryResult {
+    pub name: String,
+    pub optional: bool,
+    #[serde(flatten)]
+    pub outcome: RepositoryOutcome,
+    pub duration_ms: u64,
+    /// Time and device round trips of each installation step that ran
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepTiming>,
+}
+
+/// How long an installation step took, and how many shell invocations it needed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StepTiming {
+    pub step: String,
+    pub duration_ms: u64,
+    /// Shell invocations sent to the device
+    pub round_trips: u64,
+    /// Commands run, which is more than `round_trips` when the step batched them
+    pub commands: u64,
+}
+
+impl StepTiming {
+    /// Round trips saved by batching the step's commands
+    pub fn saved_round_trips(&self) -> u64 {
+        self.commands.saturating_sub(self.round_trips)
+    }
+}
+
+/// Outcome of a factory reset, listing anything that couldn't be undone
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResetReport {
+    /// Reset actions that failed
+    pub errors: Vec<String>,
+    /// PenumbraOS changes still present on the device after the reset
+    pub remaining: Vec<String>,
+}
+
+impl ResetReport {
+    /// True when every action succeeded and nothing was left behind
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty() && self.remaining.is_empty()
+    }
+
+    pub fn log_summary(&self) {
+        if self.is_clean() {
+            info!("Device is back to a clean state");
+            return;
+        }
+
+        warn!("Reset could not undo everything:");
+        for error in &self.errors {
+            error!("  {}", error);
+        }
+        for remaining in &self.remaining {
+            warn!("  {}", remaining);
+        }
+    }
+}
+
+/// What happened after the device was rebooted at the end of an install
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RebootOutcome {
+    /// Rebooted without waiting for the device to come back
+    Rebooted,
+    /// The device booted and every verification step passed
+    Verified { boot_ms: u64 },
+    /// The device booted, but a verification step failed
+    VerificationFailed { boot_ms: u64, error: String },
+    /// The device didn't finish booting, or couldn't be reconnected to
+    Failed { error: String },
+}
+
+impl RebootOutcome {
+    pub fn succeeded(&self) -> bool {
+        matches!(
+            self,
+            RebootOutcome::Rebooted | RebootOutcome::Verified { .. }
+        )
+    }
+}
+
+/// Conditions a run happened under, so a reported failure can be reproduced
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InstallEnvironment {
+    pub installer_version: String,
+    pub os: String,
+    pub arch: String,
+    /// How the device was reached: `server`, `usb`, or `replay`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adb_backend: Option<String>,
+    /// Version of the local ADB server, when one is running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adb_server_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ConfigSource>,
+}
+
+impl InstallEnvironment {
+    /// Captures this computer's side of the environment. The device fields are left unset
+    pub fn host(config: Option<&ConfigSource>) -> Self {
+        Self {
+            installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            adb_backend: None,
+            adb_server_version: AdbManager::server_version().ok(),
+            device_fingerprint: None,
+            config: config.cloned(),
+        }
+    }
+}
+
+/// Per-repository outcome of an install or download run
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InstallReport {
+    /// Set by the engine for installs and downloads it ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<InstallEnvironment>,
+    pub repositories: Vec<RepositoryResult>,
+    /// Set when the install ended by rebooting the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reboot: Option<RebootOutcome>,
+    /// Every warning of the run, including those of global setup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<InstallWarning>,
+}
+
+impl InstallReport {
+    pub fn record(
+        &mut self,
+        name: &str,
+        optional: bool,
+        outcome: RepositoryOutcome,
+        duration: Duration,
+    ) {
+        self.repositories.push(RepositoryResult {
+            name: name.to_string(),
+            optional,
+            outcome,
+            duration_ms: duration.as_millis() as u64,
+            steps: Vec::new(),
+        });
+    }
+
+    /// Attaches step timings to the repository recorded last
+    pub fn record_steps(&mut self, steps: Vec<StepTiming>) {
+        if let Some(result) = self.repositories.last_mut() {
+            result.steps = steps;
+        }
+    }
+
+    /// Repositories that failed, including optional ones
+    pub fn failures(&self) -> impl Iterator<Item = &RepositoryResult> {
+        self.repositories
+            .iter()
+            .filter(|r| matches!(r.outcome, RepositoryOutcome::Failed { .. }))
+    }
+
+    /// True when every required repository completed. Optional failures are tolerated
+    pub fn succeeded(&self) -> bool {
+        !self.failures().any(|r| !r.optional)
+            && self.reboot.as_ref().is_none_or(RebootOutcome::succeeded)
+    }
+
+    pub fn log_summary(&self) {
+        info!("Summary:");
+        for result in &self.repositories {
+            let seconds = result.duration_ms as f64 / 1000.0;
+            match &result.outcome {
+                RepositoryOutcome::Installed {
+                    version, warnings, ..
+                } => {
+                    info!(
+                        "  {}: installed {} ({:.1}s){}",
+                        result.name,
+                        version.as_deref().unwrap_or("cached version"),
+                        seconds,
+                        match warnings.len() {
+                            0 => String::new(),
+                            1 => ", 1 warning".to_string(),
+                            count => format!(", {} warnings", count),
+                        }
+                    );
+                    for step in result
+                        .steps
+                        .iter()
+                        .filter(|step| step.saved_round_trips() > 0)
+                    {
+                        info!(
+                            "    {}: {} commands in {} round trip(s), {:.1}s",
+                            step.step,
+                            step.commands,
+                            step.round_trips,
+                            step.duration_ms as f64 / 1000.0
+                        );
+                    }
+                }
+                RepositoryOutcome::Downloaded { version, .. } => {
+                    info!(
+                        "  {}: downloaded {} ({:.1}s)",
+                        result.name, version, seconds
+                    )
+                }
+                RepositoryOutcome::Skipped { reason } => {
+                    warn!("  {}: skipped ({})", result.name, reason)
+                }
+                RepositoryOutcome::Failed { error } => {
+                    error!("  {}: failed: {}", result.name, error)
+                }
+            }
+        }
+    }
+
+    /// Logs every warning of the run together, so they aren't lost among the progress output
+    pub fn log_warnings(&self) {
+        if self.warnings.is_empty() {
+            return;
+        }
+
+        warn!("==================================================");
+        warn!("{} warning(s) during this run:", self.warnings.len());
+        for warning in &self.warnings {
+            warn!(
+                "  [{}] {}",
+                warning.repository.as_deref().unwrap_or("global"),
+                warning.message
+            );
+        }
+        warn!("==================================================");
+    }
+
+    /// Logs how the device came back from the reboot, if it was waited for
+    pub fn log_reboot(&self) {
+        match &self.reboot {
+            None | Some(RebootOutcome::Rebooted) => {}
+            Some(RebootOutcome::Verified { boot_ms }) => info!(
+                "Device rebooted and verified ({:.1}s to boot)",
+                *boot_ms as f64 / 1000.0
+            ),
+            Some(RebootOutcome::VerificationFailed { boot_ms, error }) => error!(
+                "Device rebooted ({:.1}s to boot), but verification failed: {}",
+                *boot_ms as f64 / 1000.0,
+                error
+            ),
+            Some(RebootOutcome::Failed { error }) => {
+                error!("Device did not come back from the reboot: {}", error)
+            }
+        }
+    }
+}
+
+/// Outcome of provisioning one device in a batch
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceResult {
+    pub serial: String,
+    /// Set when the device couldn't be connected to, or the install stopped before finishing
+    pub error: Option<String>,
+    pub report: Option<InstallReport>,
+    pub duration_ms: u64,
+}
+
+impl DeviceResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.report.as_ref().is_some_and(|r| r.succeeded())
+    }
+
+    fn details(&self) -> String {
+        if let Some(error) = &self.error {
+            return error.clone();
+        }
+
+        let Some(report) = &self.report else {
+            return String::new();
+        };
+        let failed: Vec<&str> = report.failures().map(|r| r.name.as_str()).collect();
+        if failed.is_empty() {
+            match &report.reboot {
+                Some(RebootOutcome::VerificationFailed { error, .. })
+                | Some(RebootOutcome::Failed { error }) => format!("reboot failed: {}", error),
+                _ => format!("{} repositories installed", report.repositories.len()),
+            }
+        } else {
+            format!("{} failed", failed.join(", "))
+        }
+    }
+}
+
+/// Per-device outcome of a batch provisioning run
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ProvisionReport {
+    pub devices: Vec<DeviceResult>,
+}
+
+impl ProvisionReport {
+    pub fn record(&mut self, serial: &str, result: Result<InstallReport>, duration: Duration) {
+        let (report, error) = match result {
+            Ok(report) => (Some(report), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        self.devices.push(DeviceResult {
+            serial: serial.to_string(),
+            error,
+            report,
+            duration_ms: duration.as_millis() as u64,
+        });
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &DeviceResult> {
+        self.devices.iter().filter(|device| !device.succeeded())
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Logs one row per device with its result, duration, and what failed
+    pub fn log_table(&self) {
+        let width = self
+            .devices
+            .iter()
+            .map(|device| device.serial.len())
+            .max()
+            .unwrap_or(0)
+            .max("SERIAL".len());
+
+        info!(
+            "{:<width$}  {:<6}  {:>7}  DETAILS",
+            "SERIAL", "RESULT", "TIME"
+        );
+        for device in &self.devices {
+            let seconds = format!("{:.1}s", device.duration_ms as f64 / 1000.0);
+            if device.succeeded() {
+                info!(
+                    "{:<width$}  {:<6}  {:>7}  {}",
+                    device.serial,
+                    "ok",
+                    seconds,
+                    device.details()
+                );
+            } else {
+                error!(
+                    "{:<width$}  {:<6}  {:>7}  {}",
+                    device.serial,
+                    "failed",
+                    seconds,
+                    device.details()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::github::GitHubClient;
+use crate::{task, Repository, Result};
+
+/// Most repositories resolved at once, to stay clear of GitHub's secondary rate limits
+const MAX_CONCURRENT_RESOLUTIONS: usize = 4;
+
+/// Progress of resolving the selected repositories before downloading, reported as each one
+/// finishes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResolutionProgress {
+    /// Repository that finished resolving
+    pub repo: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Why the repository couldn't be resolved. It fails again when it's installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub resolved: usize,
+    pub total: usize,
+}
+
+/// Resolves the version of each of `repos` and fetches its release's asset list, several at a
+/// time, so one slow response doesn't hold up the rest. Asset lists are kept by `github` for
+/// the download. Returns the versions in the order of `repos`
+pub async fn resolve_versions(
+    github: &GitHubClient,
+    repos: &[Repository],
+    progress: Option<&mpsc::UnboundedSender<ResolutionProgress>>,
+) -> Vec<Result<String>> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_RESOLUTIONS));
+    let resolved = Arc::new(AtomicUsize::new(0));
+    let total = repos.len();
+
+    let jobs = repos.iter().map(|repo| {
+        let github = github.clone();
+        let repo = repo.clone();
+        let permits = permits.clone();
+        let resolved = resolved.clone();
+        let progress = progress.cloned();
+
+        async move {
+            // The semaphore is never closed
+            let _permit = permits.acquire_owned().await.ok();
+            let result = resolve(&github, &repo).await;
+
+            if let Some(progress) = progress {
+                let (version, error) = match &result {
+                    Ok(version) => (Some(version.clone()), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                // The subscriber may have gone away
+                let _ = progress.send(ResolutionProgress {
+                    repo: repo.name.clone(),
+                    version,
+                    error,
+                    resolved: resolved.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                });
+            }
+            result
+        }
+    });
+
+    task::spawn_all(jobs)
+        .await
+        .into_iter()
+        .map(|result| result.and_then(|result| result))
+        .collect()
+}
+
+async fn resolve(github: &GitHubClient, repo: &Repository) -> Result<String> {
+    let version = github.get_version(repo).await?;
+    // Repositories installed from tags alone have no release to list
+    if !repo.release_assets.is_empty() {
+        github
+            .release_assets(&repo.owner, &repo.repo, &version)
+            .await?;
+    }
+    Ok(version)
+}
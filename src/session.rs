@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::mem::discriminant;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::adb::RemoteStat;
+use crate::config::{InstallConfig, RebootTarget, Repository};
+use crate::redact::redact;
+use crate::{InstallerError, Result};
+
+/// First line of a session file, describing the installation that was recorded
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionHeader {
+    pub installer_version: String,
+    pub config: InstallConfig,
+    pub repositories: Vec<Repository>,
+}
+
+/// An ADB command or HTTP request issued by the engine
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Request {
+    Shell {
+        command: String,
+    },
+    Push {
+        local: String,
+        remote: String,
+    },
+    Pull {
+        remote: String,
+    },
+    Stat {
+        path: String,
+    },
+    Install {
+        apk: String,
+    },
+    /// Split APKs of one package, installed together, base first
+    InstallSplits {
+        apks: Vec<String>,
+    },
+    Reboot {
+        #[serde(default)]
+        target: RebootTarget,
+    },
+    Http {
+        url: String,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Output(String),
+    Done,
+    Error(String),
+    Stat(Option<RemoteStat>),
+    /// HTTP response metadata. Only GitHub API bodies are kept; downloads record their size
+    Http {
+        status: u16,
+        bytes: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<Value>,
+        /// Next page of a paginated API response, from its `Link` header
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<String>,
+    },
+}
+
+impl Response {
+    pub fn from_output(result: &Result<String>) -> Self {
+        match result {
+            Ok(output) => Response::Output(output.clone()),
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+
+    pub fn from_done(result: &Result<()>) -> Self {
+        match result {
+            Ok(()) => Response::Done,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+
+    pub fn from_stat(result: &Result<Option<RemoteStat>>) -> Self {
+        match result {
+            Ok(stat) => Response::Stat(*stat),
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+
+    pub fn into_output(self) -> Result<String> {
+        match self {
+            Response::Output(output) => Ok(output),
+            Response::Error(e) => Err(InstallerError::Adb(e)),
+            other => Err(mismatch(&other)),
+        }
+    }
+
+    /// Status, body and next page URL of a recorded HTTP response
+    pub fn into_http(self) -> Result<(u16, Option<Value>, Option<String>)> {
+        match self {
+            Response::Http {
+                status, body, next, ..
+            } => Ok((status, body, next)),
+            Response::Error(e) => Err(InstallerError::GitHub(e)),
+            other => Err(mismatch(&other)),
+        }
+    }
+
+    pub fn into_stat(self) -> Result<Option<RemoteStat>> {
+        match self {
+            Response::Stat(stat) => Ok(stat),
+            Response::Error(e) => Err(InstallerError::Adb(e)),
+            other => Err(mismatch(&other)),
+        }
+    }
+
+    pub fn into_done(self) -> Result<()> {
+        match self {
+            Response::Done => Ok(()),
+            Response::Error(e) => Err(InstallerError::Adb(e)),
+            other => Err(mismatch(&other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionEntry {
+    pub request: Request,
+    pub response: Response,
+}
+
+enum Mode {
+    /// Entries are appended to the file as they happen, so a crash still leaves a usable session
+    Record(File),
+    /// Recorded entries, taken as they are used, and the index after the last one used
+    Replay {
+        entries: Vec<Option<SessionEntry>>,
+        next: usize,
+    },
+}
+
+/// A debug recording of everything the engine sent to the device and network, or a recording
+/// being replayed in place of a real device and network
+pub struct Session {
+    mode: Mutex<Mode>,
+    header: SessionHeader,
+    /// Values redacted from recorded commands, such as those given for variables
+    secrets: Mutex<Vec<String>>,
+}
+
+impl Session {
+    /// Starts recording to `path`, replacing any existing file. The header's config contents
+    /// and commands, and `secrets` wherever they appear, are redacted, as sessions are shared to
+    /// debug installations
+    pub fn record(path: &Path, header: SessionHeader, secrets: Vec<String>) -> Result<Arc<Self>> {
+        let mut secrets = secrets;
+        secrets.extend(header.config.secrets());
+        let header = SessionHeader {
+            installer_version: header.installer_version,
+            config: header.config.redacted(),
+            repositories: header
+                .repositories
+                .iter()
+                .map(Repository::redacted)
+                .collect(),
+        };
+
+        let mut file = File::create(path)?;
+        writeln!(
+            file,
+            "{}",
+            redact(&serde_json::to_string(&header)?, &secrets)
+        )?;
+        info!("Recording session to {}", path.display());
+
+        Ok(Arc::new(Self {
+            mode: Mutex::new(Mode::Record(file)),
+            header,
+            secrets: Mutex::new(secrets),
+        }))
+    }
+
+    /// Loads a recorded session for replay
+    pub fn replay(path: &Path) -> Result<Arc<Self>> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str::<SessionHeader>(&line?)?,
+            None => {
+                return Err(InstallerError::Config(format!(
+                    "Session file {} is empty",
+                    path.display()
+                )))
+            }
+        };
+
+        let mut entries = Vec::new();
+        for line in lines {
+            entries.push(serde_json::from_str::<SessionEntry>(&line?)?);
+        }
+
+        if header.installer_version != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "Session was recorded with installer {}, replaying with {}",
+                header.installer_version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+
+        Ok(Arc::new(Self {
+            mode: Mutex::new(Mode::Replay {
+                entries: entries.into_iter().map(Some).collect(),
+                next: 0,
+            }),
+            header,
+            secrets: Mutex::new(Vec::new()),
+        }))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(*self.mode.lock().unwrap(), Mode::Replay { .. })
+    }
+
+    pub fn header(&self) -> &SessionHeader {
+        &self.header
+    }
+
+    /// Redacts `value` from commands recorded from now on, such as a value entered for a
+    /// variable or the content of a config file
+    pub fn add_secret(&self, value: &str) {
+        self.secrets.lock().unwrap().push(value.to_string());
+    }
+
+    /// Appends an exchange to the recording, with secrets redacted from shell commands. Does
+    /// nothing when replaying
+    pub fn log(&self, request: Request, response: Response) {
+        if let Mode::Record(file) = &mut *self.mode.lock().unwrap() {
+            let entry = SessionEntry {
+                request: self.redacted(request),
+                response,
+            };
+            let written = serde_json::to_string(&entry)
+                .map_err(InstallerError::from)
+                .and_then(|line| writeln!(file, "{}", line).map_err(InstallerError::from));
+
+            if let Err(e) = written {
+                warn!("Failed to record session entry: {}", e);
+            }
+        }
+    }
+
+    /// `request` as it is recorded, with secrets redacted from shell commands
+    fn redacted(&self, request: Request) -> Request {
+        match request {
+            Request::Shell { command } => Request::Shell {
+                command: redact(&command, &self.secrets.lock().unwrap()),
+            },
+            request => request,
+        }
+    }
+
+    /// The recorded response to `request`, or `None` when recording. Each recorded response is
+    /// used once
+    pub fn replayed(&self, request: &Request) -> Result<Option<Response>> {
+        let request = &self.redacted(request.clone());
+        let mut mode = self.mode.lock().unwrap();
+        let Mode::Replay { entries, next } = &mut *mode else {
+            return Ok(None);
+        };
+
+        let exact = entries.iter().position(|entry| {
+            entry
+                .as_ref()
+                .is_some_and(|entry| &entry.request == request)
+        });
+
+        // Some commands embed run-specific values such as the lock holder's pid, so fall back to
+        // the next unused recorded request of the same kind
+        let index = exact.or_else(|| {
+            let index = (*next..entries.len()).find(|&index| entries[index].is_some())?;
+            let entry = entries[index].as_ref()?;
+            if discriminant(&entry.request) != discriminant(request) {
+                return None;
+            }
+
+            warn!(
+                "Replaying {:?} in place of differing request {:?}",
+                entry.request, request
+            );
+            Some(index)
+        });
+
+        match index.and_then(|index| Some((index, entries[index].take()?))) {
+            Some((index, entry)) => {
+                *next = index + 1;
+                Ok(Some(entry.response))
+            }
+            None => Err(InstallerError::Internal(format!(
+                "Replay diverged from the recording: no response recorded for {:?}",
+                request
+            ))),
+        }
+    }
+}
+
+fn mismatch(response: &Response) -> InstallerError {
+    InstallerError::Internal(format!(
+        "Replay diverged from the recording: unexpected response {:?}",
+        response
+    ))
+}
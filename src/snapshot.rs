@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::adb::{quote, DeviceIdentity};
+use crate::update::UpdateState;
+use crate::{AdbManager, InstallConfig, InstallStep, Repository, Result, VersionSpec};
+
+/// What each repository of a config left on a device, exported from one device so another can
+/// be brought to the same state
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceSnapshot {
+    pub installer_version: String,
+    pub config: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    pub device: DeviceIdentity,
+    /// Repositories with anything on the device, in installation order
+    pub repositories: Vec<RepositorySnapshot>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RepositorySnapshot {
+    pub name: String,
+    /// Release last installed by `update`. Unknown for devices only installed to directly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Version code of each installed package
+    #[serde(default)]
+    pub packages: BTreeMap<String, u64>,
+    /// SHA-256 of each file under the repository's `PushFiles` destinations, by device path
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+    /// Content of each file written by the repository's `CreateConfig` steps, by device path.
+    /// Only captured when asked for, as these can hold API keys and other secrets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub settings: BTreeMap<String, String>,
+}
+
+impl DeviceSnapshot {
+    /// Reads what each of `repos` left on the device, leaving out those with nothing there. The
+    /// content of `CreateConfig` files is only read with `include_settings`
+    pub async fn capture(
+        config: &InstallConfig,
+        repos: &[Repository],
+        adb: &mut AdbManager,
+        include_settings: bool,
+    ) -> Result<Self> {
+        let device = adb.device_identity().await?;
+        let serial = adb.serial_number().await?;
+        let installed = UpdateState::load()
+            .devices
+            .remove(&serial)
+            .map(|state| state.installed)
+            .unwrap_or_default();
+
+        let mut repositories = Vec::new();
+        for repo in repos {
+            let version = installed.get(&repo.name).cloned();
+            let snapshot =
+                RepositorySnapshot::capture(repo, version, adb, include_settings).await?;
+            if !snapshot.is_empty() {
+                repositories.push(snapshot);
+            }
+        }
+
+        Ok(Self {
+            installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            config: config.name.clone(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            device,
+            repositories,
+        })
+    }
+
+    /// Writes the snapshot as JSON to `path`, readable only by the current user as its settings
+    /// can hold secrets
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        // The mode only applies to new files
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Whether any repository's settings were captured
+    pub fn has_settings(&self) -> bool {
+        self.repositories
+            .iter()
+            .any(|repo| !repo.settings.is_empty())
+    }
+
+    /// The snapshot's repositories from `config`, in installation order. Each is pinned to its
+    /// snapshot version when known, with a warning when it isn't, and its `CreateConfig` steps
+    /// write the snapshot's content in place of the configured one
+    pub fn repositories(&self, config: &InstallConfig) -> Vec<Repository> {
+        for snapshot in &self.repositories {
+            if config.get_repository(&snapshot.name).is_none() {
+                warn!(
+                    "Skipping {}, as it isn't in the '{}' config",
+                    snapshot.name, config.name
+                );
+            }
+        }
+
+        config
+            .all_repositories()
+            .iter()
+            .filter_map(|repo| {
+                let snapshot = self.get(&repo.name)?;
+                let mut repo = repo.clone();
+                match &snapshot.version {
+                    Some(version) => repo.version = VersionSpec::Version(version.clone()),
+                    None => warn!(
+                        "The snapshot has no version of {}, so the version the config selects \
+                         now is installed, which may differ from the snapshot device's",
+                        repo.name
+                    ),
+                }
+                for step in &mut repo.installation {
+                    use_settings(step, &snapshot.settings);
+                }
+                Some(repo)
+            })
+            .collect()
+    }
+
+    /// Names of the snapshot's repositories whose state in `current` differs, in snapshot order.
+    /// Versions are only compared when both are known
+    pub fn differences(&self, current: &DeviceSnapshot) -> Vec<String> {
+        self.repositories
+            .iter()
+            .filter(|wanted| match current.get(&wanted.name) {
+                Some(found) => !wanted.matches(found),
+                None => true,
+            })
+            .map(|wanted| wanted.name.clone())
+            .collect()
+    }
+
+    fn get(&self, name: &str) -> Option<&RepositorySnapshot> {
+        self.repositories.iter().find(|repo| repo.name == name)
+    }
+}
+
+impl RepositorySnapshot {
+    async fn capture(
+        repo: &Repository,
+        version: Option<String>,
+        adb: &mut AdbManager,
+        include_settings: bool,
+    ) -> Result<Self> {
+        let mut snapshot = Self {
+            name: repo.name.clone(),
+            version,
+            ..Default::default()
+        };
+
+        for pattern in repo.package_patterns() {
+            for package in adb.list_packages(&pattern.replace('*', "")).await? {
+                if let Some(installed) = adb.installed_package(&package).await? {
+                    snapshot.packages.insert(package, installed.version_code);
+                }
+            }
+        }
+
+        for step in repo.installation.iter().flat_map(InstallStep::flatten) {
+            match step {
+                InstallStep::PushFiles { files } => {
+                    for file in files {
+                        let output = adb
+                            .shell(&format!(
+                                "find {} -type f -exec sha256sum {{}} + 2>/dev/null || true",
+                                quote(file.remote_location())
+                            ))
+                            .await?;
+                        snapshot.files.extend(output.lines().filter_map(|line| {
+                            let (hash, path) = line.split_once(char::is_whitespace)?;
+                            Some((path.trim().to_string(), hash.to_lowercase()))
+                        }));
+                    }
+                }
+                InstallStep::CreateConfig { path, .. } if include_settings => {
+                    if let Some(content) = adb.read_file(path).await? {
+                        snapshot.settings.insert(path.clone(), content);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Nothing of the repository is on the device
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.files.is_empty() && self.settings.is_empty()
+    }
+
+    fn matches(&self, other: &RepositorySnapshot) -> bool {
+        let same_version = match (&self.version, &other.version) {
+            (Some(version), Some(other)) => version == other,
+            _ => true,
+        };
+        same_version
+            && self.packages == other.packages
+            && self.files == other.files
+            && self.settings == other.settings
+    }
+}
+
+/// Makes `CreateConfig` steps writing a path in `settings` write its content, replacing any
+/// existing file
+fn use_settings(step: &mut InstallStep, settings: &BTreeMap<String, String>) {
+    match step {
+        InstallStep::Parallel { steps } => {
+            for step in steps {
+                use_settings(step, settings);
+            }
+        }
+        InstallStep::CreateConfig {
+            path,
+            content,
+            only_if_missing,
+            merge,
+            ..
+        } => {
+            if let Some(saved) = settings.get(path.as_str()) {
+                *content = saved.clone();
+                *only_if_missing = false;
+                *merge = None;
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::adb::AdbManager;
+use crate::config::{CustomStep, InstallStep};
+use crate::{InstallerError, Result};
+
+pub type StepFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+pub type PromptFuture<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
+pub type InputFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<String>>> + Send + 'a>>;
+
+/// What a custom step has access to while it runs
+pub struct StepContext<'a> {
+    pub adb: &'a mut AdbManager,
+    /// The repository the step belongs to, or `global` for global setup steps
+    pub repo_name: &'a str,
+    /// Directory holding the repository's downloaded assets
+    pub asset_dir: &'a Path,
+}
+
+/// Handler for a custom install step type
+///
+/// ```ignore
+/// struct Flash;
+///
+/// impl StepExecutor for Flash {
+///     fn execute<'a>(&'a self, step: &'a CustomStep, context: StepContext<'a>) -> StepFuture<'a> {
+///         Box::pin(async move {
+///             let partition = step.params["partition"].as_str().unwrap_or("boot");
+///             context.adb.shell(&format!("flash {}", partition)).await?;
+///             Ok(())
+///         })
+///     }
+/// }
+/// ```
+pub trait StepExecutor: Send + Sync {
+    fn execute<'a>(&'a self, step: &'a CustomStep, context: StepContext<'a>) -> StepFuture<'a>;
+}
+
+/// Asks the user to confirm `Prompt` steps, such as on a terminal or in a dialog
+pub trait Prompter: Send + Sync {
+    /// Resolves to whether the user chose to continue. `default` is the suggested answer
+    fn confirm<'a>(&'a self, message: &'a str, default: bool) -> PromptFuture<'a>;
+
+    /// Resolves to a value for a variable a step needs that was given none, or `None` if the
+    /// user gave none. Prompters that can't ask for text always resolve to `None`
+    fn input<'a>(&'a self, request: &'a InputRequest) -> InputFuture<'a> {
+        let _ = request;
+        Box::pin(async { Ok(None) })
+    }
+}
+
+/// A variable with no value, asked for when a step using it is about to run
+#[derive(Debug, Clone, Serialize)]
+pub struct InputRequest {
+    pub repository: String,
+    pub variable: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Summary of the step needing the value
+    pub step: String,
+}
+
+/// Custom step executors, keyed by the step's `type`
+#[derive(Clone, Default)]
+pub struct StepRegistry {
+    executors: HashMap<String, Arc<dyn StepExecutor>>,
+}
+
+impl StepRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `executor` for steps of `step_type`, replacing any previous executor. Built-in
+    /// step types cannot be overridden
+    pub fn register(
+        &mut self,
+        step_type: impl Into<String>,
+        executor: impl StepExecutor + 'static,
+    ) -> Result<()> {
+        let step_type = step_type.into();
+        if InstallStep::BUILTIN_TYPES.contains(&step_type.as_str()) {
+            return Err(InstallerError::Config(format!(
+                "Cannot override built-in step type '{}'",
+                step_type
+            )));
+        }
+
+        self.executors.insert(step_type, Arc::new(executor));
+        Ok(())
+    }
+
+    pub fn get(&self, step_type: &str) -> Option<Arc<dyn StepExecutor>> {
+        self.executors.get(step_type).cloned()
+    }
+
+    pub fn contains(&self, step_type: &str) -> bool {
+        self.executors.contains_key(step_type)
+    }
+}
@@ -0,0 +1,111 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic;
+use std::sync::Once;
+use tokio::task::{JoinError, JoinSet};
+use tracing::error;
+
+use crate::{InstallerError, Result};
+
+/// Replaces the default panic hook with one that writes the panic and its backtrace to the
+/// installer log, so panics in background tasks end up in log dumps and error reports
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        panic::set_hook(Box::new(|info| {
+            error!("{}\n{}", info, Backtrace::force_capture());
+        }));
+    });
+}
+
+/// Runs `f` on the blocking thread pool. A panic becomes `InstallerError::Internal`
+pub async fn spawn_blocking<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(join_error)
+}
+
+/// Runs every job on the blocking thread pool at once, returning their results in completion
+/// order. A panic becomes `InstallerError::Internal`
+pub async fn spawn_blocking_all<I, F, T>(jobs: I) -> Vec<Result<T>>
+where
+    I: IntoIterator<Item = F>,
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let mut set = JoinSet::new();
+    for job in jobs {
+        set.spawn_blocking(job);
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = set.join_next().await {
+        results.push(result.map_err(join_error));
+    }
+    results
+}
+
+/// Runs every future as a separate task at once, returning their results in the order given. A
+/// panic becomes `InstallerError::Internal`
+pub async fn spawn_all<I, F>(futures: I) -> Vec<Result<F::Output>>
+where
+    I: IntoIterator<Item = F>,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let mut set = JoinSet::new();
+    let ids: Vec<_> = futures
+        .into_iter()
+        .map(|future| set.spawn(future).id())
+        .collect();
+
+    let mut results = HashMap::new();
+    while let Some(result) = set.join_next_with_id().await {
+        match result {
+            Ok((id, output)) => results.insert(id, Ok(output)),
+            Err(e) => results.insert(e.id(), Err(join_error(e))),
+        };
+    }
+    ids.iter()
+        .map(|id| {
+            results
+                .remove(id)
+                .unwrap_or_else(|| Err(InstallerError::Internal("Task was lost".to_string())))
+        })
+        .collect()
+}
+
+/// Runs `future` as a separate task. A panic becomes `InstallerError::Internal`
+pub async fn spawn<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future).await.map_err(join_error)
+}
+
+fn join_error(error: JoinError) -> InstallerError {
+    if error.is_panic() {
+        InstallerError::Internal(format!(
+            "Task panicked: {}",
+            panic_message(error.into_panic())
+        ))
+    } else {
+        InstallerError::Internal(format!("Task failed: {}", error))
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
@@ -0,0 +1,155 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+use crate::platform::Platform;
+use crate::report::{InstallReport, RepositoryOutcome};
+use crate::{InstallerError, Result};
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Coarse failure classification. Error messages are never reported, as they can contain paths
+/// and device identifiers
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCategory {
+    NoDevice,
+    MultipleDevices,
+    Adb,
+    GitHub,
+    Network,
+    Config,
+    InstallationStep,
+    ApkInstallation,
+    Io,
+    Cancelled,
+    Other,
+}
+
+impl From<&InstallerError> for FailureCategory {
+    fn from(error: &InstallerError) -> Self {
+        match error.root() {
+            InstallerError::NoDevice => FailureCategory::NoDevice,
+            InstallerError::MultipleDevices => FailureCategory::MultipleDevices,
+            InstallerError::Adb(_) => FailureCategory::Adb,
+            InstallerError::GitHub(_) => FailureCategory::GitHub,
+            InstallerError::Network(_) => FailureCategory::Network,
+            InstallerError::Config(_)
+            | InstallerError::Yaml(_)
+            | InstallerError::NoRepositoriesFound
+            | InstallerError::RepositoryNotFound { .. } => FailureCategory::Config,
+            InstallerError::InstallationStep { .. } => FailureCategory::InstallationStep,
+            InstallerError::ApkInstallation { .. } => FailureCategory::ApkInstallation,
+            InstallerError::Io(_) | InstallerError::FileNotFound { .. } => FailureCategory::Io,
+            InstallerError::Cancelled => FailureCategory::Cancelled,
+            _ => FailureCategory::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum InstallOutcome {
+    Success,
+    /// Completed, but at least one required repository failed
+    PartialFailure,
+    Failed {
+        category: FailureCategory,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryEvent {
+    pub name: String,
+    pub version: Option<String>,
+    pub status: String,
+}
+
+/// Anonymized summary of a single install run. Contains no device, user, or host identifiers
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstallEvent {
+    pub installer_version: String,
+    pub os: String,
+    pub config_name: String,
+    pub repositories: Vec<RepositoryEvent>,
+    #[serde(flatten)]
+    pub outcome: InstallOutcome,
+    pub duration_ms: u64,
+}
+
+impl InstallEvent {
+    pub fn new(
+        config_name: &str,
+        result: std::result::Result<&InstallReport, &InstallerError>,
+        duration: Duration,
+    ) -> Self {
+        let (repositories, outcome) = match result {
+            Ok(report) => {
+                let repositories = report
+                    .repositories
+                    .iter()
+                    .map(|result| {
+                        let (version, status) = match &result.outcome {
+                            RepositoryOutcome::Installed { version, .. } => {
+                                (version.clone(), "installed")
+                            }
+                            RepositoryOutcome::Downloaded { version, .. } => {
+                                (Some(version.clone()), "downloaded")
+                            }
+                            RepositoryOutcome::Skipped { .. } => (None, "skipped"),
+                            RepositoryOutcome::Failed { .. } => (None, "failed"),
+                        };
+
+                        RepositoryEvent {
+                            name: result.name.clone(),
+                            version,
+                            status: status.to_string(),
+                        }
+                    })
+                    .collect();
+
+                let outcome = if report.succeeded() {
+                    InstallOutcome::Success
+                } else {
+                    InstallOutcome::PartialFailure
+                };
+
+                (repositories, outcome)
+            }
+            Err(error) => (
+                Vec::new(),
+                InstallOutcome::Failed {
+                    category: error.into(),
+                },
+            ),
+        };
+
+        Self {
+            installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            config_name: config_name.to_string(),
+            repositories,
+            outcome,
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+}
+
+/// Posts an install event to the telemetry endpoint
+pub async fn submit(endpoint: &str, event: &InstallEvent) -> Result<()> {
+    let client = Client::builder()
+        .user_agent(Platform::user_agent())
+        .timeout(SUBMIT_TIMEOUT)
+        .build()?;
+
+    client
+        .post(endpoint)
+        .json(event)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!("Sent anonymous install telemetry");
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::config::AssetTransform;
+use crate::{InstallerError, Result};
+
+/// Runs `steps` in order on the downloaded asset at `path`, returning the file they leave
+pub fn apply(path: &Path, steps: &[AssetTransform]) -> Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for step in steps {
+        path = apply_step(&path, step).map_err(|e| InstallerError::InstallationStep {
+            step: step.describe(),
+            reason: format!("{}: {}", file_name(&path), e),
+        })?;
+    }
+    Ok(path)
+}
+
+fn apply_step(path: &Path, step: &AssetTransform) -> io::Result<PathBuf> {
+    match step {
+        AssetTransform::Rename { to } => {
+            let target = path.with_file_name(to);
+            fs::rename(path, &target)?;
+            Ok(target)
+        }
+        AssetTransform::Unzip { member, to } => {
+            let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+            let entry = archive
+                .by_name(member)
+                .map_err(|e| io::Error::other(format!("{}: {}", member, e)))?;
+            let name = to
+                .as_deref()
+                .unwrap_or_else(|| member.rsplit('/').next().unwrap_or(member));
+            if entry.is_dir() || matches!(name, "" | "." | "..") {
+                return Err(io::Error::other(format!("{} isn't a file", member)));
+            }
+            replace(path, &path.with_file_name(name), entry)
+        }
+        AssetTransform::Gunzip { to } => {
+            let name = match to {
+                Some(to) => to.as_str(),
+                None => file_name(path)
+                    .strip_suffix(".gz")
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| io::Error::other("no .gz extension to remove, so set `to`"))?,
+            };
+            let target = path.with_file_name(name);
+            replace(path, &target, GzDecoder::new(File::open(path)?))
+        }
+        AssetTransform::Chmod { mode } => {
+            let mode = u32::from_str_radix(mode, 8).map_err(io::Error::other)?;
+            set_mode(path, mode)?;
+            Ok(path.to_path_buf())
+        }
+    }
+}
+
+/// Writes `content` to `target`, then removes `source`. A partial `target` never remains
+fn replace(source: &Path, target: &Path, mut content: impl Read) -> io::Result<PathBuf> {
+    let partial = target.with_file_name(format!("{}.partial", file_name(target)));
+    let written = File::create(&partial)
+        .and_then(|mut file| io::copy(&mut content, &mut file))
+        .and_then(|_| fs::rename(&partial, target));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    if source != target {
+        fs::remove_file(source)?;
+    }
+    Ok(target.to_path_buf())
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// An empty directory of its own for each test
+    fn scratch_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "penumbra-transform-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut archive = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in entries {
+            archive.start_file(*name, options).unwrap();
+            archive.write_all(content).unwrap();
+        }
+        archive.finish().unwrap();
+    }
+
+    #[test]
+    fn renames_within_the_directory() {
+        let dir = scratch_dir();
+        let asset = dir.join("tool-linux-arm64");
+        fs::write(&asset, b"binary").unwrap();
+
+        let result = apply(
+            &asset,
+            &[AssetTransform::Rename {
+                to: "tool".to_string(),
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(result, dir.join("tool"));
+        assert_eq!(fs::read(&result).unwrap(), b"binary");
+        assert!(!asset.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unzips_a_member_named_after_its_entry() {
+        let dir = scratch_dir();
+        let archive = dir.join("release.zip");
+        write_zip(
+            &archive,
+            &[("README.md", b"readme"), ("bin/tool", b"binary")],
+        );
+
+        let result = apply(
+            &archive,
+            &[AssetTransform::Unzip {
+                member: "bin/tool".to_string(),
+                to: None,
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(result, dir.join("tool"));
+        assert_eq!(fs::read(&result).unwrap(), b"binary");
+        assert!(!archive.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_archive_when_the_member_is_missing() {
+        let dir = scratch_dir();
+        let archive = dir.join("release.zip");
+        write_zip(&archive, &[("README.md", b"readme")]);
+
+        let error = apply(
+            &archive,
+            &[AssetTransform::Unzip {
+                member: "bin/tool".to_string(),
+                to: None,
+            }],
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("bin/tool"), "{}", error);
+        assert!(archive.exists());
+        assert!(!dir.join("tool.partial").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gunzips_without_the_extension() {
+        let dir = scratch_dir();
+        let asset = dir.join("model.bin.gz");
+        let mut encoder = GzEncoder::new(File::create(&asset).unwrap(), Compression::default());
+        encoder.write_all(b"weights").unwrap();
+        encoder.finish().unwrap();
+
+        let result = apply(&asset, &[AssetTransform::Gunzip { to: None }]).unwrap();
+
+        assert_eq!(result, dir.join("model.bin"));
+        assert_eq!(fs::read(&result).unwrap(), b"weights");
+        assert!(!asset.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gunzip_needs_a_name_without_a_gz_extension() {
+        let dir = scratch_dir();
+        let asset = dir.join("model.bin");
+        fs::write(&asset, b"weights").unwrap();
+
+        assert!(apply(&asset, &[AssetTransform::Gunzip { to: None }]).is_err());
+        assert!(asset.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_modes_that_arent_octal() {
+        let dir = scratch_dir();
+        let asset = dir.join("tool");
+        fs::write(&asset, b"binary").unwrap();
+
+        let steps = [AssetTransform::Chmod {
+            mode: "rwx".to_string(),
+        }];
+        assert!(apply(&asset, &steps).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_steps_in_order() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch_dir();
+        let archive = dir.join("release.zip");
+        write_zip(&archive, &[("tool", b"binary")]);
+
+        let result = apply(
+            &archive,
+            &[
+                AssetTransform::Unzip {
+                    member: "tool".to_string(),
+                    to: Some("penumbra-tool".to_string()),
+                },
+                AssetTransform::Chmod {
+                    mode: "755".to_string(),
+                },
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(result, dir.join("penumbra-tool"));
+        let mode = fs::metadata(&result).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::plan::InstallPlan;
+use crate::platform::Platform;
+use crate::Result;
+
+/// Repository name to release version
+pub type Versions = BTreeMap<String, String>;
+
+/// What the updater last did to a device
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DeviceUpdateState {
+    /// Versions from the last successful update
+    #[serde(default)]
+    pub installed: Versions,
+    /// Versions from the last failed update. Not retried until a newer release appears
+    #[serde(default)]
+    pub failed: Option<Versions>,
+    /// Seconds since the Unix epoch
+    #[serde(default)]
+    pub last_attempt: u64,
+}
+
+/// Per-device update history, keyed by device serial number. Kept so unattended updates don't
+/// reinstall the same releases, or retry a failing release forever
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpdateState {
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceUpdateState>,
+}
+
+/// Whether a device should be updated to a plan's versions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateCheck {
+    /// Repositories whose resolved release differs from the installed one
+    Available(Versions),
+    UpToDate,
+    /// These exact versions already failed to install on the device
+    PreviouslyFailed,
+}
+
+impl UpdateState {
+    fn path() -> PathBuf {
+        Platform::state_dir().join("update_state.json")
+    }
+
+    /// Loads the saved state. A missing or unreadable file is treated as empty
+    pub fn load() -> Self {
+        Platform::load_json(&Self::path(), "update state")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        Platform::save_json(&Self::path(), self)
+    }
+
+    /// Compares the versions in `plan` with what was last applied to `device`
+    pub fn check(&self, device: &str, plan: &InstallPlan) -> UpdateCheck {
+        let target = plan_versions(plan);
+        let Some(state) = self.devices.get(device) else {
+            return UpdateCheck::Available(target);
+        };
+
+        if state.failed.as_ref() == Some(&target) {
+            return UpdateCheck::PreviouslyFailed;
+        }
+
+        let changed: Versions = target
+            .into_iter()
+            .filter(|(name, version)| state.installed.get(name) != Some(version))
+            .collect();
+        if changed.is_empty() {
+            UpdateCheck::UpToDate
+        } else {
+            UpdateCheck::Available(changed)
+        }
+    }
+
+    /// The version of `repo` last installed to `device`. Without a device, the version every
+    /// device with `repo` installed has, if they agree
+    pub fn installed_version(&self, repo: &str, device: Option<&str>) -> Option<&str> {
+        let mut versions = self
+            .devices
+            .iter()
+            .filter(|(serial, _)| device.is_none_or(|device| device == serial.as_str()))
+            .filter_map(|(_, state)| state.installed.get(repo).map(String::as_str));
+        let version = versions.next()?;
+        versions.all(|other| other == version).then_some(version)
+    }
+
+    /// Records the outcome of updating `device` to the versions in `plan`
+    pub fn record(&mut self, device: &str, plan: &InstallPlan, succeeded: bool) {
+        let state = self.devices.entry(device.to_string()).or_default();
+        let versions = plan_versions(plan);
+
+        if succeeded {
+            state.installed.extend(versions);
+            state.failed = None;
+        } else {
+            state.failed = Some(versions);
+        }
+        state.last_attempt = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+    }
+}
+
+fn plan_versions(plan: &InstallPlan) -> Versions {
+    plan.repositories
+        .iter()
+        .map(|repo| (repo.name.clone(), repo.version.clone()))
+        .collect()
+}
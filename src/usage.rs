@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use crate::adb::FileType;
+use crate::{AdbManager, InstallConfig, Repository, Result};
+
+/// Filesystem holding installed apps and their data
+pub(crate) const DATA_PARTITION: &str = "/data";
+
+/// Estimated storage each repository takes up on the device
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UsageReport {
+    pub config_name: String,
+    pub repositories: Vec<RepositoryUsage>,
+    /// Bytes free on the data partition, if `df` could be read
+    pub free_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryUsage {
+    pub name: String,
+    pub packages: Vec<PackageUsage>,
+    pub paths: Vec<PathUsage>,
+}
+
+/// An installed package and the size of its APKs. App data isn't readable over ADB
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PackageUsage {
+    pub package: String,
+    pub apks: Vec<String>,
+    pub bytes: u64,
+}
+
+/// A file or directory tree the repository wrote to the device
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PathUsage {
+    pub path: String,
+    pub bytes: u64,
+}
+
+impl UsageReport {
+    /// Measures the packages and paths of each repository present on the device. A path shared
+    /// by several repositories counts towards the first
+    pub async fn resolve(
+        config: &InstallConfig,
+        repos: &[Repository],
+        adb: &mut AdbManager,
+    ) -> Result<Self> {
+        let mut counted = HashSet::new();
+        let mut repositories = Vec::new();
+
+        for repo in repos {
+            let usage = RepositoryUsage::resolve(repo, adb, &mut counted).await?;
+            repositories.push(usage);
+        }
+
+        let free_bytes = match adb.free_space(DATA_PARTITION).await {
+            Ok(free) => Some(free),
+            Err(e) => {
+                warn!("Couldn't read free space: {}", e);
+                None
+            }
+        };
+
+        Ok(Self {
+            config_name: config.name.clone(),
+            repositories,
+            free_bytes,
+        })
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.repositories
+            .iter()
+            .map(RepositoryUsage::total_bytes)
+            .sum()
+    }
+
+    pub fn log_summary(&self) {
+        info!("Storage used on the device, by repository:");
+        for repo in &self.repositories {
+            if repo.is_empty() {
+                continue;
+            }
+
+            info!("  {}: {}", repo.name, megabytes(repo.total_bytes()));
+            for package in &repo.packages {
+                info!("    {}: {}", package.package, megabytes(package.bytes));
+            }
+            for path in &repo.paths {
+                info!("    {}: {}", path.path, megabytes(path.bytes));
+            }
+        }
+
+        info!("Total: {}", megabytes(self.total_bytes()));
+        if let Some(free) = self.free_bytes {
+            info!("Free on {}: {}", DATA_PARTITION, megabytes(free));
+        }
+    }
+}
+
+impl RepositoryUsage {
+    async fn resolve(
+        repo: &Repository,
+        adb: &mut AdbManager,
+        counted: &mut HashSet<String>,
+    ) -> Result<Self> {
+        let mut usage = Self {
+            name: repo.name.clone(),
+            packages: Vec::new(),
+            paths: Vec::new(),
+        };
+
+        for pattern in repo.package_patterns() {
+            for package in adb.list_packages(&pattern.replace('*', "")).await? {
+                if !counted.insert(format!("package:{}", package)) {
+                    continue;
+                }
+
+                let apks = adb.package_apks(&package).await?;
+                let mut bytes = 0;
+                for apk in &apks {
+                    bytes += adb.stat(apk).await?.map_or(0, |stat| stat.size);
+                }
+                usage.packages.push(PackageUsage {
+                    package,
+                    apks,
+                    bytes,
+                });
+            }
+        }
+
+        for path in repo.device_paths() {
+            if !counted.insert(path.clone()) {
+                continue;
+            }
+
+            let bytes = match adb.stat(&path).await? {
+                Some(stat) if stat.file_type == FileType::Directory => {
+                    adb.disk_usage(&path).await?
+                }
+                Some(stat) if stat.file_type == FileType::File => stat.size,
+                _ => continue,
+            };
+            usage.paths.push(PathUsage { path, bytes });
+        }
+
+        Ok(usage)
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        let packages: u64 = self.packages.iter().map(|package| package.bytes).sum();
+        let paths: u64 = self.paths.iter().map(|path| path.bytes).sum();
+        packages + paths
+    }
+
+    /// Nothing of the repository is on the device
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.paths.is_empty()
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+}
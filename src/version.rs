@@ -0,0 +1,205 @@
+use semver::{Version, VersionReq};
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::github::GitHubClient;
+use crate::{InstallerError, Result};
+
+pub type VersionFuture<'a> = Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
+/// Picks the tag of a repository to install, chosen by the repository's `version`
+pub trait VersionResolver: Send + Sync {
+    fn resolve<'a>(
+        &'a self,
+        github: &'a GitHubClient,
+        owner: &'a str,
+        repo: &'a str,
+    ) -> VersionFuture<'a>;
+}
+
+/// The newest published release, or the newest prerelease when there are only prereleases
+pub struct LatestRelease;
+
+impl VersionResolver for LatestRelease {
+    fn resolve<'a>(
+        &'a self,
+        github: &'a GitHubClient,
+        owner: &'a str,
+        repo: &'a str,
+    ) -> VersionFuture<'a> {
+        Box::pin(github.get_latest_version(owner, repo))
+    }
+}
+
+/// The newest tag, for repositories that tag builds without publishing releases. Tags that
+/// parse as semantic versions are compared as such; otherwise the first tag GitHub lists is used
+pub struct LatestTag;
+
+impl VersionResolver for LatestTag {
+    fn resolve<'a>(
+        &'a self,
+        github: &'a GitHubClient,
+        owner: &'a str,
+        repo: &'a str,
+    ) -> VersionFuture<'a> {
+        Box::pin(async move {
+            let tags = github.list_tags(owner, repo).await?;
+            newest_tag(&tags)
+                .cloned()
+                .ok_or_else(|| InstallerError::GitHub(format!("No tags found in {owner}/{repo}")))
+        })
+    }
+}
+
+/// The highest release, or tag when `tags` is set, whose semantic version is within `range`
+pub struct SemverRange {
+    range: VersionReq,
+    tags: bool,
+}
+
+impl SemverRange {
+    pub fn new(range: &str, tags: bool) -> Result<Self> {
+        let range = VersionReq::parse(range).map_err(|e| {
+            InstallerError::Config(format!("Invalid version range '{}': {}", range, e))
+        })?;
+        Ok(Self { range, tags })
+    }
+
+    /// The candidate tag with the highest version within the range
+    fn highest(&self, candidates: Vec<String>) -> Option<String> {
+        candidates
+            .into_iter()
+            .filter_map(|tag| Some((tag_version(&tag)?, tag)))
+            .filter(|(version, _)| self.range.matches(version))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, tag)| tag)
+    }
+}
+
+impl VersionResolver for SemverRange {
+    fn resolve<'a>(
+        &'a self,
+        github: &'a GitHubClient,
+        owner: &'a str,
+        repo: &'a str,
+    ) -> VersionFuture<'a> {
+        Box::pin(async move {
+            let candidates = if self.tags {
+                github.list_tags(owner, repo).await?
+            } else {
+                github.list_release_tags(owner, repo).await?
+            };
+
+            self.highest(candidates).ok_or_else(|| {
+                InstallerError::GitHub(format!(
+                    "No {} of {}/{} matches {}",
+                    if self.tags { "tag" } else { "release" },
+                    owner,
+                    repo,
+                    self.range
+                ))
+            })
+        })
+    }
+}
+
+/// A tag named in the config, used as is
+pub struct Fixed(pub String);
+
+impl VersionResolver for Fixed {
+    fn resolve<'a>(
+        &'a self,
+        _github: &'a GitHubClient,
+        _owner: &'a str,
+        _repo: &'a str,
+    ) -> VersionFuture<'a> {
+        Box::pin(async move { Ok(self.0.clone()) })
+    }
+}
+
+/// The tag with the highest semantic version, or the first listed when none parse as one
+fn newest_tag(tags: &[String]) -> Option<&String> {
+    tags.iter()
+        .filter_map(|tag| Some((tag_version(tag)?, tag)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag)
+        .or(tags.first())
+}
+
+/// Semantic version of a tag such as `v1.2.0` or `release-1.2.0`, ignoring anything before the
+/// first digit
+fn tag_version(tag: &str) -> Option<Version> {
+    let version = tag.trim_start_matches(|c: char| !c.is_ascii_digit());
+    Version::parse(version).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_prefixed_tags() {
+        assert_eq!(tag_version("v1.2.0"), Some(Version::new(1, 2, 0)));
+        assert_eq!(tag_version("release-0.4.1"), Some(Version::new(0, 4, 1)));
+        assert_eq!(
+            tag_version("v2.0.0-beta.1"),
+            Some(Version::parse("2.0.0-beta.1").unwrap())
+        );
+        assert_eq!(tag_version("nightly"), None);
+        assert_eq!(tag_version("v1.2"), None);
+    }
+
+    #[test]
+    fn newest_tag_compares_versions() {
+        let listed = tags(&["v1.9.0", "v1.10.0", "nightly", "v1.2.3"]);
+        assert_eq!(newest_tag(&listed).map(String::as_str), Some("v1.10.0"));
+    }
+
+    #[test]
+    fn newest_tag_falls_back_to_the_first_listed() {
+        let listed = tags(&["nightly-2024-05-01", "nightly-2024-04-01"]);
+        assert_eq!(
+            newest_tag(&listed).map(String::as_str),
+            Some("nightly-2024-05-01")
+        );
+        assert_eq!(newest_tag(&[]), None);
+    }
+
+    #[test]
+    fn semver_range_picks_the_highest_match() {
+        let range = SemverRange::new("^1.2", false).unwrap();
+        let candidates = tags(&["v1.1.0", "v1.2.0", "v1.4.2", "v2.0.0", "v1.3.9", "latest"]);
+        assert_eq!(range.highest(candidates), Some("v1.4.2".to_string()));
+    }
+
+    #[test]
+    fn semver_range_skips_prereleases_unless_asked() {
+        let candidates = tags(&["v1.0.0", "v1.1.0-rc.1"]);
+        let stable = SemverRange::new(">=1.0.0", false).unwrap();
+        assert_eq!(
+            stable.highest(candidates.clone()),
+            Some("v1.0.0".to_string())
+        );
+
+        let prerelease = SemverRange::new(">=1.1.0-rc.0", false).unwrap();
+        assert_eq!(
+            prerelease.highest(candidates),
+            Some("v1.1.0-rc.1".to_string())
+        );
+    }
+
+    #[test]
+    fn semver_range_without_a_match() {
+        let range = SemverRange::new("~3.0", true).unwrap();
+        assert_eq!(range.highest(tags(&["v1.0.0", "v2.5.0"])), None);
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(SemverRange::new("not a range", false).is_err());
+    }
+}